@@ -0,0 +1,16 @@
+mod backoff;
+mod blocked;
+mod breaker;
+mod channels;
+mod connector;
+mod status;
+mod supervisor;
+
+pub(crate) use backoff::Backoff;
+pub(crate) use blocked::BlockedState;
+pub(crate) use breaker::CircuitBreaker;
+pub(crate) use channels::{ChannelManager, EventPublisher, Publisher};
+pub(crate) use connector::{open_connection, AmqpConnector, BrokerSession, SessionEnd};
+use connector::{BrokerConnector, ConnectErrors};
+pub(crate) use status::{StatusReader, StatusTracker};
+pub(crate) use supervisor::Supervisor;
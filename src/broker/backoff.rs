@@ -0,0 +1,102 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// Exponential backoff between reconnection attempts, doubling from `base` up to `max`
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    jitter: bool,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Backoff {
+        Backoff {
+            base,
+            max,
+            jitter: true,
+            attempt: 0,
+        }
+    }
+
+    /// Disables the jitter, the delays follow the exact exponential schedule
+    pub fn without_jitter(self) -> Backoff {
+        Backoff {
+            jitter: false,
+            ..self
+        }
+    }
+
+    /// Delay to wait before the next attempt
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .base
+            .saturating_mul(2_u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        if !self.jitter {
+            return delay;
+        }
+
+        // equal jitter, half of the delay is kept and the other half is randomized
+        delay / 2 + delay.mul_f64(random_fraction()) / 2
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+// a fraction in [0, 1), the std hasher keys are randomly seeded
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+
+    (random >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_schedule() {
+        let mut backoff =
+            Backoff::new(Duration::from_secs(1), Duration::from_secs(10)).without_jitter();
+
+        let delays: Vec<Duration> = (0..6).map(|_| backoff.next_delay()).collect();
+
+        assert_eq!(
+            delays,
+            [1, 2, 4, 8, 10, 10].map(Duration::from_secs).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_backoff_reset() {
+        let mut backoff =
+            Backoff::new(Duration::from_secs(1), Duration::from_secs(10)).without_jitter();
+        backoff.next_delay();
+        backoff.next_delay();
+
+        backoff.reset();
+
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_backoff_jitter_bounds() {
+        let mut backoff = Backoff::new(Duration::from_secs(8), Duration::from_secs(8));
+
+        for _ in 0..100 {
+            let delay = backoff.next_delay();
+
+            assert!(delay >= Duration::from_secs(4));
+            assert!(delay <= Duration::from_secs(8));
+        }
+    }
+}
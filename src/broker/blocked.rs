@@ -0,0 +1,267 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use amqprs::{callbacks::ConnectionCallback, connection::Connection, Close};
+use log::{info, warn};
+use tokio::sync::{watch, Mutex};
+
+use super::StatusTracker;
+
+#[derive(Debug, Default)]
+struct Blocks {
+    /// Reason of the resource alarm blocking the connection
+    connection: Option<String>,
+    /// Channels the broker stopped with the flow control
+    flow: BTreeSet<u16>,
+}
+
+impl Blocks {
+    fn is_blocked(&self) -> bool {
+        self.connection.is_some() || !self.flow.is_empty()
+    }
+}
+
+/// Whether the broker blocked the connection, with the reason it provided, or stopped one of
+/// its channels with the flow control.
+/// Resource alarms on the broker block the publishers until the resources are available again.
+#[derive(Debug, Clone)]
+pub struct BlockedState {
+    blocks: Arc<watch::Sender<Blocks>>,
+    /// Queues the operations waiting to pass, so that they are released in order
+    turns: Arc<Mutex<()>>,
+    status: StatusTracker,
+}
+
+impl Default for BlockedState {
+    fn default() -> BlockedState {
+        BlockedState {
+            blocks: Arc::new(watch::channel(Blocks::default()).0),
+            turns: Arc::new(Mutex::new(())),
+            status: StatusTracker::default(),
+        }
+    }
+}
+
+impl BlockedState {
+    /// The blocks are reported in the connection status
+    pub fn with_status(self, status: StatusTracker) -> BlockedState {
+        BlockedState { status, ..self }
+    }
+
+    /// Records the block, the warning is logged only on the first notification
+    pub fn block(&self, reason: &str) {
+        let blocked = self.blocks.send_if_modified(|blocks| {
+            if blocks.connection.is_some() {
+                return false;
+            }
+            blocks.connection = Some(reason.to_owned());
+
+            true
+        });
+
+        if blocked {
+            self.report();
+            warn!(
+                "connection blocked by the broker, pausing the deliveries: {}",
+                reason
+            );
+        }
+    }
+
+    pub fn unblock(&self) {
+        let unblocked = self
+            .blocks
+            .send_if_modified(|blocks| blocks.connection.take().is_some());
+
+        if unblocked {
+            self.report();
+            info!("connection unblocked by the broker, resuming the deliveries");
+        }
+    }
+
+    /// Records a flow control request of the broker for the channel
+    pub fn flow(&self, channel_id: u16, active: bool) {
+        let changed = self.blocks.send_if_modified(|blocks| match active {
+            true => blocks.flow.remove(&channel_id),
+            false => blocks.flow.insert(channel_id),
+        });
+
+        if changed {
+            self.report();
+            match active {
+                true => info!("flow resumed on channel {}", channel_id),
+                false => warn!(
+                    "flow stopped on channel {} by the broker, pausing the publishes",
+                    channel_id
+                ),
+            }
+        }
+    }
+
+    /// Forgets the blocks of a lost connection, a new connection starts unblocked
+    pub fn reset(&self) {
+        let reset = self.blocks.send_if_modified(|blocks| {
+            let blocked = blocks.is_blocked();
+            *blocks = Blocks::default();
+
+            blocked
+        });
+
+        if reset {
+            self.report();
+        }
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.blocks.borrow().is_blocked()
+    }
+
+    /// Waits until the connection is not blocked
+    pub async fn unblocked(&self) {
+        let mut blocks = self.blocks.subscribe();
+        // the sender is owned by self, so it cannot be dropped while waiting
+        let _ = blocks.wait_for(|blocks| !blocks.is_blocked()).await;
+    }
+
+    /// Waits until publishing is allowed, the operations waiting are released in the order
+    /// they started to wait
+    pub async fn pass(&self) {
+        // the lock is fair, the waiting operations take their turn in order
+        let _turn = self.turns.lock().await;
+        self.unblocked().await;
+    }
+
+    fn report(&self) {
+        self.status.blocked(self.is_blocked());
+    }
+}
+
+/// Connection callback recording the broker block notifications
+pub struct BlockedCallback {
+    state: BlockedState,
+}
+
+impl BlockedCallback {
+    pub fn new(state: BlockedState) -> BlockedCallback {
+        BlockedCallback { state }
+    }
+}
+
+#[async_trait::async_trait]
+impl ConnectionCallback for BlockedCallback {
+    async fn close(&mut self, connection: &Connection, close: Close) -> amqprs::error::Result<()> {
+        warn!("close request for connection {}: {}", connection, close);
+
+        Ok(())
+    }
+
+    async fn blocked(&mut self, _connection: &Connection, reason: String) {
+        self.state.block(&reason);
+    }
+
+    async fn unblocked(&mut self, _connection: &Connection) {
+        self.state.unblock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_blocked_state_transitions() {
+        let state = BlockedState::default();
+
+        assert!(!state.is_blocked());
+
+        state.block("low on memory");
+        state.block("low on disk");
+
+        assert!(state.is_blocked());
+        assert_eq!(
+            state.blocks.borrow().connection,
+            Some("low on memory".to_owned())
+        );
+
+        state.unblock();
+
+        assert!(!state.is_blocked());
+    }
+
+    #[tokio::test]
+    async fn test_unblocked_waits_for_the_unblock() {
+        let state = BlockedState::default();
+        state.block("low on memory");
+
+        let waiting_state = state.clone();
+        let waiting = tokio::spawn(async move { waiting_state.unblocked().await });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+
+        state.unblock();
+
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_flow_control_gate() {
+        let state = BlockedState::default();
+
+        state.flow(1, false);
+        state.flow(2, false);
+        state.block("low on memory");
+        state.flow(1, true);
+        state.unblock();
+
+        assert!(state.is_blocked());
+
+        state.flow(2, true);
+
+        assert!(!state.is_blocked());
+
+        state.flow(3, false);
+        state.reset();
+
+        assert!(!state.is_blocked());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pass_releases_in_order() {
+        let state = BlockedState::default();
+        let passed = Arc::new(std::sync::Mutex::new(vec![]));
+        state.flow(1, false);
+
+        let mut operations = vec![];
+        for operation in 0..3 {
+            let state = state.clone();
+            let passed = passed.clone();
+            operations.push(tokio::spawn(async move {
+                state.pass().await;
+                passed.lock().unwrap().push(operation);
+            }));
+            // the operations start to wait one after the other
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(passed.lock().unwrap().is_empty());
+
+        state.flow(1, true);
+        for operation in operations {
+            operation.await.unwrap();
+        }
+
+        assert_eq!(*passed.lock().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_unblocked_when_not_blocked() {
+        tokio::time::timeout(Duration::from_secs(1), BlockedState::default().unblocked())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use tokio::{sync::watch, time::Instant};
+
+use crate::config::BreakerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    Closed,
+    /// The broker operations keep failing, the deliveries are paused
+    Open,
+    /// A probe is checking whether the broker recovered
+    HalfOpen,
+}
+
+/// What a caller waiting for the breaker to close should do next
+#[derive(Debug, PartialEq)]
+enum Probe {
+    Closed,
+    Now,
+    Wait(Duration),
+}
+
+#[derive(Debug)]
+struct Failures {
+    consecutive: u32,
+    /// Start of the failures streak, older streaks are restarted
+    first_at: Option<Instant>,
+    last_probe_at: Option<Instant>,
+}
+
+/// Pauses the broker operations after repeated consecutive failures within a window.
+/// An open breaker is probed at most once per probe interval, a successful probe closes it
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    window: Duration,
+    probe_interval: Duration,
+    failures: Mutex<Failures>,
+    state: watch::Sender<BreakerState>,
+    openings: AtomicU64,
+    closings: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &BreakerConfig) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold: config.failures,
+            window: config.window,
+            probe_interval: config.probe_interval,
+            failures: Mutex::new(Failures {
+                consecutive: 0,
+                first_at: None,
+                last_probe_at: None,
+            }),
+            state: watch::channel(BreakerState::Closed).0,
+            openings: AtomicU64::new(0),
+            closings: AtomicU64::new(0),
+        }
+    }
+
+    /// A breaker that never opens
+    pub fn disabled() -> CircuitBreaker {
+        CircuitBreaker::new(&BreakerConfig {
+            failures: 0,
+            window: Duration::ZERO,
+            probe_interval: Duration::from_secs(1),
+        })
+    }
+
+    pub fn state(&self) -> BreakerState {
+        *self.state.borrow()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state() != BreakerState::Closed
+    }
+
+    pub fn record_success(&self) {
+        let mut failures = self.failures.lock().expect("breaker poisoned, fatal.");
+        failures.consecutive = 0;
+        failures.first_at = None;
+
+        // an operation started before the breaker opened does not close it, only a probe does
+        if self.state() == BreakerState::HalfOpen {
+            self.state.send_replace(BreakerState::Closed);
+            self.closings.fetch_add(1, Ordering::Relaxed);
+            info!("circuit breaker closed, the broker operations succeed again, resuming the deliveries");
+        }
+    }
+
+    pub fn record_failure(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut failures = self.failures.lock().expect("breaker poisoned, fatal.");
+
+        match self.state() {
+            BreakerState::Closed => {
+                match failures.first_at {
+                    Some(first_at) if now.duration_since(first_at) < self.window => {
+                        failures.consecutive += 1
+                    }
+                    _ => {
+                        failures.consecutive = 1;
+                        failures.first_at = Some(now);
+                    }
+                }
+
+                if failures.consecutive >= self.threshold {
+                    failures.last_probe_at = Some(now);
+                    self.state.send_replace(BreakerState::Open);
+                    self.openings.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "circuit breaker opened after {} consecutive broker operation failures, pausing the deliveries",
+                        failures.consecutive
+                    );
+                }
+            }
+            BreakerState::HalfOpen => {
+                self.state.send_replace(BreakerState::Open);
+                warn!(
+                    "circuit breaker probe failed, probing again in {:?}",
+                    self.probe_interval
+                );
+            }
+            BreakerState::Open => (),
+        }
+    }
+
+    /// Takes the probe of an open breaker once the probe interval elapsed since the last one
+    fn probe(&self) -> Probe {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().expect("breaker poisoned, fatal.");
+
+        match self.state() {
+            BreakerState::Closed => Probe::Closed,
+            // another caller is probing
+            BreakerState::HalfOpen => Probe::Wait(self.probe_interval),
+            BreakerState::Open => {
+                let next_probe_at = failures
+                    .last_probe_at
+                    .map_or(now, |last_probe_at| last_probe_at + self.probe_interval);
+                if now < next_probe_at {
+                    return Probe::Wait(next_probe_at - now);
+                }
+
+                failures.last_probe_at = Some(now);
+                self.state.send_replace(BreakerState::HalfOpen);
+                debug!("circuit breaker half open, probing the broker");
+
+                Probe::Now
+            }
+        }
+    }
+
+    /// Waits until the breaker is closed, running `probe` when it is the turn of the caller
+    pub async fn closed<F, Fut>(&self, probe: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+    {
+        let mut state = self.state.subscribe();
+
+        loop {
+            match self.probe() {
+                Probe::Closed => return,
+                Probe::Now => match probe().await {
+                    Ok(_) => self.record_success(),
+                    Err(err) => {
+                        debug!("circuit breaker probe failed: {}", err);
+                        self.record_failure();
+                    }
+                },
+                Probe::Wait(delay) => {
+                    state.borrow_and_update();
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => (),
+                        _ = state.changed() => (),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Times the breaker opened since it was created
+    pub fn openings(&self) -> u64 {
+        self.openings.load(Ordering::Relaxed)
+    }
+
+    /// Times the breaker closed since it was created
+    pub fn closings(&self) -> u64 {
+        self.closings.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(&BreakerConfig {
+            failures: 3,
+            window: Duration::from_secs(10),
+            probe_interval: Duration::from_secs(5),
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_opens_after_consecutive_failures() {
+        let breaker = breaker();
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(breaker.openings(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_failures_outside_the_window() {
+        let breaker = breaker();
+
+        breaker.record_failure();
+        breaker.record_failure();
+        tokio::time::advance(Duration::from_secs(10)).await;
+        breaker.record_failure();
+
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_probes_are_rate_limited() {
+        let breaker = breaker();
+        (0..3).for_each(|_| breaker.record_failure());
+
+        assert_eq!(breaker.probe(), Probe::Wait(Duration::from_secs(5)));
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(breaker.probe(), Probe::Now);
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        // a single probe at a time
+        assert_eq!(breaker.probe(), Probe::Wait(Duration::from_secs(5)));
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(breaker.probe(), Probe::Wait(Duration::from_secs(5)));
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(breaker.probe(), Probe::Now);
+        breaker.record_success();
+
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.probe(), Probe::Closed);
+        assert_eq!(breaker.openings(), 1);
+        assert_eq!(breaker.closings(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_closed_after_a_successful_probe() {
+        let breaker = breaker();
+        (0..3).for_each(|_| breaker.record_failure());
+        let start = Instant::now();
+        let probes = Arc::new(Mutex::new(vec![]));
+
+        breaker
+            .closed(|| {
+                let probes = probes.clone();
+                async move {
+                    let mut probes = probes.lock().unwrap();
+                    probes.push(start.elapsed());
+
+                    match probes.len() {
+                        1 | 2 => Err("channel closed".to_owned()),
+                        _ => Ok(()),
+                    }
+                }
+            })
+            .await;
+
+        assert_eq!(
+            *probes.lock().unwrap(),
+            [5, 10, 15].map(Duration::from_secs).to_vec()
+        );
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_disabled_breaker_never_opens() {
+        let breaker = CircuitBreaker::disabled();
+
+        (0..100).for_each(|_| breaker.record_failure());
+
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+}
@@ -0,0 +1,445 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use amqprs::channel::{BasicPublishArguments, Channel};
+use amqprs::BasicProperties;
+#[cfg(test)]
+use mockall::automock;
+use tokio::sync::{watch, Mutex};
+
+/// What a channel opened on the broker connection is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChannelRole {
+    /// Deliveries of the consumer with the given index
+    Consume(usize),
+    Publish,
+    /// Declarations of the topology
+    Management,
+}
+
+/// Opens the channels on the current broker connection
+#[async_trait::async_trait]
+#[cfg_attr(test, automock)]
+pub trait ChannelFactory<C: 'static>: Send + Sync {
+    async fn open(&self, role: ChannelRole) -> Result<C, String>;
+}
+
+pub trait ManagedChannel: Clone + Send + Sync + 'static {
+    fn is_open(&self) -> bool;
+}
+
+impl ManagedChannel for Channel {
+    fn is_open(&self) -> bool {
+        Channel::is_open(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelState {
+    /// Never requested on the connection
+    Unopened,
+    Open,
+    /// Closed by the broker, opened again on the next request
+    Closed,
+}
+
+/// Owns the broker connection through its channel factory and hands out one channel per role,
+/// opened on the first request and opened again once the broker closed it
+pub struct ConnectionHandle<C, F> {
+    factory: F,
+    channels: Mutex<BTreeMap<ChannelRole, C>>,
+}
+
+impl<C: ManagedChannel, F: ChannelFactory<C>> ConnectionHandle<C, F> {
+    pub fn new(factory: F) -> ConnectionHandle<C, F> {
+        ConnectionHandle {
+            factory,
+            channels: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn factory(&self) -> &F {
+        &self.factory
+    }
+
+    /// The open channel of the role, opening it when missing or closed
+    pub async fn channel(&self, role: ChannelRole) -> Result<C, String> {
+        // held while opening, so that concurrent requests share the same channel
+        let mut channels = self.channels.lock().await;
+        if let Some(channel) = channels.get(&role).filter(|channel| channel.is_open()) {
+            return Ok(channel.clone());
+        }
+
+        let channel = self.factory.open(role).await?;
+        channels.insert(role, channel.clone());
+
+        Ok(channel)
+    }
+
+    /// The channel of the role when still open, never opening a new one.
+    /// The deliveries can only be settled on the channel they were received on
+    pub async fn current(&self, role: ChannelRole) -> Option<C> {
+        self.channels
+            .lock()
+            .await
+            .get(&role)
+            .filter(|channel| channel.is_open())
+            .cloned()
+    }
+
+    pub async fn state(&self, role: ChannelRole) -> ChannelState {
+        match self.channels.lock().await.get(&role) {
+            Some(channel) if channel.is_open() => ChannelState::Open,
+            Some(_) => ChannelState::Closed,
+            None => ChannelState::Unopened,
+        }
+    }
+
+    /// Forgets all the channels, returning the open ones to close
+    pub async fn take_open(&self) -> Vec<(ChannelRole, C)> {
+        std::mem::take(&mut *self.channels.lock().await)
+            .into_iter()
+            .filter(|(_, channel)| channel.is_open())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelsState {
+    Closed,
+    /// The epoch is increased on every connection, the publish channel can be reopened within it
+    Open {
+        epoch: u64,
+    },
+}
+
+#[derive(Debug)]
+struct Channels<C> {
+    epoch: u64,
+    publish: Option<C>,
+}
+
+/// Shares the publish channel of the broker connection, kept apart from the consume channels so
+/// that a channel error on publish does not stop the consumption.
+/// The publishers always get the channel of the current connection.
+#[derive(Debug, Clone)]
+pub struct ChannelManager<C> {
+    channels: Arc<watch::Sender<Channels<C>>>,
+}
+
+impl<C: ManagedChannel> Default for ChannelManager<C> {
+    fn default() -> ChannelManager<C> {
+        ChannelManager {
+            channels: Arc::new(
+                watch::channel(Channels {
+                    epoch: 0,
+                    publish: None,
+                })
+                .0,
+            ),
+        }
+    }
+}
+
+impl<C: ManagedChannel> ChannelManager<C> {
+    /// Opens the publish channel of a new connection
+    pub async fn open<F: ChannelFactory<C>>(
+        &self,
+        handle: &ConnectionHandle<C, F>,
+    ) -> Result<(), String> {
+        let publish = handle.channel(ChannelRole::Publish).await?;
+
+        self.channels.send_modify(|channels| {
+            channels.epoch += 1;
+            channels.publish = Some(publish);
+        });
+
+        Ok(())
+    }
+
+    /// Reopens the publish channel when the broker closed it, returning whether it was reopened
+    pub async fn recover<F: ChannelFactory<C>>(
+        &self,
+        handle: &ConnectionHandle<C, F>,
+    ) -> Result<bool, String> {
+        let usable = match &self.channels.borrow().publish {
+            Some(publish) => publish.is_open(),
+            // closed along with the connection, the next connection opens it again
+            None => return Ok(false),
+        };
+        if usable {
+            return Ok(false);
+        }
+
+        let publish = handle.channel(ChannelRole::Publish).await?;
+        self.channels
+            .send_modify(|channels| channels.publish = Some(publish));
+
+        Ok(true)
+    }
+
+    /// Forgets the publish channel of a lost connection, the connection handle closes it
+    pub fn close(&self) {
+        self.channels
+            .send_if_modified(|channels| channels.publish.take().is_some());
+    }
+
+    pub fn state(&self) -> ChannelsState {
+        let channels = self.channels.borrow();
+
+        match channels.publish {
+            Some(_) => ChannelsState::Open {
+                epoch: channels.epoch,
+            },
+            None => ChannelsState::Closed,
+        }
+    }
+
+    pub fn publisher(&self) -> Publisher<C> {
+        Publisher {
+            channels: self.channels.subscribe(),
+        }
+    }
+}
+
+/// Publishes the events of the agent on the broker
+#[async_trait::async_trait]
+pub trait EventPublisher: Send + Sync {
+    async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        properties: BasicProperties,
+        content: Vec<u8>,
+    ) -> Result<(), String>;
+}
+
+/// Handle to the publish channel of the current connection
+#[derive(Debug, Clone)]
+pub struct Publisher<C> {
+    channels: watch::Receiver<Channels<C>>,
+}
+
+impl<C: ManagedChannel> Publisher<C> {
+    /// The publish channel, missing while disconnected or while it is being reopened
+    pub fn channel(&self) -> Option<C> {
+        self.channels
+            .borrow()
+            .publish
+            .as_ref()
+            .filter(|publish| publish.is_open())
+            .cloned()
+    }
+}
+
+#[async_trait::async_trait]
+impl EventPublisher for Publisher<Channel> {
+    /// Fails while disconnected, the event is not retained
+    async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        properties: BasicProperties,
+        content: Vec<u8>,
+    ) -> Result<(), String> {
+        self.channel()
+            .ok_or_else(|| "the publish channel is not open".to_owned())?
+            .basic_publish(
+                properties,
+                content,
+                BasicPublishArguments::new(exchange, routing_key),
+            )
+            .await
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct FakeChannel {
+        id: u32,
+        role: ChannelRole,
+        open: Arc<AtomicBool>,
+    }
+
+    impl ManagedChannel for FakeChannel {
+        fn is_open(&self) -> bool {
+            self.open.load(Ordering::SeqCst)
+        }
+    }
+
+    type FakeHandle = ConnectionHandle<FakeChannel, MockChannelFactory<FakeChannel>>;
+
+    fn handle() -> FakeHandle {
+        let opened = AtomicU32::new(0);
+        let mut factory = MockChannelFactory::new();
+        factory.expect_open().returning(move |role| {
+            Ok(FakeChannel {
+                id: opened.fetch_add(1, Ordering::SeqCst),
+                role,
+                open: Arc::new(AtomicBool::new(true)),
+            })
+        });
+
+        ConnectionHandle::new(factory)
+    }
+
+    #[tokio::test]
+    async fn test_connection_handle_opens_the_channels_lazily() {
+        let mut factory = MockChannelFactory::new();
+        factory
+            .expect_open()
+            .withf(|role| *role == ChannelRole::Management)
+            .times(1)
+            .returning(|role| {
+                Ok(FakeChannel {
+                    id: 0,
+                    role,
+                    open: Arc::new(AtomicBool::new(true)),
+                })
+            });
+        let handle = ConnectionHandle::new(factory);
+
+        assert_eq!(
+            handle.state(ChannelRole::Management).await,
+            ChannelState::Unopened
+        );
+        assert!(handle.current(ChannelRole::Management).await.is_none());
+
+        let first = handle.channel(ChannelRole::Management).await.unwrap();
+        let reused = handle.channel(ChannelRole::Management).await.unwrap();
+
+        assert_eq!(first.id, reused.id);
+        assert_eq!(
+            handle.state(ChannelRole::Management).await,
+            ChannelState::Open
+        );
+        assert_eq!(
+            handle.state(ChannelRole::Publish).await,
+            ChannelState::Unopened
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_handle_channel_per_role() {
+        let handle = handle();
+
+        let first = handle.channel(ChannelRole::Consume(0)).await.unwrap();
+        let second = handle.channel(ChannelRole::Consume(1)).await.unwrap();
+        let publish = handle.channel(ChannelRole::Publish).await.unwrap();
+
+        assert_eq!(first.role, ChannelRole::Consume(0));
+        assert_eq!(second.role, ChannelRole::Consume(1));
+        assert_eq!(publish.role, ChannelRole::Publish);
+        assert_ne!(first.id, second.id);
+        assert_eq!(handle.take_open().await.len(), 3);
+        assert_eq!(
+            handle.state(ChannelRole::Publish).await,
+            ChannelState::Unopened
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_handle_recreates_a_closed_channel() {
+        let handle = handle();
+        let closed = handle.channel(ChannelRole::Consume(0)).await.unwrap();
+        closed.open.store(false, Ordering::SeqCst);
+
+        assert_eq!(
+            handle.state(ChannelRole::Consume(0)).await,
+            ChannelState::Closed
+        );
+        assert!(handle.current(ChannelRole::Consume(0)).await.is_none());
+        assert!(handle.take_open().await.is_empty());
+
+        let reopened = handle.channel(ChannelRole::Consume(0)).await.unwrap();
+
+        assert_ne!(reopened.id, closed.id);
+        assert_eq!(
+            handle.state(ChannelRole::Consume(0)).await,
+            ChannelState::Open
+        );
+    }
+
+    #[tokio::test]
+    async fn test_channel_manager_open() {
+        let manager = ChannelManager::default();
+        let publisher = manager.publisher();
+
+        assert_eq!(manager.state(), ChannelsState::Closed);
+        assert!(publisher.channel().is_none());
+
+        let handle = handle();
+        manager.open(&handle).await.unwrap();
+        let publish = publisher.channel().unwrap();
+
+        assert_eq!(manager.state(), ChannelsState::Open { epoch: 1 });
+        assert_eq!(publish.role, ChannelRole::Publish);
+        assert_eq!(
+            handle.current(ChannelRole::Publish).await.unwrap().id,
+            publish.id
+        );
+    }
+
+    #[tokio::test]
+    async fn test_channel_manager_reconnection() {
+        let manager = ChannelManager::default();
+        let publisher = manager.publisher();
+        manager.open(&handle()).await.unwrap();
+        let closed = publisher.channel().unwrap();
+
+        manager.close();
+        assert_eq!(manager.state(), ChannelsState::Closed);
+        assert!(publisher.channel().is_none());
+        assert!(!manager.recover(&handle()).await.unwrap());
+
+        manager.open(&handle()).await.unwrap();
+
+        // the publisher picks up the channel of the new connection
+        assert_eq!(manager.state(), ChannelsState::Open { epoch: 2 });
+        assert!(!Arc::ptr_eq(
+            &publisher.channel().unwrap().open,
+            &closed.open
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_channel_manager_recovers_the_publish_channel() {
+        let manager = ChannelManager::default();
+        let publisher = manager.publisher();
+        let handle = handle();
+        manager.open(&handle).await.unwrap();
+
+        assert!(!manager.recover(&handle).await.unwrap());
+
+        let broken = publisher.channel().unwrap();
+        broken.open.store(false, Ordering::SeqCst);
+        assert!(publisher.channel().is_none());
+
+        assert!(manager.recover(&handle).await.unwrap());
+        assert_eq!(manager.state(), ChannelsState::Open { epoch: 1 });
+        assert_ne!(publisher.channel().unwrap().id, broken.id);
+    }
+
+    #[tokio::test]
+    async fn test_channel_manager_open_failure() {
+        let manager = ChannelManager::<FakeChannel>::default();
+        let mut factory = MockChannelFactory::new();
+        factory
+            .expect_open()
+            .returning(|_| Err("connection closed".to_owned()));
+
+        assert_eq!(
+            manager
+                .open(&ConnectionHandle::new(factory))
+                .await
+                .unwrap_err(),
+            "connection closed"
+        );
+        assert_eq!(manager.state(), ChannelsState::Closed);
+    }
+}
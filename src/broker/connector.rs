@@ -0,0 +1,658 @@
+use std::{sync::Arc, time::Duration};
+
+use amqprs::{
+    callbacks::ChannelCallback,
+    channel::{BasicCancelArguments, Channel},
+    connection::Connection,
+    Ack, BasicProperties, Cancel, CloseChannel, Nack, Return,
+};
+use log::{info, warn};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+use super::blocked::{BlockedCallback, BlockedState};
+use super::channels::{
+    ChannelFactory, ChannelManager, ChannelRole, ChannelState, ConnectionHandle,
+};
+use crate::config::{BrokerConfig, Config, SubscriptionConfig, TopologyConfig};
+use crate::events::{AckBatch, RabbitMqConsumer};
+
+// a channel closed by the broker does not close the connection, so it is checked periodically
+const CHANNEL_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+type ConsumerFactory = Arc<dyn Fn(&str, usize) -> RabbitMqConsumer + Send + Sync>;
+
+/// Queue consumed by every session, the consumers of its policy are built by the factory
+#[derive(Clone)]
+struct Subscription {
+    name: String,
+    topology: TopologyConfig,
+    consumer_factory: ConsumerFactory,
+}
+
+/// Subscription of a session, bound to the queue declared for it
+struct BoundSubscription {
+    subscription: Subscription,
+    queue_name: String,
+}
+
+/// Establishes the broker sessions, each one with its own consumer attached
+#[async_trait::async_trait]
+pub trait BrokerConnector: Send {
+    type Session: BrokerSession;
+
+    async fn connect(&mut self) -> Result<Self::Session, ConnectErrors>;
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ConnectErrors {
+    #[error("{0}")]
+    ConnectionError(String),
+    #[error("another consumer is already active on queue {0}")]
+    ExclusiveConsumerError(String),
+}
+
+impl From<String> for ConnectErrors {
+    fn from(error: String) -> ConnectErrors {
+        ConnectErrors::ConnectionError(error)
+    }
+}
+
+impl ConnectErrors {
+    /// Explains a consume failure, an exclusive consumer is refused while another one is active
+    fn from_consume_error(queue_name: &str, exclusive: bool, error: &str) -> ConnectErrors {
+        if exclusive && error.contains("ACCESS_REFUSED") {
+            return ConnectErrors::ExclusiveConsumerError(queue_name.to_owned());
+        }
+
+        ConnectErrors::ConnectionError(format!("unable to consume from the queue: {}", error))
+    }
+}
+
+/// Why a session is no longer usable
+#[derive(Debug, PartialEq)]
+pub enum SessionEnd {
+    /// The connection or one of the channels is gone
+    Lost,
+    /// The broker cancelled the consumer with the given tag, e.g. the queue was deleted
+    Cancelled(String),
+}
+
+#[async_trait::async_trait]
+pub trait BrokerSession: Send {
+    /// Stops the deliveries, the ones already received can still be acknowledged
+    async fn cancel(&mut self) -> Result<(), String>;
+    /// Resolves when the session is no longer usable
+    async fn closed(&mut self) -> SessionEnd;
+    /// Sends the pending acknowledgements and requeues the deliveries never handled
+    async fn settle_stragglers(&mut self) -> Result<(), String>;
+    /// Closes the channels, the connection is left open
+    async fn close_channels(&mut self) -> Result<(), String>;
+    /// Releases the session resources, errors are ignored as the session may already be gone
+    async fn close(self);
+}
+
+pub struct AmqpConnector {
+    config: Config,
+    blocked: BlockedState,
+    channels: ChannelManager<Channel>,
+    subscriptions: Vec<Subscription>,
+    /// Waited once, on the first connection only
+    warm_up: Option<Duration>,
+}
+
+impl AmqpConnector {
+    pub fn new(
+        config: &Config,
+        blocked: BlockedState,
+        channels: ChannelManager<Channel>,
+    ) -> AmqpConnector {
+        AmqpConnector {
+            config: config.to_owned(),
+            blocked,
+            channels,
+            subscriptions: vec![],
+            warm_up: config.delivery.warm_up,
+        }
+    }
+
+    /// The queue of the subscription is consumed on every session, by the consumers built by
+    /// the factory with the queue name and their index among all the consumers of the session
+    pub fn with_subscription(
+        mut self,
+        subscription: &SubscriptionConfig,
+        consumer_factory: impl Fn(&str, usize) -> RabbitMqConsumer + Send + Sync + 'static,
+    ) -> AmqpConnector {
+        self.subscriptions.push(Subscription {
+            name: subscription.name.to_owned(),
+            topology: subscription.topology.to_owned(),
+            consumer_factory: Arc::new(consumer_factory),
+        });
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl BrokerConnector for AmqpConnector {
+    type Session = AmqpSession;
+
+    async fn connect(&mut self) -> Result<AmqpSession, ConnectErrors> {
+        let config = &self.config;
+        let connection = open_connection(&config.broker).await?;
+        // a new connection starts unblocked, the broker notifies again if the alarm is still active
+        self.blocked.reset();
+        connection
+            .register_callback(BlockedCallback::new(self.blocked.clone()))
+            .await
+            .map_err(|err| format!("unable to attach the connection callback: {}", err))?;
+
+        let (cancelled, cancellations) = mpsc::unbounded_channel();
+        let handle = ConnectionHandle::new(AmqpChannels {
+            connection,
+            cancelled,
+            blocked: self.blocked.clone(),
+        });
+        // the publish channel is kept apart, a failed publish does not close the consume channels
+        self.channels.open(&handle).await?;
+        // the topology is declared on its own channel, opened again on the next declaration
+        // when a failed declaration closed it
+        let channel = handle.channel(ChannelRole::Management).await?;
+
+        if let Some(dead_letter) = config
+            .topology
+            .dead_letter
+            .as_ref()
+            .filter(|dead_letter| dead_letter.declare)
+        {
+            channel
+                .exchange_declare(dead_letter.exchange_arguments())
+                .await
+                .map_err(|err| format!("unable to declare the dead letter exchange: {}", err))?;
+            channel
+                .queue_declare(dead_letter.parking_queue_arguments())
+                .await
+                .map_err(|err| format!("unable to declare the parking queue: {}", err))?;
+            channel
+                .queue_bind(dead_letter.parking_bind_arguments())
+                .await
+                .map_err(|err| format!("unable to bind the parking queue: {}", err))?;
+        }
+
+        let mut subscriptions = vec![];
+        for subscription in &self.subscriptions {
+            subscriptions.push(BoundSubscription {
+                queue_name: declare_subscription(&channel, &subscription.topology).await?,
+                subscription: subscription.to_owned(),
+            });
+        }
+
+        match config.delivery.prefetch_count {
+            0 => info!("prefetch count unlimited"),
+            prefetch_count => info!("prefetch count {}", prefetch_count),
+        }
+
+        // the backlog queued while the agent was down waits for the host to recover
+        if let Some(warm_up) = self.warm_up.take() {
+            info!("warming up for {:?} before consuming", warm_up);
+            tokio::time::sleep(warm_up).await;
+        }
+
+        // every consumer has a channel of its own, indexed across the subscriptions
+        let mut consumers = vec![];
+        for (position, bound) in subscriptions.iter().enumerate() {
+            for index in 0..config.delivery.consumer_count {
+                consumers.push(
+                    attach_consumer(
+                        &handle,
+                        config,
+                        bound,
+                        SessionSlot {
+                            subscription: position,
+                            index,
+                            channel: consumers.len(),
+                        },
+                        &attach_steps(false, config.delivery.recover_deliveries),
+                    )
+                    .await?,
+                );
+            }
+
+            match bound.subscription.topology.consumer_priority {
+                Some(priority) => info!(
+                    "subscription {} consuming from queue {} with {} consumers, priority {}",
+                    bound.subscription.name,
+                    bound.queue_name,
+                    config.delivery.consumer_count,
+                    priority
+                ),
+                None => info!(
+                    "subscription {} consuming from queue {} with {} consumers",
+                    bound.subscription.name, bound.queue_name, config.delivery.consumer_count
+                ),
+            }
+        }
+
+        Ok(AmqpSession {
+            handle,
+            config: config.to_owned(),
+            subscriptions,
+            consumers,
+            cancellations,
+            channels: self.channels.clone(),
+        })
+    }
+}
+
+/// Declares the queue of the subscription, or a server-named one, and binds it to the exchange,
+/// again on every reconnection
+async fn declare_subscription(
+    channel: &Channel,
+    topology: &TopologyConfig,
+) -> Result<String, ConnectErrors> {
+    let (queue_name, _, _) = channel
+        .queue_declare(topology.declare_arguments())
+        .await
+        .map_err(|err| {
+            format!(
+                "unable to declare the queue: {}",
+                topology.declare_error_hint(&err.to_string())
+            )
+        })?
+        .ok_or_else(|| "unable to declare the queue: no reply from the broker".to_owned())?;
+
+    if topology.declare_exchange {
+        channel
+            .exchange_declare(topology.exchange_arguments())
+            .await
+            .map_err(|err| {
+                format!(
+                    "unable to declare the exchange `{}`: {}",
+                    topology.exchange, err
+                )
+            })?;
+    }
+
+    for bind_arguments in topology.bind_arguments(&queue_name) {
+        let routing_key = bind_arguments.routing_key.to_owned();
+        channel.queue_bind(bind_arguments).await.map_err(|err| {
+            format!(
+                "unable to bind the queue with routing key {}: {}",
+                routing_key,
+                topology.bind_error_hint(&err.to_string())
+            )
+        })?;
+    }
+
+    Ok(queue_name)
+}
+
+/// Steps attaching a consumer to its channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AttachStep {
+    /// The prefetch count, the broker keeps it per channel
+    Qos,
+    /// Asks the broker to redeliver the unacknowledged deliveries of the channel
+    Recover,
+    Consume,
+}
+
+/// The deliveries are recovered only on a channel reopened within the session, before
+/// consuming again. The redeliveries of the events already handled are skipped as duplicates
+fn attach_steps(reopened: bool, recover: bool) -> Vec<AttachStep> {
+    let mut steps = vec![AttachStep::Qos];
+    if reopened && recover {
+        steps.push(AttachStep::Recover);
+    }
+    steps.push(AttachStep::Consume);
+
+    steps
+}
+
+/// Position of a consumer within its session
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SessionSlot {
+    subscription: usize,
+    /// Among the consumers of the subscription, suffixing the consumer tag
+    index: usize,
+    /// Among all the consumers of the session, each one with a channel of its own
+    channel: usize,
+}
+
+async fn attach_consumer(
+    handle: &ConnectionHandle<Channel, AmqpChannels>,
+    config: &Config,
+    bound: &BoundSubscription,
+    slot: SessionSlot,
+    steps: &[AttachStep],
+) -> Result<SessionConsumer, ConnectErrors> {
+    let BoundSubscription {
+        subscription,
+        queue_name,
+    } = bound;
+    let role = ChannelRole::Consume(slot.channel);
+    let channel = handle.channel(role).await?;
+    let consumer = (subscription.consumer_factory)(queue_name, slot.channel);
+    let acks = consumer.acks();
+    let mut consumer = Some(consumer);
+    let mut consumer_tag = String::new();
+
+    for step in steps {
+        match step {
+            AttachStep::Qos => channel
+                .basic_qos(config.delivery.qos_arguments())
+                .await
+                .map_err(|err| format!("unable to set the prefetch count: {}", err))?,
+            AttachStep::Recover => channel
+                .basic_recover(true)
+                .await
+                .map_err(|err| format!("unable to recover the deliveries: {}", err))?,
+            AttachStep::Consume => {
+                let consumer = consumer
+                    .take()
+                    .expect("a consumer is attached only once, fatal.");
+                consumer_tag = channel
+                    .basic_consume(
+                        consumer,
+                        subscription.topology.consume_arguments(
+                            queue_name,
+                            slot.index,
+                            config.delivery.ack_mode,
+                        ),
+                    )
+                    .await
+                    .map_err(|err| {
+                        ConnectErrors::from_consume_error(
+                            queue_name,
+                            subscription.topology.exclusive_consumer,
+                            &err.to_string(),
+                        )
+                    })?;
+            }
+        }
+    }
+
+    Ok(SessionConsumer {
+        slot,
+        role,
+        consumer_tag,
+        acks,
+    })
+}
+
+/// Opens a connection to the broker, failing when the handshake exceeds the connect timeout
+pub async fn open_connection(broker: &BrokerConfig) -> Result<Connection, String> {
+    let arguments = broker
+        .connection_arguments()
+        .map_err(|err| err.to_string())?;
+
+    tokio::time::timeout(broker.connect_timeout, Connection::open(&arguments))
+        .await
+        .map_err(|_| {
+            format!(
+                "connection to {}:{} timed out after {:?}",
+                broker.host, broker.port, broker.connect_timeout
+            )
+        })?
+        .map_err(|err| broker.connection_error_hint(&err.to_string()))
+}
+
+/// Opens the channels on the connection of a session, each one with its own callback
+struct AmqpChannels {
+    connection: Connection,
+    cancelled: mpsc::UnboundedSender<String>,
+    blocked: BlockedState,
+}
+
+#[async_trait::async_trait]
+impl ChannelFactory<Channel> for AmqpChannels {
+    async fn open(&self, role: ChannelRole) -> Result<Channel, String> {
+        let channel = self
+            .connection
+            .open_channel(None)
+            .await
+            .map_err(|err| format!("unable to open a {:?} channel: {}", role, err))?;
+        channel
+            .register_callback(SessionChannelCallback {
+                cancelled: self.cancelled.clone(),
+                blocked: self.blocked.clone(),
+            })
+            .await
+            .map_err(|err| format!("unable to attach the channel callback: {}", err))?;
+
+        Ok(channel)
+    }
+}
+
+/// Channel callback reporting the consumers cancelled by the broker and its flow control requests
+struct SessionChannelCallback {
+    cancelled: mpsc::UnboundedSender<String>,
+    blocked: BlockedState,
+}
+
+#[async_trait::async_trait]
+impl ChannelCallback for SessionChannelCallback {
+    async fn close(&mut self, channel: &Channel, close: CloseChannel) -> amqprs::error::Result<()> {
+        warn!("close request for channel {}: {}", channel, close);
+
+        Ok(())
+    }
+
+    async fn cancel(&mut self, channel: &Channel, cancel: Cancel) -> amqprs::error::Result<()> {
+        warn!(
+            "consumer {} on channel {} cancelled by the broker, the queue was deleted or its node is unavailable",
+            cancel.consumer_tag(),
+            channel
+        );
+        // the session is gone when nobody listens anymore
+        let _ = self.cancelled.send(cancel.consumer_tag().to_owned());
+
+        Ok(())
+    }
+
+    /// The publishes and the acknowledgements wait until the broker resumes the flow
+    async fn flow(&mut self, channel: &Channel, active: bool) -> amqprs::error::Result<bool> {
+        self.blocked.flow(channel.channel_id(), active);
+
+        Ok(active)
+    }
+
+    async fn publish_ack(&mut self, _channel: &Channel, _ack: Ack) {}
+
+    async fn publish_nack(&mut self, _channel: &Channel, _nack: Nack) {}
+
+    async fn publish_return(
+        &mut self,
+        channel: &Channel,
+        ret: Return,
+        _basic_properties: BasicProperties,
+        _content: Vec<u8>,
+    ) {
+        warn!("message returned on channel {}: {}", channel, ret);
+    }
+}
+
+struct SessionConsumer {
+    slot: SessionSlot,
+    /// A consume channel is never opened again, its consumer and deliveries are gone with it
+    role: ChannelRole,
+    consumer_tag: String,
+    acks: Arc<AckBatch>,
+}
+
+pub struct AmqpSession {
+    handle: ConnectionHandle<Channel, AmqpChannels>,
+    /// Attaches a new consumer to a consume channel reopened within the session
+    config: Config,
+    subscriptions: Vec<BoundSubscription>,
+    consumers: Vec<SessionConsumer>,
+    /// Tags of the consumers cancelled by the broker
+    cancellations: mpsc::UnboundedReceiver<String>,
+    channels: ChannelManager<Channel>,
+}
+
+impl AmqpSession {
+    fn connection(&self) -> &Connection {
+        &self.handle.factory().connection
+    }
+}
+
+#[async_trait::async_trait]
+impl BrokerSession for AmqpSession {
+    async fn cancel(&mut self) -> Result<(), String> {
+        for SessionConsumer {
+            role, consumer_tag, ..
+        } in &self.consumers
+        {
+            let Some(channel) = self.handle.current(*role).await else {
+                continue;
+            };
+            channel
+                .basic_cancel(BasicCancelArguments::new(consumer_tag))
+                .await
+                .map_err(|err| {
+                    format!("unable to cancel the consumer {}: {}", consumer_tag, err)
+                })?;
+        }
+
+        Ok(())
+    }
+
+    async fn closed(&mut self) -> SessionEnd {
+        let consumers = &mut self.consumers;
+        let channels = &self.channels;
+        let handle = &self.handle;
+        let config = &self.config;
+        let subscriptions = &self.subscriptions;
+
+        tokio::select! {
+            _ = handle.factory().connection.listen_network_io_failure() => SessionEnd::Lost,
+            _ = async {
+                let mut interval = tokio::time::interval(CHANNEL_CHECK_INTERVAL);
+                loop {
+                    // a consume channel closed by the broker is reopened with a new consumer,
+                    // the session is lost when it cannot be reopened
+                    for consumer in consumers.iter_mut() {
+                        if handle.state(consumer.role).await == ChannelState::Open {
+                            continue;
+                        }
+                        let steps = attach_steps(true, config.delivery.recover_deliveries);
+                        let bound = &subscriptions[consumer.slot.subscription];
+                        match attach_consumer(handle, config, bound, consumer.slot, &steps).await {
+                            Ok(reattached) => {
+                                warn!(
+                                    "consume channel of the consumer {} closed by the broker, reopened",
+                                    consumer.consumer_tag
+                                );
+                                *consumer = reattached;
+                            }
+                            Err(err) => {
+                                warn!("unable to reopen the consume channel: {}", err);
+                                return;
+                            }
+                        }
+                    }
+                    // the publish channel is reopened on its own, the consumers are not affected
+                    match channels.recover(handle).await {
+                        Ok(true) => warn!("publish channel closed by the broker, reopened"),
+                        Ok(false) => (),
+                        Err(err) => {
+                            warn!("unable to reopen the publish channel: {}", err);
+                            return;
+                        }
+                    }
+                    interval.tick().await;
+                }
+            } => SessionEnd::Lost,
+            Some(consumer_tag) = self.cancellations.recv() => SessionEnd::Cancelled(consumer_tag),
+        }
+    }
+
+    async fn settle_stragglers(&mut self) -> Result<(), String> {
+        for consumer in &self.consumers {
+            // the broker requeues the deliveries of a closed channel on its own
+            let Some(channel) = self.handle.current(consumer.role).await else {
+                continue;
+            };
+            consumer
+                .acks
+                .settle_stragglers(&channel)
+                .await
+                .map_err(|err| {
+                    format!(
+                        "unable to settle the deliveries of the consumer {}: {}",
+                        consumer.consumer_tag, err
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+
+    async fn close_channels(&mut self) -> Result<(), String> {
+        self.channels.close();
+        for (role, channel) in self.handle.take_open().await {
+            channel
+                .close()
+                .await
+                .map_err(|err| format!("unable to close the {:?} channel: {}", role, err))?;
+        }
+
+        Ok(())
+    }
+
+    async fn close(mut self) {
+        let _ = self.close_channels().await;
+        if self.connection().is_open() {
+            let _ = self.connection().clone().close().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consume_error_translation() {
+        let refused = "ACCESS_REFUSED - queue 'vanvitelli.agent_1' in vhost '/' in exclusive use";
+
+        assert_eq!(
+            ConnectErrors::from_consume_error("vanvitelli.agent_1", true, refused),
+            ConnectErrors::ExclusiveConsumerError("vanvitelli.agent_1".to_owned())
+        );
+        assert_eq!(
+            ConnectErrors::ExclusiveConsumerError("vanvitelli.agent_1".to_owned()).to_string(),
+            "another consumer is already active on queue vanvitelli.agent_1"
+        );
+        assert_eq!(
+            ConnectErrors::from_consume_error("vanvitelli.agent_1", false, refused),
+            ConnectErrors::ConnectionError(format!(
+                "unable to consume from the queue: {}",
+                refused
+            ))
+        );
+        assert_eq!(
+            ConnectErrors::from_consume_error("vanvitelli.agent_1", true, "NOT_FOUND"),
+            ConnectErrors::ConnectionError(
+                "unable to consume from the queue: NOT_FOUND".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn test_attach_steps() {
+        assert_eq!(
+            attach_steps(false, true),
+            vec![AttachStep::Qos, AttachStep::Consume]
+        );
+        // the deliveries are recovered before consuming on the reopened channel
+        assert_eq!(
+            attach_steps(true, true),
+            vec![AttachStep::Qos, AttachStep::Recover, AttachStep::Consume]
+        );
+        assert_eq!(
+            attach_steps(true, false),
+            vec![AttachStep::Qos, AttachStep::Consume]
+        );
+    }
+}
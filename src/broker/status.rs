@@ -0,0 +1,304 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::watch, time::Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting,
+}
+
+/// Wall time of the event handlings since the agent started
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HandlingDurations {
+    pub count: u64,
+    /// Handlings exceeding the slow threshold
+    pub slow: u64,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    total: Duration,
+}
+
+impl HandlingDurations {
+    fn record(&mut self, duration: Duration, slow: bool) {
+        self.count += 1;
+        self.slow += u64::from(slow);
+        self.min = Some(self.min.map_or(duration, |min| min.min(duration)));
+        self.max = Some(self.max.map_or(duration, |max| max.max(duration)));
+        self.total = self.total.saturating_add(duration);
+    }
+
+    /// Missing until the first handling
+    pub fn average(&self) -> Option<Duration> {
+        self.total
+            .checked_div(u32::try_from(self.count).unwrap_or(u32::MAX))
+    }
+}
+
+/// Outcomes of the event handlings since the agent started
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HandleOutcomes {
+    pub handled: u64,
+    /// Decoded events with nothing to do for this agent
+    pub skipped: u64,
+    /// Failed or timed out handlings
+    pub rejected: u64,
+    /// Rejected handlings whose handler panicked
+    pub panicked: u64,
+}
+
+/// Snapshot of the broker connection, telling whether the agent is connected and consuming
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    /// Missing while disconnected
+    pub connected_since: Option<Instant>,
+    /// Kept across the reconnections, missing until the first delivery
+    pub last_delivery_at: Option<Instant>,
+    pub consumer_active: bool,
+    /// Failed connection attempts since the last connection
+    pub reconnect_attempts: u64,
+    pub blocked: bool,
+    /// Kept across the reconnections
+    pub handling: HandlingDurations,
+    pub outcomes: HandleOutcomes,
+    /// Deliveries acknowledged without being handled, as their event was too old
+    pub expired: u64,
+    /// Deliveries received on the current channels and not settled yet
+    pub unacked: u64,
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> ConnectionStatus {
+        ConnectionStatus {
+            state: ConnectionState::Connecting,
+            connected_since: None,
+            last_delivery_at: None,
+            consumer_active: false,
+            reconnect_attempts: 0,
+            blocked: false,
+            handling: HandlingDurations::default(),
+            outcomes: HandleOutcomes::default(),
+            expired: 0,
+            unacked: 0,
+        }
+    }
+}
+
+/// Maintains the connection status, shared by the supervision loop, the connection callback
+/// and the consumers
+#[derive(Debug, Clone)]
+pub struct StatusTracker {
+    status: Arc<watch::Sender<ConnectionStatus>>,
+}
+
+impl Default for StatusTracker {
+    fn default() -> StatusTracker {
+        StatusTracker {
+            status: Arc::new(watch::channel(ConnectionStatus::default()).0),
+        }
+    }
+}
+
+impl StatusTracker {
+    pub fn attempt_failed(&self) {
+        self.status
+            .send_modify(|status| status.reconnect_attempts += 1);
+    }
+
+    /// The consumers are subscribed once the session is established
+    pub fn connected(&self) {
+        self.status.send_modify(|status| {
+            status.state = ConnectionState::Connected;
+            status.connected_since = Some(Instant::now());
+            status.consumer_active = true;
+            status.reconnect_attempts = 0;
+        });
+    }
+
+    pub fn disconnected(&self) {
+        self.status.send_modify(|status| {
+            status.state = ConnectionState::Reconnecting;
+            status.connected_since = None;
+            status.consumer_active = false;
+            // a new connection starts unblocked
+            status.blocked = false;
+        });
+    }
+
+    pub fn delivered(&self) {
+        self.status
+            .send_modify(|status| status.last_delivery_at = Some(Instant::now()));
+    }
+
+    pub fn handled(&self, duration: Duration, slow: bool) {
+        self.status
+            .send_modify(|status| status.handling.record(duration, slow));
+    }
+
+    pub fn succeeded(&self) {
+        self.status
+            .send_modify(|status| status.outcomes.handled += 1);
+    }
+
+    pub fn skipped(&self) {
+        self.status
+            .send_modify(|status| status.outcomes.skipped += 1);
+    }
+
+    pub fn rejected(&self) {
+        self.status
+            .send_modify(|status| status.outcomes.rejected += 1);
+    }
+
+    pub fn panicked(&self) {
+        self.status
+            .send_modify(|status| status.outcomes.panicked += 1);
+    }
+
+    pub fn expired(&self) {
+        self.status.send_modify(|status| status.expired += 1);
+    }
+
+    pub fn unacked(&self, count: u64) {
+        self.status
+            .send_if_modified(|status| std::mem::replace(&mut status.unacked, count) != count);
+    }
+
+    pub fn blocked(&self, blocked: bool) {
+        self.status
+            .send_if_modified(|status| std::mem::replace(&mut status.blocked, blocked) != blocked);
+    }
+
+    pub fn reader(&self) -> StatusReader {
+        StatusReader {
+            status: self.status.subscribe(),
+        }
+    }
+}
+
+/// Read handle to the connection status, polled by the health reporting
+#[derive(Debug, Clone)]
+pub struct StatusReader {
+    status: watch::Receiver<ConnectionStatus>,
+}
+
+impl StatusReader {
+    pub fn snapshot(&self) -> ConnectionStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Waits for the connection, false when the tracker is gone
+    pub async fn connected(&mut self) -> bool {
+        self.status
+            .wait_for(|status| status.state == ConnectionState::Connected)
+            .await
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_status_transitions() {
+        let tracker = StatusTracker::default();
+        let reader = tracker.reader();
+        let start = Instant::now();
+
+        assert_eq!(reader.snapshot(), ConnectionStatus::default());
+
+        tracker.attempt_failed();
+        tracker.attempt_failed();
+        assert_eq!(reader.snapshot().reconnect_attempts, 2);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        tracker.connected();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        tracker.delivered();
+        tracker.blocked(true);
+
+        assert_eq!(
+            reader.snapshot(),
+            ConnectionStatus {
+                state: ConnectionState::Connected,
+                connected_since: Some(start + Duration::from_secs(1)),
+                last_delivery_at: Some(start + Duration::from_secs(2)),
+                consumer_active: true,
+                reconnect_attempts: 0,
+                blocked: true,
+                handling: HandlingDurations::default(),
+                outcomes: HandleOutcomes::default(),
+                expired: 0,
+                unacked: 0,
+            }
+        );
+
+        tracker.disconnected();
+
+        assert_eq!(
+            reader.snapshot(),
+            ConnectionStatus {
+                state: ConnectionState::Reconnecting,
+                connected_since: None,
+                last_delivery_at: Some(start + Duration::from_secs(2)),
+                consumer_active: false,
+                reconnect_attempts: 0,
+                blocked: false,
+                handling: HandlingDurations::default(),
+                outcomes: HandleOutcomes::default(),
+                expired: 0,
+                unacked: 0,
+            }
+        );
+
+        tracker.attempt_failed();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        tracker.connected();
+
+        let reconnected = reader.snapshot();
+        assert_eq!(reconnected.state, ConnectionState::Connected);
+        assert_eq!(
+            reconnected.connected_since,
+            Some(start + Duration::from_secs(3))
+        );
+        assert_eq!(reconnected.reconnect_attempts, 0);
+        assert!(reconnected.consumer_active);
+    }
+
+    #[test]
+    fn test_handling_durations() {
+        let tracker = StatusTracker::default();
+        let reader = tracker.reader();
+
+        assert_eq!(reader.snapshot().handling.average(), None);
+
+        tracker.handled(Duration::from_secs(2), false);
+        tracker.handled(Duration::from_secs(7), true);
+        tracker.handled(Duration::from_secs(3), false);
+        tracker.disconnected();
+
+        let handling = reader.snapshot().handling;
+        assert_eq!(handling.count, 3);
+        assert_eq!(handling.slow, 1);
+        assert_eq!(handling.min, Some(Duration::from_secs(2)));
+        assert_eq!(handling.max, Some(Duration::from_secs(7)));
+        assert_eq!(handling.average(), Some(Duration::from_secs(4)));
+    }
+
+    #[tokio::test]
+    async fn test_status_reader_waits_for_the_connection() {
+        let tracker = StatusTracker::default();
+        let mut reader = tracker.reader();
+
+        let connected = tokio::spawn(async move { reader.connected().await });
+        tracker.connected();
+
+        assert!(connected.await.unwrap());
+
+        let mut orphan = StatusTracker::default().reader();
+        assert!(!orphan.connected().await);
+    }
+}
@@ -0,0 +1,329 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use log::{info, warn};
+use thiserror::Error;
+
+use super::{
+    Backoff, BrokerConnector, BrokerSession, ConnectErrors, SessionEnd, StatusReader, StatusTracker,
+};
+use crate::exit_codes::{BROKER_UNREACHABLE, RUNTIME_FAILURE};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SupervisorErrors {
+    #[error("broker unreachable after {0} attempts, last error: {1}")]
+    BrokerUnreachableError(u32, String),
+    #[error("{0}, stop the other agent instance before starting this one")]
+    ConsumerConflictError(String),
+}
+
+impl SupervisorErrors {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SupervisorErrors::BrokerUnreachableError(..) => BROKER_UNREACHABLE,
+            SupervisorErrors::ConsumerConflictError(_) => RUNTIME_FAILURE,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReconnectCounters {
+    pub attempts: AtomicU64,
+    pub failures: AtomicU64,
+    pub connections: AtomicU64,
+    /// Consumers cancelled by the broker
+    pub cancellations: AtomicU64,
+}
+
+/// Keeps a broker session open, reconnecting with backoff whenever it is lost
+pub struct Supervisor<C: BrokerConnector> {
+    connector: C,
+    backoff: Backoff,
+    /// Consecutive failed attempts before giving up, unlimited when missing
+    max_attempts: Option<u32>,
+    status: StatusTracker,
+    counters: Arc<ReconnectCounters>,
+    session: Option<C::Session>,
+}
+
+impl<C: BrokerConnector> Supervisor<C> {
+    pub fn new(connector: C, backoff: Backoff, max_attempts: Option<u32>) -> Supervisor<C> {
+        Supervisor {
+            connector,
+            backoff,
+            max_attempts,
+            status: StatusTracker::default(),
+            counters: Arc::new(ReconnectCounters::default()),
+            session: None,
+        }
+    }
+
+    /// The status is shared with the consumers, recording their deliveries
+    pub fn with_status(self, status: StatusTracker) -> Supervisor<C> {
+        Supervisor { status, ..self }
+    }
+
+    pub fn status(&self) -> StatusReader {
+        self.status.reader()
+    }
+
+    pub fn counters(&self) -> Arc<ReconnectCounters> {
+        self.counters.clone()
+    }
+
+    /// The current session, kept here so it outlives a cancelled `run`
+    pub fn take_session(&mut self) -> Option<C::Session> {
+        self.session.take()
+    }
+
+    /// Runs until the broker cannot be reached within the allowed attempts
+    pub async fn run(&mut self) -> Result<(), SupervisorErrors> {
+        let mut failed_attempts: u32 = 0;
+
+        loop {
+            self.counters.attempts.fetch_add(1, Ordering::Relaxed);
+
+            let session = match self.connector.connect().await {
+                Ok(session) => session,
+                // refused on the first connection, another agent instance is consuming.
+                // Later the refusal can come from the consumer of the lost connection, so it is retried
+                Err(err @ ConnectErrors::ExclusiveConsumerError(_))
+                    if self.counters.connections.load(Ordering::Relaxed) == 0 =>
+                {
+                    return Err(SupervisorErrors::ConsumerConflictError(err.to_string()));
+                }
+                Err(err) => {
+                    failed_attempts += 1;
+                    self.counters.failures.fetch_add(1, Ordering::Relaxed);
+                    self.status.attempt_failed();
+
+                    if self
+                        .max_attempts
+                        .map_or(false, |max| failed_attempts >= max)
+                    {
+                        return Err(SupervisorErrors::BrokerUnreachableError(
+                            failed_attempts,
+                            err.to_string(),
+                        ));
+                    }
+
+                    let delay = self.backoff.next_delay();
+                    warn!(
+                        "broker connection attempt {} failed, retrying in {:?}: {}",
+                        failed_attempts, delay, err
+                    );
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
+
+            failed_attempts = 0;
+            self.backoff.reset();
+            self.counters.connections.fetch_add(1, Ordering::Relaxed);
+            self.status.connected();
+            info!(
+                "connected to the broker, {} connections established",
+                self.counters.connections.load(Ordering::Relaxed)
+            );
+
+            let end = self.session.insert(session).closed().await;
+
+            self.status.disconnected();
+            if let Some(session) = self.session.take() {
+                session.close().await;
+            }
+
+            match end {
+                SessionEnd::Lost => warn!("broker session lost, reconnecting"),
+                // the queue is declared, bound and consumed again by the new session
+                SessionEnd::Cancelled(consumer_tag) => {
+                    self.counters.cancellations.fetch_add(1, Ordering::Relaxed);
+                    let delay = self.backoff.next_delay();
+                    warn!(
+                        "consumer {} cancelled by the broker, subscribing again in {:?}",
+                        consumer_tag, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::broker::status::ConnectionState;
+
+    // each entry is the outcome of a connection attempt, successful sessions end right away
+    struct FakeConnector {
+        outcomes: VecDeque<Result<SessionEnd, ConnectErrors>>,
+        attempts: Arc<Mutex<Vec<Instant>>>,
+    }
+
+    struct FakeSession {
+        end: Option<SessionEnd>,
+    }
+
+    #[async_trait::async_trait]
+    impl BrokerSession for FakeSession {
+        async fn cancel(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn closed(&mut self) -> SessionEnd {
+            self.end.take().unwrap_or(SessionEnd::Lost)
+        }
+
+        async fn settle_stragglers(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn close_channels(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn close(self) {}
+    }
+
+    #[async_trait::async_trait]
+    impl BrokerConnector for FakeConnector {
+        type Session = FakeSession;
+
+        async fn connect(&mut self) -> Result<FakeSession, ConnectErrors> {
+            self.attempts.lock().unwrap().push(Instant::now());
+
+            match self.outcomes.pop_front() {
+                Some(Ok(end)) => Ok(FakeSession { end: Some(end) }),
+                Some(Err(err)) => Err(err),
+                None => Err("broker gone".to_owned().into()),
+            }
+        }
+    }
+
+    fn supervisor(
+        outcomes: Vec<Result<SessionEnd, ConnectErrors>>,
+        max_attempts: Option<u32>,
+    ) -> (Supervisor<FakeConnector>, Arc<Mutex<Vec<Instant>>>) {
+        let attempts = Arc::new(Mutex::new(vec![]));
+        let connector = FakeConnector {
+            outcomes: outcomes.into(),
+            attempts: attempts.clone(),
+        };
+        let backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(4)).without_jitter();
+
+        (Supervisor::new(connector, backoff, max_attempts), attempts)
+    }
+
+    fn delays(attempts: &[Instant]) -> Vec<Duration> {
+        attempts.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervisor_retry_schedule() {
+        let failure = || Err("connection refused".to_owned().into());
+        let (mut supervisor, attempts) = supervisor(
+            vec![
+                failure(),
+                failure(),
+                failure(),
+                failure(),
+                Ok(SessionEnd::Lost),
+            ],
+            Some(5),
+        );
+
+        let result = supervisor.run().await;
+
+        // the backoff restarts from the base delay once connected
+        assert_eq!(
+            delays(&attempts.lock().unwrap()),
+            [1, 2, 4, 4, 0, 1, 2, 4, 4]
+                .map(Duration::from_secs)
+                .to_vec()
+        );
+        assert_eq!(
+            result,
+            Err(SupervisorErrors::BrokerUnreachableError(
+                5,
+                "broker gone".to_owned()
+            ))
+        );
+        assert_eq!(supervisor.counters().attempts.load(Ordering::Relaxed), 10);
+        assert_eq!(supervisor.counters().failures.load(Ordering::Relaxed), 9);
+        assert_eq!(supervisor.counters().connections.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervisor_status() {
+        let (mut supervisor, _) = supervisor(vec![Ok(SessionEnd::Lost)], Some(1));
+        let status = supervisor.status();
+
+        assert_eq!(status.snapshot().state, ConnectionState::Connecting);
+
+        let _ = supervisor.run().await;
+
+        let snapshot = status.snapshot();
+        assert_eq!(snapshot.state, ConnectionState::Reconnecting);
+        assert!(!snapshot.consumer_active);
+        assert_eq!(snapshot.reconnect_attempts, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervisor_subscribes_again_after_cancellation() {
+        let (mut supervisor, attempts) = supervisor(
+            vec![
+                Ok(SessionEnd::Cancelled("vanvitelli-agent_1".to_owned())),
+                Ok(SessionEnd::Lost),
+            ],
+            Some(1),
+        );
+
+        let _ = supervisor.run().await;
+
+        // the cancelled session waits for the backoff, the lost one reconnects right away
+        assert_eq!(
+            delays(&attempts.lock().unwrap()),
+            [1, 0].map(Duration::from_secs).to_vec()
+        );
+        assert_eq!(
+            supervisor.counters().cancellations.load(Ordering::Relaxed),
+            1
+        );
+        assert_eq!(supervisor.counters().connections.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervisor_exclusive_consumer_conflict() {
+        let conflict = || {
+            Err(ConnectErrors::ExclusiveConsumerError(
+                "vanvitelli.agent_1".to_owned(),
+            ))
+        };
+
+        let (mut first_connection, _) = supervisor(vec![conflict()], None);
+        let result = first_connection.run().await;
+
+        assert_eq!(
+            result,
+            Err(SupervisorErrors::ConsumerConflictError(
+                "another consumer is already active on queue vanvitelli.agent_1".to_owned()
+            ))
+        );
+        assert_eq!(result.unwrap_err().exit_code(), RUNTIME_FAILURE);
+
+        // the consumer of the lost connection can still be active on the broker
+        let (mut reconnection, attempts) =
+            supervisor(vec![Ok(SessionEnd::Lost), conflict(), conflict()], Some(2));
+        let result = reconnection.run().await;
+
+        assert_eq!(attempts.lock().unwrap().len(), 3);
+        assert_eq!(result.unwrap_err().exit_code(), BROKER_UNREACHABLE);
+    }
+}
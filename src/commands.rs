@@ -0,0 +1,49 @@
+use thiserror::Error;
+
+use crate::exit_codes::{CONFIGURATION_ERROR, RUNTIME_FAILURE};
+
+mod check_config;
+mod gather;
+mod list_gatherers;
+mod preflight;
+mod print_default_config;
+mod replay;
+mod run_request;
+mod version;
+
+pub(crate) use check_config::check_config;
+pub(crate) use gather::{gather, GatherArgs};
+pub(crate) use list_gatherers::list_gatherers;
+pub(crate) use preflight::{
+    preflight, AmqpBrokerProbe, BROKER_PROBE_TIMEOUT, GATHERER_PROBE_TIMEOUT,
+};
+pub(crate) use print_default_config::print_default_config;
+pub(crate) use replay::replay;
+pub(crate) use run_request::run_request;
+pub(crate) use version::{version, BUILD_VERSION};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CommandErrors {
+    #[error("{0}")]
+    UsageError(String),
+    #[error("{0}")]
+    GatheringError(String),
+    #[error("{0}")]
+    InvalidConfigError(String),
+    #[error("{0}")]
+    PreflightError(String),
+    #[error("{0}")]
+    ReplayError(String),
+}
+
+impl CommandErrors {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CommandErrors::UsageError(_) => CONFIGURATION_ERROR,
+            CommandErrors::GatheringError(_) => RUNTIME_FAILURE,
+            CommandErrors::InvalidConfigError(_) => CONFIGURATION_ERROR,
+            CommandErrors::PreflightError(_) => RUNTIME_FAILURE,
+            CommandErrors::ReplayError(_) => RUNTIME_FAILURE,
+        }
+    }
+}
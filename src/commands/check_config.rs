@@ -0,0 +1,115 @@
+use std::io::Write;
+
+use super::CommandErrors;
+use crate::config::{Cli, Config};
+
+/// Loads and validates the configuration like the agent does at startup, without connecting
+pub fn check_config(cli: &Cli, out: &mut impl Write) -> Result<(), CommandErrors> {
+    let write_error = |err: std::io::Error| CommandErrors::InvalidConfigError(err.to_string());
+
+    match Config::check(cli) {
+        Ok(config) => {
+            for warning in config.warnings() {
+                writeln!(out, "warning: {}", warning).map_err(write_error)?;
+            }
+
+            writeln!(out, "configuration OK").map_err(write_error)
+        }
+        Err(errors) => {
+            writeln!(out, "configuration errors:").map_err(write_error)?;
+            for error in &errors {
+                writeln!(out, "  - {}", error).map_err(write_error)?;
+            }
+
+            Err(CommandErrors::InvalidConfigError(format!(
+                "found {} configuration errors",
+                errors.len()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn config_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("vanvitelli_test_{}.toml", name));
+        std::fs::write(&path, content).unwrap();
+
+        path
+    }
+
+    fn run_check_config(config: PathBuf) -> (Result<(), CommandErrors>, String) {
+        let cli = Cli {
+            config: Some(config),
+            ..Default::default()
+        };
+        let mut output: Vec<u8> = vec![];
+
+        let result = check_config(&cli, &mut output);
+
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_check_valid_config() {
+        let path = config_file(
+            "check_valid_config",
+            r#"
+            agent_id = "agent_1"
+
+            [amqp]
+            host = "rabbit.local"
+            "#,
+        );
+
+        let (result, output) = run_check_config(path.to_owned());
+        std::fs::remove_file(path).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(output, "configuration OK\n");
+    }
+
+    #[test]
+    fn test_check_config_multiple_errors() {
+        let path = config_file(
+            "check_config_multiple_errors",
+            r#"
+            agent_id = "agent_1"
+
+            [amqp]
+            port = 0
+            exchange = ""
+
+            [tls]
+            enabled = true
+            ca_cert = "/not/existing/ca.pem"
+            "#,
+        );
+
+        let (result, output) = run_check_config(path.to_owned());
+        std::fs::remove_file(path).unwrap();
+
+        let error = result.err().unwrap();
+        assert_eq!(
+            error,
+            CommandErrors::InvalidConfigError("found 3 configuration errors".to_owned())
+        );
+        assert_eq!(error.exit_code(), 2);
+        assert!(output.starts_with("configuration errors:\n"));
+        assert!(output.contains("  - invalid amqp port `0`, port should be greater than 0\n"));
+        assert!(output.contains("  - missing `exchange`, the value cannot be empty\n"));
+        assert!(output.contains("  - invalid tls file /not/existing/ca.pem"));
+    }
+
+    #[test]
+    fn test_check_missing_config_file() {
+        let (result, output) = run_check_config(PathBuf::from("/not/existing/config.toml"));
+
+        assert!(result.is_err());
+        assert!(output.contains("could not read configuration file /not/existing/config.toml"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    time::Duration,
+};
+
+use serde_json::json;
+
+use super::CommandErrors;
+use crate::gatherers::{FactRequest, FactsGatheringRequest, GatherersRegistry};
+use crate::logging::Correlation;
+
+const LOCAL_EXECUTION_ID: &str = "local";
+const LOCAL_GROUP_ID: &str = "local";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GatherArgs {
+    pub gatherer: String,
+    pub name: String,
+    pub argument: String,
+    pub check_id: Option<String>,
+    pub timeout: Option<Duration>,
+}
+
+/// Runs a single fact request through the registry and prints the gathered facts as JSON
+pub async fn gather(
+    registry: &GatherersRegistry,
+    args: GatherArgs,
+    out: &mut impl Write,
+) -> Result<(), CommandErrors> {
+    let gatherer = registry
+        .get_gatherer(args.gatherer.to_owned())
+        .map_err(|err| CommandErrors::UsageError(err.to_string()))?;
+
+    let fact_request = FactRequest {
+        argument: args.argument,
+        check_id: args.check_id.unwrap_or_default(),
+        gatherer: args.gatherer.to_owned(),
+        name: args.name,
+        metadata: BTreeMap::new(),
+    };
+    let request = FactsGatheringRequest {
+        execution_id: LOCAL_EXECUTION_ID.to_owned(),
+        group_id: LOCAL_GROUP_ID.to_owned(),
+        facts_requests_by_gatherer: HashMap::from([(args.gatherer, vec![fact_request])]),
+        timeout: args.timeout,
+        correlation: Correlation::default(),
+    };
+
+    let facts_gathered = match args.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, gatherer.gather(request))
+            .await
+            .map_err(|_| {
+                CommandErrors::GatheringError(format!("gathering timed out after {:?}", timeout))
+            })?,
+        None => gatherer.gather(request).await,
+    };
+
+    let facts: Vec<serde_json::Value> = facts_gathered
+        .facts_gathered
+        .iter()
+        .map(|fact| {
+            json!({
+                "name": fact.name,
+                "check_id": fact.check_id,
+                "value": fact.value,
+                "error": fact.error.as_ref().map(|err| err.to_string()),
+            })
+        })
+        .collect();
+
+    let printed = serde_json::to_string_pretty(&facts)
+        .map_err(|err| CommandErrors::GatheringError(err.to_string()))?;
+    writeln!(out, "{}", printed).map_err(|err| CommandErrors::GatheringError(err.to_string()))?;
+
+    if facts_gathered
+        .facts_gathered
+        .iter()
+        .any(|fact| fact.error.is_some())
+    {
+        return Err(CommandErrors::GatheringError(
+            "some facts could not be gathered".to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gatherers::{Fact, FactsGathered, Gatherer, GatherersRegistryBuilder, MockGatherer};
+
+    struct SlowGatherer;
+
+    #[async_trait::async_trait]
+    impl Gatherer for SlowGatherer {
+        async fn gather(&self, fact_request: FactsGatheringRequest) -> FactsGathered {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+
+            FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: fact_request.execution_id,
+                facts_gathered: vec![],
+                group_id: fact_request.group_id,
+            }
+        }
+
+        fn name(&self) -> String {
+            "slow".to_owned()
+        }
+    }
+
+    fn gather_args(gatherer: &str) -> GatherArgs {
+        GatherArgs {
+            gatherer: gatherer.to_owned(),
+            name: "totem".to_owned(),
+            argument: "totem.token".to_owned(),
+            check_id: Some("check1".to_owned()),
+            timeout: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gather() {
+        let mut mockgatherer = MockGatherer::new();
+        mockgatherer
+            .expect_gather()
+            .times(1)
+            .returning(|request| FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id,
+                facts_gathered: request.facts_requests_by_gatherer["corosync.conf@v1"]
+                    .iter()
+                    .map(|fact_request| Fact {
+                        name: fact_request.name.to_owned(),
+                        check_id: fact_request.check_id.to_owned(),
+                        value: json!(30000),
+                        error: None,
+                    })
+                    .collect(),
+                group_id: request.group_id,
+            });
+
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("corosync.conf", "v1", mockgatherer);
+        let registry = builder.build_registry();
+        let mut output: Vec<u8> = vec![];
+
+        gather(&registry, gather_args("corosync.conf@v1"), &mut output)
+            .await
+            .unwrap();
+
+        let facts: serde_json::Value = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(
+            facts,
+            json!([{
+                "name": "totem",
+                "check_id": "check1",
+                "value": 30000,
+                "error": null,
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gather_unknown_gatherer() {
+        let registry = GatherersRegistryBuilder::new().build_registry();
+        let mut output: Vec<u8> = vec![];
+
+        let error = gather(&registry, gather_args("unknown"), &mut output)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            error,
+            CommandErrors::UsageError("gatherer `unknown` not found".to_owned())
+        );
+        assert_eq!(error.exit_code(), 2);
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_gather_timeout() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("slow", "v1", SlowGatherer);
+        let registry = builder.build_registry();
+        let mut output: Vec<u8> = vec![];
+
+        let args = GatherArgs {
+            timeout: Some(Duration::from_millis(10)),
+            ..gather_args("slow")
+        };
+
+        let error = gather(&registry, args, &mut output).await.err().unwrap();
+
+        assert!(matches!(error, CommandErrors::GatheringError(_)));
+        assert_eq!(error.exit_code(), 1);
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::{self, Write};
+
+use serde_json::json;
+
+use crate::gatherers::GatherersRegistry;
+
+pub fn list_gatherers(
+    registry: &GatherersRegistry,
+    as_json: bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if as_json {
+        let gatherers: Vec<serde_json::Value> = registry
+            .gatherers_versions()
+            .into_iter()
+            .map(|(name, versions)| json!({ "name": name, "versions": versions }))
+            .collect();
+
+        return writeln!(out, "{}", serde_json::to_string_pretty(&gatherers)?);
+    }
+
+    for gatherer in registry.inspect_gatherers() {
+        writeln!(out, "{}", gatherer)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gatherers::{GatherersRegistryBuilder, MockGatherer};
+
+    fn registry() -> GatherersRegistry {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("corosync.conf", "v2", MockGatherer::new());
+        builder.add_gatherer("corosync.conf", "v1", MockGatherer::new());
+        builder.add_gatherer("cibadmin", "v1", MockGatherer::new());
+
+        builder.build_registry()
+    }
+
+    #[test]
+    fn test_list_gatherers() {
+        let mut output: Vec<u8> = vec![];
+
+        list_gatherers(&registry(), false, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "cibadmin - v1\ncorosync.conf - v1/v2\n"
+        );
+    }
+
+    #[test]
+    fn test_list_gatherers_json() {
+        let mut output: Vec<u8> = vec![];
+
+        list_gatherers(&registry(), true, &mut output).unwrap();
+
+        let gatherers: serde_json::Value = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(
+            gatherers,
+            json!([
+                { "name": "cibadmin", "versions": ["v1"] },
+                { "name": "corosync.conf", "versions": ["v1", "v2"] },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_list_gatherers_empty_registry() {
+        let mut output: Vec<u8> = vec![];
+
+        list_gatherers(
+            &GatherersRegistryBuilder::new().build_registry(),
+            false,
+            &mut output,
+        )
+        .unwrap();
+
+        assert!(output.is_empty());
+    }
+}
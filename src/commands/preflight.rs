@@ -0,0 +1,227 @@
+use std::{io::Write, time::Duration};
+
+use amqprs::channel::ExchangeDeclareArguments;
+
+use super::CommandErrors;
+use crate::broker::open_connection;
+use crate::config::{BrokerConfig, TopologyConfig};
+use crate::gatherers::GatherersRegistry;
+
+pub const BROKER_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const GATHERER_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[async_trait::async_trait]
+pub trait BrokerProbe: Sync {
+    fn description(&self) -> String;
+    async fn probe(&self) -> Result<(), String>;
+}
+
+/// Connects to the broker and checks passively that the exchange exists, declaring nothing
+pub struct AmqpBrokerProbe {
+    broker: BrokerConfig,
+    exchange: String,
+}
+
+impl AmqpBrokerProbe {
+    pub fn new(broker: &BrokerConfig, topology: &TopologyConfig) -> AmqpBrokerProbe {
+        AmqpBrokerProbe {
+            broker: broker.to_owned(),
+            exchange: topology.exchange.to_owned(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BrokerProbe for AmqpBrokerProbe {
+    fn description(&self) -> String {
+        format!(
+            "broker {}:{}, exchange {}",
+            self.broker.host, self.broker.port, self.exchange
+        )
+    }
+
+    async fn probe(&self) -> Result<(), String> {
+        let connection = open_connection(&self.broker).await?;
+
+        let result = match connection.open_channel(None).await {
+            Ok(channel) => channel
+                .exchange_declare(
+                    ExchangeDeclareArguments::new(&self.exchange, "topic")
+                        .passive(true)
+                        .finish(),
+                )
+                .await
+                .map_err(|err| format!("exchange not available: {}", err)),
+            Err(err) => Err(format!("could not open a channel: {}", err)),
+        };
+
+        let _ = connection.close().await;
+
+        result
+    }
+}
+
+/// Checks the broker and every registered gatherer, reporting all the failures at once
+pub async fn preflight(
+    broker: &impl BrokerProbe,
+    registry: &GatherersRegistry,
+    broker_timeout: Duration,
+    gatherer_timeout: Duration,
+    out: &mut impl Write,
+) -> Result<(), CommandErrors> {
+    let mut checks: Vec<(String, Result<(), String>)> = vec![(
+        broker.description(),
+        with_timeout(broker_timeout, broker.probe()).await,
+    )];
+
+    for (name, gatherer) in registry.versioned_gatherers() {
+        checks.push((
+            format!("gatherer {}", name),
+            with_timeout(gatherer_timeout, gatherer.probe()).await,
+        ));
+    }
+
+    let write_error = |err: std::io::Error| CommandErrors::PreflightError(err.to_string());
+    let mut failures = 0;
+
+    for (check, result) in &checks {
+        match result {
+            Ok(_) => writeln!(out, "OK    {}", check).map_err(write_error)?,
+            Err(reason) => {
+                failures += 1;
+                writeln!(out, "FAIL  {}: {}", check, reason).map_err(write_error)?
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(CommandErrors::PreflightError(format!(
+            "{} of {} preflight checks failed",
+            failures,
+            checks.len()
+        )));
+    }
+
+    Ok(())
+}
+
+async fn with_timeout(
+    timeout: Duration,
+    probe: impl std::future::Future<Output = Result<(), String>>,
+) -> Result<(), String> {
+    tokio::time::timeout(timeout, probe)
+        .await
+        .unwrap_or_else(|_| Err(format!("timed out after {}ms", timeout.as_millis())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gatherers::{
+        FactsGathered, FactsGatheringRequest, Gatherer, GatherersRegistryBuilder, MockGatherer,
+    };
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    struct FakeBrokerProbe {
+        result: Result<(), String>,
+    }
+
+    #[async_trait::async_trait]
+    impl BrokerProbe for FakeBrokerProbe {
+        fn description(&self) -> String {
+            "broker rabbit.local:5672, exchange trento.checks".to_owned()
+        }
+
+        async fn probe(&self) -> Result<(), String> {
+            self.result.to_owned()
+        }
+    }
+
+    struct HangingGatherer;
+
+    #[async_trait::async_trait]
+    impl Gatherer for HangingGatherer {
+        async fn gather(&self, _fact_request: FactsGatheringRequest) -> FactsGathered {
+            std::future::pending().await
+        }
+
+        fn name(&self) -> String {
+            "hanging".to_owned()
+        }
+
+        async fn probe(&self) -> Result<(), String> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+
+            Ok(())
+        }
+    }
+
+    fn probing_gatherer(result: Result<(), String>) -> MockGatherer {
+        let mut gatherer = MockGatherer::new();
+        gatherer
+            .expect_probe()
+            .times(1)
+            .returning(move || result.to_owned());
+
+        gatherer
+    }
+
+    #[tokio::test]
+    async fn test_preflight_all_passed() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("corosync.conf", "v1", probing_gatherer(Ok(())));
+        let mut output: Vec<u8> = vec![];
+
+        let result = preflight(
+            &FakeBrokerProbe { result: Ok(()) },
+            &builder.build_registry(),
+            TIMEOUT,
+            TIMEOUT,
+            &mut output,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "OK    broker rabbit.local:5672, exchange trento.checks\nOK    gatherer corosync.conf@v1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_preflight_reports_all_failures() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer(
+            "cibadmin",
+            "v1",
+            probing_gatherer(Err("cibadmin binary not found".to_owned())),
+        );
+        builder.add_gatherer("corosync.conf", "v1", probing_gatherer(Ok(())));
+        builder.add_gatherer("hanging", "v1", HangingGatherer);
+        let mut output: Vec<u8> = vec![];
+
+        let result = preflight(
+            &FakeBrokerProbe {
+                result: Err("connection refused".to_owned()),
+            },
+            &builder.build_registry(),
+            TIMEOUT,
+            TIMEOUT,
+            &mut output,
+        )
+        .await;
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "FAIL  broker rabbit.local:5672, exchange trento.checks: connection refused\n\
+             FAIL  gatherer cibadmin@v1: cibadmin binary not found\n\
+             OK    gatherer corosync.conf@v1\n\
+             FAIL  gatherer hanging@v1: timed out after 50ms\n"
+        );
+        assert_eq!(
+            result.err().unwrap(),
+            CommandErrors::PreflightError("3 of 4 preflight checks failed".to_owned())
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use std::io::{self, Write};
+
+use crate::config::default_config_template;
+
+pub fn print_default_config(out: &mut impl Write) -> io::Result<()> {
+    write!(out, "{}", default_config_template())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_default_config() {
+        let mut output: Vec<u8> = vec![];
+
+        print_default_config(&mut output).unwrap();
+
+        let template = String::from_utf8(output).unwrap();
+        assert!(template.starts_with("# vanvitelli configuration file"));
+        assert!(template.contains("[amqp]\n"));
+        assert!(template.contains("host = \"localhost\"\n"));
+    }
+}
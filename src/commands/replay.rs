@@ -0,0 +1,239 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+
+use super::CommandErrors;
+use crate::events::{outcome_label, EventRecorder, EventsHandler};
+
+/// Handles the recorded deliveries with the policy, in their recording order and without a
+/// broker. The corrupt recordings are skipped, failing the replay once all the others are handled
+pub async fn replay<H: EventsHandler + ?Sized>(
+    handler: &H,
+    source: &Path,
+    output_file: Option<&Path>,
+    out: &mut impl Write,
+) -> Result<(), CommandErrors> {
+    let recordings = recordings(source)?;
+
+    let mut corrupt = 0;
+    let mut outcomes: Vec<Value> = vec![];
+    for recording in &recordings {
+        let message = match EventRecorder::read(recording) {
+            Ok(message) => message,
+            Err(err) => {
+                warn!(
+                    "skipping corrupt recording {}: {}",
+                    recording.display(),
+                    err
+                );
+                corrupt += 1;
+                outcomes.push(json!({
+                    "recording": recording,
+                    "error": format!("corrupt recording: {}", err),
+                }));
+                continue;
+            }
+        };
+
+        let event_type = handler.event_type(&message.body, message.context.encoding());
+        let result = handler.handle_event(&message.body, &message.context).await;
+        outcomes.push(json!({
+            "recording": recording,
+            "event_type": event_type,
+            "message_id": message.context.message_id,
+            "outcome": outcome_label(&result),
+            "error": result.err().map(|err| err.to_string()),
+        }));
+    }
+
+    let printed = serde_json::to_string_pretty(&outcomes)
+        .map_err(|err| CommandErrors::ReplayError(err.to_string()))?;
+
+    match output_file {
+        Some(output_file) => fs::write(output_file, printed).map_err(|err| {
+            CommandErrors::ReplayError(format!(
+                "could not write output file {}: {}",
+                output_file.display(),
+                err
+            ))
+        })?,
+        None => writeln!(out, "{}", printed)
+            .map_err(|err| CommandErrors::ReplayError(err.to_string()))?,
+    }
+
+    if corrupt > 0 {
+        return Err(CommandErrors::ReplayError(format!(
+            "{} of {} recordings could not be read",
+            corrupt,
+            recordings.len()
+        )));
+    }
+
+    Ok(())
+}
+
+/// The payloads recorded in a directory, or a single payload
+fn recordings(source: &Path) -> Result<Vec<PathBuf>, CommandErrors> {
+    if source.is_file() {
+        return Ok(vec![source.to_owned()]);
+    }
+
+    EventRecorder::recorded(source).map_err(|err| {
+        CommandErrors::UsageError(format!(
+            "could not read recordings {}: {}",
+            source.display(),
+            err
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::*;
+    use crate::events::fixtures::{execution_cancelled, fact_request, facts_gathering_requested};
+    use crate::events::{DedupCache, EventContext, EventsPolicy, ExecutionQueue, Message};
+    use crate::gatherers::{GatherersRegistryBuilder, GatheringEngine};
+
+    fn record_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vanvitelli_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn policy() -> EventsPolicy {
+        let engine = Arc::new(GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        ));
+
+        EventsPolicy::new(
+            ["agent_1"],
+            "sap-node-1",
+            engine,
+            DedupCache::new(Duration::ZERO, 0),
+            ExecutionQueue::default(),
+        )
+        .unwrap()
+    }
+
+    async fn record(recorder: &EventRecorder, body: Vec<u8>, message_id: &str) -> PathBuf {
+        let message = Message {
+            body,
+            context: EventContext {
+                message_id: Some(message_id.to_owned()),
+                ..EventContext::json()
+            },
+        };
+        let event_type = policy().event_type(&message.body, message.context.encoding());
+
+        recorder
+            .record(&message, event_type.as_deref())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replay_in_recording_order() {
+        let dir = record_dir("replay");
+        let recorder = EventRecorder::new(&dir);
+        record(
+            &recorder,
+            facts_gathering_requested()
+                .target("agent_1", vec![fact_request("unknown", "fact1")])
+                .build_json(),
+            "message1",
+        )
+        .await;
+        record(
+            &recorder,
+            facts_gathering_requested()
+                .execution_id("exec2")
+                .target("agent_2", vec![fact_request("unknown", "fact1")])
+                .build_json(),
+            "message2",
+        )
+        .await;
+        record(&recorder, execution_cancelled().build_json(), "message3").await;
+        let mut output: Vec<u8> = vec![];
+
+        let result = replay(&policy(), &dir, None, &mut output).await;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok());
+        let outcomes: Vec<Value> = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            outcomes
+                .iter()
+                .map(|outcome| (
+                    outcome["message_id"].as_str().unwrap(),
+                    outcome["outcome"].as_str().unwrap()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("message1", "handled"),
+                ("message2", "skipped_other_agent"),
+                ("message3", "handled"),
+            ]
+        );
+        assert_eq!(
+            outcomes[0]["event_type"],
+            "Trento.Checks.V1.FactsGatheringRequested"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_skips_corrupt_recordings() {
+        let dir = record_dir("replay_corrupt");
+        let recorder = EventRecorder::new(&dir);
+        let corrupt = record(&recorder, b"{}".to_vec(), "message1").await;
+        fs::write(corrupt.with_extension("json"), "{\"routing_key\": ").unwrap();
+        let single = record(
+            &recorder,
+            facts_gathering_requested()
+                .target("agent_2", vec![fact_request("unknown", "fact1")])
+                .build_json(),
+            "message2",
+        )
+        .await;
+        let output_path = dir.join("outcomes.out");
+        let mut output: Vec<u8> = vec![];
+
+        let result = replay(&policy(), &dir, Some(&output_path), &mut output).await;
+        let written: Vec<Value> =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        let mut single_output: Vec<u8> = vec![];
+        let single_result = replay(&policy(), &single, None, &mut single_output).await;
+        let missing_result = replay(&policy(), &dir.join("missing"), None, &mut output).await;
+        fs::remove_dir_all(&dir).unwrap();
+
+        let error = result.err().unwrap();
+        assert!(matches!(error, CommandErrors::ReplayError(_)));
+        assert_eq!(error.to_string(), "1 of 2 recordings could not be read");
+        assert_eq!(error.exit_code(), 1);
+        assert!(output.is_empty());
+        assert_eq!(written.len(), 2);
+        assert!(written[0]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("corrupt recording"));
+        assert_eq!(written[1]["outcome"], "skipped_other_agent");
+
+        assert!(single_result.is_ok());
+        let single_outcomes: Vec<Value> = serde_json::from_slice(&single_output).unwrap();
+        assert_eq!(single_outcomes.len(), 1);
+        assert_eq!(single_outcomes[0]["message_id"], "message2");
+
+        assert!(matches!(
+            missing_result.err().unwrap(),
+            CommandErrors::UsageError(_)
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+use std::{fs, io::Write, path::Path};
+
+use super::CommandErrors;
+use crate::gatherers::{FactsGatheringRequest, GatheringEngine};
+
+/// Executes a facts gathering request read from a JSON file, without a broker
+pub async fn run_request(
+    engine: &GatheringEngine,
+    request_file: &Path,
+    output_file: Option<&Path>,
+    out: &mut impl Write,
+) -> Result<(), CommandErrors> {
+    let content = fs::read_to_string(request_file).map_err(|err| {
+        CommandErrors::UsageError(format!(
+            "could not read request file {}: {}",
+            request_file.display(),
+            err
+        ))
+    })?;
+    let request: FactsGatheringRequest = serde_json::from_str(&content).map_err(|err| {
+        CommandErrors::UsageError(format!(
+            "invalid request file {}: {}",
+            request_file.display(),
+            err
+        ))
+    })?;
+
+    let facts_gathered = engine.gather(request).await;
+
+    let printed = serde_json::to_string_pretty(&facts_gathered)
+        .map_err(|err| CommandErrors::GatheringError(err.to_string()))?;
+
+    match output_file {
+        Some(output_file) => fs::write(output_file, printed).map_err(|err| {
+            CommandErrors::GatheringError(format!(
+                "could not write output file {}: {}",
+                output_file.display(),
+                err
+            ))
+        }),
+        None => writeln!(out, "{}", printed)
+            .map_err(|err| CommandErrors::GatheringError(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::gatherers::{FactsGathered, GatherersRegistryBuilder};
+
+    fn request_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("vanvitelli_test_{}.json", name));
+        fs::write(&path, content).unwrap();
+
+        path
+    }
+
+    fn engine() -> GatheringEngine {
+        GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_run_request_with_unknown_gatherer() {
+        let path = request_file(
+            "run_request_unknown_gatherer",
+            &json!({
+                "execution_id": "exec1",
+                "group_id": "group1",
+                "facts_requests_by_gatherer": {
+                    "unknown": [{
+                        "argument": "arg1",
+                        "check_id": "check1",
+                        "gatherer": "unknown",
+                        "name": "fact1",
+                    }]
+                }
+            })
+            .to_string(),
+        );
+        let mut output: Vec<u8> = vec![];
+
+        let result = run_request(&engine(), &path, None, &mut output).await;
+        fs::remove_file(path).unwrap();
+
+        assert!(result.is_ok());
+
+        let facts_gathered: FactsGathered = serde_json::from_slice(&output).unwrap();
+        assert_eq!(facts_gathered.agent_id, "agent_1");
+        assert_eq!(facts_gathered.exeuction_id, "exec1");
+        assert_eq!(facts_gathered.facts_gathered.len(), 1);
+        assert!(facts_gathered.facts_gathered[0].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_run_request_to_output_file() {
+        let path = request_file(
+            "run_request_output",
+            r#"{"execution_id": "exec1", "group_id": "group1", "facts_requests_by_gatherer": {}}"#,
+        );
+        let output_path = std::env::temp_dir().join("vanvitelli_test_run_request_output.out.json");
+        let mut output: Vec<u8> = vec![];
+
+        let result = run_request(&engine(), &path, Some(&output_path), &mut output).await;
+        let written = fs::read_to_string(&output_path).unwrap();
+        fs::remove_file(path).unwrap();
+        fs::remove_file(output_path).unwrap();
+
+        assert!(result.is_ok());
+        assert!(output.is_empty());
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&written).unwrap(),
+            json!({
+                "agent_id": "agent_1",
+                "agent_name": "sap-node-1",
+                "execution_id": "exec1",
+                "facts_gathered": [],
+                "group_id": "group1",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_request_invalid_file() {
+        let path = request_file("run_request_invalid", "{\"execution_id\": ");
+        let mut output: Vec<u8> = vec![];
+
+        let result = run_request(&engine(), &path, None, &mut output).await;
+        fs::remove_file(path).unwrap();
+
+        let error = result.err().unwrap();
+        assert!(matches!(error, CommandErrors::UsageError(_)));
+        assert_eq!(error.exit_code(), 2);
+    }
+}
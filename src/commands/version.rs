@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+
+use serde_json::json;
+
+use crate::events::SUPPORTED_EVENT_TYPES;
+
+const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+const GIT_SHA: &str = env!("VANVITELLI_GIT_SHA");
+const BUILD_TIMESTAMP: &str = env!("VANVITELLI_BUILD_TIMESTAMP");
+
+/// Version reported by --version, the build information is provided by build.rs
+pub const BUILD_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("VANVITELLI_GIT_SHA"),
+    " ",
+    env!("VANVITELLI_BUILD_TIMESTAMP"),
+    ")"
+);
+
+pub fn version(as_json: bool, out: &mut impl Write) -> io::Result<()> {
+    if as_json {
+        let information = json!({
+            "version": CRATE_VERSION,
+            "git_sha": GIT_SHA,
+            "build_timestamp": BUILD_TIMESTAMP,
+            "supported_events": SUPPORTED_EVENT_TYPES,
+        });
+
+        return writeln!(out, "{}", serde_json::to_string_pretty(&information)?);
+    }
+
+    writeln!(out, "vanvitelli {}", BUILD_VERSION)?;
+    writeln!(out, "supported events:")?;
+    for event_type in SUPPORTED_EVENT_TYPES {
+        writeln!(out, "  {}", event_type)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version() {
+        let mut output: Vec<u8> = vec![];
+
+        version(false, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "vanvitelli {}\nsupported events:\n  Trento.Checks.V1.FactsGatheringRequested\n  \
+                 Trento.Checks.V2.FactsGatheringRequested\n  Trento.Checks.V1.ExecutionCancelled\n  Trento.Checks.V1.ExecutionCompleted\n",
+                BUILD_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn test_version_json() {
+        let mut output: Vec<u8> = vec![];
+
+        version(true, &mut output).unwrap();
+
+        let information: serde_json::Value = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(
+            information,
+            json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "git_sha": GIT_SHA,
+                "build_timestamp": BUILD_TIMESTAMP,
+                "supported_events": SUPPORTED_EVENT_TYPES,
+            })
+        );
+        assert_eq!(
+            information["supported_events"],
+            json!([
+                "Trento.Checks.V1.FactsGatheringRequested",
+                "Trento.Checks.V2.FactsGatheringRequested",
+                "Trento.Checks.V1.ExecutionCancelled",
+                "Trento.Checks.V1.ExecutionCompleted"
+            ])
+        );
+    }
+}
@@ -0,0 +1,126 @@
+#[cfg(test)]
+use mockall::automock;
+use uuid::Uuid;
+
+use super::ConfigErrors;
+
+const MACHINE_ID_PATHS: [&str; 2] = ["/etc/machine-id", "/var/lib/dbus/machine-id"];
+// same namespace used by the trento agent, so both derive the same id for a host
+const AGENT_ID_NAMESPACE: &str = "fb92284e-aa5e-47f6-a883-bf9469e7a0dc";
+
+#[cfg_attr(test, automock)]
+pub trait MachineIdSource {
+    fn read_machine_id(&self, path: &str) -> std::io::Result<String>;
+}
+
+pub struct FileMachineIdSource;
+
+impl MachineIdSource for FileMachineIdSource {
+    fn read_machine_id(&self, path: &str) -> std::io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+}
+
+pub fn discover_agent_id(source: &impl MachineIdSource) -> Result<String, ConfigErrors> {
+    let mut failures: Vec<String> = vec![];
+
+    for path in MACHINE_ID_PATHS {
+        match source.read_machine_id(path) {
+            Ok(machine_id) if !machine_id.trim().is_empty() => {
+                return Ok(agent_id_from_machine_id(machine_id.trim()));
+            }
+            Ok(_) => failures.push(format!("{} is empty", path)),
+            Err(err) => failures.push(format!("{}: {}", path, err)),
+        }
+    }
+
+    Err(ConfigErrors::AgentIdDiscoveryError(failures.join(", ")))
+}
+
+fn agent_id_from_machine_id(machine_id: &str) -> String {
+    let namespace = Uuid::parse_str(AGENT_ID_NAMESPACE).unwrap();
+
+    Uuid::new_v5(&namespace, machine_id.as_bytes()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind};
+
+    use super::*;
+
+    const MACHINE_ID: &str = "8f4a5c3e1d2b4a6f9e8d7c6b5a4f3e2d";
+
+    #[test]
+    fn test_discover_agent_id_from_etc() {
+        let mut source = MockMachineIdSource::new();
+        source
+            .expect_read_machine_id()
+            .times(1)
+            .returning(|_| Ok(format!("{}\n", MACHINE_ID)));
+
+        assert_eq!(
+            discover_agent_id(&source).unwrap(),
+            agent_id_from_machine_id(MACHINE_ID)
+        );
+    }
+
+    #[test]
+    fn test_discover_agent_id_fallback_to_dbus() {
+        let mut source = MockMachineIdSource::new();
+        source
+            .expect_read_machine_id()
+            .times(2)
+            .returning(|path| match path {
+                "/var/lib/dbus/machine-id" => Ok(MACHINE_ID.to_owned()),
+                _ => Err(Error::new(ErrorKind::NotFound, "not found")),
+            });
+
+        assert_eq!(
+            discover_agent_id(&source).unwrap(),
+            agent_id_from_machine_id(MACHINE_ID)
+        );
+    }
+
+    #[test]
+    fn test_discover_agent_id_skips_empty_machine_id() {
+        let mut source = MockMachineIdSource::new();
+        source
+            .expect_read_machine_id()
+            .times(2)
+            .returning(|path| match path {
+                "/etc/machine-id" => Ok("\n".to_owned()),
+                _ => Ok(MACHINE_ID.to_owned()),
+            });
+
+        assert_eq!(
+            discover_agent_id(&source).unwrap(),
+            agent_id_from_machine_id(MACHINE_ID)
+        );
+    }
+
+    #[test]
+    fn test_discover_agent_id_no_machine_id() {
+        let mut source = MockMachineIdSource::new();
+        source
+            .expect_read_machine_id()
+            .times(2)
+            .returning(|_| Err(Error::new(ErrorKind::NotFound, "not found")));
+
+        assert_eq!(
+            discover_agent_id(&source).err().unwrap(),
+            ConfigErrors::AgentIdDiscoveryError(
+                "/etc/machine-id: not found, /var/lib/dbus/machine-id: not found".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn test_agent_id_is_stable() {
+        let agent_id = agent_id_from_machine_id(MACHINE_ID);
+
+        assert_eq!(agent_id, agent_id_from_machine_id(MACHINE_ID));
+        assert_ne!(agent_id, agent_id_from_machine_id("another_machine_id"));
+        assert!(Uuid::parse_str(&agent_id).is_ok());
+    }
+}
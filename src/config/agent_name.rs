@@ -0,0 +1,96 @@
+use std::io;
+
+#[cfg(test)]
+use mockall::automock;
+
+use super::ConfigErrors;
+
+const HOSTNAME_PATHS: [&str; 2] = ["/proc/sys/kernel/hostname", "/etc/hostname"];
+const MAX_AGENT_NAME_LENGTH: usize = 64;
+
+#[cfg_attr(test, automock)]
+pub trait HostnameSource {
+    fn read_hostname(&self) -> io::Result<String>;
+}
+
+pub struct SystemHostnameSource;
+
+impl HostnameSource for SystemHostnameSource {
+    fn read_hostname(&self) -> io::Result<String> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "hostname not found");
+
+        for path in HOSTNAME_PATHS {
+            match std::fs::read_to_string(path) {
+                Ok(hostname) => return Ok(hostname),
+                Err(err) => last_error = err,
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+/// Hostname used as agent name when none is configured, `None` when it cannot be read
+pub fn discover_hostname(source: &impl HostnameSource) -> Option<String> {
+    source
+        .read_hostname()
+        .ok()
+        .map(|hostname| hostname.trim().to_owned())
+        .filter(|hostname| !hostname.is_empty())
+}
+
+pub fn validate_agent_name(name: &str) -> Result<(), ConfigErrors> {
+    if name.len() > MAX_AGENT_NAME_LENGTH {
+        return Err(ConfigErrors::InvalidValueError(
+            "agent-name".to_owned(),
+            format!("at most {} characters are allowed", MAX_AGENT_NAME_LENGTH),
+        ));
+    }
+    if !name.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+        return Err(ConfigErrors::InvalidValueError(
+            "agent-name".to_owned(),
+            "only printable ascii characters are allowed".to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover_hostname() {
+        let mut source = MockHostnameSource::new();
+        source
+            .expect_read_hostname()
+            .times(1)
+            .returning(|| Ok("sap-node-1\n".to_owned()));
+
+        assert_eq!(discover_hostname(&source), Some("sap-node-1".to_owned()));
+    }
+
+    #[test]
+    fn test_discover_hostname_unavailable() {
+        let mut source = MockHostnameSource::new();
+        source
+            .expect_read_hostname()
+            .times(1)
+            .returning(|| Err(io::Error::new(io::ErrorKind::NotFound, "not found")));
+
+        assert_eq!(discover_hostname(&source), None);
+    }
+
+    #[test]
+    fn test_validate_agent_name() {
+        assert!(validate_agent_name("sap-node-1 (primary)").is_ok());
+
+        for invalid in ["x".repeat(65), "nodè".to_owned(), "node\n1".to_owned()] {
+            assert!(matches!(
+                validate_agent_name(&invalid),
+                Err(ConfigErrors::InvalidValueError(key, _)) if key == "agent-name"
+            ));
+        }
+    }
+}
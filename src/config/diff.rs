@@ -0,0 +1,274 @@
+use super::Config;
+
+// keys that can be applied to a running agent, everything else requires a restart
+const RELOADABLE_KEYS: [&str; 6] = [
+    "log-level",
+    "log-format",
+    "log-modules",
+    "execution-timeout",
+    "max-executions",
+    "enabled-gatherers",
+];
+
+/// Keys changed between the running configuration and a reloaded one
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigDiff {
+    pub reloadable: Vec<&'static str>,
+    pub restart_required: Vec<&'static str>,
+}
+
+impl ConfigDiff {
+    pub fn between(running: &Config, reloaded: &Config) -> ConfigDiff {
+        let changes = [
+            ("amqp-host", running.broker.host != reloaded.broker.host),
+            ("amqp-port", running.broker.port != reloaded.broker.port),
+            ("amqp-user", running.broker.user != reloaded.broker.user),
+            (
+                "amqp-password",
+                running.broker.password != reloaded.broker.password,
+            ),
+            ("amqp-vhost", running.broker.vhost != reloaded.broker.vhost),
+            (
+                "amqp-heartbeat",
+                running.broker.heartbeat != reloaded.broker.heartbeat,
+            ),
+            (
+                "amqp-connect-timeout",
+                running.broker.connect_timeout != reloaded.broker.connect_timeout,
+            ),
+            (
+                "client-properties",
+                running.broker.client_properties != reloaded.broker.client_properties,
+            ),
+            ("tls", running.broker.tls != reloaded.broker.tls),
+            ("agent-id", running.agent_id != reloaded.agent_id),
+            ("agent-name", running.agent_name != reloaded.agent_name),
+            (
+                "agent-aliases",
+                running.agent_aliases != reloaded.agent_aliases,
+            ),
+            (
+                "allowed-group-ids",
+                running.allowed_group_ids != reloaded.allowed_group_ids,
+            ),
+            (
+                "denied-group-ids",
+                running.denied_group_ids != reloaded.denied_group_ids,
+            ),
+            (
+                "group-skip-notices",
+                running.group_skip_notices != reloaded.group_skip_notices,
+            ),
+            (
+                "exchange",
+                running.topology.exchange != reloaded.topology.exchange,
+            ),
+            (
+                "routing-keys",
+                running.topology.routing_keys != reloaded.topology.routing_keys,
+            ),
+            (
+                "exchange-declare",
+                running.topology.declare_exchange != reloaded.topology.declare_exchange
+                    || running.topology.exchange_type != reloaded.topology.exchange_type
+                    || running.topology.exchange_durable != reloaded.topology.exchange_durable,
+            ),
+            ("results", running.results != reloaded.results),
+            ("queue", running.topology.queue != reloaded.topology.queue),
+            (
+                "queue-mode",
+                running.topology.queue_mode != reloaded.topology.queue_mode,
+            ),
+            (
+                "queue-arguments",
+                running.topology.queue_arguments != reloaded.topology.queue_arguments,
+            ),
+            (
+                "passive-queue",
+                running.topology.passive_queue != reloaded.topology.passive_queue,
+            ),
+            (
+                "skip-bindings",
+                running.topology.skip_bindings != reloaded.topology.skip_bindings,
+            ),
+            (
+                "consumer-tag",
+                running.topology.consumer_tag != reloaded.topology.consumer_tag,
+            ),
+            (
+                "exclusive-consumer",
+                running.topology.exclusive_consumer != reloaded.topology.exclusive_consumer,
+            ),
+            (
+                "consumer-priority",
+                running.topology.consumer_priority != reloaded.topology.consumer_priority,
+            ),
+            (
+                "dead-letter",
+                running.topology.dead_letter != reloaded.topology.dead_letter,
+            ),
+            (
+                "subscriptions",
+                running.subscriptions != reloaded.subscriptions,
+            ),
+            ("delivery", running.delivery != reloaded.delivery),
+            ("log-level", running.logging.level != reloaded.logging.level),
+            (
+                "log-format",
+                running.logging.format != reloaded.logging.format,
+            ),
+            (
+                "log-modules",
+                running.logging.modules != reloaded.logging.modules,
+            ),
+            ("runtime", running.runtime != reloaded.runtime),
+            ("facts-dump", running.facts_dump != reloaded.facts_dump),
+            (
+                "record-events",
+                running.record_events != reloaded.record_events,
+            ),
+            ("pid-file", running.pid_file != reloaded.pid_file),
+            ("dry-run", running.dry_run != reloaded.dry_run),
+            (
+                "execution-timeout",
+                running.execution_timeout != reloaded.execution_timeout,
+            ),
+            (
+                "max-executions",
+                running.max_executions != reloaded.max_executions,
+            ),
+            (
+                "enabled-gatherers",
+                running.enabled_gatherers != reloaded.enabled_gatherers,
+            ),
+            (
+                "execution-queue-depth",
+                running.execution_queue_depth != reloaded.execution_queue_depth,
+            ),
+            (
+                "group-rate-limit",
+                running.group_rate_limit != reloaded.group_rate_limit
+                    || running.rate_limit_mode != reloaded.rate_limit_mode,
+            ),
+            (
+                "handler-timeouts",
+                running.handler_timeouts != reloaded.handler_timeouts,
+            ),
+            ("replay-ttl", running.replay_ttl != reloaded.replay_ttl),
+            (
+                "replay-capacity",
+                running.replay_capacity != reloaded.replay_capacity,
+            ),
+            (
+                "drain-timeout",
+                running.drain_timeout != reloaded.drain_timeout,
+            ),
+            (
+                "close-timeout",
+                running.close_timeout != reloaded.close_timeout,
+            ),
+            ("reconnect", running.reconnect != reloaded.reconnect),
+            ("breaker", running.breaker != reloaded.breaker),
+            ("disk-guard", running.disk_guard != reloaded.disk_guard),
+        ];
+
+        let mut diff = ConfigDiff::default();
+
+        for (key, _) in changes.into_iter().filter(|(_, changed)| *changed) {
+            if RELOADABLE_KEYS.contains(&key) {
+                diff.reloadable.push(key);
+            } else {
+                diff.restart_required.push(key);
+            }
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reloadable.is_empty() && self.restart_required.is_empty()
+    }
+
+    /// Copies the reloadable values of `reloaded` into `running`, leaving the other ones untouched
+    pub fn apply(&self, running: &mut Config, reloaded: &Config) {
+        for key in &self.reloadable {
+            match *key {
+                "log-level" => running.logging.level = reloaded.logging.level.to_owned(),
+                "log-format" => running.logging.format = reloaded.logging.format,
+                "log-modules" => running.logging.modules = reloaded.logging.modules.to_owned(),
+                "execution-timeout" => running.execution_timeout = reloaded.execution_timeout,
+                "max-executions" => running.max_executions = reloaded.max_executions,
+                "enabled-gatherers" => {
+                    running.enabled_gatherers = reloaded.enabled_gatherers.to_owned()
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::config::{layer::ConfigLayer, LogFormat};
+
+    fn config(layer: ConfigLayer) -> Config {
+        Config::from_layer(ConfigLayer {
+            agent_id: Some("agent_1".to_owned()),
+            ..layer
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        let running = config(ConfigLayer::default());
+
+        assert!(ConfigDiff::between(&running, &running.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_reloadable_and_restart_required() {
+        let running = config(ConfigLayer::default());
+        let reloaded = config(ConfigLayer {
+            amqp_host: Some("rabbit.local".to_owned()),
+            amqp_password: Some("secret".to_owned()),
+            log_level: Some("debug".to_owned()),
+            log_format: Some(LogFormat::Json),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            ConfigDiff::between(&running, &reloaded),
+            ConfigDiff {
+                reloadable: vec!["log-level", "log-format"],
+                restart_required: vec!["amqp-host", "amqp-password"],
+            }
+        );
+    }
+
+    #[test]
+    fn test_diff_apply_only_reloadable() {
+        let mut running = config(ConfigLayer::default());
+        let reloaded = config(ConfigLayer {
+            amqp_host: Some("rabbit.local".to_owned()),
+            log_level: Some("debug".to_owned()),
+            log_modules: Some(BTreeMap::from([("amqprs".to_owned(), "warn".to_owned())])),
+            execution_timeout: Some(45),
+            max_executions: Some(2),
+            enabled_gatherers: Some(vec!["corosync.conf".to_owned()]),
+            ..Default::default()
+        });
+        let initial = running.clone();
+
+        ConfigDiff::between(&running, &reloaded).apply(&mut running, &reloaded);
+
+        assert_eq!(running.broker, initial.broker);
+        assert_eq!(running.logging, reloaded.logging);
+        assert_eq!(running.execution_timeout, reloaded.execution_timeout);
+        assert_eq!(running.max_executions, Some(2));
+        assert_eq!(running.enabled_gatherers, vec!["corosync.conf".to_owned()]);
+    }
+}
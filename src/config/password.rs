@@ -0,0 +1,93 @@
+use std::{fs, path::Path};
+
+use super::ConfigErrors;
+
+/// Reads the broker password from a secret file, the content is never part of the errors
+pub fn read_password_file(path: &Path) -> Result<String, ConfigErrors> {
+    let content = fs::read_to_string(path).map_err(|err| {
+        ConfigErrors::PasswordFileError(path.display().to_string(), err.to_string())
+    })?;
+
+    let password = content
+        .strip_suffix('\n')
+        .map(|password| password.strip_suffix('\r').unwrap_or(password))
+        .unwrap_or(&content);
+
+    if password.is_empty() {
+        return Err(ConfigErrors::PasswordFileError(
+            path.display().to_string(),
+            "the file is empty".to_owned(),
+        ));
+    }
+
+    Ok(password.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn password_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("vanvitelli_test_{}", name));
+        fs::write(&path, content).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn test_read_password_file() {
+        let path = password_file("password", "secret");
+
+        let password = read_password_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(password.unwrap(), "secret");
+    }
+
+    #[test]
+    fn test_read_password_file_trims_trailing_newline() {
+        let path = password_file("password_newline", "secret\n");
+        let crlf_path = password_file("password_crlf", "secret\r\n");
+
+        let password = read_password_file(&path);
+        let crlf_password = read_password_file(&crlf_path);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&crlf_path).unwrap();
+
+        assert_eq!(password.unwrap(), "secret");
+        assert_eq!(crlf_password.unwrap(), "secret");
+    }
+
+    #[test]
+    fn test_read_password_file_keeps_inner_whitespaces() {
+        let path = password_file("password_spaces", " sec ret \n");
+
+        let password = read_password_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(password.unwrap(), " sec ret ");
+    }
+
+    #[test]
+    fn test_read_empty_password_file() {
+        let path = password_file("password_empty", "\n");
+
+        let password = read_password_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            password,
+            Err(ConfigErrors::PasswordFileError(_, reason)) if reason == "the file is empty"
+        ));
+    }
+
+    #[test]
+    fn test_read_missing_password_file() {
+        assert!(matches!(
+            read_password_file(Path::new("/not/existing/password")),
+            Err(ConfigErrors::PasswordFileError(path, _)) if path == "/not/existing/password"
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+
+use amqprs::{FieldTable, FieldValue};
+use serde::{Deserialize, Serialize};
+
+use super::{ConfigErrors, MAX_SHORT_STRING_LENGTH};
+
+// sent as amqp long integers, the broker rejects them as strings
+const LONG_ARGUMENTS: [&str; 7] = [
+    "x-message-ttl",
+    "x-expires",
+    "x-max-length",
+    "x-max-length-bytes",
+    "x-max-priority",
+    "x-delivery-limit",
+    "x-quorum-initial-group-size",
+];
+const STRING_ARGUMENTS: [(&str, &[&str]); 2] = [
+    ("x-queue-type", &["classic", "quorum", "stream"]),
+    (
+        "x-overflow",
+        &["drop-head", "reject-publish", "reject-publish-dlx"],
+    ),
+];
+// replicated queue types, they cannot be exclusive or auto-deleted
+const REPLICATED_QUEUE_TYPES: [&str; 2] = ["quorum", "stream"];
+
+/// Value of an optional queue argument, as written in the configuration file
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum QueueArgument {
+    Long(i64),
+    Text(String),
+    Bool(bool),
+}
+
+impl QueueArgument {
+    fn field_value(&self) -> FieldValue {
+        match self {
+            QueueArgument::Long(value) => FieldValue::l(*value),
+            QueueArgument::Text(value) => FieldValue::S(
+                value
+                    .as_str()
+                    .try_into()
+                    .expect("invalid queue argument value, fatal."),
+            ),
+            QueueArgument::Bool(value) => FieldValue::t(*value),
+        }
+    }
+}
+
+/// Checks the type and the value of the arguments known to the broker,
+/// the other `x-` arguments are passed through verbatim
+pub fn validate_queue_arguments(
+    arguments: &BTreeMap<String, QueueArgument>,
+) -> Result<(), ConfigErrors> {
+    for (key, value) in arguments {
+        let invalid = |reason: String| {
+            Err(ConfigErrors::InvalidValueError(
+                "queue-arguments".to_owned(),
+                format!("`{}` {}", key, reason),
+            ))
+        };
+
+        if !key.starts_with("x-") {
+            return invalid("is not an optional queue argument, they start with x-".to_owned());
+        }
+        // the names are amqp short strings
+        if key.len() > MAX_SHORT_STRING_LENGTH {
+            return invalid(format!(
+                "is too long, at most {} bytes are allowed",
+                MAX_SHORT_STRING_LENGTH
+            ));
+        }
+
+        if LONG_ARGUMENTS.contains(&key.as_str()) {
+            match value {
+                QueueArgument::Long(number) if *number >= 0 => (),
+                _ => return invalid("should be a non negative number".to_owned()),
+            }
+        }
+
+        if let Some((_, allowed)) = STRING_ARGUMENTS
+            .iter()
+            .find(|(name, _)| *name == key.as_str())
+        {
+            match value {
+                QueueArgument::Text(text) if allowed.contains(&text.as_str()) => (),
+                _ => return invalid(format!("should be one of {}", allowed.join(", "))),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the arguments declare a quorum or a stream queue
+pub fn is_replicated(arguments: &BTreeMap<String, QueueArgument>) -> bool {
+    matches!(
+        arguments.get("x-queue-type"),
+        Some(QueueArgument::Text(queue_type)) if REPLICATED_QUEUE_TYPES.contains(&queue_type.as_str())
+    )
+}
+
+pub fn queue_arguments_table(arguments: &BTreeMap<String, QueueArgument>) -> FieldTable {
+    let mut table = FieldTable::new();
+    for (key, value) in arguments {
+        table.insert(
+            key.as_str()
+                .try_into()
+                .expect("invalid queue argument name, fatal."),
+            value.field_value(),
+        );
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(arguments: &[(&str, QueueArgument)]) -> BTreeMap<String, QueueArgument> {
+        arguments
+            .iter()
+            .map(|(key, value)| ((*key).to_owned(), value.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_queue_arguments_table() {
+        let arguments = arguments(&[
+            ("x-queue-type", QueueArgument::Text("quorum".to_owned())),
+            ("x-message-ttl", QueueArgument::Long(60000)),
+            ("x-max-length", QueueArgument::Long(1000)),
+            (
+                "x-overflow",
+                QueueArgument::Text("reject-publish".to_owned()),
+            ),
+            ("x-single-active-consumer", QueueArgument::Bool(true)),
+        ]);
+
+        assert!(validate_queue_arguments(&arguments).is_ok());
+
+        let table = queue_arguments_table(&arguments);
+        let get = |key: &str| table.get(&key.try_into().unwrap());
+
+        assert_eq!(
+            get("x-queue-type"),
+            Some(&FieldValue::S("quorum".try_into().unwrap()))
+        );
+        assert_eq!(get("x-message-ttl"), Some(&FieldValue::l(60000)));
+        assert_eq!(get("x-max-length"), Some(&FieldValue::l(1000)));
+        assert_eq!(
+            get("x-overflow"),
+            Some(&FieldValue::S("reject-publish".try_into().unwrap()))
+        );
+        assert_eq!(get("x-single-active-consumer"), Some(&FieldValue::t(true)));
+    }
+
+    #[test]
+    fn test_validate_queue_arguments() {
+        let invalid = [
+            arguments(&[("x-message-ttl", QueueArgument::Text("60000".to_owned()))]),
+            arguments(&[("x-max-length", QueueArgument::Long(-1))]),
+            arguments(&[("x-queue-type", QueueArgument::Text("lazy".to_owned()))]),
+            arguments(&[("x-overflow", QueueArgument::Long(1))]),
+            arguments(&[("message-ttl", QueueArgument::Long(60000))]),
+            arguments(&[(&format!("x-{}", "a".repeat(254)), QueueArgument::Bool(true))]),
+        ];
+
+        for arguments in invalid {
+            assert!(matches!(
+                validate_queue_arguments(&arguments),
+                Err(ConfigErrors::InvalidValueError(key, _)) if key == "queue-arguments"
+            ));
+        }
+
+        // unknown arguments are passed through verbatim
+        assert!(validate_queue_arguments(&arguments(&[(
+            "x-custom",
+            QueueArgument::Text("anything".to_owned())
+        )]))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_is_replicated() {
+        assert!(is_replicated(&arguments(&[(
+            "x-queue-type",
+            QueueArgument::Text("quorum".to_owned())
+        )])));
+        assert!(!is_replicated(&arguments(&[(
+            "x-queue-type",
+            QueueArgument::Text("classic".to_owned())
+        )])));
+        assert!(!is_replicated(&BTreeMap::new()));
+    }
+}
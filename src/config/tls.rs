@@ -0,0 +1,280 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use amqprs::tls::TlsAdaptor;
+use tokio_rustls::{
+    rustls::{
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, ClientConfig, Error, PrivateKey, ServerName,
+    },
+    TlsConnector,
+};
+
+use super::ConfigErrors;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub ca_cert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Name used for SNI and certificate verification, defaults to the broker host
+    pub server_name: Option<String>,
+    pub verify_peer: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig {
+            enabled: false,
+            ca_cert: None,
+            client_cert: None,
+            client_key: None,
+            server_name: None,
+            verify_peer: true,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn validate(&self) -> Result<(), ConfigErrors> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(_), None) => {
+                return Err(ConfigErrors::EmptyValueError("tls-client-key".to_owned()))
+            }
+            (None, Some(_)) => {
+                return Err(ConfigErrors::EmptyValueError("tls-client-cert".to_owned()))
+            }
+            _ => (),
+        }
+
+        for path in [&self.ca_cert, &self.client_cert, &self.client_key]
+            .into_iter()
+            .flatten()
+        {
+            File::open(path).map_err(|err| tls_file_error(path, err))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn server_name(&self, host: &str) -> String {
+        self.server_name
+            .to_owned()
+            .unwrap_or_else(|| host.to_owned())
+    }
+
+    pub fn tls_adaptor(&self, host: &str) -> Result<TlsAdaptor, ConfigErrors> {
+        let domain = self.server_name(host);
+
+        if !self.verify_peer {
+            return Ok(TlsAdaptor::new(self.insecure_connector()?, domain));
+        }
+
+        let adaptor = match (&self.client_cert, &self.client_key) {
+            (Some(client_cert), Some(client_key)) => TlsAdaptor::with_client_auth(
+                self.ca_cert.as_deref(),
+                client_cert,
+                client_key,
+                domain,
+            ),
+            _ => TlsAdaptor::without_client_auth(self.ca_cert.as_deref(), domain),
+        };
+
+        adaptor.map_err(|err| ConfigErrors::TlsError(err.to_string()))
+    }
+
+    // verification of the broker certificate is disabled, only client certificates are loaded
+    fn insecure_connector(&self) -> Result<TlsConnector, ConfigErrors> {
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(NoPeerVerification));
+
+        let client_config = match (&self.client_cert, &self.client_key) {
+            (Some(client_cert), Some(client_key)) => builder
+                .with_client_auth_cert(load_certificates(client_cert)?, load_key(client_key)?)
+                .map_err(|err| ConfigErrors::TlsError(err.to_string()))?,
+            _ => builder.with_no_client_auth(),
+        };
+
+        Ok(TlsConnector::from(Arc::new(client_config)))
+    }
+}
+
+struct NoPeerVerification;
+
+impl ServerCertVerifier for NoPeerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>, ConfigErrors> {
+    let file = File::open(path).map_err(|err| tls_file_error(path, err))?;
+
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|err| tls_file_error(path, err))?;
+
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, ConfigErrors> {
+    let file = File::open(path).map_err(|err| tls_file_error(path, err))?;
+
+    rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(file))
+        .map_err(|err| tls_file_error(path, err))?
+        .into_iter()
+        .next()
+        .map(PrivateKey)
+        .ok_or_else(|| {
+            ConfigErrors::TlsFileError(
+                path.display().to_string(),
+                "no pkcs8 private key found".to_owned(),
+            )
+        })
+}
+
+fn tls_file_error(path: &Path, err: std::io::Error) -> ConfigErrors {
+    ConfigErrors::TlsFileError(path.display().to_string(), err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tls_config() -> TlsConfig {
+        TlsConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tls_server_name_from_host() {
+        assert_eq!(tls_config().server_name("rabbit.local"), "rabbit.local");
+    }
+
+    #[test]
+    fn test_tls_server_name_override() {
+        let config = TlsConfig {
+            server_name: Some("rabbit.internal".to_owned()),
+            ..tls_config()
+        };
+
+        assert_eq!(config.server_name("10.0.0.1"), "rabbit.internal");
+    }
+
+    #[test]
+    fn test_tls_validate_disabled_skips_files() {
+        let config = TlsConfig {
+            enabled: false,
+            ca_cert: Some("/not/existing/ca.pem".into()),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_tls_validate_missing_ca_cert() {
+        let config = TlsConfig {
+            ca_cert: Some("/not/existing/ca.pem".into()),
+            ..tls_config()
+        };
+
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigErrors::TlsFileError(path, _)) if path == "/not/existing/ca.pem"
+        ));
+    }
+
+    #[test]
+    fn test_tls_validate_missing_client_key() {
+        let config = TlsConfig {
+            client_cert: Some("/not/existing/client.pem".into()),
+            ..tls_config()
+        };
+
+        assert_eq!(
+            config.validate().err().unwrap(),
+            ConfigErrors::EmptyValueError("tls-client-key".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_tls_validate_missing_client_cert_file() {
+        let ca_cert = std::env::temp_dir().join("vanvitelli_test_tls_ca.pem");
+        std::fs::write(&ca_cert, "").unwrap();
+
+        let config = TlsConfig {
+            ca_cert: Some(ca_cert.to_owned()),
+            client_cert: Some("/not/existing/client.pem".into()),
+            client_key: Some("/not/existing/client.key".into()),
+            ..tls_config()
+        };
+
+        let result = config.validate();
+        std::fs::remove_file(ca_cert).unwrap();
+
+        assert!(matches!(
+            result,
+            Err(ConfigErrors::TlsFileError(path, _)) if path == "/not/existing/client.pem"
+        ));
+    }
+
+    #[test]
+    fn test_tls_insecure_adaptor_without_client_auth() {
+        let config = TlsConfig {
+            verify_peer: false,
+            ..tls_config()
+        };
+
+        assert!(config.tls_adaptor("rabbit.local").is_ok());
+    }
+
+    // requires a tls enabled broker, certificates are taken from the VANVITELLI_TEST_TLS_* variables
+    #[tokio::test]
+    #[ignore]
+    async fn test_tls_handshake() {
+        let config = TlsConfig {
+            ca_cert: std::env::var("VANVITELLI_TEST_TLS_CA_CERT")
+                .ok()
+                .map(PathBuf::from),
+            client_cert: std::env::var("VANVITELLI_TEST_TLS_CLIENT_CERT")
+                .ok()
+                .map(PathBuf::from),
+            client_key: std::env::var("VANVITELLI_TEST_TLS_CLIENT_KEY")
+                .ok()
+                .map(PathBuf::from),
+            ..tls_config()
+        };
+        config.validate().unwrap();
+
+        let mut arguments =
+            amqprs::connection::OpenConnectionArguments::new("localhost", 5671, "wanda", "wanda");
+        arguments.tls_adaptor(config.tls_adaptor("localhost").unwrap());
+
+        let connection = amqprs::connection::Connection::open(&arguments)
+            .await
+            .unwrap();
+
+        connection.close().await.unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use log::{info, warn};
+#[cfg(test)]
+use mockall::automock;
+use tokio::sync::watch;
+
+use crate::config::DiskGuardConfig;
+
+/// Free space of the filesystem holding a path, in bytes
+#[cfg_attr(test, automock)]
+pub trait FreeSpaceProbe: Send + Sync {
+    fn free_space(&self, path: &Path) -> io::Result<u64>;
+}
+
+pub struct FilesystemProbe;
+
+impl FreeSpaceProbe for FilesystemProbe {
+    fn free_space(&self, path: &Path) -> io::Result<u64> {
+        // the facts dump directory is created on the first dump, its filesystem is the parent one
+        let existing = path
+            .ancestors()
+            .find(|ancestor| ancestor.exists())
+            .unwrap_or(path);
+
+        fs2::available_space(existing)
+    }
+}
+
+/// Whether a checked filesystem is low on space, the deliveries are paused meanwhile
+#[derive(Debug, Clone)]
+pub struct DiskSpace {
+    low: Arc<watch::Sender<bool>>,
+}
+
+impl Default for DiskSpace {
+    fn default() -> DiskSpace {
+        DiskSpace {
+            low: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl DiskSpace {
+    pub fn is_low(&self) -> bool {
+        *self.low.borrow()
+    }
+
+    /// Waits until the space is available again
+    pub async fn available(&self) {
+        let mut low = self.low.subscribe();
+        // the sender is owned by self, so it cannot be dropped while waiting
+        let _ = low.wait_for(|low| !low).await;
+    }
+}
+
+/// Checks periodically the free space of the filesystems the agent writes to, so that the
+/// deliveries do not fill them up
+pub struct DiskGuard<P> {
+    paths: Vec<PathBuf>,
+    min_free: u64,
+    check_interval: Duration,
+    probe: P,
+    space: DiskSpace,
+}
+
+impl<P: FreeSpaceProbe> DiskGuard<P> {
+    pub fn new(config: &DiskGuardConfig, probe: P) -> DiskGuard<P> {
+        DiskGuard {
+            paths: config.paths.to_owned(),
+            min_free: config.min_free,
+            check_interval: config.check_interval,
+            probe,
+            space: DiskSpace::default(),
+        }
+    }
+
+    pub fn space(&self) -> DiskSpace {
+        self.space.clone()
+    }
+
+    /// Pauses the deliveries when a filesystem is below the threshold, resumes them once all of
+    /// them are above it again. Returns whether the space is low
+    pub fn check(&self) -> bool {
+        let low: Vec<String> = self
+            .paths
+            .iter()
+            .filter_map(|path| match self.probe.free_space(path) {
+                Ok(free) if free < self.min_free => {
+                    Some(format!("{} bytes free for {}", free, path.display()))
+                }
+                Ok(_) => None,
+                // an unreadable filesystem does not stop the consumption
+                Err(err) => {
+                    warn!(
+                        "unable to check the free space for {}: {}",
+                        path.display(),
+                        err
+                    );
+                    None
+                }
+            })
+            .collect();
+        let is_low = !low.is_empty();
+
+        if self.space.low.send_replace(is_low) != is_low {
+            match is_low {
+                true => warn!("low disk space, pausing the deliveries: {}", low.join(", ")),
+                false => info!("disk space available again, resuming the deliveries"),
+            }
+        }
+
+        is_low
+    }
+
+    /// Checks the free space on every tick, until the task is aborted
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.check_interval);
+
+        loop {
+            interval.tick().await;
+            self.check();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+
+    const MEGABYTE: u64 = 1024 * 1024;
+
+    fn guard(free: Arc<AtomicU64>) -> DiskGuard<MockFreeSpaceProbe> {
+        let mut probe = MockFreeSpaceProbe::new();
+        probe
+            .expect_free_space()
+            .withf(|path| path == Path::new("/var/lib/vanvitelli"))
+            .returning(move |_| Ok(free.load(Ordering::SeqCst)));
+        probe
+            .expect_free_space()
+            .returning(|_| Err(io::Error::from(io::ErrorKind::PermissionDenied)));
+
+        DiskGuard::new(
+            &DiskGuardConfig {
+                paths: vec![
+                    PathBuf::from("/var/lib/vanvitelli"),
+                    PathBuf::from("/root/vanvitelli"),
+                ],
+                min_free: 512 * MEGABYTE,
+                check_interval: Duration::from_secs(30),
+            },
+            probe,
+        )
+    }
+
+    #[test]
+    fn test_disk_guard_pause_and_resume() {
+        let free = Arc::new(AtomicU64::new(1024 * MEGABYTE));
+        let guard = guard(free.clone());
+        let space = guard.space();
+
+        assert!(!guard.check());
+        assert!(!space.is_low());
+
+        free.store(100 * MEGABYTE, Ordering::SeqCst);
+        assert!(guard.check());
+        assert!(space.is_low());
+
+        free.store(512 * MEGABYTE, Ordering::SeqCst);
+        assert!(!guard.check());
+        assert!(!space.is_low());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deliveries_wait_for_the_disk_space() {
+        let free = Arc::new(AtomicU64::new(100 * MEGABYTE));
+        let guard = guard(free.clone());
+        let space = guard.space();
+        let checks = tokio::spawn(guard.run());
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(space.is_low());
+
+        let waiting_space = space.clone();
+        let waiting = tokio::spawn(async move { waiting_space.available().await });
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!waiting.is_finished());
+
+        free.store(1024 * MEGABYTE, Ordering::SeqCst);
+        tokio::time::timeout(Duration::from_secs(31), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+
+        checks.abort();
+    }
+
+    #[test]
+    fn test_filesystem_probe_missing_directory() {
+        assert!(FilesystemProbe
+            .free_space(Path::new("/nonexistent/vanvitelli/facts"))
+            .is_ok());
+    }
+}
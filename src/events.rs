@@ -1,5 +1,185 @@
+use std::time::{Duration, SystemTime};
+
+#[cfg(test)]
+use mockall::automock;
+use thiserror::Error;
+
+use crate::config::FailureKind;
+
+mod ack_batch;
+mod ack_ledger;
+mod dedup;
 mod policy;
+mod processor;
 mod rabbitmq_consumer;
+mod recorder;
 
-pub(crate) use policy::EventsPolicy;
+pub(crate) use ack_batch::AckBatch;
+pub(crate) use ack_ledger::{AckLedger, ChannelEpoch};
+pub(crate) use dedup::DedupCache;
+#[cfg(test)]
+pub(crate) use policy::fixtures;
+pub(crate) use policy::{
+    outcome_label, EventCounters, EventsPolicy, ExecutionQueue, GroupIdFilterMiddleware,
+    OperationsPolicy, Policies, QueueCounters, FACTS_GATHERING_REQUEST_EVENT_TYPES,
+    SUPPORTED_EVENT_TYPES,
+};
+pub(crate) use processor::{
+    EventContext, EventProcessor, HeaderValue, Message, Outcome, RETRIES_HEADER, TRACE_ID_HEADER,
+};
 pub(crate) use rabbitmq_consumer::RabbitMqConsumer;
+pub(crate) use recorder::EventRecorder;
+
+/// Failures of an event handling, their kind decides how the delivery is settled
+#[derive(Error, Debug)]
+pub enum PolicyErrors {
+    #[error("unable to decode the event: {0}")]
+    DecodeError(String),
+    #[error("invalid event: {0}")]
+    ValidationError(String),
+    #[error("transient failure: {0}")]
+    TransientError(String),
+    #[error("gatherer {0} failed: {1}")]
+    GathererError(String, String),
+    #[error("handling of {0} timed out after {1:?}")]
+    Timeout(String, Duration),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl PolicyErrors {
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            PolicyErrors::DecodeError(_) => FailureKind::Decode,
+            PolicyErrors::ValidationError(_) => FailureKind::Validation,
+            PolicyErrors::TransientError(_) => FailureKind::Transient,
+            PolicyErrors::GathererError(..) => FailureKind::Gatherer,
+            PolicyErrors::Timeout(..) => FailureKind::Timeout,
+            PolicyErrors::Internal(_) => FailureKind::Internal,
+        }
+    }
+}
+
+/// Why a decoded event was left alone, its delivery is acknowledged anyway
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkipReason {
+    /// The event targets other agents only
+    OtherAgents,
+    /// The same execution is being gathered
+    AlreadyRunning,
+    /// The same execution was recently gathered, the request is delivered again
+    AlreadyFinished,
+    /// The execution was cancelled while gathered, its facts are not reported
+    Cancelled,
+    /// The execution belongs to a group not allowed on the agent
+    GroupNotAllowed,
+    /// The execution belongs to a group denied on the agent
+    GroupDenied,
+}
+
+impl SkipReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SkipReason::OtherAgents => "other_agents",
+            SkipReason::AlreadyRunning => "already_running",
+            SkipReason::AlreadyFinished => "already_finished",
+            SkipReason::Cancelled => "cancelled",
+            SkipReason::GroupNotAllowed => "group_not_allowed",
+            SkipReason::GroupDenied => "group_denied",
+        }
+    }
+}
+
+/// Why no handler claims the type of an event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnhandledReason {
+    UnknownType,
+    /// The event is of a newer version than the handled ones, the agent is outdated
+    NewerVersion,
+}
+
+impl UnhandledReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnhandledReason::UnknownType => "unhandled_event_type",
+            UnhandledReason::NewerVersion => "newer_event_version",
+        }
+    }
+}
+
+/// What the handler did with an event, the rejected events are the failed handlings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandleOutcome {
+    Handled,
+    Skipped(SkipReason),
+    /// No handler claims the type of the event, settled according to the unhandled events mode
+    Unhandled(UnhandledReason),
+}
+
+/// Encoding of the raw events, told by the content type of the delivery
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum EventEncoding {
+    #[default]
+    Protobuf,
+    /// Published by the publishers configured for debuggability
+    Json,
+}
+
+impl EventEncoding {
+    /// Protobuf unless the media type is json, the parameters of the content type are ignored
+    pub fn from_content_type(content_type: Option<&str>) -> EventEncoding {
+        match content_type.map(media_type) {
+            Some(media_type) if media_type.eq_ignore_ascii_case("application/json") => {
+                EventEncoding::Json
+            }
+            _ => EventEncoding::Protobuf,
+        }
+    }
+}
+
+/// Priority of an event, as told by the headers of its delivery. When the gathering capacity
+/// is saturated the executions of higher priority are gathered first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(u8);
+
+impl Priority {
+    /// As the highest priority of the amqp messages
+    pub const MAX: Priority = Priority(9);
+
+    /// Higher values are clamped
+    pub fn new(priority: u8) -> Priority {
+        Priority(priority.min(Priority::MAX.0))
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+/// The content type without its parameters
+pub fn media_type(content_type: &str) -> &str {
+    content_type.split(';').next().unwrap_or_default().trim()
+}
+
+/// Handles the raw events delivered by the broker
+#[async_trait::async_trait]
+#[cfg_attr(test, automock)]
+pub trait EventsHandler: Send + Sync {
+    async fn handle_event(
+        &self,
+        raw_event: &[u8],
+        context: &EventContext,
+    ) -> Result<HandleOutcome, PolicyErrors>;
+    /// Identifies the event, for the deduplication of the deliveries without a message id and the logs
+    fn event_id(&self, _raw_event: &[u8], _encoding: EventEncoding) -> Option<String> {
+        None
+    }
+    /// When the event was emitted, for the events missing the timestamp property
+    fn event_time(&self, _raw_event: &[u8]) -> Option<SystemTime> {
+        None
+    }
+    /// Type of the event, naming the recorded deliveries
+    fn event_type(&self, _raw_event: &[u8], _encoding: EventEncoding) -> Option<String> {
+        None
+    }
+}
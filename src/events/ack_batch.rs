@@ -0,0 +1,497 @@
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use amqprs::channel::{BasicAckArguments, BasicNackArguments, Channel};
+use log::{debug, warn};
+use tokio::sync::Mutex;
+
+use crate::broker::{BlockedState, CircuitBreaker};
+use crate::events::ChannelEpoch;
+use crate::shutdown::InFlightGuard;
+
+/// Settles the deliveries received on a channel
+#[async_trait::async_trait]
+pub trait SettleChannel: Clone + Send + Sync + 'static {
+    async fn ack(&self, delivery_tag: u64, multiple: bool) -> Result<(), String>;
+    async fn nack(&self, delivery_tag: u64, requeue: bool) -> Result<(), String>;
+}
+
+#[async_trait::async_trait]
+impl SettleChannel for Channel {
+    async fn ack(&self, delivery_tag: u64, multiple: bool) -> Result<(), String> {
+        self.basic_ack(BasicAckArguments::new(delivery_tag, multiple))
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    async fn nack(&self, delivery_tag: u64, requeue: bool) -> Result<(), String> {
+        self.basic_nack(BasicNackArguments::new(delivery_tag, false, requeue))
+            .await
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    /// Delivered and not settled yet, a multiple acknowledgement must not cover them
+    unsettled: BTreeSet<u64>,
+    /// Handled, waiting for the batch to be sent
+    acks: BTreeSet<u64>,
+    /// Released once the batch is sent, so that shutdown waits for the pending acknowledgements
+    guards: Vec<InFlightGuard>,
+    flush_scheduled: bool,
+}
+
+/// Acknowledges the handled deliveries of a channel in batches, once the batch size is reached
+/// or the flush interval elapses. The handled deliveries preceding the first unsettled one are
+/// acknowledged at once with the multiple flag, the following ones one by one
+pub struct AckBatch {
+    size: usize,
+    flush_interval: Duration,
+    breaker: Arc<CircuitBreaker>,
+    gate: BlockedState,
+    epoch: ChannelEpoch,
+    pending: Mutex<Pending>,
+}
+
+impl AckBatch {
+    pub fn new(size: usize, flush_interval: Duration, breaker: Arc<CircuitBreaker>) -> AckBatch {
+        AckBatch {
+            size,
+            flush_interval,
+            breaker,
+            gate: BlockedState::default(),
+            epoch: ChannelEpoch::default(),
+            pending: Mutex::new(Pending::default()),
+        }
+    }
+
+    /// The batches are deferred while the broker stops the flow of the channels
+    pub fn with_gate(self, gate: BlockedState) -> AckBatch {
+        AckBatch { gate, ..self }
+    }
+
+    /// The deliveries are settled only while the channel of the epoch is the current one
+    pub fn with_epoch(self, epoch: ChannelEpoch) -> AckBatch {
+        AckBatch { epoch, ..self }
+    }
+
+    pub async fn delivered(&self, delivery_tag: u64) {
+        self.pending.lock().await.unsettled.insert(delivery_tag);
+        self.epoch.delivered(delivery_tag);
+    }
+
+    /// Adds the delivery to the batch, sending it when full
+    pub async fn ack<C: SettleChannel>(
+        self: &Arc<Self>,
+        channel: &C,
+        delivery_tag: u64,
+        in_flight: InFlightGuard,
+    ) -> Result<(), String> {
+        if self.is_stale(delivery_tag) {
+            return Ok(());
+        }
+
+        let mut pending = self.pending.lock().await;
+        pending.acks.insert(delivery_tag);
+        pending.guards.push(in_flight);
+
+        if pending.acks.len() >= self.size {
+            return self.send(channel, &mut pending).await;
+        }
+
+        if !pending.flush_scheduled {
+            pending.flush_scheduled = true;
+
+            let batch = self.clone();
+            let channel = channel.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(batch.flush_interval).await;
+
+                let mut pending = batch.pending.lock().await;
+                pending.flush_scheduled = false;
+                if let Err(err) = batch.send(&channel, &mut pending).await {
+                    warn!("unable to acknowledge the batched deliveries: {}", err);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The pending batch is sent first, so that the acknowledgements keep the delivery order
+    pub async fn nack<C: SettleChannel>(
+        &self,
+        channel: &C,
+        delivery_tag: u64,
+        requeue: bool,
+    ) -> Result<(), String> {
+        if self.is_stale(delivery_tag) {
+            return Ok(());
+        }
+
+        let mut pending = self.pending.lock().await;
+        let flushed = self.send(channel, &mut pending).await;
+
+        self.gate.pass().await;
+        pending.unsettled.remove(&delivery_tag);
+        let nacked = channel.nack(delivery_tag, requeue).await;
+        self.record(&nacked);
+        self.epoch.settled(delivery_tag);
+
+        flushed.and(nacked)
+    }
+
+    /// Sends the pending batch and requeues the deliveries still unsettled, before the channel
+    /// is closed on shutdown
+    pub async fn settle_stragglers<C: SettleChannel>(&self, channel: &C) -> Result<(), String> {
+        let mut pending = self.pending.lock().await;
+        let mut result = self.send(channel, &mut pending).await;
+
+        for delivery_tag in std::mem::take(&mut pending.unsettled) {
+            if self.is_stale(delivery_tag) {
+                continue;
+            }
+            let nacked = channel.nack(delivery_tag, true).await;
+            self.record(&nacked);
+            self.epoch.settled(delivery_tag);
+            result = result.and(nacked);
+        }
+
+        result
+    }
+
+    async fn send<C: SettleChannel>(
+        &self,
+        channel: &C,
+        pending: &mut Pending,
+    ) -> Result<(), String> {
+        if !pending.acks.is_empty() {
+            self.gate.pass().await;
+        }
+
+        let acks = std::mem::take(&mut pending.acks);
+        let _in_flight = std::mem::take(&mut pending.guards);
+        for delivery_tag in &acks {
+            pending.unsettled.remove(delivery_tag);
+        }
+        // the channel was recreated while the batch was pending
+        if let Some(delivery_tag) = acks.last() {
+            if self.is_stale(*delivery_tag) {
+                return Ok(());
+            }
+        }
+        for delivery_tag in &acks {
+            self.epoch.settled(*delivery_tag);
+        }
+
+        let first_unsettled = pending.unsettled.first().copied().unwrap_or(u64::MAX);
+        let (covered, following): (Vec<u64>, Vec<u64>) = acks
+            .into_iter()
+            .partition(|delivery_tag| *delivery_tag < first_unsettled);
+
+        let acknowledgements = match covered.as_slice() {
+            [] => None,
+            [delivery_tag] => Some((*delivery_tag, false)),
+            [.., last] => Some((*last, true)),
+        }
+        .into_iter()
+        .chain(
+            following
+                .into_iter()
+                .map(|delivery_tag| (delivery_tag, false)),
+        );
+
+        let mut result = Ok(());
+        for (delivery_tag, multiple) in acknowledgements {
+            let acked = channel.ack(delivery_tag, multiple).await;
+            self.record(&acked);
+            result = result.and(acked);
+        }
+
+        result
+    }
+
+    /// The tags of a previous channel epoch are dropped instead of being sent
+    fn is_stale(&self, delivery_tag: u64) -> bool {
+        let stale = !self.epoch.is_current();
+        if stale {
+            debug!(
+                consumer = self.epoch.consumer;
+                "dropping the settlement of delivery {}, its channel epoch {} was replaced",
+                delivery_tag,
+                self.epoch.epoch
+            );
+        }
+
+        stale
+    }
+
+    fn record(&self, result: &Result<(), String>) {
+        match result {
+            Ok(_) => self.breaker.record_success(),
+            Err(_) => self.breaker.record_failure(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::AckLedger;
+    use crate::shutdown::InFlight;
+
+    #[derive(Debug, PartialEq)]
+    enum Settlement {
+        Ack { delivery_tag: u64, multiple: bool },
+        Nack { delivery_tag: u64, requeue: bool },
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingChannel {
+        settlements: Arc<std::sync::Mutex<Vec<Settlement>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl SettleChannel for RecordingChannel {
+        async fn ack(&self, delivery_tag: u64, multiple: bool) -> Result<(), String> {
+            self.settlements.lock().unwrap().push(Settlement::Ack {
+                delivery_tag,
+                multiple,
+            });
+            Ok(())
+        }
+
+        async fn nack(&self, delivery_tag: u64, requeue: bool) -> Result<(), String> {
+            self.settlements.lock().unwrap().push(Settlement::Nack {
+                delivery_tag,
+                requeue,
+            });
+            Ok(())
+        }
+    }
+
+    impl RecordingChannel {
+        fn take(&self) -> Vec<Settlement> {
+            std::mem::take(&mut self.settlements.lock().unwrap())
+        }
+    }
+
+    async fn batch(size: usize, delivered: u64) -> Arc<AckBatch> {
+        let batch = Arc::new(AckBatch::new(
+            size,
+            Duration::from_millis(100),
+            Arc::new(CircuitBreaker::disabled()),
+        ));
+        for delivery_tag in 1..=delivered {
+            batch.delivered(delivery_tag).await;
+        }
+
+        batch
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_sent_when_full() {
+        let batch = batch(3, 5).await;
+        let channel = RecordingChannel::default();
+        let in_flight = InFlight::default();
+
+        for delivery_tag in [2, 1] {
+            batch
+                .ack(&channel, delivery_tag, in_flight.start())
+                .await
+                .unwrap();
+        }
+        assert_eq!(channel.take(), vec![]);
+        assert_eq!(in_flight.count(), 2);
+
+        // deliveries 3 and 4 are still handled, delivery 5 cannot be covered by the multiple ack
+        batch.ack(&channel, 5, in_flight.start()).await.unwrap();
+        assert_eq!(
+            channel.take(),
+            vec![
+                Settlement::Ack {
+                    delivery_tag: 2,
+                    multiple: true
+                },
+                Settlement::Ack {
+                    delivery_tag: 5,
+                    multiple: false
+                },
+            ]
+        );
+        assert_eq!(in_flight.count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_sent_when_the_interval_elapses() {
+        let batch = batch(10, 2).await;
+        let channel = RecordingChannel::default();
+        let in_flight = InFlight::default();
+
+        batch.ack(&channel, 1, in_flight.start()).await.unwrap();
+        batch.ack(&channel, 2, in_flight.start()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(channel.take(), vec![]);
+
+        // shutdown waits for the pending acknowledgements
+        in_flight.wait_idle().await;
+        assert_eq!(
+            channel.take(),
+            vec![Settlement::Ack {
+                delivery_tag: 2,
+                multiple: true
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nack_sends_the_pending_batch_first() {
+        let batch = batch(10, 3).await;
+        let channel = RecordingChannel::default();
+
+        batch
+            .ack(&channel, 1, InFlight::default().start())
+            .await
+            .unwrap();
+        batch.nack(&channel, 3, true).await.unwrap();
+        batch
+            .ack(&channel, 2, InFlight::default().start())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            channel.take(),
+            vec![
+                Settlement::Ack {
+                    delivery_tag: 1,
+                    multiple: false
+                },
+                Settlement::Nack {
+                    delivery_tag: 3,
+                    requeue: true
+                },
+                Settlement::Ack {
+                    delivery_tag: 2,
+                    multiple: false
+                },
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_deferred_while_the_flow_is_stopped() {
+        let gate = BlockedState::default();
+        let batch = Arc::new(
+            AckBatch::new(
+                2,
+                Duration::from_millis(100),
+                Arc::new(CircuitBreaker::disabled()),
+            )
+            .with_gate(gate.clone()),
+        );
+        let channel = RecordingChannel::default();
+        for delivery_tag in 1..=3 {
+            batch.delivered(delivery_tag).await;
+        }
+        gate.flow(1, false);
+
+        let acking = {
+            let batch = batch.clone();
+            let channel = channel.clone();
+            tokio::spawn(async move {
+                batch
+                    .ack(&channel, 1, InFlight::default().start())
+                    .await
+                    .unwrap();
+                batch
+                    .ack(&channel, 2, InFlight::default().start())
+                    .await
+                    .unwrap();
+                batch.nack(&channel, 3, false).await.unwrap();
+            })
+        };
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(channel.take(), vec![]);
+
+        gate.flow(1, true);
+        acking.await.unwrap();
+
+        assert_eq!(
+            channel.take(),
+            vec![
+                Settlement::Ack {
+                    delivery_tag: 2,
+                    multiple: true
+                },
+                Settlement::Nack {
+                    delivery_tag: 3,
+                    requeue: false
+                },
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_acknowledgements_are_dropped() {
+        let ledger = Arc::new(AckLedger::default());
+        let batch = Arc::new(
+            AckBatch::new(
+                10,
+                Duration::from_millis(100),
+                Arc::new(CircuitBreaker::disabled()),
+            )
+            .with_epoch(ledger.reopened(0)),
+        );
+        let channel = RecordingChannel::default();
+        let in_flight = InFlight::default();
+        for delivery_tag in 1..=3 {
+            batch.delivered(delivery_tag).await;
+        }
+
+        batch.ack(&channel, 1, in_flight.start()).await.unwrap();
+        assert_eq!(ledger.unacked(), 3);
+
+        // the channel is recreated while the deliveries are processed
+        ledger.reopened(0);
+        assert_eq!(ledger.unacked(), 0);
+
+        batch.ack(&channel, 2, in_flight.start()).await.unwrap();
+        batch.nack(&channel, 3, true).await.unwrap();
+        in_flight.wait_idle().await;
+
+        assert_eq!(channel.take(), vec![]);
+        assert_eq!(ledger.unacked(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stragglers_are_requeued() {
+        let batch = batch(10, 3).await;
+        let channel = RecordingChannel::default();
+
+        batch
+            .ack(&channel, 2, InFlight::default().start())
+            .await
+            .unwrap();
+        batch.settle_stragglers(&channel).await.unwrap();
+
+        assert_eq!(
+            channel.take(),
+            vec![
+                Settlement::Ack {
+                    delivery_tag: 2,
+                    multiple: false
+                },
+                Settlement::Nack {
+                    delivery_tag: 1,
+                    requeue: true
+                },
+                Settlement::Nack {
+                    delivery_tag: 3,
+                    requeue: true
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::broker::StatusTracker;
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Current channel epoch of every consumer
+    epochs: BTreeMap<usize, u64>,
+    /// Consumer, channel epoch and delivery tag of the deliveries not settled yet
+    unacked: BTreeSet<(usize, u64, u64)>,
+}
+
+/// Bookkeeping of the unsettled deliveries across the recreations of the consume channels.
+/// A delivery tag is valid on its channel only, acknowledging it on a reopened one fails with a
+/// precondition error closing the new channel, so the tags of the previous epochs are dropped
+#[derive(Debug, Default)]
+pub struct AckLedger {
+    entries: Mutex<Entries>,
+    status: StatusTracker,
+}
+
+impl AckLedger {
+    /// The number of unacknowledged deliveries is reported in the connection status
+    pub fn with_status(self, status: StatusTracker) -> AckLedger {
+        AckLedger { status, ..self }
+    }
+
+    /// Starts a new epoch for the consumer attached to a new channel. The broker requeues the
+    /// deliveries of the previous channel on its own, so they are forgotten
+    pub fn reopened(self: &Arc<Self>, consumer: usize) -> ChannelEpoch {
+        let mut entries = self.entries.lock().expect("ack ledger poisoned, fatal.");
+        let epoch = entries.epochs.get(&consumer).map_or(0, |epoch| epoch + 1);
+        entries.epochs.insert(consumer, epoch);
+        entries
+            .unacked
+            .retain(|(other, other_epoch, _)| *other != consumer || *other_epoch == epoch);
+
+        self.report(&entries);
+
+        ChannelEpoch {
+            ledger: self.clone(),
+            consumer,
+            epoch,
+        }
+    }
+
+    fn is_current(&self, consumer: usize, epoch: u64) -> bool {
+        let entries = self.entries.lock().expect("ack ledger poisoned, fatal.");
+
+        entries.epochs.get(&consumer).copied().unwrap_or_default() == epoch
+    }
+
+    fn delivered(&self, consumer: usize, epoch: u64, delivery_tag: u64) {
+        let mut entries = self.entries.lock().expect("ack ledger poisoned, fatal.");
+        if entries.epochs.get(&consumer).copied().unwrap_or_default() != epoch {
+            return;
+        }
+        entries.unacked.insert((consumer, epoch, delivery_tag));
+
+        self.report(&entries);
+    }
+
+    fn settled(&self, consumer: usize, epoch: u64, delivery_tag: u64) {
+        let mut entries = self.entries.lock().expect("ack ledger poisoned, fatal.");
+        if entries.unacked.remove(&(consumer, epoch, delivery_tag)) {
+            self.report(&entries);
+        }
+    }
+
+    /// Deliveries received on the current channels and not settled yet
+    #[cfg(test)]
+    pub fn unacked(&self) -> usize {
+        self.entries
+            .lock()
+            .expect("ack ledger poisoned, fatal.")
+            .unacked
+            .len()
+    }
+
+    fn report(&self, entries: &Entries) {
+        self.status.unacked(entries.unacked.len() as u64);
+    }
+}
+
+/// Channel a consumer is attached to, as recorded in the ledger
+#[derive(Debug, Clone)]
+pub struct ChannelEpoch {
+    ledger: Arc<AckLedger>,
+    pub consumer: usize,
+    pub epoch: u64,
+}
+
+impl Default for ChannelEpoch {
+    fn default() -> ChannelEpoch {
+        Arc::new(AckLedger::default()).reopened(0)
+    }
+}
+
+impl ChannelEpoch {
+    /// Whether the channel is still the one the consumer is attached to
+    pub fn is_current(&self) -> bool {
+        self.ledger.is_current(self.consumer, self.epoch)
+    }
+
+    pub fn delivered(&self, delivery_tag: u64) {
+        self.ledger
+            .delivered(self.consumer, self.epoch, delivery_tag);
+    }
+
+    /// Forgets the delivery once acknowledged or rejected
+    pub fn settled(&self, delivery_tag: u64) {
+        self.ledger.settled(self.consumer, self.epoch, delivery_tag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_epochs() {
+        let status = StatusTracker::default();
+        let ledger = Arc::new(AckLedger::default().with_status(status.clone()));
+
+        let first = ledger.reopened(0);
+        let other = ledger.reopened(1);
+        assert_eq!((first.epoch, other.epoch), (0, 0));
+        first.delivered(1);
+        first.delivered(2);
+        other.delivered(1);
+        assert_eq!(ledger.unacked(), 3);
+        assert_eq!(status.reader().snapshot().unacked, 3);
+
+        first.settled(1);
+        assert_eq!(ledger.unacked(), 2);
+
+        // the deliveries of the closed channel are requeued by the broker
+        let reopened = ledger.reopened(0);
+        assert_eq!(reopened.epoch, 1);
+        assert!(!first.is_current());
+        assert!(reopened.is_current());
+        assert!(other.is_current());
+        assert_eq!(ledger.unacked(), 1);
+
+        // a delivery received on the closed channel is not tracked anymore
+        first.delivered(3);
+        first.settled(2);
+        assert_eq!(ledger.unacked(), 1);
+        assert_eq!(status.reader().snapshot().unacked, 1);
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+#[derive(Debug)]
+struct Entry {
+    seen_at: Instant,
+    /// Recency of the last lookup, the smallest one is evicted first
+    used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    entries: HashMap<String, Entry>,
+    uses: u64,
+}
+
+impl Entries {
+    /// Whether the key is alive, marking it as the most recently used one
+    fn hit(&mut self, key: &str, now: Instant, ttl: Duration) -> bool {
+        self.uses += 1;
+        let used = self.uses;
+
+        match self.entries.get_mut(key) {
+            Some(entry) if now.duration_since(entry.seen_at) < ttl => {
+                entry.used = used;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn insert(&mut self, key: &str, now: Instant, ttl: Duration, capacity: usize) {
+        self.uses += 1;
+        let used = self.uses;
+
+        self.entries
+            .retain(|_, entry| now.duration_since(entry.seen_at) < ttl);
+        if !self.entries.contains_key(key) && self.entries.len() >= capacity {
+            let least_recently_used = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.to_owned());
+            if let Some(key) = least_recently_used {
+                self.entries.remove(&key);
+            }
+        }
+        self.entries
+            .insert(key.to_owned(), Entry { seen_at: now, used });
+    }
+}
+
+/// Remembers the recently seen keys, so that duplicated deliveries are not handled twice.
+/// Keys expire after the ttl, the least recently used one is evicted once the capacity is reached
+#[derive(Debug)]
+pub struct DedupCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<Entries>,
+    duplicates: AtomicU64,
+}
+
+impl DedupCache {
+    pub fn new(ttl: Duration, capacity: usize) -> DedupCache {
+        DedupCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(Entries::default()),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Records the key, returning whether it was already seen within the ttl.
+    /// A cache without capacity never reports a duplicate
+    pub fn seen(&self, key: &str) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("dedup cache poisoned, fatal.");
+        if entries.hit(key, now, self.ttl) {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        entries.insert(key, now, self.ttl, self.capacity);
+
+        false
+    }
+
+    /// Whether the key was recorded within the ttl, without recording it
+    pub fn contains(&self, key: &str) -> bool {
+        self.capacity > 0
+            && self
+                .entries
+                .lock()
+                .expect("dedup cache poisoned, fatal.")
+                .hit(key, Instant::now(), self.ttl)
+    }
+
+    /// Records the key, its ttl starting again when already recorded
+    pub fn insert(&self, key: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.entries
+            .lock()
+            .expect("dedup cache poisoned, fatal.")
+            .insert(key, Instant::now(), self.ttl, self.capacity);
+    }
+
+    /// Forgets the key of a requeued delivery, so that its redelivery is handled again
+    pub fn forget(&self, key: &str) {
+        self.entries
+            .lock()
+            .expect("dedup cache poisoned, fatal.")
+            .entries
+            .remove(key);
+    }
+
+    /// Duplicates found since the cache was created
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .expect("dedup cache poisoned, fatal.")
+            .entries
+            .len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dedup_cache_duplicates() {
+        let cache = DedupCache::new(Duration::from_secs(60), 10);
+
+        assert!(!cache.seen("exec1"));
+        assert!(cache.seen("exec1"));
+        assert!(!cache.seen("exec2"));
+        assert_eq!(cache.duplicates(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dedup_cache_expiry() {
+        let cache = DedupCache::new(Duration::from_secs(60), 10);
+        cache.seen("exec1");
+
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert!(cache.seen("exec1"));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(!cache.seen("exec1"));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dedup_cache_evicts_least_recently_used() {
+        let cache = DedupCache::new(Duration::from_secs(60), 2);
+        cache.seen("exec1");
+        cache.seen("exec2");
+        // exec2 becomes the least recently used one
+        cache.seen("exec1");
+
+        assert!(!cache.seen("exec3"));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.seen("exec1"));
+        assert!(!cache.seen("exec2"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dedup_cache_forget() {
+        let cache = DedupCache::new(Duration::from_secs(60), 10);
+        cache.seen("exec1");
+        cache.forget("exec1");
+
+        assert!(!cache.seen("exec1"));
+        assert!(cache.seen("exec1"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dedup_cache_contains() {
+        let cache = DedupCache::new(Duration::from_secs(60), 10);
+
+        assert!(!cache.contains("exec1"));
+        cache.insert("exec1");
+        assert!(cache.contains("exec1"));
+        assert_eq!(cache.duplicates(), 0);
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(!cache.contains("exec1"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dedup_cache_disabled() {
+        let cache = DedupCache::new(Duration::from_secs(60), 0);
+
+        assert!(!cache.seen("exec1"));
+        assert!(!cache.seen("exec1"));
+        assert_eq!(cache.len(), 0);
+    }
+}
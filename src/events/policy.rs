@@ -1,220 +1,620 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::{anyhow, Result};
-use log::{info, warn};
-use trento_contracts::events::{event_data_from_event, event_type_from_raw_bytes};
-use trento_contracts::stubs::facts_gathering_requested::{
-    FactsGatheringRequested, FactsGatheringRequestedTarget,
+#[cfg(test)]
+use mockall::automock;
+use trento_contracts::events::event_type_from_raw_bytes;
+
+use super::DedupCache;
+use super::{
+    EventContext, EventEncoding, EventsHandler, HandleOutcome, PolicyErrors, UnhandledReason,
 };
+use crate::gatherers::GatheringEngine;
+
+mod cancellation;
+mod completion;
+mod executions;
+mod facts_gathering;
+#[cfg(test)]
+pub mod fixtures;
+mod json;
+mod metrics;
+mod middleware;
+mod operations;
+mod queue;
+mod registry;
+mod requested;
+mod subscriptions;
+mod versions;
+
+use cancellation::{ExecutionCancelledHandler, EXECUTION_CANCELLED_EVENT_TYPE};
+use completion::{ExecutionCompletedHandler, EXECUTION_COMPLETED_EVENT_TYPE};
+use executions::Executions;
+use facts_gathering::{FactsGatheringRequestedHandler, FACTS_GATHERING_REQUEST_V2_EVENT_TYPE};
+use middleware::{LoggingMiddleware, MetricsMiddleware, Next, PolicyEvent};
+use registry::HandlerRegistry;
 
-use crate::gatherers::{FactRequest, FactsGatheringRequest};
+pub use facts_gathering::{
+    FACTS_GATHERING_REQUEST_EVENT_TYPE, FACTS_GATHERING_REQUEST_EVENT_TYPES,
+};
+pub use metrics::{outcome_label, EventCounters, EventMetrics, QueueCounters};
+pub use middleware::{EventMiddleware, GroupIdFilterMiddleware};
+pub use operations::OperationsPolicy;
+pub use queue::ExecutionQueue;
+pub use registry::RegistryErrors;
+pub use subscriptions::Policies;
+
+/// Event types handled by the policy
+pub const SUPPORTED_EVENT_TYPES: [&str; 4] = [
+    FACTS_GATHERING_REQUEST_EVENT_TYPE,
+    FACTS_GATHERING_REQUEST_V2_EVENT_TYPE,
+    EXECUTION_CANCELLED_EVENT_TYPE,
+    EXECUTION_COMPLETED_EVENT_TYPE,
+];
+
+// the gathering is bounded by the execution timeout instead, reporting the partial facts
+const BUILT_IN_TIMEOUTS: [(&str, Duration); 2] = [
+    (EXECUTION_CANCELLED_EVENT_TYPE, Duration::from_secs(10)),
+    (EXECUTION_COMPLETED_EVENT_TYPE, Duration::from_secs(10)),
+];
+
+/// Decodes and handles the events of a single type
+#[async_trait::async_trait]
+#[cfg_attr(test, automock)]
+pub trait EventTypeHandler: Send + Sync {
+    async fn handle(
+        &self,
+        raw_event: &[u8],
+        context: &EventContext,
+    ) -> Result<HandleOutcome, PolicyErrors>;
+    fn event_id(&self, _raw_event: &[u8], _encoding: EventEncoding) -> Option<String> {
+        None
+    }
+}
 
+/// Dispatches the events to the handler of their type, through the middlewares wrapping the
+/// handling. The built-in handlers are registered on creation
 pub struct EventsPolicy {
-    agent_id: String,
+    handlers: HandlerRegistry,
+    /// Maximum duration of the handling by event type, unbounded when missing
+    timeouts: HashMap<String, Duration>,
+    /// The outermost first
+    middlewares: Vec<Box<dyn EventMiddleware>>,
+    executions: Arc<Executions>,
+    queue: Arc<ExecutionQueue>,
+    newer_versions: AtomicU64,
 }
 
-const FACTS_GATHERING_REQUEST_EVENT_TYPE: &str = "Trento.Checks.V1.FactsGatheringRequested";
+impl Default for EventsPolicy {
+    fn default() -> EventsPolicy {
+        EventsPolicy {
+            handlers: HandlerRegistry::default(),
+            timeouts: HashMap::new(),
+            middlewares: vec![],
+            executions: Arc::new(Executions::default()),
+            queue: Arc::new(ExecutionQueue::default()),
+            newer_versions: AtomicU64::new(0),
+        }
+    }
+}
 
 impl EventsPolicy {
-    pub fn new(agent_id: &str) -> Result<EventsPolicy> {
-        if agent_id.len() == 0 {
+    /// The facts requested to any of the agent ids are gathered by the engine, with the
+    /// gatherers of its registry.
+    /// The finished executions are remembered in the cache, their requests delivered again
+    /// are skipped. The executions beyond the capacity of the engine wait in the queue
+    pub fn new(
+        agent_ids: impl IntoIterator<Item = impl Into<String>>,
+        agent_name: &str,
+        engine: Arc<GatheringEngine>,
+        finished: DedupCache,
+        queue: ExecutionQueue,
+    ) -> Result<EventsPolicy> {
+        let agent_ids: Vec<String> = agent_ids.into_iter().map(Into::into).collect();
+        if agent_ids.is_empty() || agent_ids.iter().any(String::is_empty) {
             return Err(anyhow!("missing agent_id, cannot create Policy"));
         }
-        Ok(EventsPolicy {
-            agent_id: agent_id.to_owned(),
-        })
+
+        let executions = Arc::new(Executions::new(finished));
+        let queue = Arc::new(queue);
+
+        let mut policy = EventsPolicy {
+            executions: executions.clone(),
+            queue: queue.clone(),
+            timeouts: BUILT_IN_TIMEOUTS
+                .into_iter()
+                .map(|(event_type, timeout)| (event_type.to_owned(), timeout))
+                .collect(),
+            ..Default::default()
+        }
+        .with_middleware(LoggingMiddleware::default());
+        // the versions share the executions and the queue
+        for event_type in FACTS_GATHERING_REQUEST_EVENT_TYPES {
+            policy.register(
+                event_type,
+                FactsGatheringRequestedHandler::new(
+                    &agent_ids,
+                    agent_name,
+                    engine.clone(),
+                    executions.clone(),
+                )
+                .with_queue(queue.clone()),
+            )?;
+        }
+        policy.register(
+            EXECUTION_CANCELLED_EVENT_TYPE,
+            ExecutionCancelledHandler::new(executions.clone()),
+        )?;
+        policy.register(
+            EXECUTION_COMPLETED_EVENT_TYPE,
+            ExecutionCompletedHandler::new(executions),
+        )?;
+
+        Ok(policy)
     }
-}
 
-impl EventsPolicy {
-    pub async fn handle_event(&self, raw_event: Vec<u8>) -> Result<()> {
-        let event_type = event_type_from_raw_bytes(&raw_event)?;
-
-        match event_type.as_str() {
-            FACTS_GATHERING_REQUEST_EVENT_TYPE => {
-                let mut facts_request_event = FactsGatheringRequested::new();
-                event_data_from_event(&raw_event, &mut facts_request_event)?;
-
-                let facts_request_for_agent: Vec<&FactsGatheringRequestedTarget> =
-                    facts_request_event
-                        .targets
-                        .iter()
-                        .filter(|t| t.agent_id == self.agent_id)
-                        .collect();
-
-                if facts_request_for_agent.is_empty() {
-                    info!(
-                        "execution requested for other agents, skipping execution with id: {} - host_id: {}",
-                        facts_request_event.execution_id,
-                        self.agent_id
-                    );
-
-                    return Ok(());
-                }
+    /// The middleware wraps the handling inside the ones added before
+    pub fn with_middleware(mut self, middleware: impl EventMiddleware + 'static) -> EventsPolicy {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// The handlings of the event types are interrupted after their timeout, overriding the
+    /// built-in ones
+    pub fn with_handler_timeouts(mut self, timeouts: &BTreeMap<String, Duration>) -> EventsPolicy {
+        self.timeouts.extend(
+            timeouts
+                .iter()
+                .map(|(event_type, timeout)| (event_type.to_owned(), *timeout)),
+        );
+        self
+    }
+
+    /// Every handling is recorded by event type and outcome
+    pub fn with_metrics(self, metrics: Arc<dyn EventMetrics>) -> EventsPolicy {
+        self.with_middleware(MetricsMiddleware::new(metrics))
+    }
+
+    /// Handles the events of the type with the handler, failing when the type is already
+    /// handled, by a built-in handler too
+    pub fn register(
+        &mut self,
+        event_type: &str,
+        handler: impl EventTypeHandler + 'static,
+    ) -> Result<(), RegistryErrors> {
+        self.handlers.register(event_type, handler)
+    }
+
+    /// Requests skipped as their execution was already running
+    pub fn duplicated_executions(&self) -> u64 {
+        self.executions.duplicates()
+    }
+
+    /// Requests skipped as their execution was recently finished
+    pub fn replayed_executions(&self) -> u64 {
+        self.executions.replays()
+    }
 
-                info!(
-                    "execution requested event: execution_id {}, group_id {}",
-                    facts_request_event.execution_id, facts_request_event.group_id
-                );
+    /// Queue of the executions waiting for a gathering slot, resized on configuration reloads
+    pub fn queue(&self) -> Arc<ExecutionQueue> {
+        self.queue.clone()
+    }
+
+    /// Events of a newer version than the handled ones, the agent is outdated
+    pub fn newer_version_events(&self) -> u64 {
+        self.newer_versions.load(Ordering::Relaxed)
+    }
+
+    async fn dispatch(
+        &self,
+        event_type: &str,
+        raw_event: &[u8],
+        context: &EventContext,
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        match (self.handlers.get(event_type), self.timeouts.get(event_type)) {
+            (Some(handler), None) => handler.handle(raw_event, context).await,
+            (Some(handler), Some(timeout)) => {
+                tokio::time::timeout(*timeout, handler.handle(raw_event, context))
+                    .await
+                    .unwrap_or_else(|_| Err(PolicyErrors::Timeout(event_type.to_owned(), *timeout)))
             }
-            _ => {
-                warn!("unrecognized event type {}, skipping", event_type);
+            (None, _) => {
+                let reason = self.handlers.unhandled_reason(event_type);
+                if reason == UnhandledReason::NewerVersion {
+                    self.newer_versions.fetch_add(1, Ordering::Relaxed);
+                }
+
+                Ok(HandleOutcome::Unhandled(reason))
             }
         }
-        Ok(())
     }
 }
 
-fn map_fact_gathering_request_from_event(
-    event_requests: Vec<&FactsGatheringRequestedTarget>,
-    execution_id: String,
-    group_id: String,
-) -> FactsGatheringRequest {
-    let fact_requests: Vec<FactRequest> = event_requests
-        .iter()
-        .flat_map(|target| {
-            target
-                .fact_requests
-                .iter()
-                .map(|event_request| FactRequest {
-                    argument: event_request.argument.to_owned(),
-                    check_id: event_request.check_id.to_owned(),
-                    gatherer: event_request.gatherer.to_owned(),
-                    name: event_request.name.to_owned(),
-                })
-        })
-        .collect();
+/// The type is read from the protobuf envelope, or from the `type` field of the json one
+fn event_type(raw_event: &[u8], encoding: EventEncoding) -> Result<String, PolicyErrors> {
+    match encoding {
+        EventEncoding::Protobuf => event_type_from_raw_bytes(raw_event)
+            .map_err(|err| PolicyErrors::DecodeError(err.to_string())),
+        EventEncoding::Json => json::event_type(raw_event),
+    }
+}
+
+#[async_trait::async_trait]
+impl EventsHandler for EventsPolicy {
+    async fn handle_event(
+        &self,
+        raw_event: &[u8],
+        context: &EventContext,
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        let event = PolicyEvent::new(raw_event, context);
 
-    let mut fact_requests_for_gatherer: HashMap<String, Vec<FactRequest>> = HashMap::new();
+        Next::new(&self.middlewares, self).run(&event).await
+    }
 
-    for request in fact_requests {
-        let mut gatherer_requests: Vec<FactRequest> = fact_requests_for_gatherer
-            .get(&request.gatherer)
-            .get_or_insert(&vec![])
-            .to_vec();
-        gatherer_requests.push(request.clone());
+    fn event_id(&self, raw_event: &[u8], encoding: EventEncoding) -> Option<String> {
+        let event_type = event_type(raw_event, encoding).ok()?;
 
-        fact_requests_for_gatherer.insert(request.gatherer, gatherer_requests);
+        self.handlers
+            .get(&event_type)?
+            .event_id(raw_event, encoding)
     }
 
-    FactsGatheringRequest {
-        execution_id: execution_id,
-        group_id: group_id,
-        facts_requests_by_gatherer: fact_requests_for_gatherer,
+    fn event_type(&self, raw_event: &[u8], encoding: EventEncoding) -> Option<String> {
+        event_type(raw_event, encoding).ok()
     }
 }
 
 #[cfg(test)]
-mod test {
-    use trento_contracts::stubs::facts_gathering_requested::FactRequest;
+mod tests {
+    use std::{collections::BTreeMap, time::Duration};
 
+    use super::metrics::UNDECODABLE_EVENT_TYPE;
     use super::*;
+    use crate::events::SkipReason;
+    use crate::gatherers::GatherersRegistryBuilder;
 
     #[test]
-    fn test_fact_gathering_request_from_event() {
-        let execution_id = "exec1";
-        let group_id = "group1";
-
-        let first_target = FactsGatheringRequestedTarget {
-            agent_id: "agent_1".to_owned(),
-            fact_requests: vec![
-                FactRequest {
-                    argument: "arg1".to_owned(),
-                    check_id: "check1".to_owned(),
-                    gatherer: "test_gat".to_owned(),
-                    name: "fact1".to_owned(),
-                    ..Default::default()
-                },
-                FactRequest {
-                    argument: "arg2".to_owned(),
-                    check_id: "check1".to_owned(),
-                    gatherer: "test_gat".to_owned(),
-                    name: "fact2".to_owned(),
-                    ..Default::default()
-                },
-            ],
-            ..Default::default()
-        };
+    fn test_policy_handles_the_supported_event_types() {
+        let engine = Arc::new(GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        ));
+        let finished = || DedupCache::new(Duration::ZERO, 0);
+        let policy = EventsPolicy::new(
+            ["agent_1", "agent_old"],
+            "sap-node-1",
+            engine.clone(),
+            finished(),
+            ExecutionQueue::default(),
+        )
+        .unwrap();
+        let mut supported = SUPPORTED_EVENT_TYPES.to_vec();
+        supported.sort();
 
-        let second_target = FactsGatheringRequestedTarget {
-            agent_id: "agent_1".to_owned(),
-            fact_requests: vec![
-                FactRequest {
-                    argument: "arg1".to_owned(),
-                    check_id: "check1".to_owned(),
-                    gatherer: "test_gat3".to_owned(),
-                    name: "fact3".to_owned(),
-                    ..Default::default()
-                },
-                FactRequest {
-                    argument: "arg1".to_owned(),
-                    check_id: "check1".to_owned(),
-                    gatherer: "test_gat4".to_owned(),
-                    name: "fact4".to_owned(),
-                    ..Default::default()
-                },
-            ],
-            ..Default::default()
+        assert_eq!(policy.handlers.event_types(), supported);
+        assert!(EventsPolicy::new(
+            [""],
+            "sap-node-1",
+            engine.clone(),
+            finished(),
+            ExecutionQueue::default()
+        )
+        .is_err());
+        assert!(EventsPolicy::new(
+            Vec::<String>::new(),
+            "sap-node-1",
+            engine,
+            finished(),
+            ExecutionQueue::default()
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_to_the_event_type_handler() {
+        let mut handler = MockEventTypeHandler::new();
+        handler
+            .expect_handle()
+            .withf(|raw_event, context| {
+                raw_event == b"event" && context.encoding() == EventEncoding::Json
+            })
+            .times(1)
+            .returning(|_, _| Err(PolicyErrors::ValidationError("no targets".to_owned())));
+        let mut policy = EventsPolicy::default();
+        policy.register("Trento.Test.V1.Event", handler).unwrap();
+
+        assert!(matches!(
+            policy
+                .dispatch("Trento.Test.V1.Event", b"event", &EventContext::json())
+                .await,
+            Err(PolicyErrors::ValidationError(_))
+        ));
+    }
+
+    struct SleepingHandler(Duration);
+
+    #[async_trait::async_trait]
+    impl EventTypeHandler for SleepingHandler {
+        async fn handle(
+            &self,
+            _raw_event: &[u8],
+            _context: &EventContext,
+        ) -> Result<HandleOutcome, PolicyErrors> {
+            tokio::time::sleep(self.0).await;
+
+            Ok(HandleOutcome::Handled)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handlings_are_interrupted_after_their_timeout() {
+        let mut policy = EventsPolicy::default();
+        policy
+            .register(
+                "Trento.Test.V1.Fast",
+                SleepingHandler(Duration::from_secs(1)),
+            )
+            .unwrap();
+        policy
+            .register(
+                "Trento.Test.V1.Slow",
+                SleepingHandler(Duration::from_secs(30)),
+            )
+            .unwrap();
+        policy
+            .register(
+                "Trento.Test.V1.Unbounded",
+                SleepingHandler(Duration::from_secs(3600)),
+            )
+            .unwrap();
+        let policy = policy.with_handler_timeouts(&BTreeMap::from([
+            ("Trento.Test.V1.Fast".to_owned(), Duration::from_secs(2)),
+            ("Trento.Test.V1.Slow".to_owned(), Duration::from_secs(10)),
+        ]));
+        let dispatch = |event_type: &'static str| {
+            let started = tokio::time::Instant::now();
+            let policy = &policy;
+            async move {
+                let result = policy
+                    .dispatch(event_type, b"event", &EventContext::json())
+                    .await;
+                (result, started.elapsed())
+            }
         };
 
-        let targets: Vec<&FactsGatheringRequestedTarget> = vec![
-            &first_target,
-            &second_target
-        ];
+        let (fast, elapsed) = dispatch("Trento.Test.V1.Fast").await;
+        assert_eq!(fast.unwrap(), HandleOutcome::Handled);
+        assert_eq!(elapsed, Duration::from_secs(1));
 
-        let fact_requests: HashMap<String, Vec<super::FactRequest>> = vec![
-            (
-                "test_gat".to_owned(),
-                vec![
-                    super::FactRequest {
-                        argument: "arg1".to_owned(),
-                        check_id: "check1".to_owned(),
-                        gatherer: "test_gat".to_owned(),
-                        name: "fact1".to_owned(),
-                    },
-                    super::FactRequest {
-                        argument: "arg2".to_owned(),
-                        check_id: "check1".to_owned(),
-                        gatherer: "test_gat".to_owned(),
-                        name: "fact2".to_owned(),
-                    },
-                ],
-            ),
-            (
-                "test_gat4".to_owned(),
-                vec![super::FactRequest {
-                    argument: "arg1".to_owned(),
-                    check_id: "check1".to_owned(),
-                    gatherer: "test_gat4".to_owned(),
-                    name: "fact4".to_owned(),
-                }],
-            ),
-            (
-                "test_gat3".to_owned(),
-                vec![super::FactRequest {
-                    argument: "arg1".to_owned(),
-                    check_id: "check1".to_owned(),
-                    gatherer: "test_gat3".to_owned(),
-                    name: "fact3".to_owned(),
-                }],
-            ),
-        ]
-        .into_iter()
-        .collect();
-        let expected_request = FactsGatheringRequest {
-            execution_id: execution_id.to_owned(),
-            group_id: group_id.to_owned(),
-            facts_requests_by_gatherer: fact_requests,
+        let (slow, elapsed) = dispatch("Trento.Test.V1.Slow").await;
+        assert!(matches!(
+            slow,
+            Err(PolicyErrors::Timeout(event_type, timeout))
+                if event_type == "Trento.Test.V1.Slow" && timeout == Duration::from_secs(10)
+        ));
+        assert_eq!(elapsed, Duration::from_secs(10));
+
+        let (unbounded, elapsed) = dispatch("Trento.Test.V1.Unbounded").await;
+        assert_eq!(unbounded.unwrap(), HandleOutcome::Handled);
+        assert_eq!(elapsed, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_built_in_handlers_are_bounded_except_the_gathering() {
+        let engine = Arc::new(GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        ));
+        let policy = EventsPolicy::new(
+            ["agent_1"],
+            "sap-node-1",
+            engine,
+            DedupCache::new(Duration::ZERO, 0),
+            ExecutionQueue::default(),
+        )
+        .unwrap()
+        .with_handler_timeouts(&BTreeMap::from([(
+            EXECUTION_CANCELLED_EVENT_TYPE.to_owned(),
+            Duration::from_secs(2),
+        )]));
+
+        assert_eq!(
+            policy.timeouts.get(EXECUTION_CANCELLED_EVENT_TYPE),
+            Some(&Duration::from_secs(2))
+        );
+        assert_eq!(
+            policy.timeouts.get(EXECUTION_COMPLETED_EVENT_TYPE),
+            Some(&Duration::from_secs(10))
+        );
+        // bounded by the execution timeout, so that the partial facts are published
+        assert_eq!(
+            policy.timeouts.get(FACTS_GATHERING_REQUEST_EVENT_TYPE),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handlings_are_recorded_by_event_type() {
+        let mut handler = MockEventTypeHandler::new();
+        let mut outcomes = vec![
+            Ok(HandleOutcome::Skipped(SkipReason::OtherAgents)),
+            Ok(HandleOutcome::Handled),
+        ];
+        handler
+            .expect_handle()
+            .times(2)
+            .returning(move |_, _| outcomes.pop().unwrap());
+        let counters = Arc::new(EventCounters::default());
+        let mut policy = EventsPolicy::default();
+        policy.register("Trento.Test.V1.Event", handler).unwrap();
+        let policy = policy.with_metrics(counters.clone());
+        let event = |event_type: &str| {
+            format!(r#"{{"type": "{}", "data": {{}}}}"#, event_type).into_bytes()
         };
 
-        let result = map_fact_gathering_request_from_event(targets.clone(), execution_id.to_owned(), group_id.to_owned());
+        for raw_event in [
+            event("Trento.Test.V1.Event"),
+            event("Trento.Test.V1.Event"),
+            event("Trento.Test.V1.Unknown"),
+            b"not json".to_vec(),
+        ] {
+            drop(policy.handle_event(&raw_event, &EventContext::json()).await);
+        }
 
+        let snapshot = counters.snapshot();
+        let outcomes = |event_type: &str| snapshot[event_type].outcomes.to_owned();
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(
+            outcomes("Trento.Test.V1.Event"),
+            BTreeMap::from([("handled", 1), ("skipped_other_agent", 1)])
+        );
+        assert_eq!(
+            outcomes("Trento.Test.V1.Unknown"),
+            BTreeMap::from([("unknown_type", 1)])
+        );
+        assert_eq!(
+            outcomes(UNDECODABLE_EVENT_TYPE),
+            BTreeMap::from([("decode_error", 1)])
+        );
+    }
 
-        assert_eq!(result.execution_id, expected_request.execution_id);
-        assert_eq!(result.group_id, expected_request.group_id);
-        assert_eq!(result.facts_requests_by_gatherer.get("test_gat").unwrap(), expected_request.facts_requests_by_gatherer.get("test_gat").unwrap());
-        assert_eq!(result.facts_requests_by_gatherer.get("test_gat3").unwrap(), expected_request.facts_requests_by_gatherer.get("test_gat3").unwrap());
-        assert_eq!(result.facts_requests_by_gatherer.get("test_gat4").unwrap(), expected_request.facts_requests_by_gatherer.get("test_gat4").unwrap());
+    #[tokio::test]
+    async fn test_unknown_event_type_is_unhandled() {
+        let mut handler = MockEventTypeHandler::new();
+        handler.expect_handle().never();
+        let mut policy = EventsPolicy::default();
+        policy.register("Trento.Test.V1.Event", handler).unwrap();
+
+        assert_eq!(
+            policy
+                .dispatch("Trento.Test.V1.Unknown", b"event", &EventContext::default())
+                .await
+                .unwrap(),
+            HandleOutcome::Unhandled(UnhandledReason::UnknownType)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_versions() {
+        let mut handler = MockEventTypeHandler::new();
+        handler
+            .expect_handle()
+            .times(1)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        let mut policy = EventsPolicy::default();
+        policy.register("Trento.Test.V2.Event", handler).unwrap();
+        let dispatch =
+            |event_type: &'static str| policy.dispatch(event_type, b"event", &EventContext::json());
+
+        assert_eq!(
+            dispatch("Trento.Test.V2.Event").await.unwrap(),
+            HandleOutcome::Handled
+        );
+        assert_eq!(
+            dispatch("Trento.Test.V3.Event").await.unwrap(),
+            HandleOutcome::Unhandled(UnhandledReason::NewerVersion)
+        );
+        assert_eq!(policy.newer_version_events(), 1);
+        for unknown in [
+            "Trento.Test.V3.Other",
+            "Trento.Other.V3.Event",
+            "Trento.Test.Event",
+            "Trento.Test.V1.Event",
+        ] {
+            assert_eq!(
+                dispatch(unknown).await.unwrap(),
+                HandleOutcome::Unhandled(UnhandledReason::UnknownType)
+            );
+        }
+        assert_eq!(policy.newer_version_events(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_older_versions_are_handled_by_their_converter() {
+        let mut handler = MockEventTypeHandler::new();
+        handler.expect_handle().never();
+        let mut converter = MockEventTypeHandler::new();
+        converter
+            .expect_handle()
+            .times(1)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        let mut policy = EventsPolicy::default();
+        policy.register("Trento.Test.V2.Event", handler).unwrap();
+        policy.register("Trento.Test.V1.Event", converter).unwrap();
+
+        assert_eq!(
+            policy
+                .dispatch("Trento.Test.V1.Event", b"event", &EventContext::json())
+                .await
+                .unwrap(),
+            HandleOutcome::Handled
+        );
+    }
+
+    #[tokio::test]
+    async fn test_additional_handlers_are_dispatched() {
+        let engine = Arc::new(GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        ));
+        let mut policy = EventsPolicy::new(
+            ["agent_1"],
+            "sap-node-1",
+            engine,
+            DedupCache::new(Duration::ZERO, 0),
+            ExecutionQueue::default(),
+        )
+        .unwrap();
+        let mut handler = MockEventTypeHandler::new();
+        handler
+            .expect_handle()
+            .times(1)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+
+        policy.register("Trento.Test.V1.Custom", handler).unwrap();
+        // the built-in handlers take precedence
+        assert_eq!(
+            policy.register(
+                FACTS_GATHERING_REQUEST_EVENT_TYPE,
+                MockEventTypeHandler::new()
+            ),
+            Err(RegistryErrors::DuplicateHandler(
+                FACTS_GATHERING_REQUEST_EVENT_TYPE.to_owned()
+            ))
+        );
 
+        assert_eq!(
+            policy
+                .handle_event(
+                    br#"{"type": "Trento.Test.V1.Custom", "data": {}}"#,
+                    &EventContext::json(),
+                )
+                .await
+                .unwrap(),
+            HandleOutcome::Handled
+        );
+        // the built-in event is still validated by the built-in handler
+        assert!(matches!(
+            policy
+                .handle_event(
+                    br#"{
+                        "type": "Trento.Checks.V1.FactsGatheringRequested",
+                        "data": {"execution_id": "exec1", "group_id": "group1", "targets": []}
+                    }"#,
+                    &EventContext::json(),
+                )
+                .await,
+            Err(PolicyErrors::ValidationError(_))
+        ));
     }
 }
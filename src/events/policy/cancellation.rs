@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use log::{debug, info};
+use trento_contracts::events::event_data_from_event;
+use trento_contracts::stubs::execution_cancelled::ExecutionCancelled;
+
+use super::json::{self, JsonExecutionCancelled};
+use super::{EventTypeHandler, Executions};
+use crate::events::{EventContext, EventEncoding, HandleOutcome, PolicyErrors};
+
+pub const EXECUTION_CANCELLED_EVENT_TYPE: &str = "Trento.Checks.V1.ExecutionCancelled";
+
+/// Aborts the gathering of the executions cancelled by wanda, their results are not read anymore
+pub struct ExecutionCancelledHandler {
+    executions: Arc<Executions>,
+}
+
+impl ExecutionCancelledHandler {
+    pub fn new(executions: Arc<Executions>) -> ExecutionCancelledHandler {
+        ExecutionCancelledHandler { executions }
+    }
+
+    pub fn decode(
+        raw_event: &[u8],
+        encoding: EventEncoding,
+    ) -> Result<ExecutionCancelled, PolicyErrors> {
+        if encoding == EventEncoding::Json {
+            return json::event_data::<JsonExecutionCancelled, _>(raw_event);
+        }
+
+        let mut cancellation_event = ExecutionCancelled::new();
+        event_data_from_event(raw_event, &mut cancellation_event)
+            .map_err(|err| PolicyErrors::DecodeError(err.to_string()))?;
+
+        Ok(cancellation_event)
+    }
+
+    fn cancel(&self, execution_id: &str) {
+        if self.executions.cancel(execution_id) {
+            info!(
+                execution_id = execution_id;
+                "execution {} cancelled, abandoning its gathering",
+                execution_id
+            );
+        } else {
+            debug!(
+                execution_id = execution_id;
+                "cancellation of the unknown or finished execution {}, ignoring",
+                execution_id
+            );
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventTypeHandler for ExecutionCancelledHandler {
+    async fn handle(
+        &self,
+        raw_event: &[u8],
+        context: &EventContext,
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        let cancellation_event = ExecutionCancelledHandler::decode(raw_event, context.encoding())?;
+        self.cancel(&cancellation_event.execution_id);
+
+        Ok(HandleOutcome::Handled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::events::policy::facts_gathering::FactsGatheringRequestedHandler;
+    use crate::events::policy::fixtures::{fact_request, facts_gathering_requested};
+    use crate::events::SkipReason;
+    use crate::gatherers::{
+        FactsGathered, FactsGatheringRequest, Gatherer, GatherersRegistryBuilder, GatheringEngine,
+    };
+
+    struct SlowGatherer;
+
+    #[async_trait::async_trait]
+    impl Gatherer for SlowGatherer {
+        async fn gather(&self, _fact_request: FactsGatheringRequest) -> FactsGathered {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+
+            unreachable!("the execution is cancelled first")
+        }
+
+        fn name(&self) -> String {
+            "slow".to_owned()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_execution_has_no_result() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("slow", "v1", SlowGatherer);
+        let registry = Arc::new(builder.build_registry());
+        let executions = Arc::new(Executions::default());
+        let engine = Arc::new(GatheringEngine::new("agent_1", "sap-node-1", registry));
+        let gathering = Arc::new(FactsGatheringRequestedHandler::new(
+            &["agent_1".to_owned()],
+            "sap-node-1",
+            engine.clone(),
+            executions.clone(),
+        ));
+        let cancellation = ExecutionCancelledHandler::new(executions.clone());
+
+        let event = facts_gathering_requested()
+            .target("agent_1", vec![fact_request("slow", "fact1")])
+            .build();
+        let running = {
+            let gathering = gathering.clone();
+            tokio::spawn(async move {
+                let tracked = engine.track("exec1", "group1");
+                gathering.gather(event, &tracked).await
+            })
+        };
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!running.is_finished());
+
+        // the cancellation of other executions does not affect it
+        cancellation.cancel("exec2");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!running.is_finished());
+
+        cancellation.cancel("exec1");
+        let facts_gathered = tokio::time::timeout(Duration::from_secs(1), running)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(facts_gathered.err(), Some(SkipReason::Cancelled));
+        // the execution is forgotten, a late cancellation is ignored
+        assert!(!executions.cancel("exec1"));
+    }
+}
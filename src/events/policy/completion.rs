@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use log::{debug, info};
+use trento_contracts::events::event_data_from_event;
+use trento_contracts::stubs::execution_completed::ExecutionCompleted;
+
+use super::json::{self, JsonExecutionCompleted};
+use super::{EventTypeHandler, Executions};
+use crate::events::{EventContext, EventEncoding, HandleOutcome, PolicyErrors};
+
+pub const EXECUTION_COMPLETED_EVENT_TYPE: &str = "Trento.Checks.V1.ExecutionCompleted";
+
+/// Drops the state of the executions completed by wanda, a gathering still running is aborted
+/// as its facts are not read anymore
+pub struct ExecutionCompletedHandler {
+    executions: Arc<Executions>,
+}
+
+impl ExecutionCompletedHandler {
+    pub fn new(executions: Arc<Executions>) -> ExecutionCompletedHandler {
+        ExecutionCompletedHandler { executions }
+    }
+
+    pub fn decode(
+        raw_event: &[u8],
+        encoding: EventEncoding,
+    ) -> Result<ExecutionCompleted, PolicyErrors> {
+        if encoding == EventEncoding::Json {
+            return json::event_data::<JsonExecutionCompleted, _>(raw_event);
+        }
+
+        let mut completion_event = ExecutionCompleted::new();
+        event_data_from_event(raw_event, &mut completion_event)
+            .map_err(|err| PolicyErrors::DecodeError(err.to_string()))?;
+
+        Ok(completion_event)
+    }
+
+    fn complete(&self, execution_id: &str) {
+        // the running execution forgets its state once its gathering is aborted
+        if self.executions.cancel(execution_id) {
+            info!(
+                execution_id = execution_id;
+                "execution {} completed while gathered, abandoning its gathering",
+                execution_id
+            );
+        } else {
+            debug!(
+                execution_id = execution_id;
+                "completion of the unknown or finished execution {}, ignoring",
+                execution_id
+            );
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventTypeHandler for ExecutionCompletedHandler {
+    async fn handle(
+        &self,
+        raw_event: &[u8],
+        context: &EventContext,
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        let completion_event = ExecutionCompletedHandler::decode(raw_event, context.encoding())?;
+        self.complete(&completion_event.execution_id);
+
+        Ok(HandleOutcome::Handled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::events::policy::facts_gathering::FactsGatheringRequestedHandler;
+    use crate::events::policy::fixtures::{
+        execution_completed, fact_request, facts_gathering_requested,
+    };
+    use crate::events::SkipReason;
+    use crate::gatherers::{
+        FactsGathered, FactsGatheringRequest, Gatherer, GatherersRegistryBuilder, GatheringEngine,
+    };
+
+    struct SlowGatherer;
+
+    #[async_trait::async_trait]
+    impl Gatherer for SlowGatherer {
+        async fn gather(&self, _fact_request: FactsGatheringRequest) -> FactsGathered {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+
+            unreachable!("the execution is completed first")
+        }
+
+        fn name(&self) -> String {
+            "slow".to_owned()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_completed_execution_state_is_dropped() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("slow", "v1", SlowGatherer);
+        let registry = Arc::new(builder.build_registry());
+        let executions = Arc::new(Executions::default());
+        let engine = Arc::new(GatheringEngine::new("agent_1", "sap-node-1", registry));
+        let gathering = Arc::new(FactsGatheringRequestedHandler::new(
+            &["agent_1".to_owned()],
+            "sap-node-1",
+            engine.clone(),
+            executions.clone(),
+        ));
+        let completion = ExecutionCompletedHandler::new(executions.clone());
+        let event = |execution_id: &str| {
+            execution_completed()
+                .execution_id(execution_id)
+                .build_json()
+        };
+
+        let request = facts_gathering_requested()
+            .target("agent_1", vec![fact_request("slow", "fact1")])
+            .build();
+        let running = {
+            let gathering = gathering.clone();
+            tokio::spawn(async move {
+                let tracked = engine.track("exec1", "group1");
+                gathering.gather(request, &tracked).await
+            })
+        };
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!running.is_finished());
+
+        // the completion of unknown executions is a no-op
+        completion
+            .handle(&event("exec2"), &EventContext::json())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!running.is_finished());
+
+        assert_eq!(
+            completion
+                .handle(&event("exec1"), &EventContext::json())
+                .await
+                .unwrap(),
+            HandleOutcome::Handled
+        );
+        let facts_gathered = tokio::time::timeout(Duration::from_secs(1), running)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(facts_gathered.err(), Some(SkipReason::Cancelled));
+        // no state is left for the execution
+        assert!(!executions.cancel("exec1"));
+    }
+}
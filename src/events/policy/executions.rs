@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::events::{DedupCache, SkipReason};
+
+/// Executions being gathered, each one with the token cancelling it. The policy is shared by
+/// the consumers, so a redelivered request can arrive while its first copy is still gathered,
+/// or once it is finished
+#[derive(Debug)]
+pub struct Executions {
+    tokens: Mutex<HashMap<String, CancellationToken>>,
+    /// Executions recently finished
+    finished: DedupCache,
+    duplicates: AtomicU64,
+    replays: AtomicU64,
+}
+
+impl Default for Executions {
+    fn default() -> Executions {
+        Executions::new(DedupCache::new(Duration::ZERO, 0))
+    }
+}
+
+impl Executions {
+    /// The finished executions are remembered in the cache, until they expire or are evicted
+    pub fn new(finished: DedupCache) -> Executions {
+        Executions {
+            tokens: Mutex::new(HashMap::new()),
+            finished,
+            duplicates: AtomicU64::new(0),
+            replays: AtomicU64::new(0),
+        }
+    }
+
+    /// Tracks the execution until the returned guard is dropped, skipped when the same
+    /// execution is already running or recently finished
+    pub fn start(self: &Arc<Self>, execution_id: &str) -> Result<RunningExecution, SkipReason> {
+        let mut tokens = self.tokens.lock().expect("executions poisoned, fatal.");
+        if tokens.contains_key(execution_id) {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+            return Err(SkipReason::AlreadyRunning);
+        }
+        if self.finished.contains(execution_id) {
+            self.replays.fetch_add(1, Ordering::Relaxed);
+            return Err(SkipReason::AlreadyFinished);
+        }
+
+        let token = CancellationToken::new();
+        tokens.insert(execution_id.to_owned(), token.clone());
+
+        Ok(RunningExecution {
+            executions: self.clone(),
+            execution_id: execution_id.to_owned(),
+            token,
+        })
+    }
+
+    /// Requests skipped as their execution was already running
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    /// Requests skipped as their execution was recently finished
+    pub fn replays(&self) -> u64 {
+        self.replays.load(Ordering::Relaxed)
+    }
+
+    /// Cancels the execution, false when it is unknown or already finished
+    pub fn cancel(&self, execution_id: &str) -> bool {
+        match self
+            .tokens
+            .lock()
+            .expect("executions poisoned, fatal.")
+            .get(execution_id)
+        {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Execution in progress, remembered as finished once dropped
+pub struct RunningExecution {
+    executions: Arc<Executions>,
+    execution_id: String,
+    token: CancellationToken,
+}
+
+impl RunningExecution {
+    pub fn cancelled(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Drop for RunningExecution {
+    fn drop(&mut self) {
+        // recorded as finished before it stops running, so that no copy starts in between
+        let mut tokens = self
+            .executions
+            .tokens
+            .lock()
+            .expect("executions poisoned, fatal.");
+        self.executions.finished.insert(&self.execution_id);
+        tokens.remove(&self.execution_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_executions_cancellation() {
+        let executions = Arc::new(Executions::default());
+        let execution = executions.start("exec1").unwrap();
+
+        assert!(!executions.cancel("exec2"));
+        assert!(executions.cancel("exec1"));
+        assert!(execution.cancelled().is_cancelled());
+
+        drop(execution);
+        assert!(!executions.cancel("exec1"));
+    }
+
+    #[test]
+    fn test_executions_running_once() {
+        let executions = Arc::new(Executions::default());
+        let execution = executions.start("exec1").unwrap();
+
+        assert_eq!(
+            executions.start("exec1").err(),
+            Some(SkipReason::AlreadyRunning)
+        );
+        assert!(executions.start("exec2").is_ok());
+        assert_eq!(executions.duplicates(), 1);
+
+        // a later execution with the same id is not blocked without the replay protection
+        drop(execution);
+        assert!(executions.start("exec1").is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_finished_executions_are_not_replayed() {
+        let executions = Arc::new(Executions::new(DedupCache::new(
+            Duration::from_secs(900),
+            2,
+        )));
+        let execution = executions.start("exec1").unwrap();
+
+        // running executions are duplicates, not replays
+        assert_eq!(
+            executions.start("exec1").err(),
+            Some(SkipReason::AlreadyRunning)
+        );
+        drop(execution);
+        assert_eq!(
+            executions.start("exec1").err(),
+            Some(SkipReason::AlreadyFinished)
+        );
+        assert_eq!((executions.duplicates(), executions.replays()), (1, 1));
+
+        tokio::time::advance(Duration::from_secs(900)).await;
+        drop(executions.start("exec1").unwrap());
+
+        // the least recently used finished execution is evicted
+        drop(executions.start("exec2").unwrap());
+        drop(executions.start("exec3").unwrap());
+        assert!(executions.start("exec1").is_ok());
+        assert_eq!(
+            executions.start("exec3").err(),
+            Some(SkipReason::AlreadyFinished)
+        );
+    }
+}
@@ -0,0 +1,1167 @@
+use std::{collections::HashMap, sync::Arc};
+
+use log::{info, warn};
+use trento_contracts::events::event_data_from_event;
+use trento_contracts::stubs::facts_gathering_requested::FactsGatheringRequested;
+use uuid::Uuid;
+
+use super::json::{self, JsonFactsGatheringRequested, JsonFactsGatheringRequestedV2};
+use super::queue::ExecutionQueue;
+use super::requested::{GatheringRequested, GatheringTarget};
+use super::{event_type, EventTypeHandler, Executions};
+use crate::events::{EventContext, EventEncoding, HandleOutcome, PolicyErrors, SkipReason};
+use crate::gatherers::{
+    FactRequest, FactsGathered, FactsGatheringRequest, GatheringEngine, TrackedExecution,
+};
+use crate::logging::Correlation;
+
+pub const FACTS_GATHERING_REQUEST_EVENT_TYPE: &str = "Trento.Checks.V1.FactsGatheringRequested";
+pub const FACTS_GATHERING_REQUEST_V2_EVENT_TYPE: &str = "Trento.Checks.V2.FactsGatheringRequested";
+
+/// Every version of the request, handled alike
+pub const FACTS_GATHERING_REQUEST_EVENT_TYPES: [&str; 2] = [
+    FACTS_GATHERING_REQUEST_EVENT_TYPE,
+    FACTS_GATHERING_REQUEST_V2_EVENT_TYPE,
+];
+
+/// Gathers the facts requested to the agent, each fact request is dispatched to its gatherer
+pub struct FactsGatheringRequestedHandler {
+    /// Identities the agent is addressed with, e.g. the old and new machine id during a migration
+    agent_ids: Vec<String>,
+    agent_name: String,
+    engine: Arc<GatheringEngine>,
+    /// Shared with the cancellations
+    executions: Arc<Executions>,
+    queue: Arc<ExecutionQueue>,
+}
+
+impl FactsGatheringRequestedHandler {
+    pub fn new(
+        agent_ids: &[String],
+        agent_name: &str,
+        engine: Arc<GatheringEngine>,
+        executions: Arc<Executions>,
+    ) -> FactsGatheringRequestedHandler {
+        FactsGatheringRequestedHandler {
+            agent_ids: agent_ids.to_vec(),
+            agent_name: agent_name.to_owned(),
+            engine,
+            executions,
+            queue: Arc::new(ExecutionQueue::default()),
+        }
+    }
+
+    /// The executions wait for a gathering slot of the queue, unlimited without it
+    pub fn with_queue(mut self, queue: Arc<ExecutionQueue>) -> FactsGatheringRequestedHandler {
+        self.queue = queue;
+        self
+    }
+
+    /// The version is told by the type of the event. The json events are decoded into mirror
+    /// structs of the contract ones, both encodings and both versions result in the same
+    /// request, the fields missing in V1 taking their defaults.
+    /// The protobuf events of V2 are decoded with the V1 message, which skips the fields added
+    /// since, until the contracts ship the V2 one
+    pub fn decode(
+        raw_event: &[u8],
+        encoding: EventEncoding,
+    ) -> Result<GatheringRequested, PolicyErrors> {
+        if encoding == EventEncoding::Json {
+            return match event_type(raw_event, encoding)?.as_str() {
+                FACTS_GATHERING_REQUEST_V2_EVENT_TYPE => {
+                    json::event_data::<JsonFactsGatheringRequestedV2, _>(raw_event)
+                }
+                _ => json::event_data::<JsonFactsGatheringRequested, FactsGatheringRequested>(
+                    raw_event,
+                )
+                .map(GatheringRequested::from),
+            };
+        }
+
+        let mut facts_request_event = FactsGatheringRequested::new();
+        event_data_from_event(raw_event, &mut facts_request_event)
+            .map_err(|err| PolicyErrors::DecodeError(err.to_string()))?;
+
+        Ok(facts_request_event.into())
+    }
+
+    /// The request of a delivery, carrying the correlation ids of the delivery
+    pub(super) fn decode_delivered(
+        raw_event: &[u8],
+        context: &EventContext,
+    ) -> Result<GatheringRequested, PolicyErrors> {
+        let mut facts_request_event =
+            FactsGatheringRequestedHandler::decode(raw_event, context.encoding())?;
+        facts_request_event.correlation = context.correlation();
+
+        Ok(facts_request_event)
+    }
+
+    /// Every problem of the request is reported at once. The fact requests of the other agents
+    /// are not checked, they do not affect the execution on this agent. The facts are checked
+    /// per identity of the agent, each one reporting its own facts
+    fn validate(&self, facts_request_event: &GatheringRequested) -> Result<(), PolicyErrors> {
+        let violations = self.violations(facts_request_event);
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        Err(PolicyErrors::ValidationError(violations.join("; ")))
+    }
+
+    fn violations(&self, facts_request_event: &GatheringRequested) -> Vec<String> {
+        let mut violations = vec![];
+
+        for (field, value) in [
+            ("execution_id", &facts_request_event.execution_id),
+            ("group_id", &facts_request_event.group_id),
+        ] {
+            if value.is_empty() {
+                violations.push(format!("{} is empty", field));
+            } else if Uuid::parse_str(value).is_err() {
+                violations.push(format!("{} `{}` is not a uuid", field, value));
+            }
+        }
+
+        for (_, targets) in self.targeted_agents(facts_request_event) {
+            violations.extend(fact_request_violations(targets));
+        }
+
+        violations
+    }
+
+    /// The targets addressed to every identity of the agent, in the order of the identities.
+    /// The identities not targeted are missing
+    fn targeted_agents<'a>(
+        &'a self,
+        facts_request_event: &'a GatheringRequested,
+    ) -> Vec<(&'a str, Vec<&'a GatheringTarget>)> {
+        self.agent_ids
+            .iter()
+            .map(|agent_id| {
+                (
+                    agent_id.as_str(),
+                    targets_for_agent(facts_request_event, agent_id),
+                )
+            })
+            .filter(|(_, targets)| !targets.is_empty())
+            .collect()
+    }
+
+    /// Skipped when the request targets other agents only, when its execution is already
+    /// running or recently finished, or once it is cancelled.
+    /// The facts of every targeted identity are gathered and reported on their own, the
+    /// gatherings update the tracked execution
+    pub async fn gather(
+        &self,
+        facts_request_event: GatheringRequested,
+        tracked: &TrackedExecution<'_>,
+    ) -> Result<Vec<FactsGathered>, SkipReason> {
+        let targeted_agents = self.targeted_agents(&facts_request_event);
+
+        if targeted_agents.is_empty() {
+            info!(
+                agent_name = self.agent_name.as_str(),
+                execution_id = facts_request_event.execution_id.as_str(),
+                group_id = facts_request_event.group_id.as_str();
+                "execution requested for other agents, skipping execution with id: {} - host_id: {}",
+                facts_request_event.execution_id,
+                self.agent_ids.join(", ")
+            );
+
+            return Err(SkipReason::OtherAgents);
+        }
+
+        let execution = match self.executions.start(&facts_request_event.execution_id) {
+            Ok(execution) => execution,
+            Err(reason) => {
+                info!(
+                    agent_name = self.agent_name.as_str(),
+                    execution_id = facts_request_event.execution_id.as_str(),
+                    group_id = facts_request_event.group_id.as_str();
+                    "execution {} is {}, skipping the duplicated request",
+                    facts_request_event.execution_id,
+                    match reason {
+                        SkipReason::AlreadyFinished => "recently finished",
+                        _ => "already running",
+                    }
+                );
+
+                return Err(reason);
+            }
+        };
+
+        let mut gathered = vec![];
+        for (agent_id, targets) in targeted_agents {
+            info!(
+                agent_name = self.agent_name.as_str(),
+                execution_id = facts_request_event.execution_id.as_str(),
+                group_id = facts_request_event.group_id.as_str();
+                "execution requested event: execution_id {}, group_id {}, agent_id {}",
+                facts_request_event.execution_id,
+                facts_request_event.group_id,
+                agent_id
+            );
+
+            let request = map_fact_gathering_request_from_event(
+                targets,
+                facts_request_event.execution_id.to_owned(),
+                facts_request_event.group_id.to_owned(),
+                facts_request_event.correlation.to_owned(),
+            );
+
+            let Some(facts_gathered) = self
+                .engine
+                .gather_until_cancelled(request, agent_id, execution.cancelled(), tracked)
+                .await
+            else {
+                info!(
+                    agent_name = self.agent_name.as_str(),
+                    execution_id = facts_request_event.execution_id.as_str(),
+                    group_id = facts_request_event.group_id.as_str();
+                    "execution {} cancelled, no facts are reported",
+                    facts_request_event.execution_id
+                );
+
+                return Err(SkipReason::Cancelled);
+            };
+
+            gathered.push(facts_gathered);
+        }
+
+        Ok(gathered)
+    }
+}
+
+pub(super) fn targets_for_agent<'a>(
+    facts_request_event: &'a GatheringRequested,
+    agent_id: &str,
+) -> Vec<&'a GatheringTarget> {
+    facts_request_event
+        .targets
+        .iter()
+        .filter(|t| t.agent_id == agent_id)
+        .collect()
+}
+
+/// The fact requested more than once is not a violation, the targets are merged into a single
+/// request
+fn fact_request_violations(targets: Vec<&GatheringTarget>) -> Vec<String> {
+    let mut violations = vec![];
+
+    for fact_request in targets
+        .into_iter()
+        .flat_map(|target| target.fact_requests.iter())
+    {
+        if fact_request.name.is_empty() {
+            violations.push(format!(
+                "fact request of check {} has no name",
+                fact_request.check_id
+            ));
+        }
+        if fact_request.gatherer.is_empty() {
+            violations.push(format!(
+                "fact `{}` of check {} has no gatherer",
+                fact_request.name, fact_request.check_id
+            ));
+        }
+    }
+
+    violations
+}
+
+#[async_trait::async_trait]
+impl EventTypeHandler for FactsGatheringRequestedHandler {
+    async fn handle(
+        &self,
+        raw_event: &[u8],
+        context: &EventContext,
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        let facts_request_event =
+            FactsGatheringRequestedHandler::decode_delivered(raw_event, context)?;
+        self.validate(&facts_request_event)?;
+        // listed in flight while queued too
+        let tracked = self.engine.track(
+            &facts_request_event.execution_id,
+            &facts_request_event.group_id,
+        );
+        // the requests for other agents are skipped without taking a slot
+        let _slot = if self.targeted_agents(&facts_request_event).is_empty() {
+            None
+        } else {
+            Some(self.queue.admit(context.priority()).await?)
+        };
+
+        let gathered = match self.gather(facts_request_event, &tracked).await {
+            Ok(gathered) => gathered,
+            Err(reason) => return Ok(HandleOutcome::Skipped(reason)),
+        };
+        for facts_gathered in gathered {
+            info!(
+                agent_name = self.agent_name.as_str(),
+                execution_id = facts_gathered.exeuction_id.as_str(),
+                group_id = facts_gathered.group_id.as_str();
+                "gathered {} facts for agent {}, {} with errors",
+                facts_gathered.facts_gathered.len(),
+                facts_gathered.agent_id,
+                facts_gathered
+                    .facts_gathered
+                    .iter()
+                    .filter(|fact| fact.error.is_some())
+                    .count()
+            );
+        }
+
+        Ok(HandleOutcome::Handled)
+    }
+
+    /// The execution id of the request
+    fn event_id(&self, raw_event: &[u8], encoding: EventEncoding) -> Option<String> {
+        FactsGatheringRequestedHandler::decode(raw_event, encoding)
+            .ok()
+            .map(|facts_request_event| facts_request_event.execution_id)
+    }
+}
+
+/// The targets of the agent are merged, a fact request repeated with the same gatherer and
+/// argument is gathered once, with the metadata of its first occurrence. The conflicting
+/// requests of a fact, the same fact of a check with another gatherer or argument, are all
+/// gathered and logged. The shortest timeout of the targets applies
+pub(super) fn map_fact_gathering_request_from_event(
+    event_requests: Vec<&GatheringTarget>,
+    execution_id: String,
+    group_id: String,
+    correlation: Correlation,
+) -> FactsGatheringRequest {
+    let mut fact_requests: Vec<FactRequest> = vec![];
+
+    for event_request in event_requests
+        .iter()
+        .flat_map(|target| target.fact_requests.iter())
+    {
+        let request = FactRequest {
+            argument: event_request.argument.to_owned(),
+            check_id: event_request.check_id.to_owned(),
+            gatherer: event_request.gatherer.to_owned(),
+            name: event_request.name.to_owned(),
+            metadata: event_request.metadata.to_owned(),
+        };
+        if fact_requests.iter().any(|other| {
+            other.check_id == request.check_id
+                && other.name == request.name
+                && other.gatherer == request.gatherer
+                && other.argument == request.argument
+        }) {
+            continue;
+        }
+
+        if let Some(conflicting) = fact_requests
+            .iter()
+            .find(|other| other.check_id == request.check_id && other.name == request.name)
+        {
+            warn!(
+                execution_id = execution_id.as_str(),
+                group_id = group_id.as_str();
+                "fact `{}` of check {} is requested with gatherer {} argument `{}` and gatherer {} argument `{}`, both are gathered",
+                request.name,
+                request.check_id,
+                conflicting.gatherer,
+                conflicting.argument,
+                request.gatherer,
+                request.argument
+            );
+        }
+        fact_requests.push(request);
+    }
+
+    let mut fact_requests_for_gatherer: HashMap<String, Vec<FactRequest>> = HashMap::new();
+
+    for request in fact_requests {
+        fact_requests_for_gatherer
+            .entry(request.gatherer.to_owned())
+            .or_default()
+            .push(request);
+    }
+
+    FactsGatheringRequest {
+        execution_id: execution_id,
+        group_id: group_id,
+        facts_requests_by_gatherer: fact_requests_for_gatherer,
+        timeout: event_requests
+            .iter()
+            .filter_map(|target| target.timeout)
+            .min(),
+        correlation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use serde_json::json;
+    use trento_contracts::events::event_type_from_raw_bytes;
+    use trento_contracts::stubs::facts_gathered::FactsGathered as FactsGatheredEvent;
+    use trento_contracts::stubs::facts_gathering_requested::FactRequest;
+
+    use super::*;
+    use crate::events::policy::fixtures::{
+        fact_request, facts_gathering_requested, FactsGatheringRequestedBuilder,
+    };
+    use crate::events::EventCounters;
+    use crate::gatherers::recording::RecordingPublisher;
+    use crate::gatherers::{
+        Fact, FactGatheringErrors, FactsPublisher, Gatherer, GatherersRegistryBuilder, MockGatherer,
+    };
+
+    fn handler(agent_ids: &[&str], gatherer: MockGatherer) -> FactsGatheringRequestedHandler {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", gatherer);
+        let engine = GatheringEngine::new(
+            agent_ids[0],
+            "sap-node-1",
+            Arc::new(builder.build_registry()),
+        );
+        let agent_ids: Vec<String> = agent_ids.iter().map(|id| (*id).to_owned()).collect();
+
+        FactsGatheringRequestedHandler::new(
+            &agent_ids,
+            "sap-node-1",
+            Arc::new(engine),
+            Arc::new(Executions::default()),
+        )
+    }
+
+    fn requested() -> FactsGatheringRequestedBuilder {
+        facts_gathering_requested()
+            .target("agent_2", vec![fact_request("test_gat", "fact0")])
+            .target(
+                "agent_1",
+                vec![
+                    fact_request("test_gat", "fact1"),
+                    fact_request("missing", "fact2"),
+                ],
+            )
+    }
+
+    fn event() -> GatheringRequested {
+        requested().build().into()
+    }
+
+    #[test]
+    fn test_targeted_agents() {
+        let event: GatheringRequested = facts_gathering_requested()
+            .target("agent_old", vec![fact_request("test_gat", "fact3")])
+            .target("agent_2", vec![fact_request("test_gat", "fact0")])
+            .target("agent_1", vec![fact_request("test_gat", "fact1")])
+            .build()
+            .into();
+        let targeted = |agent_ids: &[&str]| -> Vec<String> {
+            handler(agent_ids, MockGatherer::new())
+                .targeted_agents(&event)
+                .into_iter()
+                .map(|(agent_id, targets)| {
+                    assert!(targets.iter().all(|target| target.agent_id == agent_id));
+                    agent_id.to_owned()
+                })
+                .collect()
+        };
+
+        assert_eq!(targeted(&["agent_1", "agent_9"]), vec!["agent_1"]);
+        assert_eq!(targeted(&["agent_9", "agent_old"]), vec!["agent_old"]);
+        assert_eq!(
+            targeted(&["agent_1", "agent_old"]),
+            vec!["agent_1", "agent_old"]
+        );
+        assert!(targeted(&["agent_3", "agent_9"]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_facts_are_attributed_to_the_targeted_identity() {
+        let mut gatherer = MockGatherer::new();
+        gatherer
+            .expect_gather()
+            .times(3)
+            .returning(|request| FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: vec![],
+                group_id: request.group_id.to_owned(),
+            });
+        let handler = handler(&["agent_9", "agent_1", "agent_old"], gatherer);
+        let agent_ids = |gathered: Vec<FactsGathered>| -> Vec<String> {
+            gathered
+                .into_iter()
+                .map(|facts_gathered| facts_gathered.agent_id)
+                .collect()
+        };
+        let tracked = handler.engine.track("exec1", "group1");
+
+        // the second identity only
+        assert_eq!(
+            agent_ids(handler.gather(event(), &tracked).await.unwrap()),
+            vec!["agent_1"]
+        );
+
+        let both = facts_gathering_requested()
+            .execution_id("exec2")
+            .target("agent_old", vec![fact_request("test_gat", "fact1")])
+            .target("agent_1", vec![fact_request("test_gat", "fact1")])
+            .build()
+            .into();
+        assert_eq!(
+            agent_ids(handler.gather(both, &tracked).await.unwrap()),
+            vec!["agent_1", "agent_old"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gathered_facts_are_published() {
+        let mut gatherer = MockGatherer::new();
+        gatherer
+            .expect_gather()
+            .times(1)
+            .returning(|request| FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: vec![Fact {
+                    name: "fact1".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: json!("value1"),
+                    error: None,
+                }],
+                group_id: request.group_id.to_owned(),
+            });
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", gatherer);
+        let recorder = Arc::new(RecordingPublisher::default());
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()))
+                .with_publisher(FactsPublisher::new(
+                    recorder.clone(),
+                    "trento.checks",
+                    "agents",
+                ));
+        let handler = FactsGatheringRequestedHandler::new(
+            &["agent_1".to_owned(), "agent_old".to_owned()],
+            "sap-node-1",
+            Arc::new(engine),
+            Arc::new(Executions::default()),
+        );
+        let raw_event = facts_gathering_requested()
+            .execution_id("5e3ae4f6-4b6c-4b7e-8f8d-0a2a7c1e5d3f")
+            .group_id("7c1d3a4e-2f5b-4c6d-9e8f-1a2b3c4d5e6f")
+            .target(
+                "agent_old",
+                vec![
+                    fact_request("test_gat", "fact1"),
+                    fact_request("missing", "fact2"),
+                ],
+            )
+            .build_raw();
+        let context = EventContext {
+            correlation_id: Some("corr1".to_owned()),
+            ..EventContext::default()
+        };
+
+        assert_eq!(
+            handler.handle(&raw_event, &context).await.unwrap(),
+            HandleOutcome::Handled
+        );
+
+        let published = recorder.published();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].exchange, "trento.checks");
+        assert_eq!(published[0].routing_key, "agents");
+        assert_eq!(
+            published[0].properties.correlation_id(),
+            Some(&"corr1".to_owned())
+        );
+        assert_eq!(
+            event_type_from_raw_bytes(&published[0].content).unwrap(),
+            "Trento.Checks.V1.FactsGathered"
+        );
+        let mut event = FactsGatheredEvent::new();
+        event_data_from_event(&published[0].content, &mut event).unwrap();
+        // attributed to the identity the facts were requested to, the error facts included
+        assert_eq!(event.execution_id, "5e3ae4f6-4b6c-4b7e-8f8d-0a2a7c1e5d3f");
+        assert_eq!(event.group_id, "7c1d3a4e-2f5b-4c6d-9e8f-1a2b3c4d5e6f");
+        assert_eq!(event.agent_id, "agent_old");
+        let mut facts: Vec<(String, bool)> = event
+            .facts_gathered
+            .iter()
+            .map(|fact| (fact.name.to_owned(), fact.has_error_value()))
+            .collect();
+        facts.sort();
+        assert_eq!(
+            facts,
+            vec![("fact1".to_owned(), false), ("fact2".to_owned(), true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requested_facts_are_gathered() {
+        let mut gatherer = MockGatherer::new();
+        gatherer
+            .expect_gather()
+            .withf(|request| {
+                request.execution_id == "exec1"
+                    && request.facts_requests_by_gatherer.len() == 1
+                    && request.facts_requests_by_gatherer["test_gat"].len() == 1
+            })
+            .times(1)
+            .returning(|request| FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: vec![Fact {
+                    name: "fact1".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: json!("value1"),
+                    error: None,
+                }],
+                group_id: request.group_id.to_owned(),
+            });
+
+        let handler = handler(&["agent_1"], gatherer);
+        let mut gathered = handler
+            .gather(event(), &handler.engine.track("exec1", "group1"))
+            .await
+            .unwrap();
+        assert_eq!(gathered.len(), 1);
+        let facts_gathered = gathered.remove(0);
+
+        assert_eq!(facts_gathered.agent_id, "agent_1");
+        assert_eq!(facts_gathered.agent_name, "sap-node-1");
+        assert_eq!(facts_gathered.exeuction_id, "exec1");
+        assert_eq!(facts_gathered.group_id, "group1");
+
+        let mut facts = facts_gathered.facts_gathered;
+        facts.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(
+            facts,
+            vec![
+                Fact {
+                    name: "fact1".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: json!("value1"),
+                    error: None,
+                },
+                // a missing gatherer fails its own facts only
+                Fact {
+                    name: "fact2".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: serde_json::Value::Null,
+                    error: Some(FactGatheringErrors::GathererResolutionError(
+                        "missing".to_owned(),
+                        "gatherer `missing` not found".to_owned()
+                    )),
+                },
+            ]
+        );
+    }
+
+    struct SleepingGatherer;
+
+    #[async_trait::async_trait]
+    impl Gatherer for SleepingGatherer {
+        async fn gather(&self, request: FactsGatheringRequest) -> FactsGathered {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+
+            FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: vec![],
+                group_id: request.group_id.to_owned(),
+            }
+        }
+
+        fn name(&self) -> String {
+            "test_gat".to_owned()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_duplicated_requests_are_skipped() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", SleepingGatherer);
+        let executions = Arc::new(Executions::default());
+        let handler = Arc::new(FactsGatheringRequestedHandler::new(
+            &["agent_1".to_owned()],
+            "sap-node-1",
+            Arc::new(GatheringEngine::new(
+                "agent_1",
+                "sap-node-1",
+                Arc::new(builder.build_registry()),
+            )),
+            executions.clone(),
+        ));
+        let gather = |handler: Arc<FactsGatheringRequestedHandler>| {
+            tokio::spawn(async move {
+                let tracked = handler.engine.track("exec1", "group1");
+                handler.gather(event(), &tracked).await
+            })
+        };
+
+        let (first, second) = tokio::join!(gather(handler.clone()), gather(handler.clone()));
+        let mut results = [first.unwrap(), second.unwrap()];
+        results.sort_by_key(Result::is_ok);
+
+        assert_eq!(results[0].as_ref().err(), Some(&SkipReason::AlreadyRunning));
+        assert_eq!(results[1].as_ref().unwrap()[0].exeuction_id, "exec1");
+        assert_eq!(executions.duplicates(), 1);
+
+        // the completed execution does not block a later one
+        assert!(gather(handler).await.unwrap().is_ok());
+        assert_eq!(executions.duplicates(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_beyond_the_queue_are_rejected() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", SleepingGatherer);
+        let metrics = Arc::new(EventCounters::default());
+        let queue = Arc::new(ExecutionQueue::new(Some(1), 1).with_metrics(metrics.clone()));
+        let handler = Arc::new(
+            FactsGatheringRequestedHandler::new(
+                &["agent_1".to_owned()],
+                "sap-node-1",
+                Arc::new(GatheringEngine::new(
+                    "agent_1",
+                    "sap-node-1",
+                    Arc::new(builder.build_registry()),
+                )),
+                Arc::new(Executions::default()),
+            )
+            .with_queue(queue.clone()),
+        );
+        let handle =
+            |handler: Arc<FactsGatheringRequestedHandler>, execution: u32, agent_id: &str| {
+                let raw_event = facts_gathering_requested()
+                    .execution_id(&format!("{:08x}-4b6c-4b7e-8f8d-0a2a7c1e5d3f", execution))
+                    .group_id("7c1d3a4e-2f5b-4c6d-9e8f-1a2b3c4d5e6f")
+                    .target(agent_id, vec![fact_request("test_gat", "fact1")])
+                    .build_json();
+                tokio::spawn(async move { handler.handle(&raw_event, &EventContext::json()).await })
+            };
+
+        let running = handle(handler.clone(), 1, "agent_1");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let queued = handle(handler.clone(), 2, "agent_1");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(queue.waiting(), 1);
+        assert_eq!(metrics.queue().waiting, 1);
+
+        // the delivery is retried later instead of waiting in memory
+        let rejected = handle(handler.clone(), 3, "agent_1").await.unwrap();
+        assert!(matches!(rejected, Err(PolicyErrors::TransientError(_))));
+        // the requests for other agents do not need a slot
+        assert_eq!(
+            handle(handler.clone(), 4, "agent_2")
+                .await
+                .unwrap()
+                .unwrap(),
+            HandleOutcome::Skipped(SkipReason::OtherAgents)
+        );
+
+        assert_eq!(running.await.unwrap().unwrap(), HandleOutcome::Handled);
+        assert_eq!(queued.await.unwrap().unwrap(), HandleOutcome::Handled);
+        let counters = metrics.queue();
+        assert_eq!(counters.waiting, 0);
+        assert_eq!(counters.max_waiting, 1);
+        assert_eq!(counters.waits.count, 1);
+        assert_eq!(counters.waits.max, Duration::from_secs(59));
+        assert_eq!(counters.rejected, 1);
+
+        // once drained the requests are gathered again
+        assert_eq!(
+            handle(handler, 5, "agent_1").await.unwrap().unwrap(),
+            HandleOutcome::Handled
+        );
+        assert_eq!(metrics.queue().waits.count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_requests_for_other_agents_are_skipped() {
+        let mut gatherer = MockGatherer::new();
+        gatherer.expect_gather().never();
+
+        let handler = handler(&["agent_3"], gatherer);
+
+        assert_eq!(
+            handler
+                .gather(event(), &handler.engine.track("exec1", "group1"))
+                .await
+                .err(),
+            Some(SkipReason::OtherAgents)
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        const EXECUTION_ID: &str = "7dd7a1f8-0b5b-4a4e-a2b2-a24b3ee7fc56";
+        const GROUP_ID: &str = "5d4b0b23-3a3b-4bb5-a5e5-1c0b50b7d4e2";
+        let valid = || requested().execution_id(EXECUTION_ID).group_id(GROUP_ID);
+        let untargeted = || {
+            facts_gathering_requested()
+                .execution_id(EXECUTION_ID)
+                .group_id(GROUP_ID)
+        };
+        let cases: Vec<(FactsGatheringRequested, Vec<&str>)> = vec![
+            (valid().build(), vec![]),
+            (
+                valid().execution_id("").build(),
+                vec!["execution_id is empty"],
+            ),
+            (
+                valid().group_id("group1").build(),
+                vec!["group_id `group1` is not a uuid"],
+            ),
+            (
+                untargeted()
+                    .target("agent_1", vec![fact_request("test_gat", "")])
+                    .build(),
+                vec!["fact request of check check1 has no name"],
+            ),
+            (
+                untargeted()
+                    .target("agent_1", vec![fact_request("", "fact1")])
+                    .build(),
+                vec!["fact `fact1` of check check1 has no gatherer"],
+            ),
+            // merged into a single request
+            (
+                untargeted()
+                    .target("agent_1", vec![fact_request("test_gat", "fact1")])
+                    .target("agent_1", vec![fact_request("other_gat", "fact1")])
+                    .build(),
+                vec![],
+            ),
+            // the fact requests of the other agents are not checked
+            (
+                untargeted()
+                    .target("agent_2", vec![fact_request("", "")])
+                    .build(),
+                vec![],
+            ),
+            (
+                facts_gathering_requested()
+                    .execution_id("")
+                    .group_id("")
+                    .target(
+                        "agent_1",
+                        vec![fact_request("", "fact1"), fact_request("test_gat", "")],
+                    )
+                    .build(),
+                vec![
+                    "execution_id is empty",
+                    "group_id is empty",
+                    "fact `fact1` of check check1 has no gatherer",
+                    "fact request of check check1 has no name",
+                ],
+            ),
+        ];
+        let handler = handler(&["agent_1"], MockGatherer::new());
+
+        for (event, expected) in cases {
+            let event = GatheringRequested::from(event);
+            assert_eq!(handler.violations(&event), expected);
+            match handler.validate(&event) {
+                Ok(_) => assert!(expected.is_empty()),
+                Err(PolicyErrors::ValidationError(message)) => {
+                    assert_eq!(message, expected.join("; "))
+                }
+                Err(err) => panic!("unexpected error {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_json_and_protobuf_events_result_in_the_same_request() {
+        // json fixture of the event built by requested()
+        let raw_event = br#"{
+            "type": "Trento.Checks.V1.FactsGatheringRequested",
+            "data": {
+                "execution_id": "exec1",
+                "group_id": "group1",
+                "targets": [
+                    {
+                        "agent_id": "agent_2",
+                        "fact_requests": [
+                            {"argument": "arg1", "check_id": "check1", "gatherer": "test_gat", "name": "fact0"}
+                        ]
+                    },
+                    {
+                        "agent_id": "agent_1",
+                        "fact_requests": [
+                            {"argument": "arg1", "check_id": "check1", "gatherer": "test_gat", "name": "fact1"},
+                            {"argument": "arg1", "check_id": "check1", "gatherer": "missing", "name": "fact2"}
+                        ]
+                    }
+                ]
+            }
+        }"#;
+        let handler = handler(&["agent_1"], MockGatherer::new());
+        let request = |event: &GatheringRequested| {
+            map_fact_gathering_request_from_event(
+                targets_for_agent(event, "agent_1"),
+                event.execution_id.to_owned(),
+                event.group_id.to_owned(),
+                Correlation::default(),
+            )
+        };
+
+        let json_event =
+            FactsGatheringRequestedHandler::decode(raw_event, EventEncoding::Json).unwrap();
+
+        assert_eq!(json_event, event());
+        assert_eq!(
+            FactsGatheringRequestedHandler::decode(
+                &requested().build_raw(),
+                EventEncoding::Protobuf
+            )
+            .unwrap(),
+            json_event
+        );
+        assert_eq!(request(&json_event), request(&event()));
+        assert_eq!(
+            handler.event_id(raw_event, EventEncoding::Json),
+            Some("exec1".to_owned())
+        );
+        assert!(matches!(
+            FactsGatheringRequestedHandler::decode(b"{}", EventEncoding::Json),
+            Err(PolicyErrors::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_both_versions_result_in_the_same_request() {
+        let request = |raw_event: &[u8], encoding: EventEncoding| {
+            let event = FactsGatheringRequestedHandler::decode(raw_event, encoding).unwrap();
+            map_fact_gathering_request_from_event(
+                targets_for_agent(&event, "agent_1"),
+                event.execution_id.to_owned(),
+                event.group_id.to_owned(),
+                Correlation::default(),
+            )
+        };
+        let v1 = request(&requested().build_raw(), EventEncoding::Protobuf);
+
+        assert_eq!(request(&requested().build_json(), EventEncoding::Json), v1);
+        assert_eq!(
+            request(&requested().build_json_v2(), EventEncoding::Json),
+            v1
+        );
+        assert_eq!(v1.timeout, None);
+
+        // the fields added by V2
+        let raw_event = br#"{
+            "type": "Trento.Checks.V2.FactsGatheringRequested",
+            "data": {
+                "execution_id": "exec1",
+                "group_id": "group1",
+                "targets": [
+                    {
+                        "agent_id": "agent_1",
+                        "timeout_ms": 30000,
+                        "fact_requests": [
+                            {"argument": "arg1", "check_id": "check1", "gatherer": "test_gat", "name": "fact1", "metadata": {"origin": "check1"}}
+                        ]
+                    },
+                    {
+                        "agent_id": "agent_1",
+                        "timeout_ms": 20000,
+                        "fact_requests": []
+                    }
+                ]
+            }
+        }"#;
+        let v2 = request(raw_event, EventEncoding::Json);
+
+        assert_eq!(v2.timeout, Some(Duration::from_secs(20)));
+        assert_eq!(
+            v2.facts_requests_by_gatherer["test_gat"][0].metadata,
+            BTreeMap::from([("origin".to_owned(), "check1".to_owned())])
+        );
+    }
+
+    #[test]
+    fn test_fact_gathering_request_from_event() {
+        let execution_id = "exec1";
+        let group_id = "group1";
+
+        let event: GatheringRequested = facts_gathering_requested()
+            .target(
+                "agent_1",
+                vec![
+                    fact_request("test_gat", "fact1"),
+                    FactRequest {
+                        argument: "arg2".to_owned(),
+                        ..fact_request("test_gat", "fact2")
+                    },
+                ],
+            )
+            .target(
+                "agent_1",
+                vec![
+                    fact_request("test_gat3", "fact3"),
+                    fact_request("test_gat4", "fact4"),
+                ],
+            )
+            .build()
+            .into();
+
+        let targets: Vec<&GatheringTarget> = event.targets.iter().collect();
+
+        let fact_requests: HashMap<String, Vec<super::FactRequest>> = vec![
+            (
+                "test_gat".to_owned(),
+                vec![
+                    super::FactRequest {
+                        argument: "arg1".to_owned(),
+                        check_id: "check1".to_owned(),
+                        gatherer: "test_gat".to_owned(),
+                        name: "fact1".to_owned(),
+                        metadata: BTreeMap::new(),
+                    },
+                    super::FactRequest {
+                        argument: "arg2".to_owned(),
+                        check_id: "check1".to_owned(),
+                        gatherer: "test_gat".to_owned(),
+                        name: "fact2".to_owned(),
+                        metadata: BTreeMap::new(),
+                    },
+                ],
+            ),
+            (
+                "test_gat4".to_owned(),
+                vec![super::FactRequest {
+                    argument: "arg1".to_owned(),
+                    check_id: "check1".to_owned(),
+                    gatherer: "test_gat4".to_owned(),
+                    name: "fact4".to_owned(),
+                    metadata: BTreeMap::new(),
+                }],
+            ),
+            (
+                "test_gat3".to_owned(),
+                vec![super::FactRequest {
+                    argument: "arg1".to_owned(),
+                    check_id: "check1".to_owned(),
+                    gatherer: "test_gat3".to_owned(),
+                    name: "fact3".to_owned(),
+                    metadata: BTreeMap::new(),
+                }],
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let expected_request = FactsGatheringRequest {
+            execution_id: execution_id.to_owned(),
+            group_id: group_id.to_owned(),
+            facts_requests_by_gatherer: fact_requests,
+            timeout: None,
+            correlation: Correlation::default(),
+        };
+
+        let result = map_fact_gathering_request_from_event(
+            targets.clone(),
+            execution_id.to_owned(),
+            group_id.to_owned(),
+            Correlation::default(),
+        );
+
+        assert_eq!(result.execution_id, expected_request.execution_id);
+        assert_eq!(result.group_id, expected_request.group_id);
+        assert_eq!(
+            result.facts_requests_by_gatherer.get("test_gat").unwrap(),
+            expected_request
+                .facts_requests_by_gatherer
+                .get("test_gat")
+                .unwrap()
+        );
+        assert_eq!(
+            result.facts_requests_by_gatherer.get("test_gat3").unwrap(),
+            expected_request
+                .facts_requests_by_gatherer
+                .get("test_gat3")
+                .unwrap()
+        );
+        assert_eq!(
+            result.facts_requests_by_gatherer.get("test_gat4").unwrap(),
+            expected_request
+                .facts_requests_by_gatherer
+                .get("test_gat4")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_duplicated_targets_are_merged() {
+        let conflicting = FactRequest {
+            argument: "arg2".to_owned(),
+            ..fact_request("test_gat", "fact1")
+        };
+        let event: GatheringRequested = facts_gathering_requested()
+            .target(
+                "agent_1",
+                vec![
+                    fact_request("test_gat", "fact1"),
+                    fact_request("test_gat", "fact2"),
+                ],
+            )
+            .target("agent_2", vec![fact_request("test_gat", "fact1")])
+            .target(
+                "agent_1",
+                vec![
+                    fact_request("test_gat", "fact1"),
+                    conflicting,
+                    fact_request("other_gat", "fact3"),
+                ],
+            )
+            .build()
+            .into();
+        let merged_request = |name: &str, gatherer: &str, argument: &str| super::FactRequest {
+            argument: argument.to_owned(),
+            check_id: "check1".to_owned(),
+            gatherer: gatherer.to_owned(),
+            name: name.to_owned(),
+            metadata: BTreeMap::new(),
+        };
+
+        let request = map_fact_gathering_request_from_event(
+            targets_for_agent(&event, "agent_1"),
+            "exec1".to_owned(),
+            "group1".to_owned(),
+            Correlation::default(),
+        );
+
+        assert_eq!(
+            request,
+            FactsGatheringRequest {
+                execution_id: "exec1".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([
+                    (
+                        "test_gat".to_owned(),
+                        vec![
+                            merged_request("fact1", "test_gat", "arg1"),
+                            merged_request("fact2", "test_gat", "arg1"),
+                            // the conflicting request is kept
+                            merged_request("fact1", "test_gat", "arg2"),
+                        ],
+                    ),
+                    (
+                        "other_gat".to_owned(),
+                        vec![merged_request("fact3", "other_gat", "arg1")],
+                    ),
+                ]),
+                timeout: None,
+                correlation: Correlation::default(),
+            }
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Builders of the contract events handled by the policy, encoded as wanda publishes them
+
+use serde_json::{json, Value};
+use trento_contracts::events::to_event;
+use trento_contracts::stubs::execution_cancelled::ExecutionCancelled;
+use trento_contracts::stubs::execution_completed::ExecutionCompleted;
+use trento_contracts::stubs::facts_gathering_requested::{
+    FactRequest, FactsGatheringRequested, FactsGatheringRequestedTarget,
+};
+
+use super::facts_gathering::FACTS_GATHERING_REQUEST_V2_EVENT_TYPE;
+use super::{
+    EXECUTION_CANCELLED_EVENT_TYPE, EXECUTION_COMPLETED_EVENT_TYPE,
+    FACTS_GATHERING_REQUEST_EVENT_TYPE,
+};
+
+/// Source of the protobuf envelope
+const WANDA_SOURCE: &str = "https://github.com/trento-project/wanda";
+
+/// Request of execution exec1 of group group1, without targets
+pub fn facts_gathering_requested() -> FactsGatheringRequestedBuilder {
+    FactsGatheringRequestedBuilder {
+        event: FactsGatheringRequested {
+            execution_id: "exec1".to_owned(),
+            group_id: "group1".to_owned(),
+            ..Default::default()
+        },
+    }
+}
+
+/// Fact of check check1, gathered with argument arg1
+pub fn fact_request(gatherer: &str, name: &str) -> FactRequest {
+    FactRequest {
+        argument: "arg1".to_owned(),
+        check_id: "check1".to_owned(),
+        gatherer: gatherer.to_owned(),
+        name: name.to_owned(),
+        ..Default::default()
+    }
+}
+
+pub fn execution_cancelled() -> ExecutionCancelledBuilder {
+    ExecutionCancelledBuilder {
+        event: ExecutionCancelled {
+            execution_id: "exec1".to_owned(),
+            ..Default::default()
+        },
+    }
+}
+
+pub fn execution_completed() -> ExecutionCompletedBuilder {
+    ExecutionCompletedBuilder {
+        event: ExecutionCompleted {
+            execution_id: "exec1".to_owned(),
+            ..Default::default()
+        },
+    }
+}
+
+pub struct FactsGatheringRequestedBuilder {
+    event: FactsGatheringRequested,
+}
+
+impl FactsGatheringRequestedBuilder {
+    pub fn execution_id(mut self, execution_id: &str) -> FactsGatheringRequestedBuilder {
+        self.event.execution_id = execution_id.to_owned();
+        self
+    }
+
+    pub fn group_id(mut self, group_id: &str) -> FactsGatheringRequestedBuilder {
+        self.event.group_id = group_id.to_owned();
+        self
+    }
+
+    /// Targets are kept in the order they are added, the same agent can be targeted more than once
+    pub fn target(
+        mut self,
+        agent_id: &str,
+        fact_requests: Vec<FactRequest>,
+    ) -> FactsGatheringRequestedBuilder {
+        self.event.targets.push(FactsGatheringRequestedTarget {
+            agent_id: agent_id.to_owned(),
+            fact_requests,
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn build(self) -> FactsGatheringRequested {
+        self.event
+    }
+
+    /// The protobuf envelope
+    pub fn build_raw(self) -> Vec<u8> {
+        let event_id = event_id(FACTS_GATHERING_REQUEST_EVENT_TYPE, &self.event.execution_id);
+
+        to_event(&event_id, WANDA_SOURCE, self.event).unwrap()
+    }
+
+    /// The json envelope, as published for debuggability
+    pub fn build_json(self) -> Vec<u8> {
+        json_event(FACTS_GATHERING_REQUEST_EVENT_TYPE, self.json_data())
+    }
+
+    /// The json envelope of V2, without the fields it adds as they are optional
+    pub fn build_json_v2(self) -> Vec<u8> {
+        json_event(FACTS_GATHERING_REQUEST_V2_EVENT_TYPE, self.json_data())
+    }
+
+    fn json_data(&self) -> Value {
+        let targets: Vec<Value> = self
+            .event
+            .targets
+            .iter()
+            .map(|target| {
+                let fact_requests: Vec<Value> = target
+                    .fact_requests
+                    .iter()
+                    .map(|fact_request| {
+                        json!({
+                            "argument": fact_request.argument,
+                            "check_id": fact_request.check_id,
+                            "gatherer": fact_request.gatherer,
+                            "name": fact_request.name,
+                        })
+                    })
+                    .collect();
+
+                json!({"agent_id": target.agent_id, "fact_requests": fact_requests})
+            })
+            .collect();
+
+        json!({
+            "execution_id": self.event.execution_id,
+            "group_id": self.event.group_id,
+            "targets": targets,
+        })
+    }
+}
+
+pub struct ExecutionCancelledBuilder {
+    event: ExecutionCancelled,
+}
+
+impl ExecutionCancelledBuilder {
+    pub fn execution_id(mut self, execution_id: &str) -> ExecutionCancelledBuilder {
+        self.event.execution_id = execution_id.to_owned();
+        self
+    }
+
+    pub fn build_raw(self) -> Vec<u8> {
+        let event_id = event_id(EXECUTION_CANCELLED_EVENT_TYPE, &self.event.execution_id);
+
+        to_event(&event_id, WANDA_SOURCE, self.event).unwrap()
+    }
+
+    pub fn build_json(self) -> Vec<u8> {
+        json_event(
+            EXECUTION_CANCELLED_EVENT_TYPE,
+            json!({"execution_id": self.event.execution_id}),
+        )
+    }
+}
+
+pub struct ExecutionCompletedBuilder {
+    event: ExecutionCompleted,
+}
+
+impl ExecutionCompletedBuilder {
+    pub fn execution_id(mut self, execution_id: &str) -> ExecutionCompletedBuilder {
+        self.event.execution_id = execution_id.to_owned();
+        self
+    }
+
+    pub fn build_raw(self) -> Vec<u8> {
+        let event_id = event_id(EXECUTION_COMPLETED_EVENT_TYPE, &self.event.execution_id);
+
+        to_event(&event_id, WANDA_SOURCE, self.event).unwrap()
+    }
+
+    pub fn build_json(self) -> Vec<u8> {
+        json_event(
+            EXECUTION_COMPLETED_EVENT_TYPE,
+            json!({"execution_id": self.event.execution_id}),
+        )
+    }
+}
+
+/// Stable across builds, so that the fixtures of the same event are the same bytes
+fn event_id(event_type: &str, execution_id: &str) -> String {
+    format!("{}-{}", event_type, execution_id)
+}
+
+fn json_event(event_type: &str, data: Value) -> Vec<u8> {
+    json!({"type": event_type, "data": data})
+        .to_string()
+        .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use trento_contracts::events::event_type_from_raw_bytes;
+
+    use super::*;
+    use crate::events::policy::cancellation::ExecutionCancelledHandler;
+    use crate::events::policy::completion::ExecutionCompletedHandler;
+    use crate::events::policy::facts_gathering::FactsGatheringRequestedHandler;
+    use crate::events::policy::json;
+    use crate::events::policy::requested::GatheringRequested;
+    use crate::events::EventEncoding;
+
+    fn request() -> FactsGatheringRequestedBuilder {
+        facts_gathering_requested()
+            .execution_id("exec2")
+            .group_id("group2")
+            .target("agent_2", vec![fact_request("test_gat", "fact0")])
+            .target(
+                "agent_1",
+                vec![
+                    fact_request("test_gat", "fact1"),
+                    FactRequest {
+                        argument: "".to_owned(),
+                        ..fact_request("missing", "fact2")
+                    },
+                ],
+            )
+    }
+
+    #[test]
+    fn test_facts_gathering_requested_round_trip() {
+        let event = request().build();
+        assert_eq!(event.execution_id, "exec2");
+        assert_eq!(event.group_id, "group2");
+        assert_eq!(event.targets.len(), 2);
+        assert_eq!(event.targets[1].agent_id, "agent_1");
+        assert_eq!(event.targets[1].fact_requests[1].gatherer, "missing");
+
+        let raw_event = request().build_raw();
+        assert_eq!(
+            event_type_from_raw_bytes(&raw_event).unwrap(),
+            FACTS_GATHERING_REQUEST_EVENT_TYPE
+        );
+        assert_eq!(
+            FactsGatheringRequestedHandler::decode(&raw_event, EventEncoding::Protobuf).unwrap(),
+            GatheringRequested::from(event.clone())
+        );
+
+        let json_event = request().build_json();
+        assert_eq!(
+            json::event_type(&json_event).unwrap(),
+            FACTS_GATHERING_REQUEST_EVENT_TYPE
+        );
+        assert_eq!(
+            FactsGatheringRequestedHandler::decode(&json_event, EventEncoding::Json).unwrap(),
+            GatheringRequested::from(event.clone())
+        );
+        // the same fields in V2
+        assert_eq!(
+            FactsGatheringRequestedHandler::decode(&request().build_json_v2(), EventEncoding::Json)
+                .unwrap(),
+            GatheringRequested::from(event)
+        );
+        // the same event, the same bytes
+        assert_eq!(request().build_raw(), raw_event);
+    }
+
+    #[test]
+    fn test_execution_events_round_trip() {
+        for encoding in [EventEncoding::Protobuf, EventEncoding::Json] {
+            let (cancelled, completed) = match encoding {
+                EventEncoding::Protobuf => (
+                    execution_cancelled().execution_id("exec2").build_raw(),
+                    execution_completed().execution_id("exec3").build_raw(),
+                ),
+                EventEncoding::Json => (
+                    execution_cancelled().execution_id("exec2").build_json(),
+                    execution_completed().execution_id("exec3").build_json(),
+                ),
+            };
+
+            assert_eq!(
+                ExecutionCancelledHandler::decode(&cancelled, encoding)
+                    .unwrap()
+                    .execution_id,
+                "exec2"
+            );
+            assert_eq!(
+                ExecutionCompletedHandler::decode(&completed, encoding)
+                    .unwrap()
+                    .execution_id,
+                "exec3"
+            );
+        }
+    }
+}
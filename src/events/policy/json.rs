@@ -0,0 +1,211 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::{de::DeserializeOwned, Deserialize};
+use trento_contracts::stubs::execution_cancelled::ExecutionCancelled;
+use trento_contracts::stubs::execution_completed::ExecutionCompleted;
+use trento_contracts::stubs::facts_gathering_requested::{
+    FactRequest, FactsGatheringRequested, FactsGatheringRequestedTarget,
+};
+
+use super::requested::{GatheringRequested, GatheringTarget, RequestedFact};
+use crate::events::PolicyErrors;
+use crate::logging::Correlation;
+
+/// Envelope of the json encoded events, the type is the one of the protobuf envelope
+#[derive(Debug, Deserialize)]
+struct JsonEvent<T> {
+    #[serde(rename = "type")]
+    event_type: String,
+    data: T,
+}
+
+pub fn event_type(raw_event: &[u8]) -> Result<String, PolicyErrors> {
+    let event: JsonEvent<serde::de::IgnoredAny> = serde_json::from_slice(raw_event)
+        .map_err(|err| PolicyErrors::DecodeError(err.to_string()))?;
+
+    Ok(event.event_type)
+}
+
+/// Decodes the data of the event into its mirror struct, then into the contract one
+pub fn event_data<T, E>(raw_event: &[u8]) -> Result<E, PolicyErrors>
+where
+    T: DeserializeOwned + Into<E>,
+{
+    let event: JsonEvent<T> = serde_json::from_slice(raw_event)
+        .map_err(|err| PolicyErrors::DecodeError(err.to_string()))?;
+
+    Ok(event.data.into())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JsonFactsGatheringRequested {
+    execution_id: String,
+    group_id: String,
+    targets: Vec<JsonFactsGatheringRequestedTarget>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFactsGatheringRequestedTarget {
+    agent_id: String,
+    fact_requests: Vec<JsonFactRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFactRequest {
+    #[serde(default)]
+    argument: String,
+    check_id: String,
+    gatherer: String,
+    name: String,
+}
+
+impl From<JsonFactsGatheringRequested> for FactsGatheringRequested {
+    fn from(event: JsonFactsGatheringRequested) -> FactsGatheringRequested {
+        FactsGatheringRequested {
+            execution_id: event.execution_id,
+            group_id: event.group_id,
+            targets: event
+                .targets
+                .into_iter()
+                .map(|target| FactsGatheringRequestedTarget {
+                    agent_id: target.agent_id,
+                    fact_requests: target
+                        .fact_requests
+                        .into_iter()
+                        .map(|fact_request| FactRequest {
+                            argument: fact_request.argument,
+                            check_id: fact_request.check_id,
+                            gatherer: fact_request.gatherer,
+                            name: fact_request.name,
+                            ..Default::default()
+                        })
+                        .collect(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Adds the timeout hints of the targets and the metadata of the fact requests to V1, both
+/// optional
+#[derive(Debug, Deserialize)]
+pub struct JsonFactsGatheringRequestedV2 {
+    execution_id: String,
+    group_id: String,
+    targets: Vec<JsonFactsGatheringRequestedTargetV2>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFactsGatheringRequestedTargetV2 {
+    agent_id: String,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    fact_requests: Vec<JsonFactRequestV2>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFactRequestV2 {
+    #[serde(default)]
+    argument: String,
+    check_id: String,
+    gatherer: String,
+    name: String,
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+}
+
+impl From<JsonFactsGatheringRequestedV2> for GatheringRequested {
+    fn from(event: JsonFactsGatheringRequestedV2) -> GatheringRequested {
+        GatheringRequested {
+            execution_id: event.execution_id,
+            group_id: event.group_id,
+            targets: event
+                .targets
+                .into_iter()
+                .map(|target| GatheringTarget {
+                    agent_id: target.agent_id,
+                    timeout: target.timeout_ms.map(Duration::from_millis),
+                    fact_requests: target
+                        .fact_requests
+                        .into_iter()
+                        .map(|fact_request| RequestedFact {
+                            argument: fact_request.argument,
+                            check_id: fact_request.check_id,
+                            gatherer: fact_request.gatherer,
+                            name: fact_request.name,
+                            metadata: fact_request.metadata,
+                        })
+                        .collect(),
+                })
+                .collect(),
+            correlation: Correlation::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JsonExecutionCancelled {
+    execution_id: String,
+}
+
+impl From<JsonExecutionCancelled> for ExecutionCancelled {
+    fn from(event: JsonExecutionCancelled) -> ExecutionCancelled {
+        ExecutionCancelled {
+            execution_id: event.execution_id,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JsonExecutionCompleted {
+    execution_id: String,
+}
+
+impl From<JsonExecutionCompleted> for ExecutionCompleted {
+    fn from(event: JsonExecutionCompleted) -> ExecutionCompleted {
+        ExecutionCompleted {
+            execution_id: event.execution_id,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_event_type() {
+        let raw_event = br#"{"type": "Trento.Checks.V1.ExecutionCancelled", "data": {}}"#;
+
+        assert_eq!(
+            event_type(raw_event).unwrap(),
+            "Trento.Checks.V1.ExecutionCancelled"
+        );
+        assert!(matches!(
+            event_type(b"\x0a\x04test"),
+            Err(PolicyErrors::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_json_event_data() {
+        let raw_event = br#"{
+            "type": "Trento.Checks.V1.ExecutionCancelled",
+            "data": {"execution_id": "exec1"}
+        }"#;
+        let cancellation_event: ExecutionCancelled =
+            event_data::<JsonExecutionCancelled, _>(raw_event).unwrap();
+
+        assert_eq!(cancellation_event.execution_id, "exec1");
+        assert!(matches!(
+            event_data::<JsonExecutionCancelled, ExecutionCancelled>(
+                br#"{"type": "Trento.Checks.V1.ExecutionCancelled", "data": {}}"#
+            ),
+            Err(PolicyErrors::DecodeError(_))
+        ));
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::config::FailureKind;
+use crate::events::{HandleOutcome, PolicyErrors, SkipReason, UnhandledReason};
+
+/// Event type of the events whose type cannot be read
+pub const UNDECODABLE_EVENT_TYPE: &str = "undecodable";
+
+/// Records the handlings of the policy by event type and outcome, and the executions waiting
+/// for a gathering slot
+pub trait EventMetrics: Send + Sync {
+    fn handled(&self, _event_type: &str, _outcome: &'static str, _duration: Duration) {}
+
+    /// Gauge of the executions waiting for a slot, on every change
+    fn executions_waiting(&self, _waiting: usize) {}
+
+    /// Wait of an execution admitted after waiting for a slot
+    fn execution_waited(&self, _wait: Duration) {}
+
+    /// Execution rejected as the queue was full
+    fn execution_rejected(&self) {}
+}
+
+/// Label of the outcome of a handling
+pub fn outcome_label(result: &Result<HandleOutcome, PolicyErrors>) -> &'static str {
+    match result {
+        Ok(HandleOutcome::Handled) => "handled",
+        Ok(HandleOutcome::Skipped(SkipReason::OtherAgents)) => "skipped_other_agent",
+        Ok(HandleOutcome::Skipped(SkipReason::AlreadyRunning)) => "skipped_already_running",
+        Ok(HandleOutcome::Skipped(SkipReason::AlreadyFinished)) => "skipped_already_finished",
+        Ok(HandleOutcome::Skipped(SkipReason::Cancelled)) => "skipped_cancelled",
+        Ok(HandleOutcome::Skipped(SkipReason::GroupNotAllowed)) => "skipped_group_not_allowed",
+        Ok(HandleOutcome::Skipped(SkipReason::GroupDenied)) => "skipped_group_denied",
+        Ok(HandleOutcome::Unhandled(UnhandledReason::UnknownType)) => "unknown_type",
+        Ok(HandleOutcome::Unhandled(UnhandledReason::NewerVersion)) => "newer_version",
+        Err(err) => match err.kind() {
+            FailureKind::Decode => "decode_error",
+            FailureKind::Validation => "validation_error",
+            FailureKind::Transient => "transient_error",
+            FailureKind::Gatherer => "gatherer_error",
+            FailureKind::Timeout => "timeout_error",
+            FailureKind::Internal => "internal_error",
+        },
+    }
+}
+
+/// Handlings of an event type
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventTypeCounters {
+    pub outcomes: BTreeMap<&'static str, u64>,
+    pub max: Duration,
+    total: Duration,
+}
+
+impl EventTypeCounters {
+    /// Missing until the first handling
+    pub fn average(&self) -> Option<Duration> {
+        let count: u64 = self.outcomes.values().sum();
+
+        self.total
+            .checked_div(u32::try_from(count).unwrap_or(u32::MAX))
+    }
+}
+
+/// Upper bounds of the buckets of the waits for a gathering slot
+const WAIT_BUCKETS: [Duration; 6] = [
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(30),
+    Duration::from_secs(60),
+    Duration::from_secs(300),
+];
+
+/// Observed durations, counted in cumulative buckets by upper bound
+#[derive(Debug, Clone, PartialEq)]
+pub struct DurationHistogram {
+    pub buckets: Vec<(Duration, u64)>,
+    pub count: u64,
+    pub max: Duration,
+    sum: Duration,
+}
+
+impl DurationHistogram {
+    pub fn new(bounds: &[Duration]) -> DurationHistogram {
+        DurationHistogram {
+            buckets: bounds.iter().map(|bound| (*bound, 0)).collect(),
+            count: 0,
+            max: Duration::ZERO,
+            sum: Duration::ZERO,
+        }
+    }
+
+    pub fn observe(&mut self, duration: Duration) {
+        for (bound, count) in self.buckets.iter_mut() {
+            if duration <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.max = self.max.max(duration);
+        self.sum = self.sum.saturating_add(duration);
+    }
+
+    /// Missing until the first observation
+    pub fn average(&self) -> Option<Duration> {
+        self.sum
+            .checked_div(u32::try_from(self.count).unwrap_or(u32::MAX))
+    }
+}
+
+/// Executions waiting for a gathering slot
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueCounters {
+    /// Executions waiting right now
+    pub waiting: usize,
+    pub max_waiting: usize,
+    /// Waits of the executions admitted after waiting
+    pub waits: DurationHistogram,
+    /// Executions rejected as the queue was full
+    pub rejected: u64,
+}
+
+impl Default for QueueCounters {
+    fn default() -> Self {
+        QueueCounters {
+            waiting: 0,
+            max_waiting: 0,
+            waits: DurationHistogram::new(&WAIT_BUCKETS),
+            rejected: 0,
+        }
+    }
+}
+
+/// Counts the handlings in process and the executions waiting for a slot, readable at any time
+#[derive(Debug, Default)]
+pub struct EventCounters {
+    counters: Mutex<BTreeMap<String, EventTypeCounters>>,
+    queue: Mutex<QueueCounters>,
+}
+
+impl EventCounters {
+    pub fn snapshot(&self) -> BTreeMap<String, EventTypeCounters> {
+        self.counters
+            .lock()
+            .expect("event counters poisoned, fatal.")
+            .clone()
+    }
+
+    pub fn queue(&self) -> QueueCounters {
+        self.queue_counters().clone()
+    }
+
+    fn queue_counters(&self) -> MutexGuard<'_, QueueCounters> {
+        self.queue.lock().expect("queue counters poisoned, fatal.")
+    }
+}
+
+impl EventMetrics for EventCounters {
+    fn handled(&self, event_type: &str, outcome: &'static str, duration: Duration) {
+        let mut counters = self
+            .counters
+            .lock()
+            .expect("event counters poisoned, fatal.");
+        let counters = counters.entry(event_type.to_owned()).or_default();
+
+        *counters.outcomes.entry(outcome).or_default() += 1;
+        counters.max = counters.max.max(duration);
+        counters.total = counters.total.saturating_add(duration);
+    }
+
+    fn executions_waiting(&self, waiting: usize) {
+        let mut queue = self.queue_counters();
+        queue.waiting = waiting;
+        queue.max_waiting = queue.max_waiting.max(waiting);
+    }
+
+    fn execution_waited(&self, wait: Duration) {
+        self.queue_counters().waits.observe(wait);
+    }
+
+    fn execution_rejected(&self) {
+        self.queue_counters().rejected += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_counters() {
+        let counters = EventCounters::default();
+        assert_eq!(counters.snapshot(), BTreeMap::new());
+
+        counters.handled("Trento.Test.V1.Event", "handled", Duration::from_secs(2));
+        counters.handled("Trento.Test.V1.Event", "handled", Duration::from_secs(4));
+        counters.handled("Trento.Test.V1.Event", "decode_error", Duration::ZERO);
+        counters.handled(UNDECODABLE_EVENT_TYPE, "decode_error", Duration::ZERO);
+
+        let snapshot = counters.snapshot();
+        let event = &snapshot["Trento.Test.V1.Event"];
+        assert_eq!(
+            event.outcomes,
+            BTreeMap::from([("decode_error", 1), ("handled", 2)])
+        );
+        assert_eq!(event.max, Duration::from_secs(4));
+        assert_eq!(event.average(), Some(Duration::from_secs(2)));
+        assert_eq!(
+            snapshot[UNDECODABLE_EVENT_TYPE].outcomes,
+            BTreeMap::from([("decode_error", 1)])
+        );
+    }
+
+    #[test]
+    fn test_queue_counters() {
+        let counters = EventCounters::default();
+        assert_eq!(counters.queue(), QueueCounters::default());
+        assert_eq!(counters.queue().waits.average(), None);
+
+        counters.executions_waiting(1);
+        counters.executions_waiting(2);
+        counters.executions_waiting(1);
+        counters.execution_waited(Duration::from_millis(50));
+        counters.execution_waited(Duration::from_secs(10));
+        counters.execution_rejected();
+
+        let queue = counters.queue();
+        assert_eq!(queue.waiting, 1);
+        assert_eq!(queue.max_waiting, 2);
+        assert_eq!(queue.rejected, 1);
+        assert_eq!(queue.waits.count, 2);
+        assert_eq!(queue.waits.max, Duration::from_secs(10));
+        assert_eq!(queue.waits.average(), Some(Duration::from_millis(5025)));
+        assert_eq!(
+            queue.waits.buckets,
+            vec![
+                (Duration::from_millis(100), 1),
+                (Duration::from_secs(1), 1),
+                (Duration::from_secs(5), 1),
+                (Duration::from_secs(30), 2),
+                (Duration::from_secs(60), 2),
+                (Duration::from_secs(300), 2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,633 @@
+use std::{collections::HashSet, sync::Arc};
+
+use log::{debug, error, info, warn};
+use tokio::time::{Duration, Instant};
+
+use super::facts_gathering::{
+    map_fact_gathering_request_from_event, targets_for_agent, FactsGatheringRequestedHandler,
+    FACTS_GATHERING_REQUEST_EVENT_TYPES,
+};
+use super::metrics::{outcome_label, EventMetrics, UNDECODABLE_EVENT_TYPE};
+use super::requested::GatheringRequested;
+use super::versions::LogThrottle;
+use super::{event_type, EventsPolicy};
+use crate::events::{
+    EventContext, EventEncoding, HandleOutcome, PolicyErrors, SkipReason, UnhandledReason,
+};
+use crate::gatherers::{FactGatheringErrors, GatheringEngine};
+
+/// An event going through the middlewares of the policy, its type is read once
+pub struct PolicyEvent<'a> {
+    pub raw_event: &'a [u8],
+    pub context: &'a EventContext,
+    /// Told by the content type of the delivery
+    pub encoding: EventEncoding,
+    event_type: Result<String, String>,
+}
+
+impl<'a> PolicyEvent<'a> {
+    pub fn new(raw_event: &'a [u8], context: &'a EventContext) -> PolicyEvent<'a> {
+        let encoding = context.encoding();
+
+        PolicyEvent {
+            raw_event,
+            context,
+            encoding,
+            event_type: event_type(raw_event, encoding).map_err(|err| err.to_string()),
+        }
+    }
+
+    /// The type of the undecodable events is `undecodable`
+    pub fn event_type(&self) -> &str {
+        self.event_type.as_deref().unwrap_or(UNDECODABLE_EVENT_TYPE)
+    }
+}
+
+/// Wraps the handling of the events, it either passes the event to the next middleware or
+/// settles it on its own. The last middleware passes it to the handler of its type
+#[async_trait::async_trait]
+pub trait EventMiddleware: Send + Sync {
+    async fn handle(
+        &self,
+        event: &PolicyEvent<'_>,
+        next: Next<'_>,
+    ) -> Result<HandleOutcome, PolicyErrors>;
+}
+
+/// Continuation of the middleware chain
+pub struct Next<'a> {
+    middlewares: &'a [Box<dyn EventMiddleware>],
+    policy: &'a EventsPolicy,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(middlewares: &'a [Box<dyn EventMiddleware>], policy: &'a EventsPolicy) -> Next<'a> {
+        Next {
+            middlewares,
+            policy,
+        }
+    }
+
+    pub async fn run(self, event: &PolicyEvent<'_>) -> Result<HandleOutcome, PolicyErrors> {
+        match self.middlewares.split_first() {
+            Some((middleware, middlewares)) => {
+                middleware
+                    .handle(event, Next::new(middlewares, self.policy))
+                    .await
+            }
+            None => match &event.event_type {
+                Ok(event_type) => {
+                    self.policy
+                        .dispatch(event_type, event.raw_event, event.context)
+                        .await
+                }
+                Err(err) => Err(PolicyErrors::DecodeError(err.to_owned())),
+            },
+        }
+    }
+}
+
+// the outdated agent is reported once per interval, not on every newer event
+const OUTDATED_LOG_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Logs the outcome and the duration of the handlings
+pub struct LoggingMiddleware {
+    outdated: LogThrottle,
+}
+
+impl Default for LoggingMiddleware {
+    fn default() -> LoggingMiddleware {
+        LoggingMiddleware {
+            outdated: LogThrottle::new(OUTDATED_LOG_INTERVAL),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventMiddleware for LoggingMiddleware {
+    async fn handle(
+        &self,
+        event: &PolicyEvent<'_>,
+        next: Next<'_>,
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        let started = Instant::now();
+        let result = next.run(event).await;
+
+        match result {
+            Ok(HandleOutcome::Unhandled(UnhandledReason::UnknownType)) => {
+                warn!("unrecognized event type {}, skipping", event.event_type());
+            }
+            Ok(HandleOutcome::Unhandled(UnhandledReason::NewerVersion)) => {
+                if self.outdated.allow() {
+                    error!(
+                        event_type = event.event_type();
+                        "event of type {} is newer than the handled versions, the agent is outdated and must be upgraded",
+                        event.event_type()
+                    );
+                }
+            }
+            _ => debug!(
+                event_type = event.event_type();
+                "event of type {} handled in {:?}: {}",
+                event.event_type(),
+                started.elapsed(),
+                outcome_label(&result)
+            ),
+        }
+
+        result
+    }
+}
+
+/// Records every handling by event type and outcome
+pub struct MetricsMiddleware {
+    metrics: Arc<dyn EventMetrics>,
+}
+
+impl MetricsMiddleware {
+    pub fn new(metrics: Arc<dyn EventMetrics>) -> MetricsMiddleware {
+        MetricsMiddleware { metrics }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventMiddleware for MetricsMiddleware {
+    async fn handle(
+        &self,
+        event: &PolicyEvent<'_>,
+        next: Next<'_>,
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        let started = Instant::now();
+        let result = next.run(event).await;
+        self.metrics.handled(
+            event.event_type(),
+            outcome_label(&result),
+            started.elapsed(),
+        );
+
+        result
+    }
+}
+
+/// Groups whose executions are gathered, either the listed ones only or all but the listed ones
+enum GroupFilter {
+    Allowed(HashSet<String>),
+    Denied(HashSet<String>),
+}
+
+impl GroupFilter {
+    fn skip_reason(&self, group_id: &str) -> Option<SkipReason> {
+        match self {
+            GroupFilter::Allowed(group_ids) if !group_ids.contains(group_id) => {
+                Some(SkipReason::GroupNotAllowed)
+            }
+            GroupFilter::Denied(group_ids) if group_ids.contains(group_id) => {
+                Some(SkipReason::GroupDenied)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Reports the facts requested to the agent by the skipped executions, each with an error
+struct SkipNotices {
+    agent_ids: Vec<String>,
+    engine: Arc<GatheringEngine>,
+}
+
+impl SkipNotices {
+    async fn report(&self, facts_request_event: &GatheringRequested) {
+        for agent_id in &self.agent_ids {
+            let targets = targets_for_agent(facts_request_event, agent_id);
+            if targets.is_empty() {
+                continue;
+            }
+
+            let request = map_fact_gathering_request_from_event(
+                targets,
+                facts_request_event.execution_id.to_owned(),
+                facts_request_event.group_id.to_owned(),
+                facts_request_event.correlation.to_owned(),
+            );
+            let facts_gathered = self
+                .engine
+                .skip(
+                    request,
+                    agent_id,
+                    FactGatheringErrors::SkippedByPolicyError(
+                        facts_request_event.group_id.to_owned(),
+                    ),
+                )
+                .await;
+            info!(
+                execution_id = facts_request_event.execution_id.as_str(),
+                group_id = facts_request_event.group_id.as_str();
+                "reported {} facts of the skipped execution {} for agent {}",
+                facts_gathered.facts_gathered.len(),
+                facts_request_event.execution_id,
+                agent_id
+            );
+        }
+    }
+}
+
+/// Skips the facts gathering requests of the groups not allowed, or denied, on the agent. The
+/// events that cannot be decoded are left to their handler, which reports the failure
+pub struct GroupIdFilterMiddleware {
+    filter: GroupFilter,
+    skip_notices: Option<SkipNotices>,
+}
+
+impl GroupIdFilterMiddleware {
+    /// Only the executions of the allowed groups are gathered
+    pub fn allowing(allowed_group_ids: &[String]) -> GroupIdFilterMiddleware {
+        GroupIdFilterMiddleware {
+            filter: GroupFilter::Allowed(allowed_group_ids.iter().cloned().collect()),
+            skip_notices: None,
+        }
+    }
+
+    /// The executions of the denied groups are never gathered
+    pub fn denying(denied_group_ids: &[String]) -> GroupIdFilterMiddleware {
+        GroupIdFilterMiddleware {
+            filter: GroupFilter::Denied(denied_group_ids.iter().cloned().collect()),
+            skip_notices: None,
+        }
+    }
+
+    /// The skipped executions report every fact requested to the agent with an error, instead of
+    /// leaving the execution waiting for its timeout
+    pub fn with_skip_notices(
+        self,
+        agent_ids: &[&str],
+        engine: Arc<GatheringEngine>,
+    ) -> GroupIdFilterMiddleware {
+        GroupIdFilterMiddleware {
+            skip_notices: Some(SkipNotices {
+                agent_ids: agent_ids
+                    .iter()
+                    .map(|agent_id| (*agent_id).to_owned())
+                    .collect(),
+                engine,
+            }),
+            ..self
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventMiddleware for GroupIdFilterMiddleware {
+    async fn handle(
+        &self,
+        event: &PolicyEvent<'_>,
+        next: Next<'_>,
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        if !FACTS_GATHERING_REQUEST_EVENT_TYPES.contains(&event.event_type()) {
+            return next.run(event).await;
+        }
+
+        let Ok(facts_request_event) =
+            FactsGatheringRequestedHandler::decode_delivered(event.raw_event, event.context)
+        else {
+            return next.run(event).await;
+        };
+        let Some(reason) = self.filter.skip_reason(&facts_request_event.group_id) else {
+            return next.run(event).await;
+        };
+
+        info!(
+            execution_id = facts_request_event.execution_id.as_str(),
+            group_id = facts_request_event.group_id.as_str();
+            "execution {} of the group {} is {} on the agent, skipping",
+            facts_request_event.execution_id,
+            facts_request_event.group_id,
+            match reason {
+                SkipReason::GroupDenied => "denied",
+                _ => "not allowed",
+            }
+        );
+        if let Some(skip_notices) = &self.skip_notices {
+            skip_notices.report(&facts_request_event).await;
+        }
+
+        Ok(HandleOutcome::Skipped(reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::events::policy::facts_gathering::FACTS_GATHERING_REQUEST_EVENT_TYPE;
+    use crate::events::policy::fixtures::{fact_request, facts_gathering_requested};
+    use crate::events::policy::MockEventTypeHandler;
+    use crate::gatherers::{FactsDumper, FactsGathered, GatherersRegistryBuilder};
+
+    // records its name before and after the inner handling
+    struct RecordingMiddleware {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl EventMiddleware for RecordingMiddleware {
+        async fn handle(
+            &self,
+            event: &PolicyEvent<'_>,
+            next: Next<'_>,
+        ) -> Result<HandleOutcome, PolicyErrors> {
+            self.calls.lock().unwrap().push(format!("{} in", self.name));
+            let result = next.run(event).await;
+            self.calls.lock().unwrap().push(format!(
+                "{} out: {}",
+                self.name,
+                outcome_label(&result)
+            ));
+
+            result
+        }
+    }
+
+    // settles every event as skipped
+    struct SkippingMiddleware;
+
+    #[async_trait::async_trait]
+    impl EventMiddleware for SkippingMiddleware {
+        async fn handle(
+            &self,
+            _event: &PolicyEvent<'_>,
+            _next: Next<'_>,
+        ) -> Result<HandleOutcome, PolicyErrors> {
+            Ok(HandleOutcome::Skipped(SkipReason::GroupNotAllowed))
+        }
+    }
+
+    fn policy(
+        handler: MockEventTypeHandler,
+        middlewares: Vec<Box<dyn EventMiddleware>>,
+    ) -> EventsPolicy {
+        let mut policy = EventsPolicy::default();
+        policy
+            .register(FACTS_GATHERING_REQUEST_EVENT_TYPE, handler)
+            .unwrap();
+        policy.middlewares = middlewares;
+
+        policy
+    }
+
+    fn recording(
+        names: &[&'static str],
+    ) -> (Vec<Box<dyn EventMiddleware>>, Arc<Mutex<Vec<String>>>) {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let middlewares = names
+            .iter()
+            .map(|name| {
+                Box::new(RecordingMiddleware {
+                    name,
+                    calls: calls.clone(),
+                }) as Box<dyn EventMiddleware>
+            })
+            .collect();
+
+        (middlewares, calls)
+    }
+
+    fn facts_request(group_id: &str) -> Vec<u8> {
+        facts_gathering_requested().group_id(group_id).build_json()
+    }
+
+    async fn handle(
+        policy: &EventsPolicy,
+        raw_event: &[u8],
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        let event = PolicyEvent::new(raw_event, &EventContext::json());
+
+        Next::new(&policy.middlewares, policy).run(&event).await
+    }
+
+    #[tokio::test]
+    async fn test_middlewares_wrap_the_handling_outermost_first() {
+        let mut handler = MockEventTypeHandler::new();
+        handler
+            .expect_handle()
+            .times(1)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        let (middlewares, calls) = recording(&["outer", "inner"]);
+        let policy = policy(handler, middlewares);
+
+        assert_eq!(
+            handle(&policy, &facts_request("group1")).await.unwrap(),
+            HandleOutcome::Handled
+        );
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "outer in",
+                "inner in",
+                "inner out: handled",
+                "outer out: handled"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_middlewares_short_circuit_the_handling() {
+        let mut handler = MockEventTypeHandler::new();
+        handler.expect_handle().never();
+        let (mut middlewares, calls) = recording(&["outer", "inner"]);
+        middlewares.insert(1, Box::new(SkippingMiddleware));
+        let policy = policy(handler, middlewares);
+
+        assert_eq!(
+            handle(&policy, &facts_request("group1")).await.unwrap(),
+            HandleOutcome::Skipped(SkipReason::GroupNotAllowed)
+        );
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["outer in", "outer out: skipped_group_not_allowed"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handling_errors_go_through_the_middlewares() {
+        let mut handler = MockEventTypeHandler::new();
+        handler
+            .expect_handle()
+            .times(1)
+            .returning(|_, _| Err(PolicyErrors::ValidationError("no targets".to_owned())));
+        let (middlewares, calls) = recording(&["outer", "inner"]);
+        let policy = policy(handler, middlewares);
+
+        assert!(matches!(
+            handle(&policy, &facts_request("group1")).await,
+            Err(PolicyErrors::ValidationError(_))
+        ));
+        assert!(matches!(
+            handle(&policy, b"not json").await,
+            Err(PolicyErrors::DecodeError(_))
+        ));
+        assert_eq!(
+            calls.lock().unwrap()[..4],
+            [
+                "outer in",
+                "inner in",
+                "inner out: validation_error",
+                "outer out: validation_error"
+            ]
+        );
+        assert_eq!(
+            calls.lock().unwrap()[4..],
+            [
+                "outer in",
+                "inner in",
+                "inner out: decode_error",
+                "outer out: decode_error"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_id_allow_filter() {
+        let mut handler = MockEventTypeHandler::new();
+        handler
+            .expect_handle()
+            .times(2)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        let policy = policy(
+            handler,
+            vec![Box::new(GroupIdFilterMiddleware::allowing(&[
+                "group1".to_owned()
+            ]))],
+        );
+
+        assert_eq!(
+            handle(&policy, &facts_request("group1")).await.unwrap(),
+            HandleOutcome::Handled
+        );
+        assert_eq!(
+            handle(&policy, &facts_request("group2")).await.unwrap(),
+            HandleOutcome::Skipped(SkipReason::GroupNotAllowed)
+        );
+        // undecodable requests are left to their handler
+        assert_eq!(
+            handle(
+                &policy,
+                br#"{"type": "Trento.Checks.V1.FactsGatheringRequested", "data": {}}"#
+            )
+            .await
+            .unwrap(),
+            HandleOutcome::Handled
+        );
+        // the other event types are not filtered
+        assert_eq!(
+            handle(&policy, br#"{"type": "Trento.Test.V1.Event", "data": {}}"#)
+                .await
+                .unwrap(),
+            HandleOutcome::Unhandled(UnhandledReason::UnknownType)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_id_deny_filter() {
+        let mut handler = MockEventTypeHandler::new();
+        handler
+            .expect_handle()
+            .times(1)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        let policy = policy(
+            handler,
+            vec![Box::new(GroupIdFilterMiddleware::denying(&[
+                "group1".to_owned()
+            ]))],
+        );
+
+        assert_eq!(
+            handle(&policy, &facts_request("group1")).await.unwrap(),
+            HandleOutcome::Skipped(SkipReason::GroupDenied)
+        );
+        assert_eq!(
+            handle(&policy, &facts_request("group2")).await.unwrap(),
+            HandleOutcome::Handled
+        );
+        assert_eq!(
+            outcome_label(&handle(&policy, &facts_request("group1")).await),
+            "skipped_group_denied"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_id_filter_skip_notices() {
+        let dir = std::env::temp_dir().join("vanvitelli_test_skip_notices");
+        let _ = std::fs::remove_dir_all(&dir);
+        let engine = Arc::new(
+            GatheringEngine::new(
+                "agent_1",
+                "sap-node-1",
+                Arc::new(GatherersRegistryBuilder::new().build_registry()),
+            )
+            .with_dumper(FactsDumper::new(&dir)),
+        );
+        let mut handler = MockEventTypeHandler::new();
+        handler.expect_handle().never();
+        let policy = policy(
+            handler,
+            vec![Box::new(
+                GroupIdFilterMiddleware::denying(&["group1".to_owned()])
+                    .with_skip_notices(&["agent_1"], engine),
+            )],
+        );
+
+        let skipped = handle(
+            &policy,
+            &facts_gathering_requested()
+                .target(
+                    "agent_1",
+                    vec![
+                        fact_request("test_gat", "fact1"),
+                        fact_request("other_gat", "fact2"),
+                    ],
+                )
+                .target("agent_2", vec![fact_request("test_gat", "fact3")])
+                .build_json(),
+        )
+        .await;
+        // no notice for the executions targeting other agents only
+        let other_agents = handle(
+            &policy,
+            &facts_gathering_requested()
+                .execution_id("exec2")
+                .target("agent_2", vec![fact_request("test_gat", "fact3")])
+                .build_json(),
+        )
+        .await;
+        let other_agents_notice = dir.join("exec2.json").exists();
+        let content = std::fs::read(dir.join("exec1.json")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            skipped.unwrap(),
+            HandleOutcome::Skipped(SkipReason::GroupDenied)
+        );
+        assert_eq!(
+            other_agents.unwrap(),
+            HandleOutcome::Skipped(SkipReason::GroupDenied)
+        );
+        assert!(!other_agents_notice);
+
+        let notice: FactsGathered = serde_json::from_slice(&content).unwrap();
+        assert_eq!(notice.agent_id, "agent_1");
+        assert_eq!(notice.group_id, "group1");
+        let mut facts: Vec<&str> = notice
+            .facts_gathered
+            .iter()
+            .map(|fact| fact.name.as_str())
+            .collect();
+        facts.sort();
+        assert_eq!(facts, vec!["fact1", "fact2"]);
+        assert!(notice.facts_gathered.iter().all(|fact| fact.error
+            == Some(FactGatheringErrors::SkippedByPolicyError(
+                "group1".to_owned()
+            ))));
+    }
+}
@@ -0,0 +1,81 @@
+use log::info;
+
+use super::event_type;
+use crate::events::{
+    EventContext, EventEncoding, EventsHandler, HandleOutcome, PolicyErrors, UnhandledReason,
+};
+
+/// Operator events published by trento for the agents
+const OPERATIONS_EVENT_TYPES: [&str; 1] = ["Trento.Operations.V1.OperatorExecutionRequested"];
+
+/// Handles the operational events directed to the agent, consumed on a subscription of their
+/// own. The operations are not supported yet, the known events are only decoded and logged
+#[derive(Debug, Default)]
+pub struct OperationsPolicy;
+
+#[async_trait::async_trait]
+impl EventsHandler for OperationsPolicy {
+    async fn handle_event(
+        &self,
+        raw_event: &[u8],
+        context: &EventContext,
+    ) -> Result<HandleOutcome, PolicyErrors> {
+        let event_type = event_type(raw_event, context.encoding())?;
+        if !OPERATIONS_EVENT_TYPES.contains(&event_type.as_str()) {
+            return Ok(HandleOutcome::Unhandled(UnhandledReason::UnknownType));
+        }
+
+        info!(
+            "operator event {} received on {}, the operations are not supported yet",
+            event_type, context.routing_key
+        );
+
+        Ok(HandleOutcome::Handled)
+    }
+
+    fn event_type(&self, raw_event: &[u8], encoding: EventEncoding) -> Option<String> {
+        event_type(raw_event, encoding).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_operator_events_are_logged() {
+        let policy = OperationsPolicy;
+        let event = |event_type: &str| {
+            json!({"type": event_type, "data": {"operation_id": "operation1"}})
+                .to_string()
+                .into_bytes()
+        };
+
+        assert_eq!(
+            policy
+                .handle_event(
+                    &event("Trento.Operations.V1.OperatorExecutionRequested"),
+                    &EventContext::json()
+                )
+                .await
+                .unwrap(),
+            HandleOutcome::Handled
+        );
+        assert_eq!(
+            policy
+                .handle_event(
+                    &event("Trento.Checks.V1.FactsGatheringRequested"),
+                    &EventContext::json()
+                )
+                .await
+                .unwrap(),
+            HandleOutcome::Unhandled(UnhandledReason::UnknownType)
+        );
+        assert!(matches!(
+            policy.handle_event(b"{", &EventContext::json()).await,
+            Err(PolicyErrors::DecodeError(_))
+        ));
+    }
+}
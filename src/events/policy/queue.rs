@@ -0,0 +1,297 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+use super::metrics::EventMetrics;
+use crate::events::{PolicyErrors, Priority};
+
+/// Waiting executions by priority, the highest first, then by arrival
+type WaitingKey = (Reverse<Priority>, u64);
+
+#[derive(Debug, Default)]
+struct Slots {
+    /// Unlimited executions when missing
+    capacity: Option<usize>,
+    running: usize,
+    waiting: BTreeMap<WaitingKey, oneshot::Sender<ExecutionSlot>>,
+    arrivals: u64,
+}
+
+impl Slots {
+    fn has_room(&self) -> bool {
+        match self.capacity {
+            Some(capacity) => self.running < capacity,
+            None => true,
+        }
+    }
+
+    /// Hands the free slots over to the waiting executions, the highest priority first
+    fn hand_over(&mut self, shared: &Arc<Mutex<Slots>>) {
+        while self.has_room() {
+            let Some((_, waiting)) = self.waiting.pop_first() else {
+                return;
+            };
+
+            self.running += 1;
+            // a slot received by an interrupted wait is dropped with it, and handed over again
+            if let Err(mut slot) = waiting.send(ExecutionSlot {
+                slots: Some(shared.clone()),
+            }) {
+                // not released again, the lock is held
+                slot.slots = None;
+                self.running -= 1;
+            }
+        }
+    }
+}
+
+/// Bounds the executions gathered at once. The executions exceeding the capacity wait for a
+/// slot in a bounded queue, once full the requests are rejected with a transient failure, so
+/// that they stay with the broker instead of piling up in memory.
+/// A freed slot goes to the waiting execution of highest priority, the running ones are never
+/// interrupted, not even when the capacity is reduced
+#[derive(Default)]
+pub struct ExecutionQueue {
+    depth: usize,
+    slots: Arc<Mutex<Slots>>,
+    metrics: Option<Arc<dyn EventMetrics>>,
+}
+
+impl ExecutionQueue {
+    pub fn new(max_executions: Option<usize>, depth: usize) -> ExecutionQueue {
+        ExecutionQueue {
+            depth,
+            slots: Arc::new(Mutex::new(Slots {
+                capacity: max_executions,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Reports the waiting executions and their waits to the metrics
+    pub fn with_metrics(mut self, metrics: Arc<dyn EventMetrics>) -> ExecutionQueue {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Changes the executions gathered at once. The waiting executions fitting a larger capacity
+    /// are admitted right away, with a smaller one the running executions complete and the
+    /// waiting ones are admitted as the running ones fall below it
+    pub fn resize(&self, max_executions: Option<usize>) {
+        let mut slots = lock(&self.slots);
+        slots.capacity = max_executions;
+        slots.hand_over(&self.slots);
+    }
+
+    /// Waits for a gathering slot, held until the returned one is dropped
+    pub async fn admit(&self, priority: Priority) -> Result<ExecutionSlot, PolicyErrors> {
+        let (key, slot) = {
+            let mut slots = lock(&self.slots);
+            if slots.has_room() && slots.waiting.is_empty() {
+                slots.running += 1;
+                return Ok(ExecutionSlot {
+                    slots: Some(self.slots.clone()),
+                });
+            }
+            if slots.waiting.len() >= self.depth {
+                drop(slots);
+                if let Some(metrics) = &self.metrics {
+                    metrics.execution_rejected();
+                }
+                return Err(PolicyErrors::TransientError(format!(
+                    "gathering capacity saturated, {} executions already waiting",
+                    self.depth
+                )));
+            }
+
+            let (sender, slot) = oneshot::channel();
+            let key = (Reverse(priority), slots.arrivals);
+            slots.arrivals += 1;
+            slots.waiting.insert(key, sender);
+            self.report_waiting(slots.waiting.len());
+
+            (key, slot)
+        };
+        // left when the wait is interrupted too, e.g. by the timeout of the handling
+        let _waiting = Waiting { queue: self, key };
+
+        let started = Instant::now();
+        let slot = slot.await.expect("execution queue dropped, fatal.");
+        if let Some(metrics) = &self.metrics {
+            metrics.execution_waited(started.elapsed());
+        }
+
+        Ok(slot)
+    }
+
+    /// Executions waiting for a slot right now
+    pub fn waiting(&self) -> usize {
+        lock(&self.slots).waiting.len()
+    }
+
+    fn report_waiting(&self, waiting: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.executions_waiting(waiting);
+        }
+    }
+}
+
+fn lock(slots: &Mutex<Slots>) -> MutexGuard<'_, Slots> {
+    slots.lock().expect("execution slots poisoned, fatal.")
+}
+
+/// Gathering slot of an execution, handed over to the next waiting execution once dropped.
+/// The unlimited executions hold one as well, to be counted when a capacity is set later on
+#[derive(Debug)]
+pub struct ExecutionSlot {
+    /// Missing when the slot was never received
+    slots: Option<Arc<Mutex<Slots>>>,
+}
+
+impl Drop for ExecutionSlot {
+    fn drop(&mut self) {
+        let Some(shared) = self.slots.take() else {
+            return;
+        };
+
+        let mut slots = lock(&shared);
+        slots.running -= 1;
+        slots.hand_over(&shared);
+    }
+}
+
+struct Waiting<'a> {
+    queue: &'a ExecutionQueue,
+    key: WaitingKey,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut slots = lock(&self.queue.slots);
+        slots.waiting.remove(&self.key);
+        self.queue.report_waiting(slots.waiting.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Duration;
+
+    use super::*;
+    use crate::events::EventCounters;
+
+    #[tokio::test]
+    async fn test_unlimited_executions_are_not_queued() {
+        let metrics = Arc::new(EventCounters::default());
+        let queue = ExecutionQueue::default().with_metrics(metrics.clone());
+
+        let mut slots = vec![];
+        for _ in 0..100 {
+            slots.push(queue.admit(Priority::default()).await.unwrap());
+        }
+
+        assert_eq!(queue.waiting(), 0);
+        assert_eq!(metrics.queue(), Default::default());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interrupted_waits_leave_the_queue() {
+        let metrics = Arc::new(EventCounters::default());
+        let queue = ExecutionQueue::new(Some(1), 1).with_metrics(metrics.clone());
+        let slot = queue.admit(Priority::default()).await.unwrap();
+
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), queue.admit(Priority::default()))
+                .await
+                .is_err()
+        );
+        assert_eq!(queue.waiting(), 0);
+        let counters = metrics.queue();
+        assert_eq!(counters.waiting, 0);
+        assert_eq!(counters.max_waiting, 1);
+        assert_eq!(counters.waits.count, 0);
+
+        drop(slot);
+        assert!(queue.admit(Priority::default()).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resized_capacity_applies_to_the_waiting_executions() {
+        let queue = Arc::new(ExecutionQueue::new(Some(1), 4));
+        let running = queue.admit(Priority::default()).await.unwrap();
+        let waiting: Vec<_> = (0..2)
+            .map(|_| {
+                let queue = queue.clone();
+                tokio::spawn(async move { queue.admit(Priority::default()).await.unwrap() })
+            })
+            .collect();
+        tokio::task::yield_now().await;
+        assert_eq!(queue.waiting(), 2);
+
+        // both admitted without waiting for the running execution
+        queue.resize(Some(3));
+        let mut admitted = vec![];
+        for waiting in waiting {
+            admitted.push(waiting.await.unwrap());
+        }
+        assert_eq!(queue.waiting(), 0);
+
+        // the running executions are not interrupted, the new ones wait for them
+        queue.resize(Some(1));
+        drop(running);
+        drop(admitted.pop());
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), queue.admit(Priority::default()))
+                .await
+                .is_err()
+        );
+        drop(admitted);
+        assert!(queue.admit(Priority::default()).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_higher_priorities_are_admitted_first() {
+        let queue = Arc::new(ExecutionQueue::new(Some(1), 4));
+        let admitted = Arc::new(Mutex::new(vec![]));
+        let running = queue.admit(Priority::default()).await.unwrap();
+
+        let mut waiting = vec![];
+        for (name, priority) in [
+            ("normal1", Priority::default()),
+            ("high", Priority::MAX),
+            ("normal2", Priority::default()),
+            ("medium", Priority::new(5)),
+        ] {
+            let queue = queue.clone();
+            let admitted = admitted.clone();
+            waiting.push(tokio::spawn(async move {
+                let _slot = queue.admit(priority).await.unwrap();
+                admitted.lock().unwrap().push(name);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }));
+            tokio::task::yield_now().await;
+        }
+        // the running execution is not interrupted
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(admitted.lock().unwrap().is_empty());
+        assert_eq!(queue.waiting(), 4);
+
+        drop(running);
+        for waiting in waiting {
+            waiting.await.unwrap();
+        }
+
+        assert_eq!(
+            *admitted.lock().unwrap(),
+            vec!["high", "medium", "normal1", "normal2"]
+        );
+        assert_eq!(queue.waiting(), 0);
+        assert!(queue.admit(Priority::MAX).await.is_ok());
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::versions::VersionedType;
+use super::EventTypeHandler;
+use crate::events::UnhandledReason;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RegistryErrors {
+    #[error("a handler of the event type {0} is already registered")]
+    DuplicateHandler(String),
+}
+
+/// Handlers of the event types, a type is claimed by a single handler
+#[derive(Default)]
+pub struct HandlerRegistry {
+    handlers: HashMap<String, Box<dyn EventTypeHandler>>,
+}
+
+impl HandlerRegistry {
+    /// Fails when the event type is already claimed, the registered handler is kept
+    pub fn register(
+        &mut self,
+        event_type: &str,
+        handler: impl EventTypeHandler + 'static,
+    ) -> Result<(), RegistryErrors> {
+        if self.handlers.contains_key(event_type) {
+            return Err(RegistryErrors::DuplicateHandler(event_type.to_owned()));
+        }
+        self.handlers
+            .insert(event_type.to_owned(), Box::new(handler));
+
+        Ok(())
+    }
+
+    pub fn get(&self, event_type: &str) -> Option<&dyn EventTypeHandler> {
+        self.handlers.get(event_type).map(Box::as_ref)
+    }
+
+    /// The events of a newer version than the handled ones are told apart from the unknown
+    /// ones. The older versions are handled only by a handler registered for them, e.g. one
+    /// converting them
+    pub fn unhandled_reason(&self, event_type: &str) -> UnhandledReason {
+        let Some(unhandled) = VersionedType::parse(event_type) else {
+            return UnhandledReason::UnknownType;
+        };
+        let newest_handled = self
+            .handlers
+            .keys()
+            .filter_map(|handled| VersionedType::parse(handled))
+            .filter(|handled| handled.is_version_of(&unhandled))
+            .map(|handled| handled.version)
+            .max();
+
+        match newest_handled {
+            Some(version) if unhandled.version > version => UnhandledReason::NewerVersion,
+            _ => UnhandledReason::UnknownType,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn event_types(&self) -> Vec<&str> {
+        let mut event_types: Vec<&str> = self.handlers.keys().map(String::as_str).collect();
+        event_types.sort();
+
+        event_types
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::policy::MockEventTypeHandler;
+
+    #[test]
+    fn test_duplicated_handlers_are_rejected() {
+        let mut registry = HandlerRegistry::default();
+
+        assert!(registry
+            .register("Trento.Test.V1.Event", MockEventTypeHandler::new())
+            .is_ok());
+        assert_eq!(
+            registry.register("Trento.Test.V1.Event", MockEventTypeHandler::new()),
+            Err(RegistryErrors::DuplicateHandler(
+                "Trento.Test.V1.Event".to_owned()
+            ))
+        );
+        assert!(registry.get("Trento.Test.V1.Event").is_some());
+        assert!(registry.get("Trento.Test.V1.Other").is_none());
+        assert_eq!(registry.event_types(), vec!["Trento.Test.V1.Event"]);
+    }
+}
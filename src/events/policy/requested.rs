@@ -0,0 +1,63 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use trento_contracts::stubs::facts_gathering_requested::FactsGatheringRequested;
+
+use crate::logging::Correlation;
+
+/// Facts gathering request of any supported version of the event, the fields missing in the
+/// older versions take their defaults
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GatheringRequested {
+    pub execution_id: String,
+    pub group_id: String,
+    pub targets: Vec<GatheringTarget>,
+    /// Ids of the delivery of the event, not part of its data
+    pub correlation: Correlation,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GatheringTarget {
+    pub agent_id: String,
+    /// How long the execution waits for the facts of the agent, since V2
+    pub timeout: Option<Duration>,
+    pub fact_requests: Vec<RequestedFact>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestedFact {
+    pub argument: String,
+    pub check_id: String,
+    pub gatherer: String,
+    pub name: String,
+    /// Since V2
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl From<FactsGatheringRequested> for GatheringRequested {
+    fn from(event: FactsGatheringRequested) -> GatheringRequested {
+        GatheringRequested {
+            execution_id: event.execution_id,
+            group_id: event.group_id,
+            targets: event
+                .targets
+                .into_iter()
+                .map(|target| GatheringTarget {
+                    agent_id: target.agent_id,
+                    timeout: None,
+                    fact_requests: target
+                        .fact_requests
+                        .into_iter()
+                        .map(|fact_request| RequestedFact {
+                            argument: fact_request.argument,
+                            check_id: fact_request.check_id,
+                            gatherer: fact_request.gatherer,
+                            name: fact_request.name,
+                            metadata: BTreeMap::new(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            correlation: Correlation::default(),
+        }
+    }
+}
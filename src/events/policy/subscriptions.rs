@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use super::{EventsPolicy, OperationsPolicy};
+use crate::config::SubscriptionPolicy;
+use crate::events::EventsHandler;
+
+/// The policies shared by the consumers of all the subscriptions
+pub struct Policies {
+    checks: Arc<EventsPolicy>,
+    operations: Arc<OperationsPolicy>,
+}
+
+impl Policies {
+    pub fn new(checks: Arc<EventsPolicy>, operations: Arc<OperationsPolicy>) -> Policies {
+        Policies { checks, operations }
+    }
+
+    /// Handler of the events of a subscription, as named by its configuration
+    pub fn handler(&self, policy: SubscriptionPolicy) -> Arc<dyn EventsHandler> {
+        match policy {
+            SubscriptionPolicy::Checks => self.checks.clone(),
+            SubscriptionPolicy::Operations => self.operations.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::events::policy::fixtures::{fact_request, facts_gathering_requested};
+    use crate::events::{
+        DedupCache, EventContext, ExecutionQueue, HandleOutcome, SkipReason, UnhandledReason,
+    };
+    use crate::gatherers::{GatherersRegistryBuilder, GatheringEngine};
+
+    #[tokio::test]
+    async fn test_subscriptions_dispatch_to_their_policy() {
+        let engine = Arc::new(GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        ));
+        let checks = EventsPolicy::new(
+            ["agent_1"],
+            "sap-node-1",
+            engine,
+            DedupCache::new(Duration::ZERO, 0),
+            ExecutionQueue::default(),
+        )
+        .unwrap();
+        let policies = Policies::new(Arc::new(checks), Arc::new(OperationsPolicy));
+        let request = facts_gathering_requested()
+            .target("agent_2", vec![fact_request("unknown", "fact1")])
+            .build_json();
+        let operation = json!({
+            "type": "Trento.Operations.V1.OperatorExecutionRequested",
+            "data": {"operation_id": "operation1"},
+        })
+        .to_string()
+        .into_bytes();
+        let context = EventContext::json();
+
+        let checks = policies.handler(SubscriptionPolicy::Checks);
+        let operations = policies.handler(SubscriptionPolicy::Operations);
+
+        assert_eq!(
+            checks.handle_event(&request, &context).await.unwrap(),
+            HandleOutcome::Skipped(SkipReason::OtherAgents)
+        );
+        assert_eq!(
+            checks.handle_event(&operation, &context).await.unwrap(),
+            HandleOutcome::Unhandled(UnhandledReason::UnknownType)
+        );
+        assert_eq!(
+            operations.handle_event(&operation, &context).await.unwrap(),
+            HandleOutcome::Handled
+        );
+        assert_eq!(
+            operations.handle_event(&request, &context).await.unwrap(),
+            HandleOutcome::Unhandled(UnhandledReason::UnknownType)
+        );
+    }
+}
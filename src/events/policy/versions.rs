@@ -0,0 +1,107 @@
+use std::sync::Mutex;
+
+use tokio::time::{Duration, Instant};
+
+/// Type of an event, `<namespace>.V<version>.<name>` as in `Trento.Checks.V1.ExecutionCancelled`
+#[derive(Debug, PartialEq)]
+pub struct VersionedType<'a> {
+    pub namespace: &'a str,
+    pub version: u32,
+    pub name: &'a str,
+}
+
+impl VersionedType<'_> {
+    /// Missing when the type does not follow the versioned format
+    pub fn parse(event_type: &str) -> Option<VersionedType<'_>> {
+        let mut segments = event_type.rsplitn(3, '.');
+        let name = segments.next().filter(|name| !name.is_empty())?;
+        let version = segments.next()?.strip_prefix('V')?;
+        let namespace = segments.next().filter(|namespace| !namespace.is_empty())?;
+        if !version.chars().all(|digit| digit.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(VersionedType {
+            namespace,
+            version: version.parse().ok()?,
+            name,
+        })
+    }
+
+    /// Same event in another version
+    pub fn is_version_of(&self, other: &VersionedType) -> bool {
+        self.namespace == other.namespace && self.name == other.name
+    }
+}
+
+/// Lets a repeated log line through once per interval
+#[derive(Debug)]
+pub struct LogThrottle {
+    interval: Duration,
+    logged: Mutex<Option<Instant>>,
+}
+
+impl LogThrottle {
+    pub fn new(interval: Duration) -> LogThrottle {
+        LogThrottle {
+            interval,
+            logged: Mutex::new(None),
+        }
+    }
+
+    pub fn allow(&self) -> bool {
+        let mut logged = self.logged.lock().expect("log throttle poisoned, fatal.");
+        let now = Instant::now();
+        if logged.map_or(false, |logged| now.duration_since(logged) < self.interval) {
+            return false;
+        }
+        *logged = Some(now);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versioned_type() {
+        assert_eq!(
+            VersionedType::parse("Trento.Checks.V1.FactsGatheringRequested"),
+            Some(VersionedType {
+                namespace: "Trento.Checks",
+                version: 1,
+                name: "FactsGatheringRequested",
+            })
+        );
+        assert_eq!(
+            VersionedType::parse("Trento.Checks.V12.ExecutionCancelled")
+                .map(|parsed| parsed.version),
+            Some(12)
+        );
+        for malformed in [
+            "",
+            "Trento.Checks.FactsGatheringRequested",
+            "Trento.Checks.Vx.FactsGatheringRequested",
+            "Trento.Checks.V.FactsGatheringRequested",
+            "Trento.Checks.V-1.FactsGatheringRequested",
+            "V1.FactsGatheringRequested",
+            "Trento.Checks.V1.",
+        ] {
+            assert_eq!(VersionedType::parse(malformed), None, "{}", malformed);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_log_throttle() {
+        let throttle = LogThrottle::new(Duration::from_secs(60));
+
+        assert!(throttle.allow());
+        assert!(!throttle.allow());
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert!(!throttle.allow());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(throttle.allow());
+    }
+}
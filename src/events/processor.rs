@@ -0,0 +1,1466 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::broker::StatusTracker;
+use crate::config::{AckMode, DeliveryConfig, FailureAction, RetryMode, UnhandledEvents};
+use crate::events::{
+    media_type, DedupCache, EventEncoding, EventRecorder, EventsHandler, HandleOutcome,
+    PolicyErrors, Priority, UnhandledReason,
+};
+use crate::logging::{with_correlation, Correlation};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{task::JoinError, time::Instant};
+
+#[cfg(test)]
+mod memory;
+
+#[cfg(test)]
+use memory::{InMemorySource, MessageSource};
+
+/// Failed deliveries of a republished event
+pub const RETRIES_HEADER: &str = "x-vanvitelli-retries";
+
+/// Trace of the execution the event belongs to, as stamped by wanda
+pub const TRACE_ID_HEADER: &str = "x-trace-id";
+
+/// Priority of the execution, e.g. higher for the executions started by a user, the first one
+/// set is read
+pub const PRIORITY_HEADERS: [&str; 2] = ["x-priority", "priority"];
+
+/// When the event was published, in seconds since the epoch, for the publishers not setting the
+/// timestamp property
+pub const PUBLISHED_AT_HEADER: &str = "x-published-at";
+
+/// Value of a message header, the ones the processor does not read are kept opaque
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HeaderValue {
+    Integer(i64),
+    Text(String),
+    Other,
+}
+
+/// Event delivered by the broker, independent of the transport
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Message {
+    pub body: Vec<u8>,
+    pub context: EventContext,
+}
+
+/// Metadata of the delivery of an event, handed to the handler along with the event
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventContext {
+    pub routing_key: String,
+    /// Told by the broker when the event was delivered before
+    pub redelivered: bool,
+    pub delivery_tag: u64,
+    /// When the event was emitted, as told by the publisher
+    pub timestamp: Option<SystemTime>,
+    pub message_id: Option<String>,
+    pub correlation_id: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: BTreeMap<String, HeaderValue>,
+}
+
+impl EventContext {
+    /// Retries recorded in the headers, missing or invalid values count as no retry
+    pub fn retries(&self) -> u32 {
+        match self.headers.get(RETRIES_HEADER) {
+            Some(HeaderValue::Integer(retries)) => u32::try_from(*retries).unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Ids attached to the logs of the handling, a trace id which is not text is ignored
+    pub fn correlation(&self) -> Correlation {
+        let trace_id = match self.headers.get(TRACE_ID_HEADER) {
+            Some(HeaderValue::Text(trace_id)) => Some(trace_id.to_owned()),
+            _ => None,
+        };
+
+        Correlation {
+            correlation_id: self.correlation_id.clone(),
+            trace_id,
+        }
+    }
+
+    /// The timestamp property, then the published at header. A header which is not a number of
+    /// seconds is ignored
+    pub fn published_at(&self) -> Option<SystemTime> {
+        self.timestamp.or_else(|| {
+            let seconds = match self.headers.get(PUBLISHED_AT_HEADER)? {
+                HeaderValue::Integer(seconds) => u64::try_from(*seconds).ok(),
+                HeaderValue::Text(seconds) => seconds.trim().parse().ok(),
+                HeaderValue::Other => None,
+            };
+
+            seconds.map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+        })
+    }
+
+    pub fn encoding(&self) -> EventEncoding {
+        EventEncoding::from_content_type(self.content_type.as_deref())
+    }
+
+    /// The default priority when missing or not a number, out of range values are clamped
+    pub fn priority(&self) -> Priority {
+        let Some((header, value)) = PRIORITY_HEADERS
+            .iter()
+            .find_map(|header| self.headers.get(*header).map(|value| (*header, value)))
+        else {
+            return Priority::default();
+        };
+        let priority = match value {
+            HeaderValue::Integer(priority) => Some(*priority),
+            HeaderValue::Text(priority) => priority.trim().parse().ok(),
+            HeaderValue::Other => None,
+        };
+        let Some(priority) = priority else {
+            warn!(
+                "{} header {:?} is not a number, using the default priority",
+                header, value
+            );
+            return Priority::default();
+        };
+
+        let clamped = priority.clamp(0, i64::from(Priority::MAX.value()));
+        if clamped != priority {
+            warn!(
+                "{} header {} out of range, clamped to {}",
+                header, priority, clamped
+            );
+        }
+
+        Priority::new(u8::try_from(clamped).unwrap_or_default())
+    }
+
+    /// Context of a json event, the other metadata are missing
+    #[cfg(test)]
+    pub fn json() -> EventContext {
+        EventContext {
+            content_type: Some("application/json".to_owned()),
+            ..EventContext::default()
+        }
+    }
+}
+
+/// How a message is settled with the broker
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Ack,
+    NackRequeue,
+    /// Routed to the dead letter exchange if any
+    NackDiscard,
+    /// The event is published again with the given retries, then acked
+    Republish {
+        retries: u32,
+    },
+    /// The event no handler claims is published to the unhandled events destination, then acked
+    Forward(UnhandledReason),
+    /// Acknowledged by the broker on delivery, settling it again is a channel error
+    Auto,
+}
+
+/// Decides the outcome of the messages, the transport only delivers and settles them.
+/// The handler is shared by the processors of all the consumers, a trait object unless its
+/// type is known
+pub struct EventProcessor<H: ?Sized = dyn EventsHandler> {
+    handler: Arc<H>,
+    delivery: DeliveryConfig,
+    /// Position of the consumer among the ones sharing the handler
+    index: usize,
+    dedup: Arc<DedupCache>,
+    status: StatusTracker,
+    /// The deliveries are recorded before being processed when set
+    recorder: Option<Arc<EventRecorder>>,
+}
+
+// derived, it would require a handler that can be cloned
+impl<H: ?Sized> Clone for EventProcessor<H> {
+    fn clone(&self) -> EventProcessor<H> {
+        EventProcessor {
+            handler: self.handler.clone(),
+            delivery: self.delivery.clone(),
+            index: self.index,
+            dedup: self.dedup.clone(),
+            status: self.status.clone(),
+            recorder: self.recorder.clone(),
+        }
+    }
+}
+
+impl<H: EventsHandler + ?Sized + 'static> EventProcessor<H> {
+    pub fn new(handler: Arc<H>, delivery: DeliveryConfig) -> EventProcessor<H> {
+        EventProcessor {
+            handler,
+            delivery,
+            index: 0,
+            dedup: Arc::new(DedupCache::new(Duration::ZERO, 0)),
+            status: StatusTracker::default(),
+            recorder: None,
+        }
+    }
+
+    pub fn with_index(self, index: usize) -> EventProcessor<H> {
+        EventProcessor { index, ..self }
+    }
+
+    /// The cache is shared with the other consumers, as duplicates can be delivered to any of them
+    pub fn with_dedup(self, dedup: Arc<DedupCache>) -> EventProcessor<H> {
+        EventProcessor { dedup, ..self }
+    }
+
+    /// The handling durations are recorded in the connection status
+    pub fn with_status(self, status: StatusTracker) -> EventProcessor<H> {
+        EventProcessor { status, ..self }
+    }
+
+    /// The recorder is shared with the other consumers
+    pub fn with_recorder(self, recorder: Arc<EventRecorder>) -> EventProcessor<H> {
+        EventProcessor {
+            recorder: Some(recorder),
+            ..self
+        }
+    }
+
+    /// Handles the message unless it cannot be decoded or it was already handled.
+    /// The correlation ids of the message are attached to the logs of its processing
+    pub async fn process(&self, message: &Message) -> Outcome {
+        with_correlation(message.context.correlation(), self.process_message(message)).await
+    }
+
+    async fn process_message(&self, message: &Message) -> Outcome {
+        // recorded as received, whatever happens next
+        if let Some(recorder) = &self.recorder {
+            let event_type = self
+                .handler
+                .event_type(&message.body, message.context.encoding());
+            recorder.record(message, event_type.as_deref()).await;
+        }
+
+        if !self.accepts(message) {
+            // undecodable by the handler, retrying would fail again
+            warn!(
+                consumer = self.index;
+                "discarding event with unsupported content type {} with routing key {}",
+                message.context.content_type.as_deref().unwrap_or_default(),
+                message.context.routing_key
+            );
+
+            return self.outcome(Outcome::NackDiscard);
+        }
+
+        if let Some(age) = self.expired_age(message) {
+            info!(
+                consumer = self.index;
+                "event {} emitted {:?} ago, older than the maximum age of {:?}, acknowledging it as expired",
+                self.handler.event_id(&message.body, message.context.encoding()).unwrap_or_default(),
+                age,
+                self.delivery.max_event_age.unwrap_or_default()
+            );
+            self.status.expired();
+
+            return self.outcome(Outcome::Ack);
+        }
+
+        if self.is_duplicate(message) {
+            debug!(
+                consumer = self.index;
+                "event {} is a duplicate of an event already handled, skipping",
+                self.dedup_key(message).unwrap_or_default()
+            );
+
+            return self.outcome(Outcome::Ack);
+        }
+
+        self.handle(message).await
+    }
+
+    /// The redelivery of a requeued event is handled again, while the redeliveries of the
+    /// handled ones, e.g. after a consume channel is reopened, are skipped as duplicates
+    pub fn requeued(&self, message: &Message) {
+        self.forget(self.dedup_key(message));
+    }
+
+    /// Same as `requeued`, for the messages whose body was handed over, e.g. to their republish
+    pub fn forget(&self, dedup_key: Option<String>) {
+        if let Some(key) = dedup_key {
+            self.dedup.forget(&key);
+        }
+    }
+
+    /// The age of an expired event. Events without a timestamp, or emitted in the future as told
+    /// by a skewed clock, never expire
+    fn expired_age(&self, message: &Message) -> Option<Duration> {
+        let max_event_age = self.delivery.max_event_age?;
+
+        message
+            .context
+            .published_at()
+            .or_else(|| self.handler.event_time(&message.body))
+            .and_then(|timestamp| SystemTime::now().duration_since(timestamp).ok())
+            .filter(|age| *age > max_event_age)
+    }
+
+    /// Events are identified by the message id, or by the key the handler extracts from them
+    pub fn dedup_key(&self, message: &Message) -> Option<String> {
+        message.context.message_id.clone().or_else(|| {
+            self.handler
+                .event_id(&message.body, message.context.encoding())
+        })
+    }
+
+    fn is_duplicate(&self, message: &Message) -> bool {
+        // republished events already went through the deduplication
+        if !self.dedup.enabled() || message.context.retries() > 0 {
+            return false;
+        }
+
+        self.dedup_key(message)
+            .map_or(false, |key| self.dedup.seen(&key))
+    }
+
+    /// In auto ack mode the messages are never settled
+    fn outcome(&self, outcome: Outcome) -> Outcome {
+        match self.delivery.ack_mode {
+            AckMode::Manual => outcome,
+            AckMode::Auto => Outcome::Auto,
+        }
+    }
+
+    /// Events without a content type are accepted, the parameters of the content type are ignored
+    fn accepts(&self, message: &Message) -> bool {
+        message
+            .context
+            .content_type
+            .as_ref()
+            .map_or(true, |content_type| {
+                let media_type = media_type(content_type);
+
+                self.delivery
+                    .accepted_content_types
+                    .iter()
+                    .any(|accepted| accepted.eq_ignore_ascii_case(media_type))
+            })
+    }
+
+    /// Handled and skipped events are acknowledged, the failed ones are settled according to the
+    /// kind of failure, the retried ones according to the retry mode, then discarded.
+    /// Handlings exceeding the processing timeout are interrupted and discarded, as are the
+    /// handlings that panic. The handling runs on a task of its own, so that a panic fails its
+    /// delivery only
+    async fn handle(&self, message: &Message) -> Outcome {
+        let timeout = self.delivery.processing_timeout;
+        let started = Instant::now();
+        let handler = self.handler.clone();
+        let body = message.body.clone();
+        let context = message.context.clone();
+        let mut handling = tokio::spawn(with_correlation(
+            message.context.correlation(),
+            async move { handler.handle_event(&body, &context).await },
+        ));
+        let handled = tokio::time::timeout(timeout, &mut handling).await;
+        self.record(message, started.elapsed());
+
+        let result = match handled {
+            Ok(Ok(result)) => result,
+            // the same event would panic again
+            Ok(Err(err)) => {
+                let err = PolicyErrors::Internal(panic_message(err));
+                let execution_id = self
+                    .handler
+                    .event_id(&message.body, message.context.encoding())
+                    .unwrap_or_default();
+                self.status.rejected();
+                self.status.panicked();
+                error!(
+                    consumer = self.index, execution_id = execution_id.as_str(), failure = err.kind().as_str();
+                    "handling of event {} panicked, discarding the event: {}",
+                    execution_id,
+                    err
+                );
+
+                return self.outcome(Outcome::NackDiscard);
+            }
+            // retrying a hung handling is pointless
+            Err(_) => {
+                handling.abort();
+                self.status.rejected();
+                error!(
+                    consumer = self.index;
+                    "event {} not handled within the processing timeout of {:?}, discarding the event",
+                    self.handler.event_id(&message.body, message.context.encoding()).unwrap_or_default(),
+                    timeout
+                );
+
+                return self.outcome(Outcome::NackDiscard);
+            }
+        };
+
+        match result {
+            Ok(HandleOutcome::Handled) => {
+                self.status.succeeded();
+
+                self.outcome(Outcome::Ack)
+            }
+            Ok(HandleOutcome::Skipped(reason)) => {
+                self.status.skipped();
+                debug!(
+                    consumer = self.index, reason = reason.as_str();
+                    "event {} skipped: {}",
+                    self.handler.event_id(&message.body, message.context.encoding()).unwrap_or_default(),
+                    reason.as_str()
+                );
+
+                self.outcome(Outcome::Ack)
+            }
+            Ok(HandleOutcome::Unhandled(reason)) => {
+                self.status.skipped();
+
+                self.outcome(match self.delivery.unhandled_events {
+                    UnhandledEvents::Ack => Outcome::Ack,
+                    UnhandledEvents::Deadletter => Outcome::NackDiscard,
+                    UnhandledEvents::Forward { .. } => Outcome::Forward(reason),
+                })
+            }
+            Err(err) if self.delivery.ack_mode == AckMode::Auto => {
+                self.status.rejected();
+                error!(
+                    consumer = self.index, failure = err.kind().as_str();
+                    "error during event processing, the auto acknowledged event is lost: {}",
+                    err
+                );
+
+                Outcome::Auto
+            }
+            Err(err) => {
+                self.status.rejected();
+
+                self.failure_outcome(message, &err)
+            }
+        }
+    }
+
+    /// Slow handlings are reported before the executions time out upstream
+    fn record(&self, message: &Message, elapsed: Duration) {
+        let threshold = self.delivery.slow_threshold;
+        let slow = !threshold.is_zero() && elapsed > threshold;
+        let execution_id = self
+            .handler
+            .event_id(&message.body, message.context.encoding())
+            .unwrap_or_default();
+
+        self.status.handled(elapsed, slow);
+        debug!(
+            consumer = self.index, delivery_tag = message.context.delivery_tag;
+            "processed event {} in {:?}",
+            execution_id,
+            elapsed
+        );
+        if slow {
+            warn!(
+                consumer = self.index, execution_id = execution_id.as_str();
+                "slow handling of event {}, took {:?} exceeding the threshold of {:?}",
+                execution_id,
+                elapsed,
+                threshold
+            );
+        }
+    }
+
+    fn failure_outcome(&self, message: &Message, err: &PolicyErrors) -> Outcome {
+        let kind = err.kind().as_str();
+        let retries = message.context.retries();
+
+        match self.delivery.failure_action(err.kind()) {
+            FailureAction::Ack => {
+                warn!(
+                    consumer = self.index, failure = kind;
+                    "error during event processing, acknowledging the event: {}",
+                    err
+                );
+
+                Outcome::Ack
+            }
+            FailureAction::Retry if self.delivery.requeue_on_failure => {
+                match self.delivery.retry_mode {
+                    RetryMode::Redelivered if !message.context.redelivered => {
+                        error!(
+                            consumer = self.index, failure = kind;
+                            "error during event processing, requeueing the event once: {}",
+                            err
+                        );
+
+                        Outcome::NackRequeue
+                    }
+                    RetryMode::Headers if retries < self.delivery.max_retries => {
+                        error!(
+                            consumer = self.index, failure = kind;
+                            "error during event processing, retry {} of {}: {}",
+                            retries + 1,
+                            self.delivery.max_retries,
+                            err
+                        );
+
+                        Outcome::Republish {
+                            retries: retries + 1,
+                        }
+                    }
+                    _ => self.discard(err),
+                }
+            }
+            FailureAction::Retry | FailureAction::Discard => self.discard(err),
+        }
+    }
+
+    fn discard(&self, err: &PolicyErrors) -> Outcome {
+        error!(
+            consumer = self.index, failure = err.kind().as_str();
+            "error during event processing, discarding the event: {}",
+            err
+        );
+
+        Outcome::NackDiscard
+    }
+}
+
+/// The payload of the panic, when it is a message
+fn panic_message(err: JoinError) -> String {
+    if !err.is_panic() {
+        return err.to_string();
+    }
+
+    let payload = err.into_panic();
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| (*message).to_owned())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "panic without a message".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FailureKind;
+    use crate::events::{MockEventsHandler, SkipReason};
+
+    fn delivery_config(requeue_on_failure: bool, max_retries: u32) -> DeliveryConfig {
+        DeliveryConfig {
+            requeue_on_failure,
+            recover_deliveries: true,
+            retry_mode: RetryMode::Headers,
+            max_retries,
+            prefetch_count: 10,
+            consumer_count: 2,
+            max_in_flight: Some(20),
+            accepted_content_types: vec!["application/x-protobuf".to_owned()],
+            dedup_ttl: Duration::from_secs(600),
+            dedup_capacity: 1024,
+            ack_mode: AckMode::Manual,
+            ack_batch_size: 1,
+            ack_flush_interval: Duration::from_millis(100),
+            processing_timeout: Duration::from_secs(600),
+            slow_threshold: Duration::from_secs(60),
+            warm_up: None,
+            max_event_age: None,
+            failure_actions: BTreeMap::from([
+                (FailureKind::Decode, FailureAction::Discard),
+                (FailureKind::Validation, FailureAction::Ack),
+                (FailureKind::Transient, FailureAction::Retry),
+            ]),
+            unhandled_events: UnhandledEvents::Ack,
+        }
+    }
+
+    fn processor(
+        result: fn() -> Result<HandleOutcome, PolicyErrors>,
+        requeue_on_failure: bool,
+        max_retries: u32,
+    ) -> EventProcessor<MockEventsHandler> {
+        let mut handler = MockEventsHandler::new();
+        handler
+            .expect_handle_event()
+            .returning(move |_, _| result());
+
+        EventProcessor::new(
+            Arc::new(handler),
+            delivery_config(requeue_on_failure, max_retries),
+        )
+    }
+
+    fn transient() -> Result<HandleOutcome, PolicyErrors> {
+        Err(PolicyErrors::TransientError(
+            "registry lock poisoned".to_owned(),
+        ))
+    }
+
+    fn retried(retries: i64) -> Message {
+        Message {
+            context: EventContext {
+                headers: BTreeMap::from([(
+                    RETRIES_HEADER.to_owned(),
+                    HeaderValue::Integer(retries),
+                )]),
+                ..EventContext::default()
+            },
+            ..Message::default()
+        }
+    }
+
+    fn with_message_id(message_id: &str) -> Message {
+        Message {
+            context: EventContext {
+                message_id: Some(message_id.to_owned()),
+                ..EventContext::default()
+            },
+            ..Message::default()
+        }
+    }
+
+    // the outcomes the processor settled the messages of the source with
+    async fn outcomes<H: EventsHandler + ?Sized + 'static>(
+        processor: &EventProcessor<H>,
+        messages: impl IntoIterator<Item = Message>,
+    ) -> Vec<Outcome> {
+        let mut source = InMemorySource::new(messages);
+        source.drain(processor).await;
+
+        source.outcomes()
+    }
+
+    #[tokio::test]
+    async fn test_handled_event_is_acked() {
+        assert_eq!(
+            outcomes(
+                &processor(|| Ok(HandleOutcome::Handled), true, 1),
+                [Message::default()]
+            )
+            .await,
+            vec![Outcome::Ack]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_outcomes_are_settled_and_counted() {
+        let status = StatusTracker::default();
+        let cases: [(fn() -> Result<HandleOutcome, PolicyErrors>, Outcome); 4] = [
+            (|| Ok(HandleOutcome::Handled), Outcome::Ack),
+            (
+                || Ok(HandleOutcome::Skipped(SkipReason::OtherAgents)),
+                Outcome::Ack,
+            ),
+            (
+                || Err(PolicyErrors::DecodeError("truncated message".to_owned())),
+                Outcome::NackDiscard,
+            ),
+            (
+                || {
+                    Err(PolicyErrors::ValidationError(
+                        "missing execution id".to_owned(),
+                    ))
+                },
+                Outcome::Ack,
+            ),
+        ];
+
+        for (result, outcome) in cases {
+            let processor = processor(result, true, 1).with_status(status.clone());
+            assert_eq!(
+                outcomes(&processor, [Message::default()]).await,
+                vec![outcome]
+            );
+        }
+        let counted = status.reader().snapshot().outcomes;
+        assert_eq!(
+            (counted.handled, counted.skipped, counted.rejected),
+            (1, 1, 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unhandled_events_are_settled_by_mode() {
+        let processor = |unhandled_events: UnhandledEvents| {
+            let mut handler = MockEventsHandler::new();
+            handler
+                .expect_handle_event()
+                .returning(|_, _| Ok(HandleOutcome::Unhandled(UnhandledReason::UnknownType)));
+
+            EventProcessor::new(
+                Arc::new(handler),
+                DeliveryConfig {
+                    unhandled_events,
+                    ..delivery_config(true, 1)
+                },
+            )
+        };
+        let forward = UnhandledEvents::Forward {
+            exchange: "trento.unhandled".to_owned(),
+            routing_key: "unhandled".to_owned(),
+        };
+
+        for (unhandled_events, outcome) in [
+            (UnhandledEvents::Ack, Outcome::Ack),
+            (UnhandledEvents::Deadletter, Outcome::NackDiscard),
+            (forward, Outcome::Forward(UnhandledReason::UnknownType)),
+        ] {
+            let processor = processor(unhandled_events);
+
+            assert_eq!(
+                outcomes(&processor, [Message::default()]).await,
+                vec![outcome]
+            );
+            assert_eq!(processor.status.reader().snapshot().outcomes.skipped, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_event_is_republished() {
+        assert_eq!(
+            outcomes(&processor(transient, true, 1), [Message::default()]).await,
+            vec![Outcome::Republish { retries: 1 }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_event_is_republished_from_its_buffer() {
+        let message = Message {
+            body: b"event".to_vec(),
+            ..Message::default()
+        };
+        // the handler borrows the delivered buffer, left untouched for the republish
+        let address = message.body.as_ptr() as usize;
+        let mut handler = MockEventsHandler::new();
+        handler
+            .expect_handle_event()
+            .withf(move |raw_event, _, _| raw_event.as_ptr() as usize == address)
+            .times(1)
+            .returning(|_, _| transient());
+        let processor = EventProcessor::new(Arc::new(handler), delivery_config(true, 1));
+
+        assert_eq!(
+            processor.process(&message).await,
+            Outcome::Republish { retries: 1 }
+        );
+        assert_eq!(message.body.as_ptr() as usize, address);
+        assert_eq!(message.body, b"event");
+    }
+
+    #[tokio::test]
+    async fn test_failed_event_within_retries_is_republished() {
+        assert_eq!(
+            outcomes(&processor(transient, true, 3), [retried(2)]).await,
+            vec![Outcome::Republish { retries: 3 }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_event_exceeding_retries_is_discarded() {
+        assert_eq!(
+            outcomes(&processor(transient, true, 3), [retried(3)]).await,
+            vec![Outcome::NackDiscard]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_event_is_discarded_without_requeue() {
+        assert_eq!(
+            outcomes(&processor(transient, false, 1), [Message::default()]).await,
+            vec![Outcome::NackDiscard]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_event_is_requeued_once() {
+        let processor = |retry_mode| EventProcessor {
+            delivery: DeliveryConfig {
+                retry_mode,
+                ..delivery_config(true, 1)
+            },
+            ..processor(transient, true, 1)
+        };
+        let redelivered = Message {
+            context: EventContext {
+                redelivered: true,
+                ..EventContext::default()
+            },
+            ..Message::default()
+        };
+
+        let cases = [
+            (
+                RetryMode::Redelivered,
+                Message::default(),
+                Outcome::NackRequeue,
+            ),
+            (
+                RetryMode::Redelivered,
+                redelivered.clone(),
+                Outcome::NackDiscard,
+            ),
+            (
+                RetryMode::Headers,
+                redelivered,
+                Outcome::Republish { retries: 1 },
+            ),
+            (RetryMode::Discard, Message::default(), Outcome::NackDiscard),
+        ];
+
+        for (retry_mode, message, outcome) in cases {
+            assert_eq!(
+                outcomes(&processor(retry_mode), [message]).await,
+                vec![outcome]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auto_acknowledged_events_are_never_settled() {
+        let auto = |result| EventProcessor {
+            delivery: DeliveryConfig {
+                ack_mode: AckMode::Auto,
+                ..delivery_config(true, 1)
+            },
+            ..processor(result, true, 1)
+        };
+        let unsupported = Message {
+            context: EventContext {
+                content_type: Some("application/json".to_owned()),
+                ..EventContext::default()
+            },
+            ..Message::default()
+        };
+
+        let results: [fn() -> Result<HandleOutcome, PolicyErrors>; 2] =
+            [|| Ok(HandleOutcome::Handled), transient];
+        for result in results {
+            assert_eq!(
+                outcomes(&auto(result), [Message::default()]).await,
+                vec![Outcome::Auto]
+            );
+        }
+        assert_eq!(
+            outcomes(&auto(|| Ok(HandleOutcome::Handled)), [unsupported]).await,
+            vec![Outcome::Auto]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_settled_by_kind() {
+        let cases: [(fn() -> Result<HandleOutcome, PolicyErrors>, Outcome); 4] = [
+            (
+                || Err(PolicyErrors::DecodeError("truncated message".to_owned())),
+                Outcome::NackDiscard,
+            ),
+            (
+                || {
+                    Err(PolicyErrors::ValidationError(
+                        "missing execution id".to_owned(),
+                    ))
+                },
+                Outcome::Ack,
+            ),
+            (
+                || {
+                    Err(PolicyErrors::TransientError(
+                        "publish channel closed".to_owned(),
+                    ))
+                },
+                Outcome::Republish { retries: 1 },
+            ),
+            // missing from the table, discarded
+            (
+                || {
+                    Err(PolicyErrors::GathererError(
+                        "corosync.conf".to_owned(),
+                        "file not found".to_owned(),
+                    ))
+                },
+                Outcome::NackDiscard,
+            ),
+        ];
+
+        for (result, outcome) in cases {
+            assert_eq!(
+                outcomes(&processor(result, true, 1), [Message::default()]).await,
+                vec![outcome]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_processors_share_the_handler() {
+        let mut handler = MockEventsHandler::new();
+        handler
+            .expect_handle_event()
+            .times(2)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        let handler: Arc<dyn EventsHandler> = Arc::new(handler);
+
+        let processors: Vec<EventProcessor> = (0..2)
+            .map(|index| {
+                EventProcessor::new(handler.clone(), delivery_config(true, 1)).with_index(index)
+            })
+            .collect();
+
+        for processor in &processors {
+            assert_eq!(
+                outcomes(processor, [Message::default()]).await,
+                vec![Outcome::Ack]
+            );
+        }
+
+        assert_eq!(processors[1].index, 1);
+        // the processors and the local reference
+        assert_eq!(Arc::strong_count(&handler), 3);
+    }
+
+    // the body is the handling duration in seconds
+    struct SleepingHandler;
+
+    #[async_trait::async_trait]
+    impl EventsHandler for SleepingHandler {
+        async fn handle_event(
+            &self,
+            raw_event: &[u8],
+            _context: &EventContext,
+        ) -> Result<HandleOutcome, PolicyErrors> {
+            tokio::time::sleep(Duration::from_secs(u64::from(raw_event[0]))).await;
+            Ok(HandleOutcome::Handled)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hung_event_is_discarded() {
+        let processor = EventProcessor::new(
+            Arc::new(SleepingHandler),
+            DeliveryConfig {
+                processing_timeout: Duration::from_secs(3),
+                ..delivery_config(true, 1)
+            },
+        );
+        let sleeping = |seconds| Message {
+            body: vec![seconds],
+            ..Message::default()
+        };
+
+        assert_eq!(
+            outcomes(&processor, [sleeping(2), sleeping(5)]).await,
+            vec![Outcome::Ack, Outcome::NackDiscard]
+        );
+    }
+
+    // panics on the events whose body is `panic`
+    struct PanickingHandler;
+
+    #[async_trait::async_trait]
+    impl EventsHandler for PanickingHandler {
+        async fn handle_event(
+            &self,
+            raw_event: &[u8],
+            _context: &EventContext,
+        ) -> Result<HandleOutcome, PolicyErrors> {
+            if raw_event == b"panic" {
+                panic!("gatherer state corrupted");
+            }
+
+            Ok(HandleOutcome::Handled)
+        }
+
+        fn event_id(&self, raw_event: &[u8], _encoding: EventEncoding) -> Option<String> {
+            Some(String::from_utf8_lossy(raw_event).into_owned())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handling_is_discarded() {
+        let status = StatusTracker::default();
+        let reader = status.reader();
+        let processor = EventProcessor::new(Arc::new(PanickingHandler), delivery_config(true, 1))
+            .with_status(status);
+        let event = |body: &str| Message {
+            body: body.as_bytes().to_vec(),
+            ..Message::default()
+        };
+
+        // the following deliveries are still processed
+        assert_eq!(
+            outcomes(
+                &processor,
+                [event("event1"), event("panic"), event("event2")]
+            )
+            .await,
+            vec![Outcome::Ack, Outcome::NackDiscard, Outcome::Ack]
+        );
+
+        let counted = reader.snapshot().outcomes;
+        assert_eq!(counted.handled, 2);
+        assert_eq!(counted.rejected, 1);
+        assert_eq!(counted.panicked, 1);
+        assert_eq!(
+            panic_message(
+                tokio::spawn(async { panic!("gatherer {} failed", "cibadmin") })
+                    .await
+                    .unwrap_err()
+            ),
+            "gatherer cibadmin failed"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_handlings_are_recorded() {
+        let status = StatusTracker::default();
+        let reader = status.reader();
+        let processor = EventProcessor::new(
+            Arc::new(SleepingHandler),
+            DeliveryConfig {
+                slow_threshold: Duration::from_secs(3),
+                ..delivery_config(true, 1)
+            },
+        )
+        .with_status(status);
+        let sleeping = |seconds| Message {
+            body: vec![seconds],
+            ..Message::default()
+        };
+
+        assert_eq!(
+            outcomes(&processor, [sleeping(1), sleeping(5), sleeping(3)]).await,
+            vec![Outcome::Ack; 3]
+        );
+
+        let handling = reader.snapshot().handling;
+        assert_eq!(handling.count, 3);
+        // only the handling exceeding the threshold is slow
+        assert_eq!(handling.slow, 1);
+        assert_eq!(handling.min, Some(Duration::from_secs(1)));
+        assert_eq!(handling.max, Some(Duration::from_secs(5)));
+        assert_eq!(handling.average(), Some(Duration::from_secs(3)));
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_are_recorded_before_handling() {
+        let dir = std::env::temp_dir().join("vanvitelli_test_processor_record");
+        let _ = std::fs::remove_dir_all(&dir);
+        let blocker = std::env::temp_dir().join("vanvitelli_test_processor_record_blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let processor = |dir: &std::path::Path| {
+            let mut handler = MockEventsHandler::new();
+            handler
+                .expect_event_type()
+                .returning(|_, _| Some("Trento.Test.V1.Event".to_owned()));
+            handler.expect_event_id().returning(|_, _| None);
+            handler
+                .expect_handle_event()
+                .times(1)
+                .returning(|_, _| Ok(HandleOutcome::Handled));
+
+            EventProcessor::new(Arc::new(handler), delivery_config(true, 1))
+                .with_recorder(Arc::new(EventRecorder::new(dir)))
+        };
+        let unsupported = Message {
+            context: EventContext::json(),
+            ..Message::default()
+        };
+
+        let recorded = processor(&dir);
+        assert_eq!(
+            outcomes(&recorded, [Message::default(), unsupported]).await,
+            vec![Outcome::Ack, Outcome::NackDiscard]
+        );
+        let recordings = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // the discarded delivery is recorded too
+        assert_eq!(recordings, 4);
+
+        // the handling proceeds when the recording fails
+        let unrecorded = processor(&blocker.join("events"));
+        assert_eq!(
+            outcomes(&unrecorded, [Message::default()]).await,
+            vec![Outcome::Ack]
+        );
+        std::fs::remove_file(blocker).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expired_events_are_acked_without_handling() {
+        let status = StatusTracker::default();
+        let reader = status.reader();
+        let mut handler = MockEventsHandler::new();
+        handler
+            .expect_handle_event()
+            .times(2)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        handler.expect_event_id().returning(|_, _| None);
+        handler.expect_event_time().returning(|raw_event| {
+            (!raw_event.is_empty()).then(|| SystemTime::now() - Duration::from_secs(7200))
+        });
+        let processor = EventProcessor::new(
+            Arc::new(handler),
+            DeliveryConfig {
+                max_event_age: Some(Duration::from_secs(3600)),
+                ..delivery_config(true, 1)
+            },
+        )
+        .with_status(status);
+        let emitted = |age| Message {
+            context: EventContext {
+                timestamp: Some(SystemTime::now() - Duration::from_secs(age)),
+                ..EventContext::default()
+            },
+            ..Message::default()
+        };
+        let from_payload = Message {
+            body: vec![1],
+            ..Message::default()
+        };
+        let from_header = Message {
+            context: EventContext {
+                headers: BTreeMap::from([(
+                    PUBLISHED_AT_HEADER.to_owned(),
+                    HeaderValue::Integer(1_700_000_000),
+                )]),
+                ..EventContext::default()
+            },
+            ..Message::default()
+        };
+
+        assert_eq!(
+            outcomes(
+                &processor,
+                [
+                    emitted(7200),
+                    emitted(60),
+                    Message::default(),
+                    from_payload,
+                    from_header
+                ]
+            )
+            .await,
+            vec![Outcome::Ack; 5]
+        );
+        assert_eq!(reader.snapshot().expired, 3);
+    }
+
+    #[test]
+    fn test_event_age() {
+        let processor = |max_event_age| {
+            let mut handler = MockEventsHandler::new();
+            handler.expect_event_time().returning(|_| None);
+
+            EventProcessor::new(
+                Arc::new(handler),
+                DeliveryConfig {
+                    max_event_age,
+                    ..delivery_config(true, 1)
+                },
+            )
+        };
+        let emitted = |timestamp| Message {
+            context: EventContext {
+                timestamp: Some(timestamp),
+                ..EventContext::default()
+            },
+            ..Message::default()
+        };
+        let published = |published_at: HeaderValue| Message {
+            context: EventContext {
+                headers: BTreeMap::from([(PUBLISHED_AT_HEADER.to_owned(), published_at)]),
+                ..EventContext::default()
+            },
+            ..Message::default()
+        };
+        let seconds_ago = |age: Duration| {
+            (SystemTime::now() - age)
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        let hour = Duration::from_secs(3600);
+        let now = SystemTime::now();
+
+        assert!(processor(Some(hour))
+            .expired_age(&emitted(now - 2 * hour))
+            .map_or(false, |age| age >= 2 * hour));
+        assert_eq!(
+            processor(Some(hour)).expired_age(&emitted(now - hour / 2)),
+            None
+        );
+        // skewed clock of the publisher
+        assert_eq!(
+            processor(Some(hour)).expired_age(&emitted(now + hour)),
+            None
+        );
+        assert_eq!(processor(Some(hour)).expired_age(&Message::default()), None);
+        assert_eq!(processor(None).expired_age(&emitted(now - 2 * hour)), None);
+
+        let old = seconds_ago(2 * hour);
+        let fresh = seconds_ago(hour / 2);
+        assert!(processor(Some(hour))
+            .expired_age(&published(HeaderValue::Integer(
+                i64::try_from(old).unwrap()
+            )))
+            .is_some());
+        assert!(processor(Some(hour))
+            .expired_age(&published(HeaderValue::Text(old.to_string())))
+            .is_some());
+        assert_eq!(
+            processor(Some(hour)).expired_age(&published(HeaderValue::Integer(
+                i64::try_from(fresh).unwrap()
+            ))),
+            None
+        );
+        assert_eq!(
+            processor(Some(hour))
+                .expired_age(&published(HeaderValue::Text("yesterday".to_owned()))),
+            None
+        );
+        assert_eq!(
+            processor(Some(hour)).expired_age(&published(HeaderValue::Integer(-1))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_published_at() {
+        let context = |timestamp, published_at: Option<i64>| EventContext {
+            timestamp,
+            headers: published_at
+                .map(|seconds| {
+                    BTreeMap::from([(
+                        PUBLISHED_AT_HEADER.to_owned(),
+                        HeaderValue::Integer(seconds),
+                    )])
+                })
+                .unwrap_or_default(),
+            ..EventContext::default()
+        };
+        let at = |seconds| UNIX_EPOCH + Duration::from_secs(seconds);
+
+        // the timestamp property comes first
+        assert_eq!(
+            context(Some(at(1_700_000_000)), Some(1_600_000_000)).published_at(),
+            Some(at(1_700_000_000))
+        );
+        assert_eq!(
+            context(None, Some(1_600_000_000)).published_at(),
+            Some(at(1_600_000_000))
+        );
+        assert_eq!(context(None, None).published_at(), None);
+    }
+
+    #[tokio::test]
+    async fn test_content_types() {
+        let with_content_type = |content_type: &str| Message {
+            context: EventContext {
+                content_type: Some(content_type.to_owned()),
+                ..EventContext::default()
+            },
+            ..Message::default()
+        };
+        let mut handler = MockEventsHandler::new();
+        handler
+            .expect_handle_event()
+            .times(3)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        let processor = EventProcessor::new(Arc::new(handler), delivery_config(true, 1));
+
+        assert_eq!(
+            outcomes(
+                &processor,
+                [
+                    with_content_type("application/x-protobuf"),
+                    with_content_type("Application/X-Protobuf; proto=trento"),
+                    Message::default(),
+                    with_content_type("application/json"),
+                    with_content_type("application/octet-stream"),
+                ]
+            )
+            .await,
+            vec![
+                Outcome::Ack,
+                Outcome::Ack,
+                Outcome::Ack,
+                Outcome::NackDiscard,
+                Outcome::NackDiscard
+            ]
+        );
+    }
+
+    #[test]
+    fn test_context_correlation() {
+        let context = EventContext {
+            correlation_id: Some("corr1".to_owned()),
+            headers: BTreeMap::from([(
+                TRACE_ID_HEADER.to_owned(),
+                HeaderValue::Text("trace1".to_owned()),
+            )]),
+            ..EventContext::default()
+        };
+        let untraced = EventContext {
+            headers: BTreeMap::from([(TRACE_ID_HEADER.to_owned(), HeaderValue::Integer(1))]),
+            ..EventContext::default()
+        };
+
+        assert_eq!(
+            context.correlation(),
+            Correlation {
+                correlation_id: Some("corr1".to_owned()),
+                trace_id: Some("trace1".to_owned()),
+            }
+        );
+        assert_eq!(untraced.correlation(), Correlation::default());
+    }
+
+    #[tokio::test]
+    async fn test_json_content_type_encoding() {
+        let with_content_type = |content_type: &str| Message {
+            context: EventContext {
+                content_type: Some(content_type.to_owned()),
+                ..EventContext::default()
+            },
+            ..Message::default()
+        };
+        let mut handler = MockEventsHandler::new();
+        handler
+            .expect_handle_event()
+            .withf(|_, context| context.encoding() == EventEncoding::Json)
+            .times(1)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        handler
+            .expect_handle_event()
+            .withf(|_, context| context.encoding() == EventEncoding::Protobuf)
+            .times(2)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        let mut delivery = delivery_config(true, 1);
+        delivery
+            .accepted_content_types
+            .push("application/json".to_owned());
+        let processor = EventProcessor::new(Arc::new(handler), delivery);
+
+        assert_eq!(
+            outcomes(
+                &processor,
+                [
+                    with_content_type("application/json; charset=utf-8"),
+                    with_content_type("application/x-protobuf"),
+                    Message::default(),
+                ]
+            )
+            .await,
+            vec![Outcome::Ack; 3]
+        );
+    }
+
+    fn deduplicating_processor(handler: MockEventsHandler) -> EventProcessor<MockEventsHandler> {
+        EventProcessor::new(Arc::new(handler), delivery_config(true, 1))
+            .with_dedup(Arc::new(DedupCache::new(Duration::from_secs(600), 10)))
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_by_message_id() {
+        let mut handler = MockEventsHandler::new();
+        handler
+            .expect_handle_event()
+            .times(2)
+            .returning(|_, _| Ok(HandleOutcome::Handled));
+        let processor = deduplicating_processor(handler);
+
+        assert_eq!(
+            outcomes(
+                &processor,
+                [
+                    with_message_id("message1"),
+                    with_message_id("message1"),
+                    with_message_id("message2"),
+                ]
+            )
+            .await,
+            vec![Outcome::Ack; 3]
+        );
+        assert_eq!(processor.dedup.duplicates(), 1);
+    }
+
+    #[test]
+    fn test_recovered_deliveries_deduplication() {
+        let processor = deduplicating_processor(MockEventsHandler::new());
+        let handled = with_message_id("message1");
+        let requeued = with_message_id("message2");
+
+        assert!(!processor.is_duplicate(&handled));
+        assert!(!processor.is_duplicate(&requeued));
+        processor.requeued(&requeued);
+
+        // recovered after the consume channel is reopened, only the requeued event is handled
+        assert!(processor.is_duplicate(&handled));
+        assert!(!processor.is_duplicate(&requeued));
+    }
+
+    #[test]
+    fn test_duplicates_by_handler_key() {
+        let mut handler = MockEventsHandler::new();
+        handler
+            .expect_event_id()
+            .times(2)
+            .returning(|_, _| Some("exec1".to_owned()));
+        let processor = deduplicating_processor(handler);
+
+        assert!(!processor.is_duplicate(&Message::default()));
+        assert!(processor.is_duplicate(&Message::default()));
+        // republished events are not deduplicated
+        assert!(!processor.is_duplicate(&retried(1)));
+    }
+
+    #[test]
+    fn test_retries_header() {
+        let garbage = EventContext {
+            headers: BTreeMap::from([(
+                RETRIES_HEADER.to_owned(),
+                HeaderValue::Text("many".to_owned()),
+            )]),
+            ..EventContext::default()
+        };
+
+        assert_eq!(EventContext::default().retries(), 0);
+        assert_eq!(garbage.retries(), 0);
+        assert_eq!(retried(-1).context.retries(), 0);
+        assert_eq!(retried(2).context.retries(), 2);
+    }
+
+    #[test]
+    fn test_context_priority() {
+        let prioritized = |header: &str, value: HeaderValue| EventContext {
+            headers: BTreeMap::from([(header.to_owned(), value)]),
+            ..EventContext::default()
+        };
+
+        assert_eq!(EventContext::default().priority(), Priority::default());
+        assert_eq!(
+            prioritized("x-priority", HeaderValue::Integer(3)).priority(),
+            Priority::new(3)
+        );
+        assert_eq!(
+            prioritized("priority", HeaderValue::Text(" 7 ".to_owned())).priority(),
+            Priority::new(7)
+        );
+        assert_eq!(
+            prioritized("x-priority", HeaderValue::Integer(42)).priority(),
+            Priority::MAX
+        );
+        assert_eq!(
+            prioritized("priority", HeaderValue::Integer(-1)).priority(),
+            Priority::default()
+        );
+        assert_eq!(
+            prioritized("x-priority", HeaderValue::Text("urgent".to_owned())).priority(),
+            Priority::default()
+        );
+        assert_eq!(
+            prioritized("x-priority", HeaderValue::Other).priority(),
+            Priority::default()
+        );
+    }
+}
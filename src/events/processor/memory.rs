@@ -0,0 +1,50 @@
+use std::collections::VecDeque;
+
+use super::{EventProcessor, EventsHandler, Message, Outcome};
+
+/// Delivers the messages to the processor and settles them with their outcome
+#[async_trait::async_trait]
+pub trait MessageSource: Send {
+    async fn receive(&mut self) -> Option<Message>;
+    async fn settle(&mut self, message: Message, outcome: Outcome);
+
+    /// Processes the messages one after the other, until the source is exhausted
+    async fn drain<H: EventsHandler + ?Sized + 'static>(&mut self, processor: &EventProcessor<H>) {
+        while let Some(message) = self.receive().await {
+            let outcome = processor.process(&message).await;
+            self.settle(message, outcome).await;
+        }
+    }
+}
+
+/// Source of a fixed list of messages, recording how they were settled
+#[derive(Debug, Default)]
+pub struct InMemorySource {
+    messages: VecDeque<Message>,
+    settled: Vec<(Message, Outcome)>,
+}
+
+impl InMemorySource {
+    pub fn new(messages: impl IntoIterator<Item = Message>) -> InMemorySource {
+        InMemorySource {
+            messages: messages.into_iter().collect(),
+            settled: vec![],
+        }
+    }
+
+    /// Outcomes of the settled messages, in the order they were settled
+    pub fn outcomes(&self) -> Vec<Outcome> {
+        self.settled.iter().map(|(_, outcome)| *outcome).collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageSource for InMemorySource {
+    async fn receive(&mut self) -> Option<Message> {
+        self.messages.pop_front()
+    }
+
+    async fn settle(&mut self, message: Message, outcome: Outcome) {
+        self.settled.push((message, outcome));
+    }
+}
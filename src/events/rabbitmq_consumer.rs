@@ -1,46 +1,882 @@
-use crate::events::policy::EventsPolicy;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::broker::{BlockedState, CircuitBreaker, Publisher, StatusTracker};
+use crate::config::{AckMode, DeliveryConfig, UnhandledEvents};
+use crate::disk_guard::DiskSpace;
+use crate::events::{
+    AckBatch, AckLedger, ChannelEpoch, DedupCache, EventContext, EventProcessor, EventRecorder,
+    EventsHandler, HeaderValue, Message, Outcome, UnhandledReason, RETRIES_HEADER,
+};
+use crate::shutdown::{InFlight, InFlightGuard};
 use amqprs::{
-    channel::{BasicAckArguments, Channel},
+    channel::{BasicPublishArguments, Channel},
     consumer::AsyncConsumer,
-    BasicProperties, Deliver,
+    BasicProperties, Deliver, FieldTable, FieldValue,
 };
-use log::{debug, error};
+use log::{debug, warn};
+use tokio::task::JoinHandle;
+
+// publishing to the default exchange routes the event to the queue named by the routing key
+const DEFAULT_EXCHANGE: &str = "";
+// why the agent published an event it did not handle
+const REASON_HEADER: &str = "x-vanvitelli-reason";
+
+/// Adapter of the event processor to the amqprs deliveries, settling them with the broker.
+/// The consumers of every channel share the same handler
+pub struct RabbitMqConsumer<H: ?Sized = dyn EventsHandler> {
+    processor: EventProcessor<H>,
+    queue: String,
+    /// Position of the consumer among the ones sharing the handler
+    index: usize,
+    delivery: DeliveryConfig,
+    in_flight: InFlight,
+    blocked: BlockedState,
+    disk: DiskSpace,
+    /// Channel for the republished events, the delivery one is used when missing
+    publisher: Option<Publisher<Channel>>,
+    breaker: Arc<CircuitBreaker>,
+    /// Channel the consumer is attached to, its delivery tags are stale once it is replaced
+    epoch: ChannelEpoch,
+    acks: Arc<AckBatch>,
+    status: StatusTracker,
+}
 
-pub struct RabbitMqConsumer {
-    policy: EventsPolicy,
+// derived, it would require a handler that can be cloned
+impl<H: ?Sized> Clone for RabbitMqConsumer<H> {
+    fn clone(&self) -> RabbitMqConsumer<H> {
+        RabbitMqConsumer {
+            processor: self.processor.clone(),
+            queue: self.queue.clone(),
+            index: self.index,
+            delivery: self.delivery.clone(),
+            in_flight: self.in_flight.clone(),
+            blocked: self.blocked.clone(),
+            disk: self.disk.clone(),
+            publisher: self.publisher.clone(),
+            breaker: self.breaker.clone(),
+            epoch: self.epoch.clone(),
+            acks: self.acks.clone(),
+            status: self.status.clone(),
+        }
+    }
 }
 
-impl RabbitMqConsumer {
-    pub fn new(events_policy: EventsPolicy) -> RabbitMqConsumer {
+impl<H: EventsHandler + ?Sized + 'static> RabbitMqConsumer<H> {
+    pub fn new(
+        handler: Arc<H>,
+        queue: &str,
+        delivery: DeliveryConfig,
+        in_flight: InFlight,
+    ) -> RabbitMqConsumer<H> {
+        let breaker = Arc::new(CircuitBreaker::disabled());
+        let epoch = ChannelEpoch::default();
+
+        RabbitMqConsumer {
+            processor: EventProcessor::new(handler, delivery.clone()),
+            queue: queue.to_owned(),
+            index: 0,
+            acks: ack_batch(&delivery, &breaker, &BlockedState::default(), &epoch),
+            epoch,
+            delivery,
+            in_flight,
+            blocked: BlockedState::default(),
+            disk: DiskSpace::default(),
+            publisher: None,
+            breaker,
+            status: StatusTracker::default(),
+        }
+    }
+
+    pub fn with_index(self, index: usize) -> RabbitMqConsumer<H> {
+        RabbitMqConsumer {
+            processor: self.processor.with_index(index),
+            index,
+            ..self
+        }
+    }
+
+    /// Deliveries are not processed while the broker blocks the connection, the publishes and
+    /// the acknowledgements wait while it stops the flow of the channels
+    pub fn with_blocked_state(self, blocked: BlockedState) -> RabbitMqConsumer<H> {
+        RabbitMqConsumer {
+            acks: ack_batch(&self.delivery, &self.breaker, &blocked, &self.epoch),
+            blocked,
+            ..self
+        }
+    }
+
+    /// A consumer is built for every channel it is attached to, so a new channel epoch starts
+    /// for its index in the ledger shared with the other consumers
+    pub fn with_ack_ledger(self, ledger: Arc<AckLedger>) -> RabbitMqConsumer<H> {
+        let epoch = ledger.reopened(self.index);
+
+        RabbitMqConsumer {
+            acks: ack_batch(&self.delivery, &self.breaker, &self.blocked, &epoch),
+            epoch,
+            ..self
+        }
+    }
+
+    /// The cache is shared with the other consumers, as duplicates can be delivered to any of them
+    pub fn with_dedup(self, dedup: Arc<DedupCache>) -> RabbitMqConsumer<H> {
+        RabbitMqConsumer {
+            processor: self.processor.with_dedup(dedup),
+            ..self
+        }
+    }
+
+    /// Deliveries are not processed while the disk the agent writes to is low on space
+    pub fn with_disk_space(self, disk: DiskSpace) -> RabbitMqConsumer<H> {
+        RabbitMqConsumer { disk, ..self }
+    }
+
+    pub fn with_publisher(self, publisher: Publisher<Channel>) -> RabbitMqConsumer<H> {
+        RabbitMqConsumer {
+            publisher: Some(publisher),
+            ..self
+        }
+    }
+
+    /// Records the outcome of the acknowledgements and the publishes,
+    /// deliveries are not processed while the breaker is open
+    pub fn with_breaker(self, breaker: Arc<CircuitBreaker>) -> RabbitMqConsumer<H> {
+        RabbitMqConsumer {
+            acks: ack_batch(&self.delivery, &breaker, &self.blocked, &self.epoch),
+            breaker,
+            ..self
+        }
+    }
+
+    /// Every delivery and its handling duration are recorded in the connection status
+    pub fn with_status(self, status: StatusTracker) -> RabbitMqConsumer<H> {
+        RabbitMqConsumer {
+            processor: self.processor.with_status(status.clone()),
+            status,
+            ..self
+        }
+    }
+
+    /// Every consumed delivery is recorded before being processed
+    pub fn with_recorder(self, recorder: Arc<EventRecorder>) -> RabbitMqConsumer<H> {
         RabbitMqConsumer {
-            policy: events_policy,
+            processor: self.processor.with_recorder(recorder),
+            ..self
+        }
+    }
+
+    /// Acknowledgements of the deliveries, settled again on shutdown
+    pub fn acks(&self) -> Arc<AckBatch> {
+        self.acks.clone()
+    }
+
+    /// Processes the delivery on a task of its own, so that a slow event does not hold back the
+    /// following ones. The delivery is settled by `settle` once processed, which releases the
+    /// in-flight guard
+    fn spawn_handling<S, F>(
+        &self,
+        in_flight: InFlightGuard,
+        message: Message,
+        settle: S,
+    ) -> JoinHandle<()>
+    where
+        S: FnOnce(Outcome, Message, InFlightGuard) -> F + Send + 'static,
+        F: Future<Output = ()> + Send,
+    {
+        let processor = self.processor.clone();
+
+        tokio::spawn(async move {
+            let outcome = processor.process(&message).await;
+
+            settle(outcome, message, in_flight).await;
+        })
+    }
+
+    /// The acknowledged deliveries keep the in-flight guard until their batch is sent
+    async fn settle(
+        &self,
+        channel: &Channel,
+        deliver: &Deliver,
+        properties: &BasicProperties,
+        message: Message,
+        outcome: Outcome,
+        in_flight: InFlightGuard,
+    ) {
+        debug!(
+            consumer = self.index;
+            "settling delivery {} - {}: {:?}",
+            deliver, channel, outcome
+        );
+
+        let delivery_tag = message.context.delivery_tag;
+        let result = match outcome {
+            Outcome::Ack => self.acks.ack(channel, delivery_tag, in_flight).await,
+            Outcome::Republish { retries } => {
+                // the body is handed over to the publish, the key is kept for a requeue
+                let dedup_key = self.processor.dedup_key(&message);
+                match self
+                    .republish(channel, properties, message.body, retries)
+                    .await
+                {
+                    Ok(_) => self.acks.ack(channel, delivery_tag, in_flight).await,
+                    // a failed publish leaves the consume channel open, so the delivery is requeued
+                    Err(err) => {
+                        self.breaker.record_failure();
+                        warn!(
+                            consumer = self.index;
+                            "unable to republish delivery {}, requeueing it: {}",
+                            delivery_tag,
+                            err
+                        );
+
+                        self.processor.forget(dedup_key);
+                        self.acks.nack(channel, delivery_tag, true).await
+                    }
+                }
+            }
+            Outcome::Forward(reason) => {
+                let dedup_key = self.processor.dedup_key(&message);
+                match self
+                    .forward(channel, properties, message.body, reason)
+                    .await
+                {
+                    Ok(_) => self.acks.ack(channel, delivery_tag, in_flight).await,
+                    Err(err) => {
+                        self.breaker.record_failure();
+                        warn!(
+                            consumer = self.index;
+                            "unable to forward the unhandled delivery {}, requeueing it: {}",
+                            delivery_tag,
+                            err
+                        );
+
+                        self.processor.forget(dedup_key);
+                        self.acks.nack(channel, delivery_tag, true).await
+                    }
+                }
+            }
+            Outcome::NackRequeue => {
+                self.processor.requeued(&message);
+                self.acks.nack(channel, delivery_tag, true).await
+            }
+            Outcome::NackDiscard => self.acks.nack(channel, delivery_tag, false).await,
+            Outcome::Auto => Ok(()),
+        };
+
+        // the broker requeues the unacknowledged deliveries once the channel is gone
+        if let Err(err) = result {
+            warn!(
+                consumer = self.index;
+                "unable to acknowledge delivery {}: {}",
+                delivery_tag,
+                err
+            );
+        }
+    }
+
+    /// Publishes the event back to the consumed queue only, as other agents bound
+    /// to the exchange already received it
+    async fn republish(
+        &self,
+        channel: &Channel,
+        properties: &BasicProperties,
+        content: Vec<u8>,
+        retries: u32,
+    ) -> Result<(), String> {
+        self.publish_channel(channel)
+            .await?
+            .basic_publish(
+                republish_properties(properties, retries),
+                content,
+                BasicPublishArguments::new(DEFAULT_EXCHANGE, &self.queue),
+            )
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    /// Publishes the event no handler claims to the unhandled events destination, with its
+    /// original properties
+    async fn forward(
+        &self,
+        channel: &Channel,
+        properties: &BasicProperties,
+        content: Vec<u8>,
+        reason: UnhandledReason,
+    ) -> Result<(), String> {
+        let UnhandledEvents::Forward {
+            exchange,
+            routing_key,
+        } = &self.delivery.unhandled_events
+        else {
+            return Err("no destination of the unhandled events".to_owned());
+        };
+
+        self.publish_channel(channel)
+            .await?
+            .basic_publish(
+                forward_properties(properties, reason.as_str()),
+                content,
+                BasicPublishArguments::new(exchange, routing_key),
+            )
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    async fn publish_channel(&self, channel: &Channel) -> Result<Channel, String> {
+        // waits while the broker stops the flow, before picking the channel as it can be reopened
+        self.blocked.pass().await;
+
+        match &self.publisher {
+            Some(publisher) => publisher
+                .channel()
+                .ok_or_else(|| "the publish channel is not open".to_owned()),
+            None => Ok(channel.to_owned()),
         }
     }
 }
 
 #[async_trait::async_trait]
-impl AsyncConsumer for RabbitMqConsumer {
+impl<H: EventsHandler + ?Sized + 'static> AsyncConsumer for RabbitMqConsumer<H> {
     async fn consume(
         &mut self,
         channel: &Channel,
         deliver: Deliver,
-        _basic_properties: BasicProperties,
+        basic_properties: BasicProperties,
         content: Vec<u8>,
     ) {
-        debug!("consume delivery {} on channel {}", deliver, channel);
+        self.status.delivered();
+        // released once the delivery is acknowledged, waits while too many deliveries are handled
+        let in_flight = self.in_flight.acquire().await;
+        // the auto acknowledged deliveries are never requeued on shutdown
+        if self.delivery.ack_mode == AckMode::Manual {
+            self.acks.delivered(deliver.delivery_tag()).await;
+        }
+
+        debug!(
+            consumer = self.index;
+            "consume delivery {} on channel {}",
+            deliver,
+            channel
+        );
 
-        match self.policy.handle_event(content).await {
-            Ok(_) => {
-                debug!("processed event {} - {}", deliver, channel)
+        // the results could not be published, the next deliveries wait in the prefetch buffer
+        if self.blocked.is_blocked() {
+            debug!(
+                consumer = self.index;
+                "delivery {} paused until the connection is unblocked",
+                deliver.delivery_tag()
+            );
+            self.blocked.unblocked().await;
+        }
+
+        // the handlings write to the disk, the next deliveries wait in the prefetch buffer
+        if self.disk.is_low() {
+            debug!(
+                consumer = self.index;
+                "delivery {} paused until the disk space is available",
+                deliver.delivery_tag()
+            );
+            self.disk.available().await;
+        }
+
+        // the broker operations keep failing, the deliveries wait until a probe succeeds
+        if self.breaker.is_open() {
+            debug!(
+                consumer = self.index;
+                "delivery {} paused until the circuit breaker closes",
+                deliver.delivery_tag()
+            );
+            self.breaker
+                .closed(|| async {
+                    channel
+                        .basic_qos(self.delivery.qos_arguments())
+                        .await
+                        .map_err(|err| err.to_string())
+                })
+                .await;
+        }
+
+        let consumer = self.clone();
+        let channel = channel.clone();
+        self.spawn_handling(
+            in_flight,
+            message(&deliver, &basic_properties, content),
+            move |outcome, message, in_flight| async move {
+                consumer
+                    .settle(
+                        &channel,
+                        &deliver,
+                        &basic_properties,
+                        message,
+                        outcome,
+                        in_flight,
+                    )
+                    .await
+            },
+        );
+    }
+}
+
+fn ack_batch(
+    delivery: &DeliveryConfig,
+    breaker: &Arc<CircuitBreaker>,
+    blocked: &BlockedState,
+    epoch: &ChannelEpoch,
+) -> Arc<AckBatch> {
+    Arc::new(
+        AckBatch::new(
+            delivery.ack_batch_size,
+            delivery.ack_flush_interval,
+            breaker.clone(),
+        )
+        .with_gate(blocked.clone())
+        .with_epoch(epoch.clone()),
+    )
+}
+
+/// The delivery as seen by the event processor
+fn message(deliver: &Deliver, properties: &BasicProperties, content: Vec<u8>) -> Message {
+    Message {
+        body: content,
+        context: EventContext {
+            routing_key: deliver.routing_key().to_owned(),
+            redelivered: deliver.redelivered(),
+            delivery_tag: deliver.delivery_tag(),
+            timestamp: timestamp(properties),
+            message_id: properties.message_id().cloned(),
+            correlation_id: properties.correlation_id().cloned(),
+            content_type: properties.content_type().cloned(),
+            headers: properties.headers().map(headers).unwrap_or_default(),
+        },
+    }
+}
+
+/// The timestamp property is in seconds since the epoch
+fn timestamp(properties: &BasicProperties) -> Option<SystemTime> {
+    properties
+        .timestamp()
+        .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Integer and string headers are kept, the other field types are not read by the processor
+fn headers(table: &FieldTable) -> BTreeMap<String, HeaderValue> {
+    table
+        .as_ref()
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                FieldValue::l(value) => HeaderValue::Integer(*value),
+                FieldValue::I(value) => HeaderValue::Integer(i64::from(*value)),
+                FieldValue::i(value) => HeaderValue::Integer(i64::from(*value)),
+                FieldValue::S(value) => HeaderValue::Text(value.to_string()),
+                _ => HeaderValue::Other,
+            };
+
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Copy of the original properties, with the retries header updated
+fn republish_properties(properties: &BasicProperties, retries: u32) -> BasicProperties {
+    let mut headers = properties
+        .headers()
+        .cloned()
+        .unwrap_or_else(FieldTable::new);
+    headers.insert(
+        RETRIES_HEADER
+            .try_into()
+            .expect("invalid retries header name, fatal."),
+        FieldValue::l(i64::from(retries)),
+    );
+
+    properties.clone().with_headers(headers).finish()
+}
+
+/// Copy of the original properties, with the reason of the publish
+fn forward_properties(properties: &BasicProperties, reason: &str) -> BasicProperties {
+    let mut headers = properties
+        .headers()
+        .cloned()
+        .unwrap_or_else(FieldTable::new);
+    headers.insert(
+        REASON_HEADER
+            .try_into()
+            .expect("invalid reason header name, fatal."),
+        FieldValue::S(reason.try_into().expect("invalid reason, fatal.")),
+    );
+
+    properties.clone().with_headers(headers).finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+    };
+
+    use super::*;
+    use crate::config::{FailureAction, FailureKind, RetryMode};
+    use crate::events::policy::fixtures::{fact_request, facts_gathering_requested};
+    use crate::events::processor::PUBLISHED_AT_HEADER;
+    use crate::events::{EventsPolicy, ExecutionQueue, HandleOutcome, PolicyErrors};
+    use crate::gatherers::{
+        FactsGathered, FactsGatheringRequest, Gatherer, GatherersRegistryBuilder, GatheringEngine,
+        MockGatherer,
+    };
+    use crate::logging::Correlation;
+
+    fn delivery_config() -> DeliveryConfig {
+        DeliveryConfig {
+            requeue_on_failure: true,
+            recover_deliveries: true,
+            retry_mode: RetryMode::Headers,
+            max_retries: 1,
+            prefetch_count: 10,
+            consumer_count: 2,
+            max_in_flight: Some(20),
+            accepted_content_types: vec!["application/x-protobuf".to_owned()],
+            dedup_ttl: Duration::from_secs(600),
+            dedup_capacity: 1024,
+            ack_mode: AckMode::Manual,
+            ack_batch_size: 1,
+            ack_flush_interval: Duration::from_millis(100),
+            processing_timeout: Duration::from_secs(600),
+            slow_threshold: Duration::from_secs(60),
+            warm_up: None,
+            max_event_age: None,
+            failure_actions: BTreeMap::from([
+                (FailureKind::Decode, FailureAction::Discard),
+                (FailureKind::Validation, FailureAction::Ack),
+                (FailureKind::Transient, FailureAction::Retry),
+            ]),
+            unhandled_events: UnhandledEvents::Ack,
+        }
+    }
+
+    fn properties_with_retries(retries: FieldValue) -> BasicProperties {
+        let mut headers = FieldTable::new();
+        headers.insert(RETRIES_HEADER.try_into().unwrap(), retries);
+
+        BasicProperties::default()
+            .with_content_type("application/x-protobuf")
+            .with_headers(headers)
+            .finish()
+    }
+
+    fn retries(properties: &BasicProperties) -> u32 {
+        EventContext {
+            headers: properties.headers().map(headers).unwrap_or_default(),
+            ..EventContext::default()
+        }
+        .retries()
+    }
+
+    // gathers with a slow gatherer, recording the highest number of concurrent handlings
+    struct GatheringHandler {
+        gatherer: MockGatherer,
+        running: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl EventsHandler for GatheringHandler {
+        async fn handle_event(
+            &self,
+            _raw_event: &[u8],
+            _context: &EventContext,
+        ) -> Result<HandleOutcome, PolicyErrors> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(running, Ordering::SeqCst);
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            self.gatherer
+                .gather(FactsGatheringRequest {
+                    execution_id: "exec1".to_owned(),
+                    group_id: "group1".to_owned(),
+                    facts_requests_by_gatherer: HashMap::new(),
+                    timeout: None,
+                    correlation: Correlation::default(),
+                })
+                .await;
+
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(HandleOutcome::Handled)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_in_flight_handlings_are_bounded() {
+        let mut gatherer = MockGatherer::new();
+        gatherer
+            .expect_gather()
+            .times(20)
+            .returning(|request| FactsGathered {
+                agent_id: "agent_1".to_owned(),
+                agent_name: "agent_1".to_owned(),
+                exeuction_id: request.execution_id,
+                facts_gathered: vec![],
+                group_id: request.group_id,
+            });
+        let handler = Arc::new(GatheringHandler {
+            gatherer,
+            running: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        });
+        let in_flight = InFlight::new(Some(3));
+
+        let deliveries: Vec<_> = (0..20)
+            .map(|index| {
+                let consumer = RabbitMqConsumer::new(
+                    handler.clone(),
+                    "vanvitelli.agent_1",
+                    delivery_config(),
+                    in_flight.clone(),
+                )
+                .with_index(index % 2);
+
+                // what consume does before settling the delivery
+                tokio::spawn(async move {
+                    let _in_flight = consumer.in_flight.acquire().await;
+                    consumer.processor.process(&Message::default()).await
+                })
+            })
+            .collect();
+
+        for delivery in deliveries {
+            assert_eq!(delivery.await.unwrap(), Outcome::Ack);
+        }
+
+        assert_eq!(handler.peak.load(Ordering::SeqCst), 3);
+        assert_eq!(in_flight.count(), 0);
+    }
+
+    // the body is the handling duration in seconds
+    struct SleepingHandler;
+
+    #[async_trait::async_trait]
+    impl EventsHandler for SleepingHandler {
+        async fn handle_event(
+            &self,
+            raw_event: &[u8],
+            _context: &EventContext,
+        ) -> Result<HandleOutcome, PolicyErrors> {
+            tokio::time::sleep(Duration::from_secs(u64::from(raw_event[0]))).await;
+            Ok(HandleOutcome::Handled)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_consumers_share_the_policy() {
+        let engine = GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        );
+        let policy = Arc::new(
+            EventsPolicy::new(
+                ["agent_1"],
+                "sap-node-1",
+                Arc::new(engine),
+                DedupCache::new(Duration::from_secs(900), 16),
+                ExecutionQueue::default(),
+            )
+            .unwrap(),
+        );
+        let consumers: Vec<RabbitMqConsumer<EventsPolicy>> = (0..2)
+            .map(|index| {
+                RabbitMqConsumer::new(
+                    policy.clone(),
+                    "vanvitelli.agent_1",
+                    DeliveryConfig {
+                        accepted_content_types: vec!["application/json".to_owned()],
+                        ..delivery_config()
+                    },
+                    InFlight::default(),
+                )
+                .with_index(index)
+            })
+            .collect();
+        let request = |message_id: &str| Message {
+            body: facts_gathering_requested()
+                .execution_id("0d0b1e6c-4b6c-4b7e-8f8d-0a2a7c1e5d3f")
+                .group_id("7c1d3a4e-2f5b-4c6d-9e8f-1a2b3c4d5e6f")
+                .target("agent_1", vec![fact_request("test_gat", "fact1")])
+                .build_json(),
+            context: EventContext {
+                message_id: Some(message_id.to_owned()),
+                ..EventContext::json()
+            },
+        };
+
+        // the same execution delivered to both consumers, under different message ids
+        for (consumer, message_id) in consumers.iter().zip(["message1", "message2"]) {
+            assert_eq!(
+                consumer.processor.process(&request(message_id)).await,
+                Outcome::Ack
+            );
+        }
+
+        // the second consumer knows the execution gathered by the first one
+        assert_eq!(policy.replayed_executions(), 1);
+        // the consumers and the local reference
+        assert_eq!(Arc::strong_count(&policy), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deliveries_are_handled_concurrently() {
+        let in_flight = InFlight::default();
+        let consumer = RabbitMqConsumer::new(
+            Arc::new(SleepingHandler),
+            "vanvitelli.agent_1",
+            delivery_config(),
+            in_flight.clone(),
+        );
+        let settled = Arc::new(Mutex::new(vec![]));
+
+        let handlings: Vec<JoinHandle<()>> = [5, 1]
+            .into_iter()
+            .map(|duration| {
+                let settled = settled.clone();
+                consumer.spawn_handling(
+                    in_flight.start(),
+                    Message {
+                        body: vec![duration],
+                        ..Message::default()
+                    },
+                    move |outcome, message, _in_flight| async move {
+                        settled.lock().unwrap().push((message.body[0], outcome));
+                    },
+                )
+            })
+            .collect();
+        assert_eq!(in_flight.count(), 2);
+
+        for handling in handlings {
+            handling.await.unwrap();
+        }
+
+        // the fast event is settled first, the slow one does not hold it back
+        assert_eq!(
+            *settled.lock().unwrap(),
+            vec![(1, Outcome::Ack), (5, Outcome::Ack)]
+        );
+        assert_eq!(in_flight.count(), 0);
+    }
+
+    #[test]
+    fn test_message_headers() {
+        let mut table = FieldTable::new();
+        table.insert("x-count".try_into().unwrap(), FieldValue::I(2));
+        table.insert(
+            "x-name".try_into().unwrap(),
+            FieldValue::S("many".try_into().unwrap()),
+        );
+        table.insert("x-flag".try_into().unwrap(), FieldValue::t(true));
+
+        assert_eq!(
+            headers(&table),
+            BTreeMap::from([
+                ("x-count".to_owned(), HeaderValue::Integer(2)),
+                ("x-flag".to_owned(), HeaderValue::Other),
+                ("x-name".to_owned(), HeaderValue::Text("many".to_owned())),
+            ])
+        );
+        assert_eq!(retries(&BasicProperties::default()), 0);
+        assert_eq!(retries(&properties_with_retries(FieldValue::l(-1))), 0);
+        assert_eq!(retries(&properties_with_retries(FieldValue::l(2))), 2);
+        assert_eq!(retries(&properties_with_retries(FieldValue::i(2))), 2);
+    }
+
+    #[test]
+    fn test_message_timestamp() {
+        let emitted = BasicProperties::default()
+            .with_timestamp(1_700_000_000)
+            .finish();
+
+        assert_eq!(
+            timestamp(&emitted),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(timestamp(&BasicProperties::default()), None);
+    }
+
+    #[test]
+    fn test_message_published_at() {
+        let published = |seconds: Option<u64>, published_at: Option<FieldValue>| {
+            let mut properties = BasicProperties::default();
+            if let Some(seconds) = seconds {
+                properties.with_timestamp(seconds);
             }
-            Err(err) => {
-                error!("error during event processing {}", err)
+            if let Some(published_at) = published_at {
+                let mut headers = FieldTable::new();
+                headers.insert(PUBLISHED_AT_HEADER.try_into().unwrap(), published_at);
+                properties.with_headers(headers);
             }
-        }
 
-        channel
-            .basic_ack(BasicAckArguments::new(deliver.delivery_tag(), false))
-            .await
-            .expect("unable to ack rabbitmq message, fatal");
+            EventContext {
+                timestamp: timestamp(&properties),
+                headers: properties.headers().map(headers).unwrap_or_default(),
+                ..EventContext::default()
+            }
+            .published_at()
+        };
+        let at = |seconds| Some(UNIX_EPOCH + Duration::from_secs(seconds));
+
+        assert_eq!(
+            published(Some(1_700_000_000), Some(FieldValue::l(1_600_000_000))),
+            at(1_700_000_000)
+        );
+        assert_eq!(
+            published(None, Some(FieldValue::l(1_600_000_000))),
+            at(1_600_000_000)
+        );
+        assert_eq!(
+            published(None, Some(FieldValue::S("1600000000".try_into().unwrap()))),
+            at(1_600_000_000)
+        );
+        assert_eq!(published(None, Some(FieldValue::t(true))), None);
+        assert_eq!(published(None, None), None);
+    }
+
+    #[test]
+    fn test_republish_properties() {
+        let republished = republish_properties(&properties_with_retries(FieldValue::l(1)), 2);
+
+        assert_eq!(retries(&republished), 2);
+        assert_eq!(
+            republished.content_type(),
+            Some(&"application/x-protobuf".to_owned())
+        );
+        assert_eq!(
+            retries(&republish_properties(&BasicProperties::default(), 1)),
+            1
+        );
+    }
+    #[test]
+    fn test_forward_properties() {
+        let forwarded = forward_properties(
+            &properties_with_retries(FieldValue::l(1)),
+            UnhandledReason::UnknownType.as_str(),
+        );
+        let forwarded_headers = forwarded.headers().map(headers).unwrap();
+
+        assert_eq!(
+            forwarded_headers[REASON_HEADER],
+            HeaderValue::Text("unhandled_event_type".to_owned())
+        );
+        assert_eq!(retries(&forwarded), 1);
+        assert_eq!(
+            forwarded.content_type(),
+            Some(&"application/x-protobuf".to_owned())
+        );
     }
 }
@@ -0,0 +1,454 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info, warn};
+use tokio::io::AsyncWriteExt;
+
+use super::Message;
+
+const PAYLOAD_EXTENSION: &str = "bin";
+const CONTEXT_EXTENSION: &str = "json";
+const UNKNOWN_EVENT_TYPE: &str = "unknown";
+
+/// Keeps the recordings bounded while the agent runs, not only at startup
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Keeps the exact bytes of every consumed delivery, to tell what the agent received.
+/// Every delivery is written to `<recorded_at>-<event_type>.bin`, along with its context in a
+/// json file of the same name. Recording is best effort, failures are logged and never fail the
+/// delivery.
+pub struct EventRecorder {
+    dir: PathBuf,
+    /// Recordings are kept forever when missing
+    retention: Option<Duration>,
+    /// In bytes, unbounded when missing
+    max_size: Option<u64>,
+}
+
+/// The payload and the context of a delivery, pruned together
+#[derive(Debug, Default)]
+struct Recording {
+    paths: Vec<PathBuf>,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl EventRecorder {
+    pub fn new(dir: &Path) -> EventRecorder {
+        EventRecorder {
+            dir: dir.to_owned(),
+            retention: None,
+            max_size: None,
+        }
+    }
+
+    pub fn with_retention(self, retention: Option<Duration>) -> EventRecorder {
+        EventRecorder { retention, ..self }
+    }
+
+    pub fn with_max_size(self, max_size: Option<u64>) -> EventRecorder {
+        EventRecorder { max_size, ..self }
+    }
+
+    /// Microseconds since the epoch, so that the recordings are listed in their order
+    pub fn payload_path(&self, recorded_at: SystemTime, event_type: Option<&str>) -> PathBuf {
+        let timestamp = recorded_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        // the event type comes from the broker, it must not escape the recording directory
+        let event_type: String = event_type
+            .filter(|event_type| !event_type.is_empty())
+            .unwrap_or(UNKNOWN_EVENT_TYPE)
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect();
+
+        self.dir
+            .join(format!("{}-{}", timestamp, event_type))
+            .with_extension(PAYLOAD_EXTENSION)
+    }
+
+    /// Returns the path of the written payload, `None` when the delivery could not be recorded
+    pub async fn record(&self, message: &Message, event_type: Option<&str>) -> Option<PathBuf> {
+        match self.write(message, event_type).await {
+            Ok(path) => {
+                debug!(
+                    "delivery {} recorded to {}",
+                    message.context.delivery_tag,
+                    path.display()
+                );
+                Some(path)
+            }
+            Err(err) => {
+                warn!(
+                    "could not record delivery {} in {}: {}",
+                    message.context.delivery_tag,
+                    self.dir.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// A recording of the same time and type is never overwritten, the following microsecond
+    /// is taken instead
+    async fn write(&self, message: &Message, event_type: Option<&str>) -> io::Result<PathBuf> {
+        let context = serde_json::to_vec_pretty(&message.context)?;
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let mut recorded_at = SystemTime::now();
+        let (path, mut payload) = loop {
+            let path = self.payload_path(recorded_at, event_type);
+            match tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(payload) => break (path, payload),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    recorded_at += Duration::from_micros(1);
+                }
+                Err(err) => return Err(err),
+            }
+        };
+        payload.write_all(&message.body).await?;
+        payload.flush().await?;
+        tokio::fs::write(path.with_extension(CONTEXT_EXTENSION), context).await?;
+
+        Ok(path)
+    }
+
+    /// The payloads recorded in the directory, in their recording order
+    pub fn recorded(dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut payloads: Vec<PathBuf> = fs::read_dir(dir)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == PAYLOAD_EXTENSION)
+            })
+            .collect();
+        payloads.sort_by_key(|path| (recorded_at(path), path.clone()));
+
+        Ok(payloads)
+    }
+
+    /// The delivery recorded in the payload and its context, failing when either is missing
+    /// or the context is corrupt
+    pub fn read(payload: &Path) -> io::Result<Message> {
+        let body = fs::read(payload)?;
+        let context =
+            serde_json::from_slice(&fs::read(payload.with_extension(CONTEXT_EXTENSION))?)?;
+
+        Ok(Message { body, context })
+    }
+
+    /// Removes the recordings older than the retention, then the oldest ones until the
+    /// directory fits the maximum size, returning the number of removed recordings
+    pub fn prune(&self) -> usize {
+        let threshold = self
+            .retention
+            .and_then(|retention| SystemTime::now().checked_sub(retention));
+
+        self.prune_before(threshold)
+    }
+
+    /// Prunes the recordings periodically, the first time right away
+    pub async fn sweep(self: Arc<EventRecorder>) {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            let pruned = self.prune();
+            if pruned > 0 {
+                info!(
+                    "pruned {} recorded deliveries from {}",
+                    pruned,
+                    self.dir.display()
+                );
+            }
+        }
+    }
+
+    fn prune_before(&self, threshold: Option<SystemTime>) -> usize {
+        let mut recordings = match self.recordings() {
+            Ok(recordings) => recordings,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return 0,
+            Err(err) => {
+                warn!(
+                    "could not prune recorded deliveries in {}: {}",
+                    self.dir.display(),
+                    err
+                );
+                return 0;
+            }
+        };
+
+        let mut pruned = 0;
+
+        if let Some(threshold) = threshold {
+            recordings.retain(|_, recording| {
+                let expired = recording
+                    .modified
+                    .map_or(false, |modified| modified < threshold);
+                if expired && self.remove(recording) {
+                    pruned += 1;
+                }
+
+                !expired
+            });
+        }
+
+        if let Some(max_size) = self.max_size {
+            let mut size: u64 = recordings.values().map(|recording| recording.size).sum();
+            // named after their recording time, the oldest first
+            for recording in recordings.values() {
+                if size <= max_size {
+                    break;
+                }
+                size = size.saturating_sub(recording.size);
+                if self.remove(recording) {
+                    pruned += 1;
+                }
+            }
+        }
+
+        pruned
+    }
+
+    /// The recordings by name, the files of other extensions are left alone
+    fn recordings(&self) -> io::Result<BTreeMap<String, Recording>> {
+        let mut recordings: BTreeMap<String, Recording> = BTreeMap::new();
+
+        for path in fs::read_dir(&self.dir)?.flatten().map(|entry| entry.path()) {
+            let recorded = path.extension().map_or(false, |extension| {
+                extension == PAYLOAD_EXTENSION || extension == CONTEXT_EXTENSION
+            });
+            if !recorded {
+                continue;
+            }
+            let (Some(name), Ok(metadata)) = (path.file_stem(), fs::metadata(&path)) else {
+                continue;
+            };
+
+            let recording = recordings
+                .entry(name.to_string_lossy().into_owned())
+                .or_default();
+            recording.size += metadata.len();
+            recording.modified = recording.modified.max(metadata.modified().ok());
+            recording.paths.push(path);
+        }
+
+        Ok(recordings)
+    }
+
+    fn remove(&self, recording: &Recording) -> bool {
+        let mut removed = true;
+        for path in &recording.paths {
+            if let Err(err) = fs::remove_file(path) {
+                warn!("could not prune {}: {}", path.display(), err);
+                removed = false;
+            }
+        }
+
+        removed
+    }
+}
+
+/// Microseconds since the epoch the payload was recorded at, as told by its name
+fn recorded_at(payload: &Path) -> Option<u128> {
+    payload
+        .file_stem()?
+        .to_str()?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::events::{EventContext, HeaderValue};
+
+    fn record_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vanvitelli_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn message() -> Message {
+        Message {
+            body: b"\x0a\x04test".to_vec(),
+            context: EventContext {
+                routing_key: "agent_1".to_owned(),
+                delivery_tag: 7,
+                message_id: Some("message1".to_owned()),
+                headers: BTreeMap::from([("x-priority".to_owned(), HeaderValue::Integer(5))]),
+                ..EventContext::json()
+            },
+        }
+    }
+
+    fn create(path: &Path, size: usize, modified: SystemTime) {
+        fs::write(path, vec![0; size]).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_payload_path() {
+        let recorder = EventRecorder::new(Path::new("/var/lib/vanvitelli/events"));
+        let recorded_at = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+
+        assert_eq!(
+            recorder.payload_path(
+                recorded_at,
+                Some("Trento.Checks.V1.FactsGatheringRequested")
+            ),
+            PathBuf::from(
+                "/var/lib/vanvitelli/events/1700000000123456-Trento.Checks.V1.FactsGatheringRequested.bin"
+            )
+        );
+        assert_eq!(
+            recorder.payload_path(recorded_at, None),
+            PathBuf::from("/var/lib/vanvitelli/events/1700000000123456-unknown.bin")
+        );
+        assert_eq!(
+            recorder.payload_path(recorded_at, Some("../Trento/Event")),
+            PathBuf::from("/var/lib/vanvitelli/events/1700000000123456-.._Trento_Event.bin")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_payload_and_context() {
+        let dir = record_dir("record_events").join("nested");
+        let recorder = EventRecorder::new(&dir);
+
+        let path = recorder
+            .record(&message(), Some("Trento.Test.V1.Event"))
+            .await
+            .unwrap();
+        let payload = fs::read(&path).unwrap();
+        let context: serde_json::Value =
+            serde_json::from_slice(&fs::read(path.with_extension("json")).unwrap()).unwrap();
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+
+        assert_eq!(path.parent(), Some(dir.as_path()));
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .ends_with("-Trento.Test.V1.Event.bin"));
+        assert_eq!(payload, message().body);
+        assert_eq!(context["routing_key"], "agent_1");
+        assert_eq!(context["delivery_tag"], 7);
+        assert_eq!(context["message_id"], "message1");
+        assert_eq!(context["content_type"], "application/json");
+        assert_eq!(context["headers"]["x-priority"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_read_recordings_in_order() {
+        let dir = record_dir("record_read");
+        let recorder = EventRecorder::new(&dir);
+        let second = Message {
+            body: b"second".to_vec(),
+            ..message()
+        };
+
+        // recorded within the same microsecond at times, never overwritten
+        let first_path = recorder.record(&message(), None).await.unwrap();
+        let second_path = recorder.record(&second, None).await.unwrap();
+        fs::write(dir.join("0-stray.json"), "{}").unwrap();
+        let recorded = EventRecorder::recorded(&dir).unwrap();
+        let read: Vec<Message> = recorded
+            .iter()
+            .map(|payload| EventRecorder::read(payload).unwrap())
+            .collect();
+        fs::remove_file(first_path.with_extension("json")).unwrap();
+        let missing_context = EventRecorder::read(&first_path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(recorded, vec![first_path, second_path]);
+        assert_eq!(read, vec![message(), second]);
+        assert!(missing_context.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_record_failure_is_not_fatal() {
+        let blocker = std::env::temp_dir().join("vanvitelli_test_record_blocker");
+        fs::write(&blocker, "").unwrap();
+        let recorder = EventRecorder::new(&blocker.join("events"));
+
+        let path = recorder.record(&message(), None).await;
+        fs::remove_file(blocker).unwrap();
+
+        assert_eq!(path, None);
+    }
+
+    #[test]
+    fn test_prune_recordings() {
+        let dir = record_dir("record_prune");
+        fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        for (name, size, modified) in [
+            ("1-old.bin", 10, now - 5 * day),
+            ("1-old.json", 10, now - 5 * day),
+            ("2-first.bin", 40, now - day),
+            ("2-first.json", 10, now - day),
+            ("3-second.bin", 40, now),
+            ("3-second.json", 10, now),
+            ("4-third.bin", 40, now),
+            ("old.log", 10, now - 5 * day),
+        ] {
+            create(&dir.join(name), size, modified);
+        }
+
+        let pruned = EventRecorder::new(&dir)
+            .with_retention(Some(3 * day))
+            .with_max_size(Some(100))
+            .prune();
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // the old one by age, then the oldest of the others by size
+        assert_eq!(pruned, 2);
+        assert_eq!(
+            left,
+            vec!["3-second.bin", "3-second.json", "4-third.bin", "old.log"]
+        );
+    }
+
+    #[test]
+    fn test_prune_missing_dir() {
+        let recorder = EventRecorder::new(&record_dir("record_prune_missing"))
+            .with_retention(Some(Duration::from_secs(60)));
+
+        assert_eq!(recorder.prune(), 0);
+    }
+}
@@ -0,0 +1,16 @@
+//! Exit codes of the agent process, init systems rely on them to tell failures apart.
+
+/// The agent was shut down cleanly
+pub const CLEAN_SHUTDOWN: i32 = 0;
+/// The agent failed while running
+pub const RUNTIME_FAILURE: i32 = 1;
+/// The configuration is invalid, restarting without changing it will fail again
+pub const CONFIGURATION_ERROR: i32 = 2;
+/// The broker could not be reached
+pub const BROKER_UNREACHABLE: i32 = 3;
+
+pub const EXIT_CODES_HELP: &str = "Exit codes:
+  0  clean shutdown
+  1  runtime failure
+  2  configuration error
+  3  broker unreachable";
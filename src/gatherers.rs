@@ -1,13 +1,36 @@
 #[cfg(test)]
 use mockall::automock;
 
+mod dump;
+mod engine;
 mod facts;
+mod in_flight;
+mod ordering;
+mod publish;
+mod rate_limit;
 mod registry;
+pub(crate) use dump::FactsDumper;
+pub(crate) use engine::GatheringEngine;
 pub(crate) use facts::*;
+pub(crate) use in_flight::{InFlightExecution, TrackedExecution};
+#[cfg(test)]
+pub(crate) use publish::recording;
+pub(crate) use publish::FactsPublisher;
+pub(crate) use rate_limit::GroupRateLimiter;
+pub(crate) use registry::{GatherersRegistry, GatherersRegistryBuilder, RegistryErrors};
 
 #[async_trait::async_trait]
 #[cfg_attr(test, automock)]
 pub trait Gatherer: Sync + Send {
     async fn gather(&self, fact_request: FactsGatheringRequest) -> FactsGathered;
     fn name(&self) -> String;
+    /// Checks that the gatherer dependencies (binaries, files) are available on the host
+    async fn probe(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Registry with the gatherers shipped with the agent
+pub fn default_registry() -> GatherersRegistry {
+    GatherersRegistryBuilder::new().build_registry()
 }
@@ -0,0 +1,220 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, warn};
+
+use super::FactsGathered;
+
+const DUMP_EXTENSION: &str = "json";
+
+/// Keeps a local copy of the gathered facts, one `<execution_id>.json` file per execution.
+/// Dumping is best effort, failures are logged and never fail the execution.
+pub struct FactsDumper {
+    dir: PathBuf,
+}
+
+impl FactsDumper {
+    pub fn new(dir: &Path) -> FactsDumper {
+        FactsDumper {
+            dir: dir.to_owned(),
+        }
+    }
+
+    pub fn dump_path(&self, execution_id: &str) -> PathBuf {
+        // the execution id comes from the broker, it must not escape the dump directory
+        let file_name: String = execution_id
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect();
+
+        self.dir.join(file_name).with_extension(DUMP_EXTENSION)
+    }
+
+    /// Returns the path of the written file, `None` when the facts could not be dumped
+    pub async fn dump(&self, facts_gathered: &FactsGathered) -> Option<PathBuf> {
+        let path = self.dump_path(&facts_gathered.exeuction_id);
+
+        match self.write(&path, facts_gathered).await {
+            Ok(_) => {
+                debug!("gathered facts dumped to {}", path.display());
+                Some(path)
+            }
+            Err(err) => {
+                warn!(
+                    agent_name = facts_gathered.agent_name.as_str(),
+                    execution_id = facts_gathered.exeuction_id.as_str(),
+                    group_id = facts_gathered.group_id.as_str();
+                    "could not dump gathered facts to {}: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    async fn write(&self, path: &Path, facts_gathered: &FactsGathered) -> io::Result<()> {
+        let content = serde_json::to_vec_pretty(facts_gathered)?;
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(path, content).await
+    }
+
+    /// Removes the dumped facts older than `retention`, returning the number of removed files
+    pub fn prune(&self, retention: Duration) -> usize {
+        match SystemTime::now().checked_sub(retention) {
+            Some(threshold) => self.prune_before(threshold),
+            None => 0,
+        }
+    }
+
+    fn prune_before(&self, threshold: SystemTime) -> usize {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return 0,
+            Err(err) => {
+                warn!(
+                    "could not prune dumped facts in {}: {}",
+                    self.dir.display(),
+                    err
+                );
+                return 0;
+            }
+        };
+
+        let mut pruned = 0;
+
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path
+                .extension()
+                .map_or(true, |extension| extension != DUMP_EXTENSION)
+            {
+                continue;
+            }
+
+            let expired = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .map_or(false, |modified| modified < threshold);
+
+            if !expired {
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(_) => pruned += 1,
+                Err(err) => warn!("could not prune {}: {}", path.display(), err),
+            }
+        }
+
+        pruned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    fn dump_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vanvitelli_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn facts_gathered(execution_id: &str) -> FactsGathered {
+        FactsGathered {
+            agent_id: "agent_1".to_owned(),
+            agent_name: "sap-node-1".to_owned(),
+            exeuction_id: execution_id.to_owned(),
+            facts_gathered: vec![],
+            group_id: "group1".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_dump_path() {
+        let dumper = FactsDumper::new(Path::new("/var/lib/vanvitelli/facts"));
+
+        assert_eq!(
+            dumper.dump_path("5e3fa7f6-6f43-4a43-a9a4-3e3bb3a0ad5a"),
+            PathBuf::from("/var/lib/vanvitelli/facts/5e3fa7f6-6f43-4a43-a9a4-3e3bb3a0ad5a.json")
+        );
+        assert_eq!(
+            dumper.dump_path("../exec.1"),
+            PathBuf::from("/var/lib/vanvitelli/facts/___exec_1.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dump_creates_missing_dir() {
+        let dir = dump_dir("dump_missing_dir").join("nested");
+        let dumper = FactsDumper::new(&dir);
+
+        let path = dumper.dump(&facts_gathered("exec1")).await;
+        let content = fs::read(dir.join("exec1.json")).unwrap();
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+
+        assert_eq!(path, Some(dir.join("exec1.json")));
+        assert_eq!(
+            serde_json::from_slice::<FactsGathered>(&content).unwrap(),
+            facts_gathered("exec1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dump_failure_is_not_fatal() {
+        let blocker = std::env::temp_dir().join("vanvitelli_test_dump_blocker");
+        fs::write(&blocker, "").unwrap();
+        let dumper = FactsDumper::new(&blocker.join("facts"));
+
+        let path = dumper.dump(&facts_gathered("exec1")).await;
+        fs::remove_file(blocker).unwrap();
+
+        assert_eq!(path, None);
+    }
+
+    #[test]
+    fn test_prune_expired_dumps() {
+        let dir = dump_dir("dump_prune");
+        fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        let ten_days = Duration::from_secs(10 * 24 * 60 * 60);
+
+        for (name, modified) in [
+            ("old.json", now - ten_days),
+            ("recent.json", now),
+            ("old.log", now - ten_days),
+        ] {
+            File::create(dir.join(name))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let pruned = FactsDumper::new(&dir).prune(Duration::from_secs(7 * 24 * 60 * 60));
+        let old_exists = dir.join("old.json").exists();
+        let recent_exists = dir.join("recent.json").exists();
+        let log_exists = dir.join("old.log").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(pruned, 1);
+        assert!(!old_exists);
+        assert!(recent_exists);
+        assert!(log_exists);
+    }
+
+    #[test]
+    fn test_prune_missing_dir() {
+        let dumper = FactsDumper::new(&dump_dir("dump_prune_missing"));
+
+        assert_eq!(dumper.prune(Duration::from_secs(60)), 0);
+    }
+}
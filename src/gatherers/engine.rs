@@ -0,0 +1,1275 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use log::{info, warn};
+use tokio::{sync::watch, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use super::in_flight::{ExecutionPhase, InFlightExecution, InFlightExecutions, TrackedExecution};
+use super::ordering::GroupOrdering;
+use super::{
+    Fact, FactGatheringErrors, FactRequest, FactsDumper, FactsGathered, FactsGatheringRequest,
+    FactsPublisher, Gatherer, GatherersRegistry, GroupRateLimiter, RegistryErrors,
+};
+use crate::config::Config;
+use crate::logging::Correlation;
+
+/// Executes a facts gathering request, dispatching each fact request to its gatherer
+pub struct GatheringEngine {
+    agent_id: String,
+    agent_name: String,
+    registry: Arc<GatherersRegistry>,
+    dumper: Option<FactsDumper>,
+    publisher: Option<FactsPublisher>,
+    dry_run: bool,
+    timeout: Option<Duration>,
+    /// Every gatherer of the registry when empty
+    enabled_gatherers: Vec<String>,
+    /// Running configuration, its execution timeout and enabled gatherers replace the fixed ones
+    config: Option<watch::Receiver<Config>>,
+    ordering: GroupOrdering,
+    rate_limiter: Option<GroupRateLimiter>,
+    in_flight: InFlightExecutions,
+}
+
+impl GatheringEngine {
+    pub fn new(
+        agent_id: &str,
+        agent_name: &str,
+        registry: Arc<GatherersRegistry>,
+    ) -> GatheringEngine {
+        GatheringEngine {
+            agent_id: agent_id.to_owned(),
+            agent_name: agent_name.to_owned(),
+            registry,
+            dumper: None,
+            publisher: None,
+            dry_run: false,
+            timeout: None,
+            enabled_gatherers: vec![],
+            config: None,
+            ordering: GroupOrdering::default(),
+            rate_limiter: None,
+            in_flight: InFlightExecutions::default(),
+        }
+    }
+
+    /// Bounds the whole execution, facts still pending when it expires get a timeout error
+    pub fn with_timeout(self, timeout: Duration) -> GatheringEngine {
+        GatheringEngine {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Runs the listed gatherers only, with any of their versions. The facts of the other ones
+    /// are reported with an error
+    pub fn with_enabled_gatherers(self, enabled_gatherers: &[String]) -> GatheringEngine {
+        GatheringEngine {
+            enabled_gatherers: enabled_gatherers.to_vec(),
+            ..self
+        }
+    }
+
+    /// Takes the execution timeout and the enabled gatherers from the running configuration,
+    /// a reload applies to the executions started afterwards
+    pub fn with_running_config(self, config: watch::Receiver<Config>) -> GatheringEngine {
+        GatheringEngine {
+            config: Some(config),
+            ..self
+        }
+    }
+
+    /// Spaces the executions of every group, the faster ones are delayed or skipped
+    pub fn with_rate_limiter(self, rate_limiter: GroupRateLimiter) -> GatheringEngine {
+        GatheringEngine {
+            rate_limiter: Some(rate_limiter),
+            ..self
+        }
+    }
+
+    /// Resolves the gatherers and logs the planned facts, without running any gatherer.
+    /// The synthetic results are dumped but never published, the server is not told about
+    /// facts that were not gathered
+    pub fn with_dry_run(self) -> GatheringEngine {
+        GatheringEngine {
+            dry_run: true,
+            ..self
+        }
+    }
+
+    /// Describes what gathering the request would do, one line per requested fact
+    pub fn plan(&self, request: &FactsGatheringRequest) -> Vec<String> {
+        let mut gatherer_names: Vec<&String> = request.facts_requests_by_gatherer.keys().collect();
+        gatherer_names.sort();
+
+        gatherer_names
+            .into_iter()
+            .flat_map(|gatherer_name| {
+                let resolution = self.gatherer(gatherer_name).map(|_| ());
+
+                request.facts_requests_by_gatherer[gatherer_name]
+                    .iter()
+                    .map(move |fact_request| match &resolution {
+                        Ok(_) => format!(
+                            "would gather fact `{}` of check {} with gatherer {}, argument `{}`",
+                            fact_request.name,
+                            fact_request.check_id,
+                            gatherer_name,
+                            fact_request.argument
+                        ),
+                        Err(err) => format!(
+                            "would not gather fact `{}` of check {}: {}",
+                            fact_request.name, fact_request.check_id, err
+                        ),
+                    })
+            })
+            .collect()
+    }
+
+    /// Keeps a local copy of every gathered facts result
+    pub fn with_dumper(self, dumper: FactsDumper) -> GatheringEngine {
+        GatheringEngine {
+            dumper: Some(dumper),
+            ..self
+        }
+    }
+
+    /// Reports every result of the executions to the server
+    pub fn with_publisher(self, publisher: FactsPublisher) -> GatheringEngine {
+        GatheringEngine {
+            publisher: Some(publisher),
+            ..self
+        }
+    }
+
+    pub async fn gather(&self, request: FactsGatheringRequest) -> FactsGathered {
+        let tracked = self.track(&request.execution_id, &request.group_id);
+
+        self.gather_until_cancelled(request, &self.agent_id, &CancellationToken::new(), &tracked)
+            .await
+            .expect("a gathering without cancellation always completes, fatal.")
+    }
+
+    /// Lists the execution in flight until the returned one is dropped, the gatherings of the
+    /// execution update it
+    pub fn track(&self, execution_id: &str, group_id: &str) -> TrackedExecution<'_> {
+        self.in_flight.track(execution_id, group_id)
+    }
+
+    /// The executions in flight, in their arrival order
+    pub fn in_flight(&self) -> Vec<InFlightExecution> {
+        self.in_flight.snapshot()
+    }
+
+    /// Stops launching gatherers and abandons the pending ones once the execution is cancelled,
+    /// in which case there is no result at all.
+    /// The executions of a group are gathered one at a time, in their arrival order, the timeout
+    /// starts once the turn of the execution comes.
+    /// A rate limited execution skipped reports an error for each of its facts.
+    /// The timeout of the request bounds the execution as well, the requester does not wait
+    /// for the facts beyond it.
+    /// Every gatherer is resolved before launching any, the facts of the unknown ones are errors
+    /// and an execution without any known gatherer is reported right away.
+    /// The progress of the gathering is reported to the tracked execution.
+    /// The facts are attributed to the identity of the agent they were requested to
+    pub async fn gather_until_cancelled(
+        &self,
+        request: FactsGatheringRequest,
+        agent_id: &str,
+        cancelled: &CancellationToken,
+        tracked: &TrackedExecution<'_>,
+    ) -> Option<FactsGathered> {
+        if self.dry_run {
+            for line in self.plan(&request) {
+                info!(
+                    execution_id = request.execution_id.as_str(),
+                    group_id = request.group_id.as_str();
+                    "dry-run: {}",
+                    line
+                );
+            }
+        }
+
+        let Resolution {
+            request,
+            mut gatherers,
+            unresolved_facts,
+        } = self.resolve(request);
+        let mut facts_gathered = unresolved_facts;
+        if request.facts_requests_by_gatherer.is_empty() && !facts_gathered.is_empty() {
+            if cancelled.is_cancelled() {
+                return None;
+            }
+
+            tracked.enter(ExecutionPhase::Reporting);
+            return Some(
+                self.report(
+                    agent_id,
+                    request.execution_id,
+                    request.group_id,
+                    &request.correlation,
+                    facts_gathered,
+                )
+                .await,
+            );
+        }
+
+        tracked.plan(request.facts_requests_by_gatherer.keys());
+        tracked.enter(ExecutionPhase::WaitingTurn);
+        let _turn = tokio::select! {
+            _ = cancelled.cancelled() => return None,
+            turn = self.ordering.turn(&request.group_id) => turn,
+        };
+        if let Some(rate_limiter) = &self.rate_limiter {
+            tracked.enter(ExecutionPhase::RateLimited);
+            let admitted = tokio::select! {
+                _ = cancelled.cancelled() => return None,
+                admitted = rate_limiter.admit(&request.group_id) => admitted,
+            };
+            if !admitted {
+                warn!(
+                    execution_id = request.execution_id.as_str(),
+                    group_id = request.group_id.as_str();
+                    "execution {} skipped, group {} gathered less than {:?} ago",
+                    request.execution_id,
+                    request.group_id,
+                    rate_limiter.interval()
+                );
+                let error =
+                    FactGatheringErrors::RateLimitedError(format!("{:?}", rate_limiter.interval()));
+
+                tracked.enter(ExecutionPhase::Reporting);
+                facts_gathered.extend(request_error_facts(&request, error));
+                return Some(
+                    self.report(
+                        agent_id,
+                        request.execution_id,
+                        request.group_id,
+                        &request.correlation,
+                        facts_gathered,
+                    )
+                    .await,
+                );
+            }
+        }
+        tracked.enter(ExecutionPhase::Gathering);
+        let timeout = match (self.timeout(), request.timeout) {
+            (Some(timeout), Some(requested)) => Some(timeout.min(requested)),
+            (timeout, requested) => timeout.or(requested),
+        };
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        for (gatherer_name, fact_requests) in request.facts_requests_by_gatherer {
+            if cancelled.is_cancelled() {
+                return None;
+            }
+
+            let gatherer = gatherers
+                .remove(&gatherer_name)
+                .expect("gatherers are resolved before gathering, fatal.");
+
+            if self.dry_run {
+                tracked.done(&gatherer_name);
+                facts_gathered.extend(error_facts(
+                    &fact_requests,
+                    FactGatheringErrors::DryRunError,
+                ));
+                continue;
+            }
+
+            let gatherer_request = FactsGatheringRequest {
+                execution_id: request.execution_id.to_owned(),
+                group_id: request.group_id.to_owned(),
+                facts_requests_by_gatherer: HashMap::from([(
+                    gatherer_name.to_owned(),
+                    fact_requests.to_owned(),
+                )]),
+                timeout: request.timeout,
+                correlation: request.correlation.clone(),
+            };
+
+            let gathering = async {
+                match deadline {
+                    Some(deadline) => {
+                        tokio::time::timeout_at(deadline, gatherer.gather(gatherer_request))
+                            .await
+                            .ok()
+                    }
+                    None => Some(gatherer.gather(gatherer_request).await),
+                }
+            };
+            let gathered = tokio::select! {
+                _ = cancelled.cancelled() => return None,
+                gathered = gathering => gathered,
+            };
+            tracked.done(&gatherer_name);
+
+            match gathered {
+                Some(gathered) => facts_gathered.extend(gathered.facts_gathered),
+                None => {
+                    warn!(
+                        execution_id = request.execution_id.as_str(),
+                        group_id = request.group_id.as_str();
+                        "execution timed out while gathering facts with {}",
+                        gatherer_name
+                    );
+                    facts_gathered.extend(error_facts(
+                        &fact_requests,
+                        FactGatheringErrors::GatheringTimeoutError(format!(
+                            "{:?}",
+                            timeout.unwrap_or_default()
+                        )),
+                    ));
+                }
+            }
+        }
+
+        tracked.enter(ExecutionPhase::Reporting);
+        Some(
+            self.report(
+                agent_id,
+                request.execution_id,
+                request.group_id,
+                &request.correlation,
+                facts_gathered,
+            )
+            .await,
+        )
+    }
+
+    /// Result of an execution not gathered, each requested fact reports the error
+    pub async fn skip(
+        &self,
+        request: FactsGatheringRequest,
+        agent_id: &str,
+        error: FactGatheringErrors,
+    ) -> FactsGathered {
+        let facts_gathered = request_error_facts(&request, error);
+
+        self.report(
+            agent_id,
+            request.execution_id,
+            request.group_id,
+            &request.correlation,
+            facts_gathered,
+        )
+        .await
+    }
+
+    /// Resolves every gatherer of the request, the unknown ones are reported in a single warning
+    /// and their facts are errors. The request is left with the resolved gatherers only
+    fn resolve(&self, request: FactsGatheringRequest) -> Resolution {
+        let mut resolution = Resolution {
+            request: FactsGatheringRequest {
+                facts_requests_by_gatherer: HashMap::new(),
+                ..request
+            },
+            gatherers: HashMap::new(),
+            unresolved_facts: vec![],
+        };
+        let mut unresolved: Vec<String> = vec![];
+
+        for (gatherer_name, fact_requests) in request.facts_requests_by_gatherer {
+            match self.gatherer(&gatherer_name) {
+                Ok(gatherer) => {
+                    resolution
+                        .gatherers
+                        .insert(gatherer_name.to_owned(), gatherer);
+                    resolution
+                        .request
+                        .facts_requests_by_gatherer
+                        .insert(gatherer_name, fact_requests);
+                }
+                Err(err) => {
+                    resolution.unresolved_facts.extend(error_facts(
+                        &fact_requests,
+                        FactGatheringErrors::GathererResolutionError(
+                            gatherer_name.to_owned(),
+                            err.to_string(),
+                        ),
+                    ));
+                    unresolved.push(gatherer_name);
+                }
+            }
+        }
+
+        if !unresolved.is_empty() {
+            unresolved.sort();
+            warn!(
+                execution_id = resolution.request.execution_id.as_str(),
+                group_id = resolution.request.group_id.as_str();
+                "execution {} requests gatherers unknown to the agent, their facts are not gathered: {}",
+                resolution.request.execution_id,
+                unresolved.join(", ")
+            );
+        }
+
+        resolution
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        match &self.config {
+            Some(config) => Some(config.borrow().execution_timeout),
+            None => self.timeout,
+        }
+    }
+
+    /// The gatherer of the registry, unless the configuration disables it
+    fn gatherer(&self, gatherer_name: &str) -> Result<Arc<dyn Gatherer>, RegistryErrors> {
+        let gatherer = self.registry.get_gatherer(gatherer_name.to_owned())?;
+        // the versions of a gatherer are enabled together
+        let name = gatherer_name.split('@').next().unwrap_or(gatherer_name);
+        let enabled = |enabled_gatherers: &[String]| {
+            enabled_gatherers.is_empty() || enabled_gatherers.iter().any(|enabled| enabled == name)
+        };
+        let is_enabled = match &self.config {
+            Some(config) => enabled(&config.borrow().enabled_gatherers),
+            None => enabled(&self.enabled_gatherers),
+        };
+        if !is_enabled {
+            return Err(RegistryErrors::GathererDisabledError(
+                gatherer_name.to_owned(),
+            ));
+        }
+
+        Ok(gatherer)
+    }
+
+    /// Result of the execution, dumped and published when the engine has a dumper and a publisher.
+    /// The results of a dry run are not published
+    async fn report(
+        &self,
+        agent_id: &str,
+        execution_id: String,
+        group_id: String,
+        correlation: &Correlation,
+        facts_gathered: Vec<Fact>,
+    ) -> FactsGathered {
+        let facts_gathered = FactsGathered {
+            agent_id: agent_id.to_owned(),
+            agent_name: self.agent_name.to_owned(),
+            exeuction_id: execution_id,
+            facts_gathered,
+            group_id,
+        };
+
+        if let Some(dumper) = &self.dumper {
+            dumper.dump(&facts_gathered).await;
+        }
+        if let Some(publisher) = self.publisher.as_ref().filter(|_| !self.dry_run) {
+            publisher.publish(&facts_gathered, correlation).await;
+        }
+
+        facts_gathered
+    }
+}
+
+/// A request whose gatherers are resolved up front
+struct Resolution {
+    /// Left with the resolved gatherers only
+    request: FactsGatheringRequest,
+    gatherers: HashMap<String, Arc<dyn Gatherer>>,
+    /// Facts of the gatherers that cannot be resolved
+    unresolved_facts: Vec<Fact>,
+}
+
+fn request_error_facts(request: &FactsGatheringRequest, error: FactGatheringErrors) -> Vec<Fact> {
+    request
+        .facts_requests_by_gatherer
+        .values()
+        .flat_map(|fact_requests| error_facts(fact_requests, error.clone()))
+        .collect()
+}
+
+fn error_facts(fact_requests: &[FactRequest], error: FactGatheringErrors) -> Vec<Fact> {
+    fact_requests
+        .iter()
+        .map(|fact_request| Fact {
+            name: fact_request.name.to_owned(),
+            check_id: fact_request.check_id.to_owned(),
+            value: serde_json::Value::Null,
+            error: Some(error.clone()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::config::Cli;
+    use crate::gatherers::recording::RecordingPublisher;
+    use crate::gatherers::{Gatherer, GatherersRegistryBuilder, MockGatherer};
+
+    fn fact_request(gatherer: &str, name: &str) -> FactRequest {
+        FactRequest {
+            argument: "arg1".to_owned(),
+            check_id: "check1".to_owned(),
+            gatherer: gatherer.to_owned(),
+            name: name.to_owned(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_engine_gather() {
+        let mut mockgatherer = MockGatherer::new();
+        mockgatherer
+            .expect_gather()
+            .withf(|request| {
+                request.facts_requests_by_gatherer.len() == 1
+                    && request.facts_requests_by_gatherer["test_gat"].len() == 2
+            })
+            .times(1)
+            .returning(|request| FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: request.facts_requests_by_gatherer["test_gat"]
+                    .iter()
+                    .map(|fact_request| Fact {
+                        name: fact_request.name.to_owned(),
+                        check_id: fact_request.check_id.to_owned(),
+                        value: json!(fact_request.argument),
+                        error: None,
+                    })
+                    .collect(),
+                group_id: request.group_id.to_owned(),
+            });
+
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", mockgatherer);
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()));
+
+        let facts_gathered = engine
+            .gather(FactsGatheringRequest {
+                execution_id: "exec1".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([(
+                    "test_gat".to_owned(),
+                    vec![
+                        fact_request("test_gat", "fact1"),
+                        fact_request("test_gat", "fact2"),
+                    ],
+                )]),
+                timeout: None,
+                correlation: Correlation::default(),
+            })
+            .await;
+
+        assert_eq!(facts_gathered.agent_id, "agent_1");
+        assert_eq!(facts_gathered.agent_name, "sap-node-1");
+        assert_eq!(facts_gathered.exeuction_id, "exec1");
+        assert_eq!(facts_gathered.group_id, "group1");
+        assert_eq!(facts_gathered.facts_gathered.len(), 2);
+        assert!(facts_gathered
+            .facts_gathered
+            .iter()
+            .all(|fact| fact.error.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_engine_unknown_gatherer_produces_error_facts() {
+        let engine = GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        );
+
+        let facts_gathered = engine
+            .gather(FactsGatheringRequest {
+                execution_id: "exec1".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([(
+                    "unknown".to_owned(),
+                    vec![fact_request("unknown", "fact1")],
+                )]),
+                timeout: None,
+                correlation: Correlation::default(),
+            })
+            .await;
+
+        assert_eq!(
+            facts_gathered.facts_gathered,
+            vec![Fact {
+                name: "fact1".to_owned(),
+                check_id: "check1".to_owned(),
+                value: serde_json::Value::Null,
+                error: Some(FactGatheringErrors::GathererResolutionError(
+                    "unknown".to_owned(),
+                    "gatherer `unknown` not found".to_owned()
+                )),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_dumps_gathered_facts() {
+        let dir = std::env::temp_dir().join("vanvitelli_test_engine_dump");
+        let engine = GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        )
+        .with_dumper(FactsDumper::new(&dir));
+
+        let facts_gathered = engine
+            .gather(FactsGatheringRequest {
+                execution_id: "exec1".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::new(),
+                timeout: None,
+                correlation: Correlation::default(),
+            })
+            .await;
+        let content = std::fs::read(dir.join("exec1.json")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            serde_json::from_slice::<FactsGathered>(&content).unwrap(),
+            facts_gathered
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_dry_run_does_not_gather() {
+        let mut mockgatherer = MockGatherer::new();
+        mockgatherer.expect_gather().times(0);
+
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", mockgatherer);
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()))
+                .with_dry_run();
+
+        let request = FactsGatheringRequest {
+            execution_id: "exec1".to_owned(),
+            group_id: "group1".to_owned(),
+            facts_requests_by_gatherer: HashMap::from([
+                (
+                    "test_gat".to_owned(),
+                    vec![
+                        fact_request("test_gat", "fact1"),
+                        fact_request("test_gat", "fact2"),
+                    ],
+                ),
+                ("unknown".to_owned(), vec![fact_request("unknown", "fact3")]),
+            ]),
+            timeout: None,
+            correlation: Correlation::default(),
+        };
+
+        let plan = engine.plan(&request);
+        let mut facts_gathered = engine.gather(request).await.facts_gathered;
+        facts_gathered.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(
+            plan,
+            vec![
+                "would gather fact `fact1` of check check1 with gatherer test_gat, argument `arg1`",
+                "would gather fact `fact2` of check check1 with gatherer test_gat, argument `arg1`",
+                "would not gather fact `fact3` of check check1: gatherer `unknown` not found",
+            ]
+        );
+        assert_eq!(
+            facts_gathered
+                .iter()
+                .map(|fact| fact.error.to_owned().unwrap())
+                .collect::<Vec<FactGatheringErrors>>(),
+            vec![
+                FactGatheringErrors::DryRunError,
+                FactGatheringErrors::DryRunError,
+                FactGatheringErrors::GathererResolutionError(
+                    "unknown".to_owned(),
+                    "gatherer `unknown` not found".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_dry_run_does_not_publish() {
+        let mut mockgatherer = MockGatherer::new();
+        mockgatherer.expect_gather().never();
+
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", mockgatherer);
+        let recorder = Arc::new(RecordingPublisher::default());
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()))
+                .with_publisher(FactsPublisher::new(
+                    recorder.clone(),
+                    "trento.checks",
+                    "agents",
+                ))
+                .with_dry_run();
+
+        let facts_gathered = engine
+            .gather(FactsGatheringRequest {
+                execution_id: "exec1".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([
+                    (
+                        "test_gat".to_owned(),
+                        vec![fact_request("test_gat", "fact1")],
+                    ),
+                    ("unknown".to_owned(), vec![fact_request("unknown", "fact2")]),
+                ]),
+                timeout: None,
+                correlation: Correlation::default(),
+            })
+            .await;
+
+        assert_eq!(facts_gathered.facts_gathered.len(), 2);
+        assert!(recorder.published().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_engine_cancelled_execution_has_no_result() {
+        let mut mockgatherer = MockGatherer::new();
+        mockgatherer.expect_gather().never();
+
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", mockgatherer);
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()));
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+
+        let facts_gathered = engine
+            .gather_until_cancelled(
+                FactsGatheringRequest {
+                    execution_id: "exec1".to_owned(),
+                    group_id: "group1".to_owned(),
+                    facts_requests_by_gatherer: HashMap::from([(
+                        "test_gat".to_owned(),
+                        vec![fact_request("test_gat", "fact1")],
+                    )]),
+                    timeout: None,
+                    correlation: Correlation::default(),
+                },
+                "agent_1",
+                &cancelled,
+                &engine.track("exec1", "group1"),
+            )
+            .await;
+
+        assert!(facts_gathered.is_none());
+    }
+
+    struct SlowGatherer;
+
+    #[async_trait::async_trait]
+    impl Gatherer for SlowGatherer {
+        async fn gather(&self, _fact_request: FactsGatheringRequest) -> FactsGathered {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+
+            unreachable!("the execution timeout expires first")
+        }
+
+        fn name(&self) -> String {
+            "slow".to_owned()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_engine_is_bounded_by_the_request_timeout() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("slow", "v1", SlowGatherer);
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()))
+                .with_timeout(Duration::from_secs(30));
+
+        let facts_gathered = engine
+            .gather(FactsGatheringRequest {
+                execution_id: "exec1".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([(
+                    "slow".to_owned(),
+                    vec![fact_request("slow", "fact1")],
+                )]),
+                timeout: Some(Duration::from_millis(50)),
+                correlation: Correlation::default(),
+            })
+            .await
+            .facts_gathered;
+
+        // the shorter timeout applies
+        assert_eq!(
+            facts_gathered[0].error,
+            Some(FactGatheringErrors::GatheringTimeoutError(
+                "50ms".to_owned()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_follows_the_running_config() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("slow", "v1", SlowGatherer);
+        let (config, receiver) = watch::channel(
+            Config::load(&Cli {
+                agent_id: Some("agent_1".to_owned()),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()))
+                .with_timeout(Duration::from_secs(30))
+                .with_running_config(receiver);
+
+        let request = |execution_id: &str| FactsGatheringRequest {
+            execution_id: execution_id.to_owned(),
+            group_id: "group1".to_owned(),
+            facts_requests_by_gatherer: HashMap::from([(
+                "slow@v1".to_owned(),
+                vec![fact_request("slow@v1", "fact1")],
+            )]),
+            timeout: None,
+            correlation: Correlation::default(),
+        };
+
+        // reloaded after the engine is built
+        config.send_modify(|config| config.execution_timeout = Duration::from_millis(50));
+        let facts_gathered = engine.gather(request("exec1")).await.facts_gathered;
+        assert_eq!(
+            facts_gathered[0].error,
+            Some(FactGatheringErrors::GatheringTimeoutError(
+                "50ms".to_owned()
+            ))
+        );
+
+        config.send_modify(|config| config.enabled_gatherers = vec!["other".to_owned()]);
+        let facts_gathered = engine.gather(request("exec2")).await.facts_gathered;
+        assert_eq!(
+            facts_gathered[0].error,
+            Some(FactGatheringErrors::GathererResolutionError(
+                "slow@v1".to_owned(),
+                "gatherer `slow@v1` is disabled by the configuration".to_owned()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_timeout_keeps_completed_facts() {
+        let mut mockgatherer = MockGatherer::new();
+        mockgatherer
+            .expect_gather()
+            .times(1)
+            .returning(|request| FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: vec![Fact {
+                    name: "fact1".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: json!("value1"),
+                    error: None,
+                }],
+                group_id: request.group_id.to_owned(),
+            });
+
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", mockgatherer);
+        builder.add_gatherer("slow", "v1", SlowGatherer);
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()))
+                .with_timeout(Duration::from_millis(50));
+
+        let mut facts_gathered = engine
+            .gather(FactsGatheringRequest {
+                execution_id: "exec1".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([
+                    (
+                        "test_gat".to_owned(),
+                        vec![fact_request("test_gat", "fact1")],
+                    ),
+                    (
+                        "slow".to_owned(),
+                        vec![fact_request("slow", "fact2"), fact_request("slow", "fact3")],
+                    ),
+                ]),
+                timeout: None,
+                correlation: Correlation::default(),
+            })
+            .await
+            .facts_gathered;
+        facts_gathered.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(
+            facts_gathered,
+            vec![
+                Fact {
+                    name: "fact1".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: json!("value1"),
+                    error: None,
+                },
+                Fact {
+                    name: "fact2".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: serde_json::Value::Null,
+                    error: Some(FactGatheringErrors::GatheringTimeoutError(
+                        "50ms".to_owned()
+                    )),
+                },
+                Fact {
+                    name: "fact3".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: serde_json::Value::Null,
+                    error: Some(FactGatheringErrors::GatheringTimeoutError(
+                        "50ms".to_owned()
+                    )),
+                },
+            ]
+        );
+    }
+
+    /// Records when the gathering of every execution starts and ends
+    #[derive(Default)]
+    struct RecordingGatherer {
+        gatherings: std::sync::Mutex<Vec<(String, Instant, Instant)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Gatherer for Arc<RecordingGatherer> {
+        async fn gather(&self, request: FactsGatheringRequest) -> FactsGathered {
+            let started = Instant::now();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            self.gatherings.lock().unwrap().push((
+                request.execution_id.to_owned(),
+                started,
+                Instant::now(),
+            ));
+
+            FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: vec![],
+                group_id: request.group_id.to_owned(),
+            }
+        }
+
+        fn name(&self) -> String {
+            "recording".to_owned()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_engine_serializes_the_executions_of_a_group() {
+        let gatherer = Arc::new(RecordingGatherer::default());
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("recording", "v1", gatherer.clone());
+        let engine = Arc::new(GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(builder.build_registry()),
+        ));
+        let gather = |execution_id: &str, group_id: &str| {
+            let engine = engine.clone();
+            let request = FactsGatheringRequest {
+                execution_id: execution_id.to_owned(),
+                group_id: group_id.to_owned(),
+                facts_requests_by_gatherer: HashMap::from([(
+                    "recording".to_owned(),
+                    vec![fact_request("recording", "fact1")],
+                )]),
+                timeout: None,
+                correlation: Correlation::default(),
+            };
+            tokio::spawn(async move { engine.gather(request).await })
+        };
+        let started = Instant::now();
+
+        let mut executions = vec![];
+        for (execution_id, group_id) in [
+            ("exec1", "group1"),
+            ("exec2", "group1"),
+            ("exec3", "group2"),
+        ] {
+            executions.push(gather(execution_id, group_id));
+            // arrived in order
+            tokio::task::yield_now().await;
+        }
+        for execution in executions {
+            execution.await.unwrap();
+        }
+
+        let mut gatherings = gatherer.gatherings.lock().unwrap().to_owned();
+        gatherings.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        let gatherings: Vec<(&str, Duration, Duration)> = gatherings
+            .iter()
+            .map(|(execution_id, start, end)| {
+                (
+                    execution_id.as_str(),
+                    start.duration_since(started),
+                    end.duration_since(started),
+                )
+            })
+            .collect();
+        // the second execution of the group waits for the first one, the other group does not
+        assert_eq!(
+            gatherings,
+            vec![
+                ("exec1", Duration::ZERO, Duration::from_secs(10)),
+                ("exec3", Duration::ZERO, Duration::from_secs(10)),
+                ("exec2", Duration::from_secs(10), Duration::from_secs(20)),
+            ]
+        );
+        // no group is left behind
+        assert_eq!(engine.ordering.pending_groups(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_engine_executions_in_flight() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("recording", "v1", Arc::new(RecordingGatherer::default()));
+        let engine = Arc::new(GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(builder.build_registry()),
+        ));
+        let in_flight = || -> Vec<(String, ExecutionPhase, Vec<String>, Vec<String>)> {
+            engine
+                .in_flight()
+                .into_iter()
+                .map(|execution| {
+                    (
+                        execution.execution_id,
+                        execution.phase,
+                        execution.gatherers_pending,
+                        execution.gatherers_done,
+                    )
+                })
+                .collect()
+        };
+
+        let mut executions = vec![];
+        for execution_id in ["exec1", "exec2"] {
+            let engine = engine.clone();
+            let request = FactsGatheringRequest {
+                execution_id: execution_id.to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([(
+                    "recording".to_owned(),
+                    vec![fact_request("recording", "fact1")],
+                )]),
+                timeout: None,
+                correlation: Correlation::default(),
+            };
+            executions.push(tokio::spawn(async move { engine.gather(request).await }));
+            tokio::task::yield_now().await;
+        }
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(
+            in_flight(),
+            vec![
+                (
+                    "exec1".to_owned(),
+                    ExecutionPhase::Gathering,
+                    vec!["recording".to_owned()],
+                    vec![]
+                ),
+                (
+                    "exec2".to_owned(),
+                    ExecutionPhase::WaitingTurn,
+                    vec!["recording".to_owned()],
+                    vec![]
+                ),
+            ]
+        );
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(
+            in_flight(),
+            vec![(
+                "exec2".to_owned(),
+                ExecutionPhase::Gathering,
+                vec!["recording".to_owned()],
+                vec![]
+            )]
+        );
+
+        for execution in executions {
+            execution.await.unwrap();
+        }
+        assert!(in_flight().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_engine_rate_limited_execution_reports_errors() {
+        let mut mockgatherer = MockGatherer::new();
+        mockgatherer
+            .expect_gather()
+            .times(1)
+            .returning(|request| FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: vec![],
+                group_id: request.group_id.to_owned(),
+            });
+
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", mockgatherer);
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()))
+                .with_rate_limiter(GroupRateLimiter::new(
+                    Duration::from_secs(10),
+                    crate::config::RateLimitMode::Skip,
+                ));
+        let request = |execution_id: &str| FactsGatheringRequest {
+            execution_id: execution_id.to_owned(),
+            group_id: "group1".to_owned(),
+            facts_requests_by_gatherer: HashMap::from([(
+                "test_gat".to_owned(),
+                vec![fact_request("test_gat", "fact1")],
+            )]),
+            timeout: None,
+            correlation: Correlation::default(),
+        };
+
+        engine.gather(request("exec1")).await;
+        let skipped = engine.gather(request("exec2")).await;
+
+        assert_eq!(skipped.exeuction_id, "exec2");
+        assert_eq!(
+            skipped.facts_gathered,
+            vec![Fact {
+                name: "fact1".to_owned(),
+                check_id: "check1".to_owned(),
+                value: serde_json::Value::Null,
+                error: Some(FactGatheringErrors::RateLimitedError("10s".to_owned())),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_partially_resolved_execution() {
+        let mut mockgatherer = MockGatherer::new();
+        mockgatherer
+            .expect_gather()
+            .withf(|request| {
+                request.facts_requests_by_gatherer.len() == 1
+                    && request
+                        .facts_requests_by_gatherer
+                        .contains_key("test_gat@v1")
+            })
+            .times(1)
+            .returning(|request| FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: vec![Fact {
+                    name: "fact1".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: json!("value1"),
+                    error: None,
+                }],
+                group_id: request.group_id.to_owned(),
+            });
+
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", mockgatherer);
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()));
+
+        let mut facts_gathered = engine
+            .gather(FactsGatheringRequest {
+                execution_id: "exec1".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([
+                    (
+                        "test_gat@v1".to_owned(),
+                        vec![fact_request("test_gat@v1", "fact1")],
+                    ),
+                    (
+                        "test_gat@v2".to_owned(),
+                        vec![fact_request("test_gat@v2", "fact2")],
+                    ),
+                ]),
+                timeout: None,
+                correlation: Correlation::default(),
+            })
+            .await
+            .facts_gathered;
+        facts_gathered.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(facts_gathered[0].value, json!("value1"));
+        assert_eq!(
+            facts_gathered[1].error,
+            Some(FactGatheringErrors::GathererResolutionError(
+                "test_gat@v2".to_owned(),
+                "gatherer `test_gat@v2` not found".to_owned()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_engine_unresolved_execution_is_not_gathered() {
+        let mut mockgatherer = MockGatherer::new();
+        mockgatherer
+            .expect_gather()
+            .times(1)
+            .returning(|request| FactsGathered {
+                agent_id: "".to_owned(),
+                agent_name: "".to_owned(),
+                exeuction_id: request.execution_id.to_owned(),
+                facts_gathered: vec![],
+                group_id: request.group_id.to_owned(),
+            });
+
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gat", "v1", mockgatherer);
+        let engine =
+            GatheringEngine::new("agent_1", "sap-node-1", Arc::new(builder.build_registry()))
+                .with_rate_limiter(GroupRateLimiter::new(
+                    Duration::from_secs(10),
+                    crate::config::RateLimitMode::Skip,
+                ));
+
+        let unresolved = engine
+            .gather(FactsGatheringRequest {
+                execution_id: "exec1".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([
+                    ("unknown".to_owned(), vec![fact_request("unknown", "fact1")]),
+                    (
+                        "test_gat@v2".to_owned(),
+                        vec![fact_request("test_gat@v2", "fact2")],
+                    ),
+                ]),
+                timeout: None,
+                correlation: Correlation::default(),
+            })
+            .await;
+        // the unresolved execution did not count against the rate limit of the group
+        let gathered = engine
+            .gather(FactsGatheringRequest {
+                execution_id: "exec2".to_owned(),
+                group_id: "group1".to_owned(),
+                facts_requests_by_gatherer: HashMap::from([(
+                    "test_gat".to_owned(),
+                    vec![fact_request("test_gat", "fact1")],
+                )]),
+                timeout: None,
+                correlation: Correlation::default(),
+            })
+            .await;
+
+        assert_eq!(unresolved.facts_gathered.len(), 2);
+        assert!(unresolved.facts_gathered.iter().all(|fact| matches!(
+            fact.error,
+            Some(FactGatheringErrors::GathererResolutionError(_, _))
+        )));
+        assert!(gathered.facts_gathered.is_empty());
+    }
+}
@@ -1,11 +1,25 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+use crate::logging::Correlation;
+
+#[derive(Error, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FactGatheringErrors {
-    // the errors
+    #[error("gatherer `{0}` could not be resolved: {1}")]
+    GathererResolutionError(String, String),
+    #[error("fact not gathered, the agent runs in dry-run mode")]
+    DryRunError,
+    #[error("fact gathering timed out after {0}")]
+    GatheringTimeoutError(String),
+    #[error("execution skipped, the group is gathered at most once every {0}")]
+    RateLimitedError(String),
+    #[error("execution skipped by agent policy, group `{0}` is not gathered on the agent")]
+    SkippedByPolicyError(String),
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Fact {
     pub name: String,
     pub check_id: String,
@@ -13,24 +27,37 @@ pub struct Fact {
     pub error: Option<FactGatheringErrors>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FactsGathered {
     pub agent_id: String,
+    #[serde(default)]
+    pub agent_name: String,
+    #[serde(rename = "execution_id")]
     pub exeuction_id: String,
     pub facts_gathered: Vec<Fact>,
     pub group_id: String,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct FactRequest {
     pub argument: String,
     pub check_id: String,
     pub gatherer: String,
     pub name: String,
+    /// Free form annotations of the request, empty for the requests predating them
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct FactsGatheringRequest {
     pub execution_id: String,
     pub group_id: String,
     pub facts_requests_by_gatherer: HashMap<String, Vec<FactRequest>>,
+    /// How long the requester waits for the facts, missing when it does not tell
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    /// Ids of the delivery of the request, stamped on the published facts
+    #[serde(skip)]
+    pub correlation: Correlation,
 }
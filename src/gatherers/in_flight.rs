@@ -0,0 +1,194 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use serde::Serialize;
+
+/// Where an execution in flight is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionPhase {
+    /// Waiting for a gathering slot
+    Queued,
+    /// Waiting for the executions of its group arrived before
+    WaitingTurn,
+    /// Delayed by the rate limit of its group
+    RateLimited,
+    Gathering,
+    /// Gathered, the result is being dumped
+    Reporting,
+}
+
+/// An execution in flight, as seen when the snapshot is taken
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InFlightExecution {
+    pub execution_id: String,
+    pub group_id: String,
+    pub started_at: SystemTime,
+    pub phase: ExecutionPhase,
+    pub gatherers_pending: Vec<String>,
+    pub gatherers_done: Vec<String>,
+}
+
+/// Executions being handled, so that the operators can tell what the agent is doing.
+/// The requests delivered more than once are listed once per copy, in their arrival order
+#[derive(Debug, Default)]
+pub struct InFlightExecutions {
+    executions: Mutex<InFlightEntries>,
+}
+
+#[derive(Debug, Default)]
+struct InFlightEntries {
+    by_arrival: BTreeMap<u64, InFlightExecution>,
+    arrivals: u64,
+}
+
+impl InFlightExecutions {
+    /// Lists the execution until the returned one is dropped, queued at first
+    pub fn track(&self, execution_id: &str, group_id: &str) -> TrackedExecution<'_> {
+        let mut entries = self.entries();
+        let arrival = entries.arrivals;
+        entries.arrivals += 1;
+        entries.by_arrival.insert(
+            arrival,
+            InFlightExecution {
+                execution_id: execution_id.to_owned(),
+                group_id: group_id.to_owned(),
+                started_at: SystemTime::now(),
+                phase: ExecutionPhase::Queued,
+                gatherers_pending: vec![],
+                gatherers_done: vec![],
+            },
+        );
+
+        TrackedExecution {
+            executions: self,
+            arrival,
+        }
+    }
+
+    /// The executions in their arrival order
+    pub fn snapshot(&self) -> Vec<InFlightExecution> {
+        self.entries().by_arrival.values().cloned().collect()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, InFlightEntries> {
+        self.executions
+            .lock()
+            .expect("in flight executions poisoned, fatal.")
+    }
+}
+
+/// Execution listed in flight, updated as it progresses
+pub struct TrackedExecution<'a> {
+    executions: &'a InFlightExecutions,
+    arrival: u64,
+}
+
+impl TrackedExecution<'_> {
+    pub fn enter(&self, phase: ExecutionPhase) {
+        self.update(|execution| execution.phase = phase);
+    }
+
+    /// The gatherers are pending until they are done, the ones of every agent identity of the
+    /// execution are added
+    pub fn plan<'g>(&self, gatherer_names: impl IntoIterator<Item = &'g String>) {
+        self.update(|execution| {
+            execution
+                .gatherers_pending
+                .extend(gatherer_names.into_iter().cloned())
+        });
+    }
+
+    /// Done whatever the outcome, errors included
+    pub fn done(&self, gatherer_name: &str) {
+        self.update(|execution| {
+            if let Some(position) = execution
+                .gatherers_pending
+                .iter()
+                .position(|pending| pending == gatherer_name)
+            {
+                execution.gatherers_pending.remove(position);
+            }
+            execution.gatherers_done.push(gatherer_name.to_owned());
+        });
+    }
+
+    fn update(&self, update: impl FnOnce(&mut InFlightExecution)) {
+        if let Some(execution) = self.executions.entries().by_arrival.get_mut(&self.arrival) {
+            update(execution);
+        }
+    }
+}
+
+impl Drop for TrackedExecution<'_> {
+    fn drop(&mut self) {
+        self.executions.entries().by_arrival.remove(&self.arrival);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracked_executions_progress() {
+        let executions = InFlightExecutions::default();
+        let phases = |executions: &InFlightExecutions| -> Vec<(String, ExecutionPhase)> {
+            executions
+                .snapshot()
+                .into_iter()
+                .map(|execution| (execution.execution_id, execution.phase))
+                .collect()
+        };
+
+        let exec1 = executions.track("exec1", "group1");
+        let duplicated = executions.track("exec1", "group1");
+        let exec2 = executions.track("exec2", "group2");
+        exec1.enter(ExecutionPhase::Gathering);
+        exec1.plan(&["gatherer1".to_owned(), "gatherer2".to_owned()]);
+        exec1.done("gatherer1");
+
+        assert_eq!(
+            phases(&executions),
+            vec![
+                ("exec1".to_owned(), ExecutionPhase::Gathering),
+                ("exec1".to_owned(), ExecutionPhase::Queued),
+                ("exec2".to_owned(), ExecutionPhase::Queued),
+            ]
+        );
+        let snapshot = executions.snapshot();
+        assert_eq!(snapshot[0].group_id, "group1");
+        assert_eq!(snapshot[0].gatherers_pending, vec!["gatherer2"]);
+        assert_eq!(snapshot[0].gatherers_done, vec!["gatherer1"]);
+
+        // the copy leaves without affecting the first one
+        drop(duplicated);
+        drop(exec2);
+        assert_eq!(
+            phases(&executions),
+            vec![("exec1".to_owned(), ExecutionPhase::Gathering)]
+        );
+
+        drop(exec1);
+        assert!(executions.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_in_flight_executions_serialization() {
+        let executions = InFlightExecutions::default();
+        let execution = executions.track("exec1", "group1");
+        execution.enter(ExecutionPhase::WaitingTurn);
+
+        let serialized = serde_json::to_value(executions.snapshot()).unwrap();
+
+        assert_eq!(serialized[0]["execution_id"], "exec1");
+        assert_eq!(serialized[0]["phase"], "waiting_turn");
+        assert!(serialized[0]["gatherers_pending"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tokio::sync::{Mutex as TurnLock, OwnedMutexGuard};
+
+/// Executions of a group, the running one included
+#[derive(Debug)]
+struct GroupQueue {
+    turn: Arc<TurnLock<()>>,
+    executions: usize,
+}
+
+/// Runs the executions of the same group one at a time, in their arrival order, as wanda
+/// expects the facts of consecutive executions of a group in order. The executions of
+/// different groups run in parallel
+#[derive(Debug, Default)]
+pub struct GroupOrdering {
+    groups: Mutex<HashMap<String, GroupQueue>>,
+}
+
+impl GroupOrdering {
+    /// Waits for the executions of the group arrived before, the turn lasts until the
+    /// returned one is dropped
+    pub async fn turn(&self, group_id: &str) -> GroupTurn<'_> {
+        let (ticket, turn) = {
+            let mut groups = self.groups();
+            let queue = groups
+                .entry(group_id.to_owned())
+                .or_insert_with(|| GroupQueue {
+                    turn: Arc::new(TurnLock::new(())),
+                    executions: 0,
+                });
+            queue.executions += 1;
+
+            // the ticket leaves the queue when the wait is interrupted too
+            (
+                Ticket {
+                    ordering: self,
+                    group_id: group_id.to_owned(),
+                },
+                queue.turn.clone(),
+            )
+        };
+
+        GroupTurn {
+            _turn: turn.lock_owned().await,
+            _ticket: ticket,
+        }
+    }
+
+    fn groups(&self) -> MutexGuard<'_, HashMap<String, GroupQueue>> {
+        self.groups.lock().expect("group ordering poisoned, fatal.")
+    }
+
+    #[cfg(test)]
+    pub fn pending_groups(&self) -> usize {
+        self.groups().len()
+    }
+}
+
+/// Turn of an execution within its group
+pub struct GroupTurn<'a> {
+    // released before the ticket, so that the group is kept while its next execution waits
+    _turn: OwnedMutexGuard<()>,
+    _ticket: Ticket<'a>,
+}
+
+struct Ticket<'a> {
+    ordering: &'a GroupOrdering,
+    group_id: String,
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        let mut groups = self.ordering.groups();
+        let Some(queue) = groups.get_mut(&self.group_id) else {
+            return;
+        };
+        queue.executions -= 1;
+        // the groups without executions are forgotten
+        if queue.executions == 0 {
+            groups.remove(&self.group_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interrupted_waits_leave_the_group() {
+        let ordering = GroupOrdering::default();
+        let turn = ordering.turn("group1").await;
+
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), ordering.turn("group1"))
+                .await
+                .is_err()
+        );
+        assert_eq!(ordering.pending_groups(), 1);
+
+        drop(turn);
+        assert_eq!(ordering.pending_groups(), 0);
+        drop(ordering.turn("group1").await);
+        assert_eq!(ordering.pending_groups(), 0);
+    }
+}
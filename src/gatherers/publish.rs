@@ -0,0 +1,386 @@
+use std::sync::Arc;
+
+use amqprs::{BasicProperties, FieldTable, FieldValue};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use trento_contracts::events::to_event;
+use trento_contracts::stubs::facts_gathered::FactsGathered as FactsGatheredEvent;
+use uuid::Uuid;
+
+use super::{Fact, FactGatheringErrors, FactsGathered};
+use crate::broker::EventPublisher;
+use crate::events::TRACE_ID_HEADER;
+use crate::logging::Correlation;
+
+pub const FACTS_GATHERED_EVENT_TYPE: &str = "Trento.Checks.V1.FactsGathered";
+const AGENT_NAME_HEADER: &str = "x-agent-name";
+
+/// Source of the protobuf envelope, the agent the server expects the facts from
+const AGENT_SOURCE: &str = "https://github.com/trento-project/agent";
+const CONTENT_TYPE: &str = "application/x-protobuf";
+// survives a broker restart, as the execution waits for it
+const PERSISTENT_DELIVERY_MODE: u8 = 2;
+
+/// Reports the gathered facts to the server, as a `FactsGathered` contract event.
+/// Publishing is best effort, failures are logged and never fail the execution.
+pub struct FactsPublisher {
+    publisher: Arc<dyn EventPublisher>,
+    exchange: String,
+    routing_key: String,
+}
+
+impl FactsPublisher {
+    pub fn new(
+        publisher: Arc<dyn EventPublisher>,
+        exchange: &str,
+        routing_key: &str,
+    ) -> FactsPublisher {
+        FactsPublisher {
+            publisher,
+            exchange: exchange.to_owned(),
+            routing_key: routing_key.to_owned(),
+        }
+    }
+
+    /// Returns whether the facts were published. The correlation ids of the request are carried
+    /// over, for the server to follow the execution
+    pub async fn publish(&self, facts_gathered: &FactsGathered, correlation: &Correlation) -> bool {
+        let result = match encode(facts_gathered) {
+            Ok(content) => {
+                self.publisher
+                    .publish(
+                        &self.exchange,
+                        &self.routing_key,
+                        properties(facts_gathered, correlation),
+                        content,
+                    )
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(_) => {
+                debug!(
+                    "{} of execution {} published to {}",
+                    FACTS_GATHERED_EVENT_TYPE, facts_gathered.exeuction_id, self.exchange
+                );
+                true
+            }
+            Err(err) => {
+                warn!(
+                    agent_name = facts_gathered.agent_name.as_str(),
+                    execution_id = facts_gathered.exeuction_id.as_str(),
+                    group_id = facts_gathered.group_id.as_str();
+                    "could not publish gathered facts to {}: {}",
+                    self.exchange,
+                    err
+                );
+                false
+            }
+        }
+    }
+}
+
+/// The agent name is a header, the contract event has the agent id only
+fn properties(facts_gathered: &FactsGathered, correlation: &Correlation) -> BasicProperties {
+    let mut headers = FieldTable::new();
+    headers.insert(
+        AGENT_NAME_HEADER
+            .try_into()
+            .expect("invalid agent name header name, fatal."),
+        FieldValue::S(
+            facts_gathered
+                .agent_name
+                .as_str()
+                .try_into()
+                .expect("invalid agent name, fatal."),
+        ),
+    );
+    if let Some(trace_id) = &correlation.trace_id {
+        headers.insert(
+            TRACE_ID_HEADER
+                .try_into()
+                .expect("invalid trace id header name, fatal."),
+            FieldValue::S(
+                trace_id
+                    .as_str()
+                    .try_into()
+                    .expect("invalid trace id, fatal."),
+            ),
+        );
+    }
+
+    let mut properties = BasicProperties::default();
+    properties
+        .with_content_type(CONTENT_TYPE)
+        .with_delivery_mode(PERSISTENT_DELIVERY_MODE)
+        .with_headers(headers);
+    if let Some(correlation_id) = &correlation.correlation_id {
+        properties.with_correlation_id(correlation_id);
+    }
+
+    properties.finish()
+}
+
+/// The protobuf envelope of the event. Its id is stable, the same result published again
+/// has the same id
+fn encode(facts_gathered: &FactsGathered) -> Result<Vec<u8>, String> {
+    let event_id = Uuid::new_v5(
+        &Uuid::NAMESPACE_OID,
+        format!(
+            "{}/{}",
+            facts_gathered.exeuction_id, facts_gathered.agent_id
+        )
+        .as_bytes(),
+    );
+
+    to_event(
+        &event_id.to_string(),
+        AGENT_SOURCE,
+        facts_gathered_event(facts_gathered)?,
+    )
+    .map_err(|err| err.to_string())
+}
+
+/// The values of the facts are free form json, mapped to the `google.protobuf.Value` of the
+/// contract through its json representation
+fn facts_gathered_event(facts_gathered: &FactsGathered) -> Result<FactsGatheredEvent, String> {
+    let facts: Vec<Value> = facts_gathered.facts_gathered.iter().map(fact).collect();
+    let event = json!({
+        "execution_id": facts_gathered.exeuction_id,
+        "agent_id": facts_gathered.agent_id,
+        "facts_gathered": facts,
+        "group_id": facts_gathered.group_id,
+    });
+
+    protobuf_json_mapping::parse_from_str(&event.to_string()).map_err(|err| err.to_string())
+}
+
+/// The facts with an error report the error only
+fn fact(fact: &Fact) -> Value {
+    match &fact.error {
+        Some(error) => json!({
+            "check_id": fact.check_id,
+            "name": fact.name,
+            "error_value": {"message": error.to_string(), "type": error_type(error)},
+        }),
+        None => json!({
+            "check_id": fact.check_id,
+            "name": fact.name,
+            "value": fact.value,
+        }),
+    }
+}
+
+fn error_type(error: &FactGatheringErrors) -> &'static str {
+    match error {
+        FactGatheringErrors::GathererResolutionError(_, _) => "gatherer-not-found",
+        FactGatheringErrors::DryRunError => "dry-run",
+        FactGatheringErrors::GatheringTimeoutError(_) => "timeout",
+        FactGatheringErrors::RateLimitedError(_) => "rate-limited",
+        FactGatheringErrors::SkippedByPolicyError(_) => "skipped-by-policy",
+    }
+}
+
+/// Publisher recording the published events instead of sending them to the broker
+#[cfg(test)]
+pub mod recording {
+    use std::sync::Mutex;
+
+    use amqprs::BasicProperties;
+
+    use crate::broker::EventPublisher;
+
+    #[derive(Clone)]
+    pub struct Published {
+        pub exchange: String,
+        pub routing_key: String,
+        pub properties: BasicProperties,
+        pub content: Vec<u8>,
+    }
+
+    #[derive(Default)]
+    pub struct RecordingPublisher {
+        published: Mutex<Vec<Published>>,
+        fail: bool,
+    }
+
+    impl RecordingPublisher {
+        /// Every publish fails, as while disconnected
+        pub fn failing() -> RecordingPublisher {
+            RecordingPublisher {
+                fail: true,
+                ..Default::default()
+            }
+        }
+
+        pub fn published(&self) -> Vec<Published> {
+            self.published.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl EventPublisher for RecordingPublisher {
+        async fn publish(
+            &self,
+            exchange: &str,
+            routing_key: &str,
+            properties: BasicProperties,
+            content: Vec<u8>,
+        ) -> Result<(), String> {
+            if self.fail {
+                return Err("the publish channel is not open".to_owned());
+            }
+
+            self.published.lock().unwrap().push(Published {
+                exchange: exchange.to_owned(),
+                routing_key: routing_key.to_owned(),
+                properties,
+                content,
+            });
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trento_contracts::events::{event_data_from_event, event_type_from_raw_bytes};
+
+    use super::recording::{Published, RecordingPublisher};
+    use super::*;
+
+    fn facts_gathered() -> FactsGathered {
+        FactsGathered {
+            agent_id: "agent_1".to_owned(),
+            agent_name: "sap-node-1".to_owned(),
+            exeuction_id: "exec1".to_owned(),
+            facts_gathered: vec![
+                Fact {
+                    name: "fact1".to_owned(),
+                    check_id: "check1".to_owned(),
+                    value: json!({"totem": {"token": 30000}, "nodes": ["node1", "node2"]}),
+                    error: None,
+                },
+                Fact {
+                    name: "fact2".to_owned(),
+                    check_id: "check2".to_owned(),
+                    value: Value::Null,
+                    error: Some(FactGatheringErrors::GatheringTimeoutError("30s".to_owned())),
+                },
+                Fact {
+                    name: "fact3".to_owned(),
+                    check_id: "check2".to_owned(),
+                    value: Value::Null,
+                    error: Some(FactGatheringErrors::GathererResolutionError(
+                        "missing".to_owned(),
+                        "gatherer not found".to_owned(),
+                    )),
+                },
+            ],
+            group_id: "group1".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_facts_gathered_are_published() {
+        let recorder = Arc::new(RecordingPublisher::default());
+        let publisher = FactsPublisher::new(recorder.clone(), "trento.checks", "agents");
+        let correlation = Correlation {
+            correlation_id: Some("corr1".to_owned()),
+            trace_id: Some("trace1".to_owned()),
+        };
+
+        assert!(publisher.publish(&facts_gathered(), &correlation).await);
+        assert!(publisher.publish(&facts_gathered(), &correlation).await);
+
+        let published = recorder.published();
+        assert_eq!(published.len(), 2);
+        let Published {
+            exchange,
+            routing_key,
+            properties,
+            content,
+        } = &published[0];
+        assert_eq!(exchange, "trento.checks");
+        assert_eq!(routing_key, "agents");
+        assert_eq!(
+            properties.content_type(),
+            Some(&"application/x-protobuf".to_owned())
+        );
+        assert_eq!(properties.delivery_mode(), Some(2));
+        assert_eq!(properties.correlation_id(), Some(&"corr1".to_owned()));
+        let header = |key: &str| {
+            properties
+                .headers()
+                .unwrap()
+                .get(&key.try_into().unwrap())
+                .cloned()
+        };
+        assert_eq!(
+            header("x-trace-id"),
+            Some(FieldValue::S("trace1".try_into().unwrap()))
+        );
+        assert_eq!(
+            header("x-agent-name"),
+            Some(FieldValue::S("sap-node-1".try_into().unwrap()))
+        );
+        assert_eq!(
+            event_type_from_raw_bytes(content).unwrap(),
+            FACTS_GATHERED_EVENT_TYPE
+        );
+        // the same result, the same bytes
+        assert_eq!(&published[1].content, content);
+
+        let mut event = FactsGatheredEvent::new();
+        event_data_from_event(content, &mut event).unwrap();
+        let event: Value =
+            serde_json::from_str(&protobuf_json_mapping::print_to_string(&event).unwrap()).unwrap();
+        assert_eq!(
+            event,
+            json!({
+                "executionId": "exec1",
+                "agentId": "agent_1",
+                "factsGathered": [
+                    {
+                        "checkId": "check1",
+                        "name": "fact1",
+                        "value": {"totem": {"token": 30000}, "nodes": ["node1", "node2"]},
+                    },
+                    {
+                        "checkId": "check2",
+                        "name": "fact2",
+                        "errorValue": {
+                            "message": "fact gathering timed out after 30s",
+                            "type": "timeout",
+                        },
+                    },
+                    {
+                        "checkId": "check2",
+                        "name": "fact3",
+                        "errorValue": {
+                            "message": "gatherer `missing` could not be resolved: gatherer not found",
+                            "type": "gatherer-not-found",
+                        },
+                    },
+                ],
+                "groupId": "group1",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish_failures_are_not_fatal() {
+        let recorder = Arc::new(RecordingPublisher::failing());
+        let publisher = FactsPublisher::new(recorder.clone(), "trento.checks", "agents");
+
+        assert!(
+            !publisher
+                .publish(&facts_gathered(), &Correlation::default())
+                .await
+        );
+        assert!(recorder.published().is_empty());
+    }
+}
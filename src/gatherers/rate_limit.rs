@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use tokio::time::{Duration, Instant};
+
+use crate::config::RateLimitMode;
+
+/// Spaces the starts of the executions of every group by a minimum interval, as a bucket of a
+/// single token refilled once per interval. A group is forgotten once its interval is elapsed
+#[derive(Debug)]
+pub struct GroupRateLimiter {
+    interval: Duration,
+    mode: RateLimitMode,
+    /// Earliest start of the next execution of every group
+    next_starts: Mutex<HashMap<String, Instant>>,
+}
+
+impl GroupRateLimiter {
+    pub fn new(interval: Duration, mode: RateLimitMode) -> GroupRateLimiter {
+        GroupRateLimiter {
+            interval,
+            mode,
+            next_starts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Waits until the execution can start in delay mode, false when it is skipped in skip mode
+    pub async fn admit(&self, group_id: &str) -> bool {
+        let start = {
+            let mut next_starts = self.next_starts();
+            let now = Instant::now();
+            // the groups not seen within their interval are no longer limited
+            next_starts.retain(|_, next_start| *next_start > now);
+
+            let start = next_starts.get(group_id).copied().unwrap_or(now);
+            if start > now && self.mode == RateLimitMode::Skip {
+                return false;
+            }
+            // reserved right away, so that the delayed executions keep their arrival order
+            next_starts.insert(group_id.to_owned(), start + self.interval);
+
+            start
+        };
+        tokio::time::sleep_until(start).await;
+
+        true
+    }
+
+    fn next_starts(&self) -> MutexGuard<'_, HashMap<String, Instant>> {
+        self.next_starts
+            .lock()
+            .expect("group rate limiter poisoned, fatal.")
+    }
+
+    #[cfg(test)]
+    pub fn limited_groups(&self) -> usize {
+        self.next_starts().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delayed_executions() {
+        let limiter = GroupRateLimiter::new(Duration::from_secs(10), RateLimitMode::Delay);
+        let started = Instant::now();
+
+        assert!(limiter.admit("group1").await);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        // other groups are not affected
+        assert!(limiter.admit("group2").await);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+
+        assert!(limiter.admit("group1").await);
+        assert_eq!(started.elapsed(), Duration::from_secs(10));
+        assert!(limiter.admit("group1").await);
+        assert_eq!(started.elapsed(), Duration::from_secs(20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_skipped_executions() {
+        let limiter = GroupRateLimiter::new(Duration::from_secs(10), RateLimitMode::Skip);
+
+        assert!(limiter.admit("group1").await);
+        assert!(!limiter.admit("group1").await);
+        assert!(limiter.admit("group2").await);
+
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert!(!limiter.admit("group1").await);
+        // the skipped executions do not push the next start further
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(limiter.admit("group1").await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_groups_not_seen_recently_are_forgotten() {
+        let limiter = GroupRateLimiter::new(Duration::from_secs(10), RateLimitMode::Skip);
+
+        assert!(limiter.admit("group1").await);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert!(limiter.admit("group2").await);
+        assert_eq!(limiter.limited_groups(), 2);
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert!(limiter.admit("group3").await);
+        assert_eq!(limiter.limited_groups(), 2);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(limiter.admit("group1").await);
+        assert_eq!(limiter.limited_groups(), 1);
+    }
+}
@@ -8,6 +8,8 @@ pub enum RegistryErrors {
     GathererNotFoundError(String),
     #[error("could not extract the gatherer version from {0}, version should follow <gathererName>@<version> syntax")]
     GathererNameAndVersionError(String),
+    #[error("gatherer `{0}` is disabled by the configuration")]
+    GathererDisabledError(String),
 }
 
 pub struct GatherersRegistry {
@@ -15,7 +17,7 @@ pub struct GatherersRegistry {
 }
 
 impl GatherersRegistry {
-    pub fn get_gatherer(self, name: String) -> Result<Arc<dyn Gatherer>, RegistryErrors> {
+    pub fn get_gatherer(&self, name: String) -> Result<Arc<dyn Gatherer>, RegistryErrors> {
         let (gatherer_name, version) = extract_version_and_gatherer_name(&name)?;
 
         let latest_version =
@@ -31,18 +33,43 @@ impl GatherersRegistry {
         }
     }
 
-    pub fn inspect_gatherers(self) -> Vec<String> {
-        let mut gatherers_list: Vec<String> = vec![];
-        for (gatherer_name, versions) in self.gatherers {
+    pub fn inspect_gatherers(&self) -> Vec<String> {
+        self.gatherers_versions()
+            .into_iter()
+            .map(|(gatherer_name, versions)| format!("{} - {}", gatherer_name, versions.join("/")))
+            .collect()
+    }
+
+    /// Gatherers with their versions, both sorted so the output is stable
+    pub fn gatherers_versions(&self) -> Vec<(String, Vec<String>)> {
+        let mut gatherers_list: Vec<(String, Vec<String>)> = vec![];
+        for (gatherer_name, versions) in &self.gatherers {
             let mut sorted_versions: Vec<String> = versions.keys().cloned().collect();
             sorted_versions.sort();
 
-            gatherers_list.push(format!("{} - {}", gatherer_name, sorted_versions.join("/")));
+            gatherers_list.push((gatherer_name.to_owned(), sorted_versions));
         }
+        gatherers_list.sort();
 
         gatherers_list
     }
 
+    /// Every registered gatherer keyed by <gathererName>@<version>, sorted by key
+    pub fn versioned_gatherers(&self) -> Vec<(String, Arc<dyn Gatherer>)> {
+        let mut gatherers: Vec<(String, Arc<dyn Gatherer>)> = self
+            .gatherers
+            .iter()
+            .flat_map(|(gatherer_name, versions)| {
+                versions.iter().map(move |(version, gatherer)| {
+                    (format!("{}@{}", gatherer_name, version), gatherer.clone())
+                })
+            })
+            .collect();
+        gatherers.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        gatherers
+    }
+
     fn get_latest_version_for_gatherer(&self, name: &str) -> Result<String, RegistryErrors> {
         match self.gatherers.get(name) {
             Some(versioned_gatherers) => {
@@ -140,6 +167,27 @@ mod tests {
         assert!(available.contains(&"test_gatherer - v1/v2".to_owned()));
     }
 
+    #[test]
+    fn test_registry_gatherers_versions_sorted() {
+        let mut builder = GatherersRegistryBuilder::new();
+        builder.add_gatherer("test_gatherer", "v2", MockGatherer::new());
+        builder.add_gatherer("test_gatherer", "v1", MockGatherer::new());
+        builder.add_gatherer("another_test", "v1", MockGatherer::new());
+
+        let registry = builder.build_registry();
+
+        assert_eq!(
+            registry.gatherers_versions(),
+            vec![
+                ("another_test".to_owned(), vec!["v1".to_owned()]),
+                (
+                    "test_gatherer".to_owned(),
+                    vec!["v1".to_owned(), "v2".to_owned()]
+                ),
+            ]
+        );
+    }
+
     #[test]
     fn test_registry_get_gatherer_invalid_name_format() {
         let mockgatherer = MockGatherer::new();
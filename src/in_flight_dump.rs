@@ -0,0 +1,80 @@
+use std::{io, sync::Arc};
+
+use log::info;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+use crate::events::{EventCounters, QueueCounters};
+use crate::gatherers::{GatheringEngine, InFlightExecution};
+
+/// Logs the executions in flight and the ones waiting for a gathering slot on every SIGUSR1,
+/// telling what the agent is doing right now
+pub struct InFlightDump {
+    signal: Signal,
+}
+
+impl InFlightDump {
+    pub fn new() -> io::Result<InFlightDump> {
+        Ok(InFlightDump {
+            signal: signal(SignalKind::user_defined1())?,
+        })
+    }
+
+    pub async fn run(mut self, engine: Arc<GatheringEngine>, counters: Arc<EventCounters>) {
+        while self.signal.recv().await.is_some() {
+            info!("{}", in_flight_report(&engine.in_flight()));
+            info!("{}", queue_report(&counters.queue()));
+        }
+    }
+}
+
+/// A single json document, so that the dump is read back at once
+fn in_flight_report(executions: &[InFlightExecution]) -> String {
+    match serde_json::to_string(executions) {
+        Ok(executions_json) => format!(
+            "{} executions in flight: {}",
+            executions.len(),
+            executions_json
+        ),
+        Err(err) => format!("unable to dump the executions in flight: {}", err),
+    }
+}
+
+fn queue_report(queue: &QueueCounters) -> String {
+    format!(
+        "{} executions waiting for a gathering slot, up to {} at once, {} rejected as the queue was full, waits {:?}",
+        queue.waiting, queue.max_waiting, queue.rejected, queue.waits.buckets
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gatherers::GatherersRegistryBuilder;
+
+    #[test]
+    fn test_in_flight_report() {
+        let engine = GatheringEngine::new(
+            "agent_1",
+            "sap-node-1",
+            Arc::new(GatherersRegistryBuilder::new().build_registry()),
+        );
+        assert_eq!(
+            in_flight_report(&engine.in_flight()),
+            "0 executions in flight: []"
+        );
+
+        let _tracked = engine.track("exec1", "group1");
+        let report = in_flight_report(&engine.in_flight());
+
+        assert!(report.starts_with("1 executions in flight: [{\"execution_id\":\"exec1\""));
+        assert!(report.contains("\"phase\":\"queued\""));
+    }
+
+    #[test]
+    fn test_queue_report() {
+        assert_eq!(
+            queue_report(&QueueCounters::default()),
+            "0 executions waiting for a gathering slot, up to 0 at once, 0 rejected as the queue was full, waits [(100ms, 0), (1s, 0), (5s, 0), (30s, 0), (60s, 0), (300s, 0)]"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    future::Future,
+    io::{self, Write},
+    sync::{OnceLock, RwLock},
+};
+
+use env_logger::{Env, Logger};
+use log::{kv::Key, Log, Metadata, Record};
+use serde_json::{Map, Value};
+
+use crate::config::{LogFormat, LoggingConfig};
+
+// structured fields attached to the log calls, emitted when present
+const CONTEXT_KEYS: [&str; 4] = ["agent_name", "consumer", "execution_id", "group_id"];
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+tokio::task_local! {
+    static CORRELATION: Correlation;
+}
+
+/// Identifiers stamped on the event by its publisher, to follow an execution across the server
+/// and agent logs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Correlation {
+    pub correlation_id: Option<String>,
+    pub trace_id: Option<String>,
+}
+
+impl Correlation {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        [
+            ("correlation_id", &self.correlation_id),
+            ("trace_id", &self.trace_id),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_ref().map(|value| (key, value.to_owned())))
+        .collect()
+    }
+}
+
+/// Attaches the correlation ids to every log line emitted by the future, the tasks it spawns
+/// are not affected
+pub async fn with_correlation<F: Future>(correlation: Correlation, future: F) -> F::Output {
+    CORRELATION.scope(correlation, future).await
+}
+
+/// Delegates to an env_logger instance that can be replaced at runtime
+struct ReloadableLogger {
+    inner: RwLock<Logger>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.read().unwrap().log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.read().unwrap().flush()
+    }
+}
+
+/// RUST_LOG, when set, takes precedence over the configured filter
+pub fn init_logger(logging: &LoggingConfig) {
+    let logger = build_logger(logging);
+    let max_level = logger.filter();
+
+    let logger = LOGGER.get_or_init(|| ReloadableLogger {
+        inner: RwLock::new(logger),
+    });
+
+    log::set_logger(logger).expect("unable to initialize the logger, fatal.");
+    log::set_max_level(max_level);
+}
+
+/// Applies a new logging configuration to the already initialized logger
+pub fn reload_logger(logging: &LoggingConfig) {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+
+    let reloaded = build_logger(logging);
+    log::set_max_level(reloaded.filter());
+    *logger.inner.write().unwrap() = reloaded;
+}
+
+fn build_logger(logging: &LoggingConfig) -> Logger {
+    let filter = logging.filter();
+    let env = match &filter {
+        Some(filter) => Env::default().default_filter_or(filter.as_str()),
+        None => Env::default(),
+    };
+    let format = logging.format;
+
+    env_logger::Builder::from_env(env)
+        .format(move |buf, record| {
+            let timestamp = buf.timestamp().to_string();
+
+            match format {
+                LogFormat::Text => write_text(buf, &timestamp, record),
+                LogFormat::Json => write_json(buf, &timestamp, record),
+            }
+        })
+        .build()
+}
+
+/// The fields of the record followed by the correlation ids of the event being handled
+fn context_fields(record: &Record) -> Vec<(&'static str, String)> {
+    let mut fields: Vec<(&'static str, String)> = CONTEXT_KEYS
+        .iter()
+        .filter_map(|key| {
+            record
+                .key_values()
+                .get(Key::from_str(key))
+                .map(|value| (*key, value.to_string()))
+        })
+        .collect();
+    fields.extend(
+        CORRELATION
+            .try_with(Correlation::fields)
+            .unwrap_or_default(),
+    );
+
+    fields
+}
+
+fn write_text(writer: &mut impl Write, timestamp: &str, record: &Record) -> io::Result<()> {
+    let fields: String = context_fields(record)
+        .iter()
+        .map(|(key, value)| format!(" {}={}", key, value))
+        .collect();
+
+    writeln!(
+        writer,
+        "[{} {:<5} {}] {}{}",
+        timestamp,
+        record.level(),
+        record.target(),
+        record.args(),
+        fields
+    )
+}
+
+fn write_json(writer: &mut impl Write, timestamp: &str, record: &Record) -> io::Result<()> {
+    let mut entry = Map::new();
+    entry.insert("timestamp".to_owned(), Value::from(timestamp));
+    entry.insert("level".to_owned(), Value::from(record.level().as_str()));
+    entry.insert("target".to_owned(), Value::from(record.target()));
+    entry.insert("message".to_owned(), Value::from(record.args().to_string()));
+
+    for (key, value) in context_fields(record) {
+        entry.insert(key.to_owned(), Value::from(value));
+    }
+
+    writeln!(writer, "{}", Value::Object(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+    use serde_json::json;
+
+    use super::*;
+
+    const TIMESTAMP: &str = "2023-11-20T10:00:00Z";
+
+    #[test]
+    fn test_write_json_with_context_fields() {
+        let fields = vec![("execution_id", "exec1"), ("group_id", "group1")];
+        let mut output: Vec<u8> = vec![];
+
+        write_json(
+            &mut output,
+            TIMESTAMP,
+            &Record::builder()
+                .args(format_args!("execution requested"))
+                .level(Level::Info)
+                .target("vanvitelli::events::policy")
+                .key_values(&fields)
+                .build(),
+        )
+        .unwrap();
+
+        let entry: Value = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(
+            entry,
+            json!({
+                "timestamp": TIMESTAMP,
+                "level": "INFO",
+                "target": "vanvitelli::events::policy",
+                "message": "execution requested",
+                "execution_id": "exec1",
+                "group_id": "group1",
+            })
+        );
+    }
+
+    #[test]
+    fn test_write_json_without_context_fields() {
+        let mut output: Vec<u8> = vec![];
+
+        write_json(
+            &mut output,
+            TIMESTAMP,
+            &Record::builder()
+                .args(format_args!("consume forever"))
+                .level(Level::Warn)
+                .target("vanvitelli")
+                .build(),
+        )
+        .unwrap();
+
+        let entry: Value = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(
+            entry,
+            json!({
+                "timestamp": TIMESTAMP,
+                "level": "WARN",
+                "target": "vanvitelli",
+                "message": "consume forever",
+            })
+        );
+    }
+
+    #[test]
+    fn test_write_text_with_context_fields() {
+        let fields = vec![("execution_id", "exec1")];
+        let mut output: Vec<u8> = vec![];
+
+        write_text(
+            &mut output,
+            TIMESTAMP,
+            &Record::builder()
+                .args(format_args!("execution requested"))
+                .level(Level::Info)
+                .target("vanvitelli::events::policy")
+                .key_values(&fields)
+                .build(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[2023-11-20T10:00:00Z INFO  vanvitelli::events::policy] execution requested execution_id=exec1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_correlation_fields() {
+        let fields = vec![("execution_id", "exec1")];
+        let write = || {
+            let mut output: Vec<u8> = vec![];
+            write_json(
+                &mut output,
+                TIMESTAMP,
+                &Record::builder()
+                    .args(format_args!("execution requested"))
+                    .level(Level::Info)
+                    .target("vanvitelli::events::policy")
+                    .key_values(&fields)
+                    .build(),
+            )
+            .unwrap();
+
+            serde_json::from_slice::<Value>(&output).unwrap()
+        };
+        let correlation = Correlation {
+            correlation_id: Some("corr1".to_owned()),
+            trace_id: None,
+        };
+
+        let entry = with_correlation(correlation, async { write() }).await;
+
+        assert_eq!(entry["execution_id"], "exec1");
+        assert_eq!(entry["correlation_id"], "corr1");
+        assert!(entry.get("trace_id").is_none());
+        // outside of the handling the ids are gone
+        assert!(write().get("correlation_id").is_none());
+    }
+
+    #[test]
+    fn test_write_text_with_agent_name() {
+        let fields = vec![("execution_id", "exec1"), ("agent_name", "sap-node-1")];
+        let mut output: Vec<u8> = vec![];
+
+        write_text(
+            &mut output,
+            TIMESTAMP,
+            &Record::builder()
+                .args(format_args!("execution requested"))
+                .level(Level::Info)
+                .target("vanvitelli::events::policy")
+                .key_values(&fields)
+                .build(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[2023-11-20T10:00:00Z INFO  vanvitelli::events::policy] execution requested agent_name=sap-node-1 execution_id=exec1\n"
+        );
+    }
+}
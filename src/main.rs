@@ -1,80 +1,519 @@
 #[macro_use]
 extern crate log;
 
+mod broker;
+mod commands;
+mod config;
+mod disk_guard;
 mod events;
+mod exit_codes;
 mod gatherers;
+mod in_flight_dump;
+mod logging;
+mod pid_file;
+mod reload;
+mod shutdown;
 
-use crate::events::{EventsPolicy, RabbitMqConsumer};
+use crate::broker::{
+    AmqpConnector, Backoff, BlockedState, ChannelManager, CircuitBreaker, StatusTracker, Supervisor,
+};
+use crate::commands::{
+    check_config, gather, list_gatherers, preflight, print_default_config, replay, run_request,
+    version, AmqpBrokerProbe, GatherArgs, BROKER_PROBE_TIMEOUT, GATHERER_PROBE_TIMEOUT,
+};
+use crate::config::{Cli, Command, Config};
+use crate::disk_guard::{DiskGuard, FilesystemProbe};
+use crate::events::{
+    AckLedger, DedupCache, EventCounters, EventRecorder, EventsHandler, EventsPolicy,
+    ExecutionQueue, GroupIdFilterMiddleware, OperationsPolicy, Policies, RabbitMqConsumer,
+};
+use crate::exit_codes::{CLEAN_SHUTDOWN, CONFIGURATION_ERROR, RUNTIME_FAILURE};
+use crate::gatherers::{
+    default_registry, FactsDumper, FactsPublisher, GatheringEngine, GroupRateLimiter,
+};
+use crate::in_flight_dump::InFlightDump;
+use crate::logging::{init_logger, reload_logger};
+use crate::pid_file::PidFile;
+use crate::reload::{resize_on_reload, watch_config_reloads, SighupTrigger};
+use crate::shutdown::{
+    InFlight, ShutdownCoordinator, ShutdownOutcome, TeardownTimeouts, TerminationSignals,
+};
 
-use amqprs::{
-    callbacks::{DefaultChannelCallback, DefaultConnectionCallback},
-    channel::{BasicConsumeArguments, QueueBindArguments, QueueDeclareArguments},
-    connection::{Connection, OpenConnectionArguments},
+use amqprs::channel::Channel;
+use clap::Parser;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
 };
-use tokio::sync::Notify;
+use tokio::sync::watch;
+
+fn main() {
+    let cli = Cli::parse();
+
+    match &cli.command {
+        Some(Command::ListGatherers { json }) => {
+            list_gatherers(&default_registry(), *json, &mut std::io::stdout())
+                .expect("unable to write the gatherers list, fatal.");
+            return;
+        }
+        Some(Command::Gather {
+            gatherer,
+            name,
+            argument,
+            check_id,
+            timeout,
+        }) => {
+            let args = GatherArgs {
+                gatherer: gatherer.to_owned(),
+                name: name.to_owned(),
+                argument: argument.to_owned(),
+                check_id: check_id.to_owned(),
+                timeout: timeout.map(Duration::from_secs),
+            };
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("unable to build the tokio runtime, fatal.");
+
+            if let Err(err) =
+                runtime.block_on(gather(&default_registry(), args, &mut std::io::stdout()))
+            {
+                eprintln!("{}", err);
+                std::process::exit(err.exit_code());
+            }
+            return;
+        }
+        Some(Command::Version { json }) => {
+            version(*json, &mut std::io::stdout())
+                .expect("unable to write the version information, fatal.");
+            return;
+        }
+        Some(Command::PrintDefaultConfig) => {
+            print_default_config(&mut std::io::stdout())
+                .expect("unable to write the configuration template, fatal.");
+            return;
+        }
+        Some(Command::CheckConfig) => {
+            if let Err(err) = check_config(&cli, &mut std::io::stdout()) {
+                eprintln!("{}", err);
+                std::process::exit(err.exit_code());
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    let config = Config::load(&cli).unwrap_or_else(|err| {
+        eprintln!("invalid configuration: {}", err);
+        std::process::exit(CONFIGURATION_ERROR);
+    });
+
+    if cli.preflight {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("unable to build the tokio runtime, fatal.");
+
+        if let Err(err) = runtime.block_on(preflight(
+            &AmqpBrokerProbe::new(&config.broker, &config.topology),
+            &default_registry(),
+            BROKER_PROBE_TIMEOUT,
+            GATHERER_PROBE_TIMEOUT,
+            &mut std::io::stdout(),
+        )) {
+            eprintln!("{}", err);
+            std::process::exit(err.exit_code());
+        }
+        return;
+    }
+
+    if let Some(Command::RunRequest {
+        file,
+        output,
+        timeout,
+    }) = &cli.command
+    {
+        let engine = gathering_engine(
+            &config,
+            timeout
+                .map(Duration::from_secs)
+                .unwrap_or(config.execution_timeout),
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("unable to build the tokio runtime, fatal.");
+
+        if let Err(err) = runtime.block_on(run_request(
+            &engine,
+            file,
+            output.as_deref(),
+            &mut std::io::stdout(),
+        )) {
+            eprintln!("{}", err);
+            std::process::exit(err.exit_code());
+        }
+        return;
+    }
+
+    if let Some(Command::Replay { dir, file, output }) = &cli.command {
+        init_logger(&config.logging);
+
+        let engine = Arc::new(gathering_engine(&config, config.execution_timeout));
+        let policy = events_policy(&config, engine, Arc::new(EventCounters::default()));
+        let source = file
+            .as_ref()
+            .or(dir.as_ref())
+            .expect("replay source enforced by the cli, fatal.");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("unable to build the tokio runtime, fatal.");
+
+        if let Err(err) = runtime.block_on(replay(
+            &policy,
+            source,
+            output.as_deref(),
+            &mut std::io::stdout(),
+        )) {
+            eprintln!("{}", err);
+            std::process::exit(err.exit_code());
+        }
+        return;
+    }
+
+    init_logger(&config.logging);
+
+    for warning in config.warnings() {
+        warn!("{}", warning);
+    }
+
+    debug!("configuration loaded: {:?}", config);
+    info!(
+        "running as agent {} ({})",
+        config.agent_id, config.agent_name
+    );
+    info!(
+        "runtime: {} worker threads, {} max blocking threads, thread name {}",
+        config.runtime.worker_threads,
+        config.runtime.max_blocking_threads,
+        config.runtime.thread_name
+    );
 
-#[tokio::main(flavor = "multi_thread", worker_threads = 2)]
-async fn main() {
-    env_logger::init();
+    let runtime = config
+        .runtime
+        .builder()
+        .build()
+        .expect("unable to build the tokio runtime, fatal.");
 
+    runtime.block_on(run(cli, config));
+
+    std::process::exit(CLEAN_SHUTDOWN);
+}
+
+/// Engine of the facts gathering requests, with the gatherers shipped with the agent
+fn gathering_engine(config: &Config, timeout: Duration) -> GatheringEngine {
+    let mut engine = GatheringEngine::new(
+        &config.agent_id,
+        &config.agent_name,
+        Arc::new(default_registry()),
+    )
+    .with_timeout(timeout)
+    .with_enabled_gatherers(&config.enabled_gatherers);
+    if let Some(dir) = &config.facts_dump.dir {
+        engine = engine.with_dumper(FactsDumper::new(dir));
+    }
+    if config.dry_run {
+        engine = engine.with_dry_run();
+    }
+    if let Some(interval) = config.group_rate_limit {
+        engine = engine.with_rate_limiter(GroupRateLimiter::new(interval, config.rate_limit_mode));
+    }
+
+    engine
+}
+
+/// Policy of the consumed events, recording its handlings in the metrics
+fn events_policy(
+    config: &Config,
+    engine: Arc<GatheringEngine>,
+    metrics: Arc<EventCounters>,
+) -> EventsPolicy {
+    let events_policy = EventsPolicy::new(
+        config.agent_ids(),
+        &config.agent_name,
+        engine.clone(),
+        DedupCache::new(config.replay_ttl, config.replay_capacity),
+        ExecutionQueue::new(config.max_executions, config.execution_queue_depth)
+            .with_metrics(metrics.clone()),
+    )
+    .expect("unable to create protobuf event policy, fatal")
+    .with_handler_timeouts(&config.handler_timeouts)
+    .with_metrics(metrics);
+    // the skipped executions are recorded too
+    let group_filter = if !config.allowed_group_ids.is_empty() {
+        GroupIdFilterMiddleware::allowing(&config.allowed_group_ids)
+    } else if !config.denied_group_ids.is_empty() {
+        GroupIdFilterMiddleware::denying(&config.denied_group_ids)
+    } else {
+        return events_policy;
+    };
+    if !config.group_skip_notices {
+        return events_policy.with_middleware(group_filter);
+    }
+
+    events_policy.with_middleware(group_filter.with_skip_notices(&config.agent_ids(), engine))
+}
+
+async fn run(cli: Cli, config: Config) {
     info!("Hello, vanvitelli!");
 
-    // open a connection to RabbitMQ server
-    let connection = Connection::open(&OpenConnectionArguments::new(
-        "localhost",
-        5674,
-        "wanda",
-        "wanda",
-    ))
-    .await
-    .expect("unable to open a rabbitmq connection, fatal.");
-
-    connection
-        .register_callback(DefaultConnectionCallback)
-        .await
-        .expect("unable to attach callback to rabbitmq connection, fatal.");
-
-    info!("Connected to rabbitmq!");
-
-    let channel = connection.open_channel(None).await.unwrap();
-    channel
-        .register_callback(DefaultChannelCallback)
-        .await
-        .expect("unable to attach channel callback to rabbitmq connection, fatal.");
-
-    // declare a server-named transient queue
-    let (queue_name, _, _) = channel
-        .queue_declare(QueueDeclareArguments::default())
-        .await
-        .unwrap()
-        .expect("unable to create a transient queue in rabbitmq connection, fatal.");
-
-    // bind the queue to exchange
-    let routing_key = "executions";
-    let exchange_name = "trento.checks";
-    channel
-        .queue_bind(QueueBindArguments::new(
-            &queue_name,
-            exchange_name,
-            routing_key,
-        ))
-        .await
-        .expect("unable to bind the queue in the rabbitmq connection, fatal.");
-
-    let args = BasicConsumeArguments::new(&queue_name, "basic_consumer")
-        .manual_ack(true)
-        .finish();
-
-    let policy =
-        EventsPolicy::new("host_id").expect("unable to create protobuf event policy, fatal");
-    let rabbit_consumer = RabbitMqConsumer::new(policy);
-
-    channel
-        .basic_consume(rabbit_consumer, args)
-        .await
-        .expect("unable to consume from rabbitmq queue, fatal.");
+    let (config_sender, config_receiver) = watch::channel(config.clone());
+    let reload_trigger =
+        SighupTrigger::new().expect("unable to install the SIGHUP handler, fatal.");
+    tokio::spawn(watch_config_reloads(
+        reload_trigger,
+        move || Config::load(&cli),
+        config_sender,
+    ));
+    let mut logging_config = config_receiver.clone();
+    tokio::spawn(async move {
+        while logging_config.changed().await.is_ok() {
+            reload_logger(&logging_config.borrow().logging);
+        }
+    });
+
+    if let (Some(dir), Some(retention)) = (&config.facts_dump.dir, config.facts_dump.retention()) {
+        let pruned = FactsDumper::new(dir).prune(retention);
+        info!(
+            "pruned {} expired facts dumps from {}",
+            pruned,
+            dir.display()
+        );
+    }
+
+    // invalid connection arguments would fail every reconnection attempt
+    if let Err(err) = config.broker.connection_arguments() {
+        eprintln!("invalid configuration: {}", err);
+        std::process::exit(CONFIGURATION_ERROR);
+    }
+
+    // bounds the handlings running at once across all the consumers
+    let in_flight = InFlight::new(config.delivery.max_in_flight);
+    let consumer_in_flight = in_flight.clone();
+    // the events are republished on a channel of their own, the gathered facts are published there
+    let channels: ChannelManager<Channel> = ChannelManager::default();
+    let publisher = channels.publisher();
+    let engine = Arc::new(
+        gathering_engine(&config, config.execution_timeout)
+            .with_publisher(FactsPublisher::new(
+                Arc::new(publisher.clone()),
+                &config.results.exchange,
+                &config.results.routing_key,
+            ))
+            .with_running_config(config_receiver.clone()),
+    );
+    // the executions in flight and the waiting ones are logged on demand
+    let in_flight_dump =
+        InFlightDump::new().expect("unable to install the SIGUSR1 handler, fatal.");
+    let event_counters = Arc::new(EventCounters::default());
+    tokio::spawn(in_flight_dump.run(engine.clone(), event_counters.clone()));
+    // a single policy of each kind, shared by the consumers of all the subscriptions
+    let events_policy = Arc::new(events_policy(&config, engine, event_counters.clone()));
+    // the gathering capacity follows the reloaded configuration
+    tokio::spawn(resize_on_reload(
+        config_receiver.clone(),
+        events_policy.queue(),
+    ));
+    let policies = Policies::new(events_policy.clone(), Arc::new(OperationsPolicy));
+    let delivery = config.delivery.to_owned();
+    let dedup = Arc::new(DedupCache::new(
+        config.delivery.dedup_ttl,
+        config.delivery.dedup_capacity,
+    ));
+    let consumer_dedup = dedup.clone();
+    // the consumed deliveries are recorded as received, the recordings are pruned meanwhile
+    let recorder = config.record_events.dir.as_ref().map(|dir| {
+        Arc::new(
+            EventRecorder::new(dir)
+                .with_retention(config.record_events.retention())
+                .with_max_size(config.record_events.max_size),
+        )
+    });
+    if let Some(recorder) = &recorder {
+        tokio::spawn(recorder.clone().sweep());
+    }
+    // updated by the connection callback, the consumers pause while the broker blocks the connection
+    // maintained by the supervisor, the connection callback and the consumers
+    let status = StatusTracker::default();
+    let consumer_status = status.clone();
+    // the acknowledgements of the deliveries received on a replaced channel are dropped
+    let ack_ledger = Arc::new(AckLedger::default().with_status(status.clone()));
+    let blocked = BlockedState::default().with_status(status.clone());
+    let consumer_blocked = blocked.clone();
+    // shared by all the consumers, the failures of one pause them all
+    let breaker = Arc::new(CircuitBreaker::new(&config.breaker));
+    let consumer_breaker = breaker.clone();
+    // the consumers pause while the disk is low on space, never when the guard is disabled
+    let disk_guard = DiskGuard::new(&config.disk_guard, FilesystemProbe);
+    let consumer_disk = disk_guard.space();
+    let disk_checks = config
+        .disk_guard
+        .enabled()
+        .then(|| tokio::spawn(disk_guard.run()));
+    // the consumers of all the subscriptions are built alike, but for the policy of their events
+    let build_consumer = Arc::new(move |handler: Arc<dyn EventsHandler>, queue: &str, index| {
+        let consumer = RabbitMqConsumer::new(
+            handler,
+            queue,
+            delivery.to_owned(),
+            consumer_in_flight.clone(),
+        )
+        .with_index(index)
+        .with_ack_ledger(ack_ledger.clone())
+        .with_blocked_state(consumer_blocked.clone())
+        .with_dedup(consumer_dedup.clone())
+        .with_publisher(publisher.clone())
+        .with_breaker(consumer_breaker.clone())
+        .with_disk_space(consumer_disk.clone())
+        .with_status(consumer_status.clone());
+
+        match &recorder {
+            Some(recorder) => consumer.with_recorder(recorder.clone()),
+            None => consumer,
+        }
+    });
+    let mut connector = AmqpConnector::new(&config, blocked, channels);
+    for subscription in config.all_subscriptions() {
+        let handler = policies.handler(subscription.policy);
+        let build_consumer = build_consumer.clone();
+        connector = connector.with_subscription(&subscription, move |queue, index| {
+            build_consumer(handler.clone(), queue, index)
+        });
+    }
+    let backoff = Backoff::new(config.reconnect.base_delay, config.reconnect.max_delay);
+    let mut supervisor =
+        Supervisor::new(connector, backoff, config.reconnect.max_attempts).with_status(status);
+    let counters = supervisor.counters();
+
+    // the pid file is written once connected to the broker
+    let mut status = supervisor.status();
+    let pid_file_path = config.pid_file.to_owned();
+    let pid_file = tokio::spawn(async move {
+        if !status.connected().await {
+            return None;
+        }
+        info!("Connected to rabbitmq!");
+
+        pid_file_path.map(|path| {
+            PidFile::acquire(&path).unwrap_or_else(|err| {
+                error!("unable to start, fatal: {}", err);
+                std::process::exit(RUNTIME_FAILURE);
+            })
+        })
+    });
+
+    let signals =
+        TerminationSignals::new().expect("unable to install the shutdown signals handler, fatal.");
+    let timeouts = TeardownTimeouts {
+        cancel: config.close_timeout,
+        drain: config.drain_timeout,
+        settle: config.close_timeout,
+        close: config.close_timeout,
+    };
+    let mut coordinator = ShutdownCoordinator::new(signals, timeouts, in_flight);
 
     info!("consume forever..., ctrl+c to exit");
-    let guard = Notify::new();
-    guard.notified().await;
+    tokio::select! {
+        result = supervisor.run() => {
+            if let Err(err) = result {
+                error!("unable to consume from rabbitmq, fatal: {}", err);
+                std::process::exit(err.exit_code());
+            }
+        }
+        _ = coordinator.requested() => (),
+    }
+
+    info!(
+        "shutting down, {} broker connections established in {} attempts, {} consumer cancellations",
+        counters.connections.load(Ordering::Relaxed),
+        counters.attempts.load(Ordering::Relaxed),
+        counters.cancellations.load(Ordering::Relaxed)
+    );
+    info!(
+        "{} duplicated deliveries skipped, {} requests of executions already running, {} of executions recently finished",
+        dedup.duplicates(),
+        events_policy.duplicated_executions(),
+        events_policy.replayed_executions()
+    );
+    let queue = event_counters.queue();
+    info!(
+        "{} executions waited for a gathering slot, up to {} at once, for {:?} at most and {:?} on average, {} rejected as the queue was full, {} still waiting",
+        queue.waits.count,
+        queue.max_waiting,
+        queue.waits.max,
+        queue.waits.average(),
+        queue.rejected,
+        queue.waiting
+    );
+    info!(
+        "{} events of a newer version than the handled ones",
+        events_policy.newer_version_events()
+    );
+    info!(
+        "circuit breaker opened {} times, closed {} times",
+        breaker.openings(),
+        breaker.closings()
+    );
+    let status = supervisor.status().snapshot();
+    info!(
+        "connection {:?}, connected for {:?}, consumer active: {}, blocked: {}, {} unacked deliveries, {} failed attempts since the last connection, last delivery {:?} ago",
+        status.state,
+        status.connected_since.map(|since| since.elapsed()),
+        status.consumer_active,
+        status.blocked,
+        status.unacked,
+        status.reconnect_attempts,
+        status.last_delivery_at.map(|at| at.elapsed())
+    );
+    info!(
+        "{} events handled, {} slow, {} expired, handling durations min {:?}, max {:?}, average {:?}",
+        status.handling.count,
+        status.handling.slow,
+        status.expired,
+        status.handling.min,
+        status.handling.max,
+        status.handling.average()
+    );
+    info!(
+        "{} events handled successfully, {} skipped, {} rejected, {} panicked",
+        status.outcomes.handled,
+        status.outcomes.skipped,
+        status.outcomes.rejected,
+        status.outcomes.panicked
+    );
+    for (event_type, counters) in event_counters.snapshot() {
+        info!(
+            "events of type {}: {:?}, handling durations max {:?}, average {:?}",
+            event_type,
+            counters.outcomes,
+            counters.max,
+            counters.average()
+        );
+    }
+    let outcome = coordinator.shutdown(supervisor.take_session()).await;
+
+    if pid_file.is_finished() {
+        drop(pid_file.await);
+    } else {
+        pid_file.abort();
+    }
+    if let Some(disk_checks) = disk_checks {
+        disk_checks.abort();
+    }
+
+    if outcome == ShutdownOutcome::Forced {
+        warn!("shutdown forced, in-flight deliveries abandoned");
+        std::process::exit(RUNTIME_FAILURE);
+    }
 }
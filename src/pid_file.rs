@@ -0,0 +1,141 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PidFileErrors {
+    #[error("another instance is running with pid {1}, pid file {0}")]
+    AlreadyRunningError(String, u32),
+    #[error("could not write pid file {0}: {1}")]
+    WriteError(String, io::Error),
+}
+
+/// Pid file owned by the running agent, removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn acquire(path: &Path) -> Result<PidFile, PidFileErrors> {
+        PidFile::acquire_with(path, std::process::id(), is_process_alive)
+    }
+
+    /// Writes `pid` to `path`, taking over pid files left behind by processes no longer alive
+    fn acquire_with(
+        path: &Path,
+        pid: u32,
+        is_alive: impl Fn(u32) -> bool,
+    ) -> Result<PidFile, PidFileErrors> {
+        let owner = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| content.trim().parse::<u32>().ok());
+
+        match owner {
+            Some(owner) if owner != pid && is_alive(owner) => {
+                return Err(PidFileErrors::AlreadyRunningError(
+                    path.display().to_string(),
+                    owner,
+                ));
+            }
+            Some(owner) => info!(
+                "taking over stale pid file {} of process {}",
+                path.display(),
+                owner
+            ),
+            None => (),
+        }
+
+        fs::write(path, format!("{}\n", pid))
+            .map_err(|err| PidFileErrors::WriteError(path.display().to_string(), err))?;
+
+        Ok(PidFile {
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("could not remove pid file {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+fn is_process_alive(pid: u32) -> bool {
+    Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pid_file_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("vanvitelli_test_{}.pid", name));
+        let _ = fs::remove_file(&path);
+
+        path
+    }
+
+    #[test]
+    fn test_pid_file_created_and_removed() {
+        let path = pid_file_path("pid_created");
+
+        let pid_file = PidFile::acquire_with(&path, 42, |_| true).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        drop(pid_file);
+
+        assert_eq!(content, "42\n");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_pid_file_owned_by_live_process() {
+        let path = pid_file_path("pid_live");
+        fs::write(&path, "41\n").unwrap();
+
+        let result = PidFile::acquire_with(&path, 42, |pid| pid == 41);
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            result,
+            Err(PidFileErrors::AlreadyRunningError(_, 41))
+        ));
+        assert_eq!(content, "41\n");
+    }
+
+    #[test]
+    fn test_pid_file_stale_takeover() {
+        let path = pid_file_path("pid_stale");
+        fs::write(&path, "41\n").unwrap();
+
+        let pid_file = PidFile::acquire_with(&path, 42, |_| false).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        drop(pid_file);
+
+        assert_eq!(content, "42\n");
+    }
+
+    #[test]
+    fn test_pid_file_with_garbage_content() {
+        let path = pid_file_path("pid_garbage");
+        fs::write(&path, "not a pid").unwrap();
+
+        let pid_file = PidFile::acquire_with(&path, 42, |_| true).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        drop(pid_file);
+
+        assert_eq!(content, "42\n");
+    }
+
+    #[test]
+    fn test_current_process_is_alive() {
+        assert!(is_process_alive(std::process::id()));
+    }
+}
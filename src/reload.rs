@@ -0,0 +1,230 @@
+use std::{io, sync::Arc};
+
+use log::{error, info, warn};
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::watch,
+};
+
+use crate::config::{Config, ConfigDiff, ConfigErrors};
+use crate::events::ExecutionQueue;
+
+/// Source of configuration reload requests
+#[async_trait::async_trait]
+pub trait ReloadTrigger: Send {
+    /// Waits for the next reload request, `false` when no more requests will come
+    async fn triggered(&mut self) -> bool;
+}
+
+pub struct SighupTrigger {
+    signal: Signal,
+}
+
+impl SighupTrigger {
+    pub fn new() -> io::Result<SighupTrigger> {
+        Ok(SighupTrigger {
+            signal: signal(SignalKind::hangup())?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ReloadTrigger for SighupTrigger {
+    async fn triggered(&mut self) -> bool {
+        self.signal.recv().await.is_some()
+    }
+}
+
+/// Reloads the configuration on every trigger, until the trigger is exhausted
+pub async fn watch_config_reloads(
+    mut trigger: impl ReloadTrigger,
+    load: impl Fn() -> Result<Config, ConfigErrors>,
+    config: watch::Sender<Config>,
+) {
+    while trigger.triggered().await {
+        info!("configuration reload requested");
+
+        if let Err(err) = reload_config(&load, &config) {
+            error!("invalid configuration, keeping the running one: {}", err);
+        }
+    }
+}
+
+/// Resizes the execution queue to the max executions of every reloaded configuration, until
+/// the configuration is dropped
+pub async fn resize_on_reload(mut config: watch::Receiver<Config>, queue: Arc<ExecutionQueue>) {
+    while config.changed().await.is_ok() {
+        let max_executions = config.borrow_and_update().max_executions;
+        queue.resize(max_executions);
+    }
+}
+
+/// Applies the reloadable subset of the loaded configuration in a single update.
+/// The running configuration is left untouched when the loaded one is invalid.
+pub fn reload_config(
+    load: impl Fn() -> Result<Config, ConfigErrors>,
+    config: &watch::Sender<Config>,
+) -> Result<ConfigDiff, ConfigErrors> {
+    let reloaded = load()?;
+    let diff = ConfigDiff::between(&config.borrow(), &reloaded);
+
+    if diff.is_empty() {
+        info!("configuration unchanged");
+        return Ok(diff);
+    }
+    if !diff.restart_required.is_empty() {
+        warn!(
+            "changed keys require a restart to be applied: {}",
+            diff.restart_required.join(", ")
+        );
+    }
+    if !diff.reloadable.is_empty() {
+        config.send_modify(|running| diff.apply(running, &reloaded));
+        info!("configuration reloaded: {}", diff.reloadable.join(", "));
+    }
+
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::config::Cli;
+    use crate::events::Priority;
+
+    struct FakeTrigger {
+        remaining: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl ReloadTrigger for FakeTrigger {
+        async fn triggered(&mut self) -> bool {
+            if self.remaining == 0 {
+                return false;
+            }
+            self.remaining -= 1;
+
+            true
+        }
+    }
+
+    fn load(cli: Cli) -> impl Fn() -> Result<Config, ConfigErrors> {
+        move || {
+            Config::load(&Cli {
+                agent_id: Some("agent_1".to_owned()),
+                ..cli.clone()
+            })
+        }
+    }
+
+    fn running_config() -> Config {
+        load(Cli::default())().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_watch_config_reloads_applies_reloadable_keys() {
+        let (sender, receiver) = watch::channel(running_config());
+        let reload = load(Cli {
+            log_level: Some("debug".to_owned()),
+            amqp_host: Some("rabbit.local".to_owned()),
+            ..Default::default()
+        });
+
+        watch_config_reloads(FakeTrigger { remaining: 1 }, reload, sender).await;
+
+        let config = receiver.borrow();
+        assert_eq!(config.logging.level, Some("debug".to_owned()));
+        assert_eq!(config.broker.host, running_config().broker.host);
+    }
+
+    #[test]
+    fn test_reload_config_rejects_invalid_config() {
+        let (sender, receiver) = watch::channel(running_config());
+        let reload = load(Cli {
+            log_level: Some("debug".to_owned()),
+            amqp_port: Some(0),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            reload_config(reload, &sender).err().unwrap(),
+            ConfigErrors::InvalidPortError(0)
+        );
+        assert!(!receiver.has_changed().unwrap());
+        assert_eq!(*receiver.borrow(), running_config());
+    }
+
+    #[test]
+    fn test_reload_config_applies_execution_timeout() {
+        let (sender, receiver) = watch::channel(running_config());
+        let reload = load(Cli {
+            execution_timeout: Some(45),
+            ..Default::default()
+        });
+
+        let diff = reload_config(reload, &sender).unwrap();
+
+        assert_eq!(diff.reloadable, vec!["execution-timeout"]);
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(receiver.borrow().execution_timeout, Duration::from_secs(45));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reloaded_max_executions_resize_the_queue() {
+        let (sender, receiver) = watch::channel(running_config());
+        let queue = Arc::new(ExecutionQueue::new(Some(1), 1));
+        let _running = queue.admit(Priority::default()).await.unwrap();
+        let resizing = tokio::spawn(resize_on_reload(receiver, queue.clone()));
+        let reload = load(Cli {
+            max_executions: Some(2),
+            ..Default::default()
+        });
+
+        let diff = reload_config(reload, &sender).unwrap();
+        assert_eq!(diff.reloadable, vec!["max-executions"]);
+        tokio::task::yield_now().await;
+
+        // admitted right away, beyond the capacity of the startup configuration
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), queue.admit(Priority::default()))
+                .await
+                .is_ok()
+        );
+
+        drop(sender);
+        resizing.await.unwrap();
+    }
+
+    #[test]
+    fn test_reload_config_applies_enabled_gatherers() {
+        let (sender, receiver) = watch::channel(running_config());
+        let reload = load(Cli {
+            enabled_gatherers: vec!["corosync.conf".to_owned()],
+            ..Default::default()
+        });
+
+        let diff = reload_config(reload, &sender).unwrap();
+
+        assert_eq!(diff.reloadable, vec!["enabled-gatherers"]);
+        assert_eq!(
+            receiver.borrow().enabled_gatherers,
+            vec!["corosync.conf".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_reload_config_restart_required_only() {
+        let (sender, receiver) = watch::channel(running_config());
+        let reload = load(Cli {
+            exchange: Some("trento.staging".to_owned()),
+            ..Default::default()
+        });
+
+        let diff = reload_config(reload, &sender).unwrap();
+
+        assert_eq!(diff.restart_required, vec!["exchange"]);
+        assert!(!receiver.has_changed().unwrap());
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{info, warn};
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::{Notify, OwnedSemaphorePermit, Semaphore},
+};
+
+use crate::broker::BrokerSession;
+
+/// Source of shutdown requests
+#[async_trait::async_trait]
+pub trait ShutdownSignals: Send {
+    /// Waits for the next shutdown request
+    async fn received(&mut self);
+}
+
+pub struct TerminationSignals {
+    terminate: Signal,
+    interrupt: Signal,
+}
+
+impl TerminationSignals {
+    pub fn new() -> io::Result<TerminationSignals> {
+        Ok(TerminationSignals {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ShutdownSignals for TerminationSignals {
+    async fn received(&mut self) {
+        tokio::select! {
+            _ = self.terminate.recv() => (),
+            _ = self.interrupt.recv() => (),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct InFlightState {
+    count: AtomicUsize,
+    idle: Notify,
+    /// Bounds the deliveries handled at once, unbounded when missing
+    permits: Option<Arc<Semaphore>>,
+}
+
+/// Counts the deliveries being handled, so that shutdown can wait for them
+#[derive(Debug, Clone, Default)]
+pub struct InFlight {
+    state: Arc<InFlightState>,
+}
+
+/// Marks a delivery as handled when dropped, releasing its permit even when the handling panics
+pub struct InFlightGuard {
+    state: Arc<InFlightState>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl InFlight {
+    pub fn new(limit: Option<usize>) -> InFlight {
+        InFlight {
+            state: Arc::new(InFlightState {
+                permits: limit.map(|limit| Arc::new(Semaphore::new(limit))),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn start(&self) -> InFlightGuard {
+        self.state.count.fetch_add(1, Ordering::SeqCst);
+
+        InFlightGuard {
+            state: self.state.clone(),
+            _permit: None,
+        }
+    }
+
+    /// Like `start`, waiting while the limit of deliveries handled at once is reached.
+    /// The waiting deliveries stay unacknowledged, so the broker stops sending more of them
+    pub async fn acquire(&self) -> InFlightGuard {
+        let permit = match &self.state.permits {
+            Some(permits) => Some(
+                permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("the in-flight semaphore is never closed, fatal."),
+            ),
+            None => None,
+        };
+
+        self.state.count.fetch_add(1, Ordering::SeqCst);
+
+        InFlightGuard {
+            state: self.state.clone(),
+            _permit: permit,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.state.count.load(Ordering::SeqCst)
+    }
+
+    pub async fn wait_idle(&self) {
+        loop {
+            // registered before checking the count, so a concurrent release is not missed
+            let idle = self.state.idle.notified();
+            if self.count() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.state.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.state.idle.notify_waiters();
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ShutdownOutcome {
+    Graceful,
+    /// A second shutdown request arrived before the shutdown completed
+    Forced,
+}
+
+/// Bounds of the shutdown phases, so that a dead broker cannot block the shutdown
+#[derive(Debug, Clone, PartialEq)]
+pub struct TeardownTimeouts {
+    pub cancel: Duration,
+    pub drain: Duration,
+    pub settle: Duration,
+    pub close: Duration,
+}
+
+/// Stops the session in order: cancels the consumers, waits for the deliveries being handled,
+/// settles the remaining ones, then closes the channels and the connection.
+/// A failed or expired phase does not prevent the following ones
+pub async fn teardown(
+    mut session: impl BrokerSession,
+    in_flight: &InFlight,
+    timeouts: &TeardownTimeouts,
+) {
+    teardown_phase("cancel the consumers", timeouts.cancel, session.cancel()).await;
+    drain(in_flight, timeouts.drain).await;
+    teardown_phase(
+        "settle the remaining deliveries",
+        timeouts.settle,
+        session.settle_stragglers(),
+    )
+    .await;
+    teardown_phase(
+        "close the channels",
+        timeouts.close,
+        session.close_channels(),
+    )
+    .await;
+    teardown_phase("close the connection", timeouts.close, async {
+        session.close().await;
+        Ok(())
+    })
+    .await;
+}
+
+async fn teardown_phase(
+    name: &str,
+    timeout: Duration,
+    phase: impl Future<Output = Result<(), String>>,
+) {
+    match tokio::time::timeout(timeout, phase).await {
+        Ok(Ok(_)) => (),
+        Ok(Err(err)) => warn!("{}", err),
+        Err(_) => warn!("unable to {} within {:?}", name, timeout),
+    }
+}
+
+async fn drain(in_flight: &InFlight, timeout: Duration) {
+    info!("waiting for {} in-flight deliveries", in_flight.count());
+    if tokio::time::timeout(timeout, in_flight.wait_idle())
+        .await
+        .is_err()
+    {
+        warn!(
+            "drain timeout of {:?} expired, {} deliveries left unacknowledged",
+            timeout,
+            in_flight.count()
+        );
+    }
+}
+
+/// Stops the consumer, lets the deliveries being handled complete and tears down the broker session.
+/// Deliveries received but never acknowledged are requeued by the broker once the channel is closed.
+pub struct ShutdownCoordinator<S: ShutdownSignals> {
+    signals: S,
+    timeouts: TeardownTimeouts,
+    in_flight: InFlight,
+}
+
+impl<S: ShutdownSignals> ShutdownCoordinator<S> {
+    pub fn new(
+        signals: S,
+        timeouts: TeardownTimeouts,
+        in_flight: InFlight,
+    ) -> ShutdownCoordinator<S> {
+        ShutdownCoordinator {
+            signals,
+            timeouts,
+            in_flight,
+        }
+    }
+
+    /// Waits for the shutdown request
+    pub async fn requested(&mut self) {
+        self.signals.received().await
+    }
+
+    pub async fn shutdown(&mut self, session: Option<impl BrokerSession>) -> ShutdownOutcome {
+        let signals = &mut self.signals;
+        let in_flight = &self.in_flight;
+        let timeouts = &self.timeouts;
+
+        let shutdown = async move {
+            match session {
+                Some(session) => teardown(session, in_flight, timeouts).await,
+                None => drain(in_flight, timeouts.drain).await,
+            }
+        };
+
+        tokio::select! {
+            _ = shutdown => ShutdownOutcome::Graceful,
+            _ = signals.received() => ShutdownOutcome::Forced,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::broker::SessionEnd;
+
+    // each request is received right away, then no more requests come
+    struct FakeSignals {
+        pending: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl ShutdownSignals for FakeSignals {
+        async fn received(&mut self) {
+            if self.pending == 0 {
+                std::future::pending::<()>().await;
+            }
+            self.pending -= 1;
+        }
+    }
+
+    // records the steps, the hung ones never complete as with a dead broker
+    struct FakeSession {
+        steps: Arc<Mutex<Vec<&'static str>>>,
+        hung: Vec<&'static str>,
+    }
+
+    impl FakeSession {
+        async fn step(&self, step: &'static str) -> Result<(), String> {
+            if self.hung.contains(&step) {
+                std::future::pending::<()>().await;
+            }
+            self.steps.lock().unwrap().push(step);
+
+            Ok(())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl BrokerSession for FakeSession {
+        async fn cancel(&mut self) -> Result<(), String> {
+            self.step("cancel").await
+        }
+
+        async fn closed(&mut self) -> SessionEnd {
+            SessionEnd::Lost
+        }
+
+        async fn settle_stragglers(&mut self) -> Result<(), String> {
+            self.step("settle").await
+        }
+
+        async fn close_channels(&mut self) -> Result<(), String> {
+            self.step("close channels").await
+        }
+
+        async fn close(self) {
+            let _ = self.step("close").await;
+        }
+    }
+
+    fn fake_session() -> (FakeSession, Arc<Mutex<Vec<&'static str>>>) {
+        let steps = Arc::new(Mutex::new(vec![]));
+
+        (
+            FakeSession {
+                steps: steps.clone(),
+                hung: vec![],
+            },
+            steps,
+        )
+    }
+
+    fn timeouts() -> TeardownTimeouts {
+        TeardownTimeouts {
+            cancel: Duration::from_secs(5),
+            drain: Duration::from_secs(30),
+            settle: Duration::from_secs(5),
+            close: Duration::from_secs(5),
+        }
+    }
+
+    // simulates a delivery handled for the given duration
+    fn handle_delivery(
+        in_flight: &InFlight,
+        duration: Duration,
+        steps: Arc<Mutex<Vec<&'static str>>>,
+    ) {
+        let guard = in_flight.start();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            steps.lock().unwrap().push("drained");
+            drop(guard);
+        });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_ordering() {
+        let in_flight = InFlight::default();
+        let (session, steps) = fake_session();
+        handle_delivery(&in_flight, Duration::from_secs(5), steps.clone());
+
+        let mut coordinator =
+            ShutdownCoordinator::new(FakeSignals { pending: 1 }, timeouts(), in_flight.clone());
+        coordinator.requested().await;
+
+        assert_eq!(
+            coordinator.shutdown(Some(session)).await,
+            ShutdownOutcome::Graceful
+        );
+        assert_eq!(
+            *steps.lock().unwrap(),
+            vec!["cancel", "drained", "settle", "close channels", "close"]
+        );
+        assert_eq!(in_flight.count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_drain_timeout() {
+        let in_flight = InFlight::default();
+        let (session, steps) = fake_session();
+        handle_delivery(&in_flight, Duration::from_secs(60), steps.clone());
+
+        let mut coordinator =
+            ShutdownCoordinator::new(FakeSignals { pending: 1 }, timeouts(), in_flight.clone());
+        coordinator.requested().await;
+
+        assert_eq!(
+            coordinator.shutdown(Some(session)).await,
+            ShutdownOutcome::Graceful
+        );
+        assert_eq!(
+            *steps.lock().unwrap(),
+            vec!["cancel", "settle", "close channels", "close"]
+        );
+        assert_eq!(in_flight.count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_forced_by_second_signal() {
+        let in_flight = InFlight::default();
+        let (session, steps) = fake_session();
+        handle_delivery(&in_flight, Duration::from_secs(60), steps.clone());
+
+        let mut coordinator =
+            ShutdownCoordinator::new(FakeSignals { pending: 2 }, timeouts(), in_flight);
+        coordinator.requested().await;
+
+        assert_eq!(
+            coordinator.shutdown(Some(session)).await,
+            ShutdownOutcome::Forced
+        );
+        assert!(!steps.lock().unwrap().contains(&"close"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_teardown_phases_are_bounded() {
+        let in_flight = InFlight::default();
+        let steps = Arc::new(Mutex::new(vec![]));
+        let session = FakeSession {
+            steps: steps.clone(),
+            hung: vec!["cancel", "close channels"],
+        };
+        handle_delivery(&in_flight, Duration::from_secs(2), steps.clone());
+        let start = tokio::time::Instant::now();
+
+        teardown(session, &in_flight, &timeouts()).await;
+
+        assert_eq!(*steps.lock().unwrap(), vec!["drained", "settle", "close"]);
+        // the hung cancel outlasts the handling, the drain does not wait anymore
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_without_session() {
+        let mut coordinator =
+            ShutdownCoordinator::new(FakeSignals { pending: 1 }, timeouts(), InFlight::default());
+
+        assert_eq!(
+            coordinator.shutdown(None::<FakeSession>).await,
+            ShutdownOutcome::Graceful
+        );
+    }
+}